@@ -5,5 +5,6 @@ mod benchmarks;
 criterion_main! {
     // benchmarks::util::benches,
     // benchmarks::indexer::intersect,
-    benchmarks::common::serialization
+    benchmarks::common::serialization,
+    benchmarks::db::write
 }
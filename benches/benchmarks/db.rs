@@ -0,0 +1,59 @@
+use criterion::{criterion_group, BenchmarkId, Criterion, Throughput};
+use monolith::indexer::{SledIndexer, SledIndexerBuilder};
+use monolith::label::{Label, Labels};
+use monolith::option::DbOpts;
+use monolith::storage::{SledStorage, SledStorageBuilder};
+use monolith::time_point::TimePoint;
+use monolith::utils::get_current_timestamp;
+use monolith::MonolithDb;
+use tempfile::TempDir;
+
+const SERIES_NUM: usize = 100;
+
+fn write_criterion_benchmark(c: &mut Criterion) {
+    let labels = (0..SERIES_NUM)
+        .map(|i| {
+            Labels::from_vec(vec![
+                Label::from_key_value("__name__", "up"),
+                Label::from_key_value("instance", i.to_string().as_str()),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("write");
+    group.throughput(Throughput::Elements(SERIES_NUM as u64));
+    // samples of the current chunk are only kept in memory with write ahead log, which is the default,
+    // and are written through into the backend without it
+    let policies = vec![
+        ("wal", DbOpts::default().wal_flush_policy),
+        ("write through", None),
+    ];
+    for (name, wal_flush_policy) in policies {
+        let dir = TempDir::new().unwrap();
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        opts.wal_flush_policy = wal_flush_policy;
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )
+        .unwrap();
+        db.wait_ready().unwrap();
+
+        let mut timestamp = get_current_timestamp();
+        group.bench_function(BenchmarkId::new("samples", name), |b| {
+            b.iter(|| {
+                timestamp += 1;
+                for labels in labels.iter() {
+                    db.write_time_points(labels.clone(), vec![TimePoint::new(timestamp, 1.0)])
+                        .unwrap();
+                }
+            })
+        });
+        db.close().unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(write, write_criterion_benchmark);
@@ -1,3 +1,4 @@
 pub mod common;
+pub mod db;
 pub mod indexer;
 pub mod util;
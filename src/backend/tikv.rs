@@ -1,3 +1,4 @@
+use crate::metrics::TIKV_ERRORS;
 use crate::Result;
 use serde::{Deserialize, Serialize};
 use tikv_client::{Config, RawClient};
@@ -7,9 +8,19 @@ use std::fs::read;
 use std::path::Path;
 use uuid::Uuid;
 
+/// Max number of key value pairs TiKV returns in one raw scan
+const MAX_SCAN_LIMIT: u32 = 10240;
+
 pub trait TiKvRawBackend: Send + Sync {
     fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()>;
     fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>>;
+    fn delete(&self, key: Vec<u8>) -> Result<()>;
+
+    /// Get all key value pairs whose key starts with `prefix`, in key order
+    fn scan_prefix(&self, prefix: Vec<u8>) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Delete all keys that start with `prefix`
+    fn delete_prefix(&self, prefix: Vec<u8>) -> Result<()>;
 
     /// Store chunk and storage/indexer mapping information
     fn init_component(&self, chunk_identifier: Vec<u8>, is_indexer: bool) -> Result<Vec<u8>> {
@@ -20,16 +31,17 @@ pub trait TiKvRawBackend: Send + Sync {
             id
         });
         if is_indexer {
-            let mut indexer_id = Uuid::new_v4().as_bytes().to_vec();
-            indexer_id.append(value[..16].to_vec().as_mut());
-            self.set(chunk_identifier.clone(), indexer_id.clone());
+            let indexer_id = Uuid::new_v4().as_bytes().to_vec();
+            let mut res = indexer_id.clone();
+            res.append(value[16..].to_vec().as_mut());
+            self.set(chunk_identifier.clone(), res)?;
             Ok(indexer_id)
         } else {
-            let mut storage_id = Uuid::new_v4().as_bytes().to_vec();
+            let storage_id = Uuid::new_v4().as_bytes().to_vec();
             let mut res = vec![];
-            res.append(value[16..].to_vec().as_mut());
-            res.append(storage_id.as_mut());
-            self.set(chunk_identifier.clone(), res);
+            res.append(value[..16].to_vec().as_mut());
+            res.extend_from_slice(storage_id.as_slice());
+            self.set(chunk_identifier.clone(), res)?;
             Ok(storage_id)
         }
     }
@@ -48,27 +60,140 @@ struct TiKvRawBackendImpl {
     client: RawClient,
 }
 
-impl TiKvRawBackendImpl {}
+impl TiKvRawBackendImpl {
+    /// Count the error of `operation` before returning it
+    fn count_error<T>(operation: &str, res: tikv_client::Result<T>) -> Result<T> {
+        if res.is_err() {
+            TIKV_ERRORS.with_label_values(&[operation]).inc();
+        }
+        Ok(res?)
+    }
+}
 
 impl TiKvRawBackend for TiKvRawBackendImpl {
     fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
         let res: tikv_client::Result<()> = futures::executor::block_on(self.client.put(key, value));
-        Ok(res?)
+        Self::count_error("put", res)
     }
 
     fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
         let res: tikv_client::Result<Option<tikv_client::Value>> =
             futures::executor::block_on(self.client.get(key));
-        Ok(res?.map(|v| v.into()))
+        Ok(Self::count_error("get", res)?.map(|v| v.into()))
+    }
+
+    fn delete(&self, key: Vec<u8>) -> Result<()> {
+        let res: tikv_client::Result<()> = futures::executor::block_on(self.client.delete(key));
+        Self::count_error("delete", res)
+    }
+
+    fn scan_prefix(&self, prefix: Vec<u8>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let end = prefix_end(&prefix);
+        let mut start = prefix;
+        let mut res: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        loop {
+            // TiKV limits the num of pairs in one scan, so we need to scan page by page
+            let pairs: tikv_client::Result<Vec<tikv_client::KvPair>> =
+                futures::executor::block_on(self.client.scan(start..end.clone(), MAX_SCAN_LIMIT));
+            let pairs = Self::count_error("scan", pairs)?;
+            let len = pairs.len();
+            for pair in pairs {
+                let (key, value): (tikv_client::Key, tikv_client::Value) = pair.into();
+                res.push((key.into(), value.into()));
+            }
+            if len < MAX_SCAN_LIMIT as usize {
+                break;
+            }
+            // next page starts right after the last key
+            start = res.last().unwrap().0.clone();
+            start.push(0);
+        }
+        Ok(res)
+    }
+
+    fn delete_prefix(&self, prefix: Vec<u8>) -> Result<()> {
+        let end = prefix_end(&prefix);
+        let res: tikv_client::Result<()> =
+            futures::executor::block_on(self.client.delete_range(prefix..end));
+        Self::count_error("delete_range", res)
+    }
+}
+
+/// Backend of one tenant, all keys are stored under the tenant's prefix.
+///
+/// Prefix is stripped from the keys returned by scan, so storage and indexer see the same keys as
+/// without tenant.
+struct PrefixedTiKvBackend {
+    inner: Box<dyn TiKvRawBackend>,
+    prefix: Vec<u8>,
+}
+
+impl PrefixedTiKvBackend {
+    fn add_prefix(&self, key: Vec<u8>) -> Vec<u8> {
+        let mut res = self.prefix.clone();
+        res.extend(key);
+        res
     }
 }
 
+impl TiKvRawBackend for PrefixedTiKvBackend {
+    fn set(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        self.inner.set(self.add_prefix(key), value)
+    }
+
+    fn get(&self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        self.inner.get(self.add_prefix(key))
+    }
+
+    fn delete(&self, key: Vec<u8>) -> Result<()> {
+        self.inner.delete(self.add_prefix(key))
+    }
+
+    fn scan_prefix(&self, prefix: Vec<u8>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let len = self.prefix.len();
+        Ok(self
+            .inner
+            .scan_prefix(self.add_prefix(prefix))?
+            .into_iter()
+            .map(|(key, value)| (key[len..].to_vec(), value))
+            .collect())
+    }
+
+    fn delete_prefix(&self, prefix: Vec<u8>) -> Result<()> {
+        self.inner.delete_prefix(self.add_prefix(prefix))
+    }
+}
+
+/// Key prefix of `tenant`.
+///
+/// Keys without tenant start with a v4 uuid, whose 7th byte is `0x4?`, so they never collide with
+/// `tenant/` whose 7th byte is `/`.
+pub(crate) fn tenant_prefix(tenant: &str) -> Vec<u8> {
+    format!("tenant/{}/", tenant).into_bytes()
+}
+
+/// Get the smallest key that larger than all keys with `prefix`.
+///
+/// Return empty vec if there is no such key, which means unbounded in TiKV.
+pub(crate) fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::max_value() {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    end
+}
+
 // Return shared backend
 #[derive(Clone)]
 pub struct TiKvRawBackendSingleton {
     config: Config,
     // if true, then instead of connect to a tikv instance, we use a in memory hashmap
     dry_run: bool,
+    // prepended to all keys, empty if not used by a tenant
+    prefix: Vec<u8>,
 }
 
 impl TiKvRawBackendSingleton {
@@ -76,6 +201,7 @@ impl TiKvRawBackendSingleton {
         Ok(TiKvRawBackendSingleton {
             config,
             dry_run: false,
+            prefix: Vec::new(),
         })
     }
 
@@ -85,20 +211,39 @@ impl TiKvRawBackendSingleton {
             Ok(TiKvRawBackendSingleton {
                 config: Config::new(Vec::<String>::new()),
                 dry_run: true,
+                prefix: Vec::new(),
             })
         } else {
             TiKvRawBackendSingleton::new(Config::new(config_file.pd_endpoints))
         }
     }
 
+    /// Backend that stores all keys of `tenant` under its own prefix
+    pub fn for_tenant(&self, tenant: &str) -> TiKvRawBackendSingleton {
+        let mut prefix = self.prefix.clone();
+        prefix.extend(tenant_prefix(tenant));
+        TiKvRawBackendSingleton {
+            config: self.config.clone(),
+            dry_run: self.dry_run,
+            prefix,
+        }
+    }
+
     pub fn get_instance(&self) -> Result<Box<dyn TiKvRawBackend>> {
-        Ok(if self.dry_run {
+        let backend: Box<dyn TiKvRawBackend> = if self.dry_run {
             Box::new(DummyTiKvBackend::new())
         } else {
             Box::new(TiKvRawBackendImpl {
                 client: RawClient::new(self.config.clone())?,
             })
-        })
+        };
+        if self.prefix.is_empty() {
+            return Ok(backend);
+        }
+        Ok(Box::new(PrefixedTiKvBackend {
+            inner: backend,
+            prefix: self.prefix.clone(),
+        }))
     }
 }
 
@@ -107,6 +252,7 @@ impl Default for TiKvRawBackendSingleton {
         TiKvRawBackendSingleton {
             config: Config::new(Vec::<String>::new()),
             dry_run: true,
+            prefix: Vec::new(),
         }
     }
 }
@@ -139,9 +285,64 @@ impl Default for TiKvBackendConfigFile {
 
 #[cfg(test)]
 mod tests {
-    use crate::backend::tikv::TiKvBackendConfigFile;
+    use crate::backend::tikv::{
+        prefix_end, tenant_prefix, PrefixedTiKvBackend, TiKvBackendConfigFile, TiKvRawBackend,
+    };
+    use crate::common::test_utils::DummyTiKvBackend;
     use crate::Result;
 
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(&[1, 2, 3]), vec![1, 2, 4]);
+        assert_eq!(prefix_end(&[1, 255, 255]), vec![2]);
+        assert_eq!(prefix_end(&[255]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_init_component() -> Result<()> {
+        let backend = DummyTiKvBackend::new();
+        let chunk = b"chunk".to_vec();
+        let storage_id = backend.init_component(chunk.clone(), false)?;
+        let indexer_id = backend.init_component(chunk.clone(), true)?;
+        assert_eq!(storage_id.len(), 16);
+        assert_eq!(indexer_id.len(), 16);
+        assert_ne!(storage_id, indexer_id);
+        // {Indexer identifier}{Storage identifier}
+        let mapping = backend.get(chunk)?.unwrap();
+        assert_eq!(mapping[..16].to_vec(), indexer_id);
+        assert_eq!(mapping[16..].to_vec(), storage_id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefixed_backend() -> Result<()> {
+        let backend = PrefixedTiKvBackend {
+            inner: Box::new(DummyTiKvBackend::new()),
+            prefix: tenant_prefix("a"),
+        };
+        backend.set(b"k1".to_vec(), b"v1".to_vec())?;
+        backend.set(b"k2".to_vec(), b"v2".to_vec())?;
+        assert_eq!(backend.get(b"k1".to_vec())?, Some(b"v1".to_vec()));
+        assert_eq!(
+            backend.inner.get(b"tenant/a/k1".to_vec())?,
+            Some(b"v1".to_vec())
+        );
+        let pairs = backend.scan_prefix(b"k".to_vec())?;
+        assert_eq!(
+            pairs,
+            vec![
+                (b"k1".to_vec(), b"v1".to_vec()),
+                (b"k2".to_vec(), b"v2".to_vec())
+            ]
+        );
+        backend.inner.set(b"k3".to_vec(), b"v3".to_vec())?;
+        backend.delete_prefix(b"k".to_vec())?;
+        assert!(backend.scan_prefix(b"k".to_vec())?.is_empty());
+        // keys out of the tenant are kept
+        assert_eq!(backend.inner.get(b"k3".to_vec())?, Some(b"v3".to_vec()));
+        Ok(())
+    }
+
     #[test]
     fn test_read_yaml_file() -> Result<()> {
         let config_file = TiKvBackendConfigFile {
@@ -4,7 +4,7 @@ use clap::{App, Arg};
 
 use monolith::indexer::*;
 use monolith::option::{DbOpts, ServerOpts};
-use monolith::server::MonolithServer;
+use monolith::server::{MonolithServer, Shutdown};
 use monolith::storage::*;
 use monolith::*;
 use std::process::exit;
@@ -55,6 +55,81 @@ fn main() {
                 .long(WORKER_NUM)
                 .default_value(default_worker_num.as_str()),
             Arg::with_name(TIKV_CONFIG).long(TIKV_CONFIG),
+            Arg::with_name(RETENTION).long(RETENTION).takes_value(true),
+            Arg::with_name(RETENTION_SIZE)
+                .long(RETENTION_SIZE)
+                .takes_value(true),
+            Arg::with_name(OUT_OF_ORDER_WINDOW)
+                .long(OUT_OF_ORDER_WINDOW)
+                .takes_value(true),
+            Arg::with_name(TENANT_CONFIG)
+                .long(TENANT_CONFIG)
+                .takes_value(true),
+            Arg::with_name(MAX_TENANTS)
+                .long(MAX_TENANTS)
+                .takes_value(true),
+            Arg::with_name(WAL_FLUSH)
+                .long(WAL_FLUSH)
+                .default_value(DEFAULT_WAL_FLUSH),
+            Arg::with_name(MAX_SERIES)
+                .long(MAX_SERIES)
+                .takes_value(true),
+            Arg::with_name(MAX_SERIES_PER_METRIC)
+                .long(MAX_SERIES_PER_METRIC)
+                .takes_value(true),
+            Arg::with_name(MAX_LABELS_PER_SERIES)
+                .long(MAX_LABELS_PER_SERIES)
+                .takes_value(true),
+            Arg::with_name(MAX_LABEL_NAME_LENGTH)
+                .long(MAX_LABEL_NAME_LENGTH)
+                .takes_value(true),
+            Arg::with_name(MAX_LABEL_VALUE_LENGTH)
+                .long(MAX_LABEL_VALUE_LENGTH)
+                .takes_value(true),
+            Arg::with_name(INGESTION_RATE)
+                .long(INGESTION_RATE)
+                .takes_value(true),
+            Arg::with_name(INGESTION_BURST)
+                .long(INGESTION_BURST)
+                .takes_value(true),
+            Arg::with_name(GRAPHITE_PORT)
+                .long(GRAPHITE_PORT)
+                .takes_value(true),
+            Arg::with_name(GRAPHITE_PICKLE_PORT)
+                .long(GRAPHITE_PICKLE_PORT)
+                .takes_value(true),
+            Arg::with_name(GRAPHITE_TEMPLATE)
+                .long(GRAPHITE_TEMPLATE)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+            Arg::with_name(LISTEN_ADDRESS)
+                .long(LISTEN_ADDRESS)
+                .takes_value(true),
+            Arg::with_name(TLS_CERT_FILE)
+                .long(TLS_CERT_FILE)
+                .takes_value(true),
+            Arg::with_name(TLS_KEY_FILE)
+                .long(TLS_KEY_FILE)
+                .takes_value(true),
+            Arg::with_name(BASIC_AUTH_FILE)
+                .long(BASIC_AUTH_FILE)
+                .takes_value(true),
+            Arg::with_name(BEARER_TOKEN_FILE)
+                .long(BEARER_TOKEN_FILE)
+                .takes_value(true),
+            Arg::with_name(ENABLE_ADMIN_API)
+                .long(ENABLE_ADMIN_API)
+                .takes_value(false),
+            Arg::with_name(REMOTE_WRITE_CONFIG)
+                .long(REMOTE_WRITE_CONFIG)
+                .takes_value(true),
+            Arg::with_name(REPLICATE_FROM)
+                .long(REPLICATE_FROM)
+                .takes_value(true),
+            Arg::with_name(REPLICA_MAX_LAG)
+                .long(REPLICA_MAX_LAG)
+                .default_value(DEFAULT_REPLICA_MAX_LAG),
         ])
         .get_matches();
 
@@ -109,8 +184,12 @@ fn main() {
                 Box::new($indexer_builder),
             )
             .unwrap();
-            let server = MonolithServer::new(server_opts, db);
-            server.serve();
+            let shutdown = Shutdown::on_signals().unwrap();
+            let server = MonolithServer::new(server_opts, db).with_shutdown(shutdown);
+            if let Err(err) = server.serve() {
+                error!("Server stopped with error, {}", err);
+                exit(1);
+            }
         };
     }
 
@@ -3,7 +3,7 @@
 
 use monolith::indexer::{TiKvIndexer, TiKvIndexerBuilder};
 use monolith::option::{DbOpts, ServerOpts};
-use monolith::server::MonolithServer;
+use monolith::server::{MonolithServer, Shutdown};
 use monolith::storage::{TiKvStorage, TiKvStorageBuilder};
 use monolith::{MonolithDb, TiKvRawBackendSingleton, DEFAULT_PORT, DEFAULT_READ_PATH};
 use std::sync::Arc;
@@ -30,6 +30,7 @@ fn main() {
         Box::new(indexer_builder),
     )
     .unwrap();
-    let server = MonolithServer::new(server_opts, db);
+    let server =
+        MonolithServer::new(server_opts, db).with_shutdown(Shutdown::on_signals().unwrap());
     let _ = server.serve();
 }
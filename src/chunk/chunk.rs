@@ -1,6 +1,6 @@
-use crate::common::label::Labels;
+use crate::common::label::{LabelMatcher, Labels, MatcherType};
 use crate::common::time_point::TimePoint;
-use crate::common::time_series::TimeSeries;
+use crate::common::time_series::{TimeSeries, TimeSeriesId};
 use crate::common::utils::{get_current_timestamp, is_duration_overlap};
 use crate::common::IdGenerator;
 use crate::{MonolithErr, Result, Timestamp, CHUNK_METADATA_FILENAME, DEFAULT_CHUNK_SIZE};
@@ -8,10 +8,14 @@ use crate::{MonolithErr, Result, Timestamp, CHUNK_METADATA_FILENAME, DEFAULT_CHU
 use crate::storage::Storage;
 use crate::MonolithErr::OutOfRangeErr;
 
+use crate::chunk::Tombstones;
 use crate::indexer::Indexer;
+use crate::limits::{Limits, SeriesLimiter};
+use crate::metrics::{Histogram, INDEXER_LOOKUP_DURATION};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -76,6 +80,12 @@ pub struct Chunk<S: Storage, I: Indexer> {
     end_time: Timestamp,
     closed: AtomicBool,
     id_generator: IdGenerator,
+    series_limiter: SeriesLimiter,
+    tombstones: RwLock<Tombstones>,
+    // tombstones are kept in memory only if not set
+    tombstone_file: Option<PathBuf>,
+    series_count: AtomicUsize,
+    lookup_duration: Arc<Histogram>,
     mutex: RwLock<()>,
 }
 
@@ -92,26 +102,161 @@ impl<S: Storage, I: Indexer> Chunk<S, I> {
                 .unwrap_or(start_time + DEFAULT_CHUNK_SIZE.parse::<Timestamp>().unwrap()),
             closed: AtomicBool::new(false),
             id_generator: IdGenerator::new(1),
+            series_limiter: SeriesLimiter::default(),
+            tombstones: RwLock::new(Tombstones::default()),
+            tombstone_file: None,
+            series_count: AtomicUsize::new(0),
+            lookup_duration: INDEXER_LOOKUP_DURATION.with_label_values(&[I::get_type_name()]),
         }
     }
 
+    /// Reject new series beyond the series limits of `limits`
+    pub fn with_limits(mut self, limits: &Limits) -> Self {
+        self.series_limiter = SeriesLimiter::new(limits);
+        self
+    }
+
+    /// Persist tombstones in `path`, existing tombstones in it are loaded
+    pub fn with_tombstone_file(self, path: PathBuf) -> Result<Self> {
+        let tombstones = Tombstones::read_from_file(&path)?;
+        Ok(self.with_tombstones(path, tombstones))
+    }
+
+    /// Persist tombstones in `path`, starting with `tombstones` read from it
+    pub fn with_tombstones(mut self, path: PathBuf, tombstones: Tombstones) -> Self {
+        self.tombstones = RwLock::new(tombstones);
+        self.tombstone_file = Some(path);
+        self
+    }
+
+    ///
+    /// Continue series ids after the series already in indexer, used when the chunk is read from disk.
+    ///
+    /// Otherwise ids of new series written into the chunk would collide with the existing ones.
+    pub fn with_existing_series(self) -> Result<Self> {
+        let ids = self.series_ids()?;
+        Ok(self.with_series_ids(ids.as_slice()))
+    }
+
+    /// Continue series ids after `ids` of the series already in indexer, see `with_existing_series`
+    pub fn with_series_ids(self, ids: &[TimeSeriesId]) -> Self {
+        let next_id = ids.iter().map(|id| id + 1).max().unwrap_or(1);
+        Chunk {
+            id_generator: IdGenerator::new(next_id),
+            series_count: AtomicUsize::new(ids.len()),
+            ..self
+        }
+    }
+
+    /// Ids of all series in indexer
+    pub fn series_ids(&self) -> Result<Vec<TimeSeriesId>> {
+        let all = LabelMatcher::new(MatcherType::RegexMatch, "__name__", ".*")?;
+        let series = self.lookup(|indexer| indexer.get_series_metadata_by_matchers(&[all]))?;
+        Ok(series.into_iter().map(|(id, _)| id).collect())
+    }
+
+    ///
+    /// Compact all series in storage, return the number of series.
+    ///
+    /// Series are compacted one by one, so that queries and late writes only wait for one series.
+    pub fn compact(&self) -> Result<usize> {
+        let ids = self.series_ids()?;
+        for id in ids.iter() {
+            let _m = self
+                .mutex
+                .write()
+                .expect("Poisoned mutex when try to compact chunk");
+            self.storage.compact_time_series(*id)?;
+        }
+        self.flush()?;
+        Ok(ids.len())
+    }
+
+    /// Close the chunk and flush its storage and indexer
     pub fn close(&self) {
         let _m = self
             .mutex
             .write()
             .expect("Poisoned mutex in chunk when try to close chunk");
-        self.closed.store(true, Ordering::SeqCst)
+        self.closed.store(true, Ordering::SeqCst);
+        if let Err(err) = self.flush() {
+            error!(
+                "Cannot flush chunk {}-{}, {}",
+                self.start_time, self.end_time, err
+            );
+        }
+    }
+
+    /// Flush buffered writes of storage and indexer to disk
+    pub fn flush(&self) -> Result<()> {
+        self.storage.flush()?;
+        self.indexer.flush()
+    }
+
+    /// Remove data of storage and indexer that is not in the chunk dir, before the chunk is removed
+    pub fn destroy(&self) -> Result<()> {
+        self.storage.destroy()?;
+        self.indexer.destroy()
     }
 
     pub fn is_closed(&self) -> bool {
         self.closed.load(Ordering::SeqCst)
     }
 
+    /// Number of time series created in this chunk, excluding the ones removed by `clean_tombstones`
+    pub fn series_count(&self) -> usize {
+        self.series_count.load(Ordering::SeqCst)
+    }
+
+    /// Run a lookup on indexer and record its latency
+    fn lookup<T>(&self, f: impl FnOnce(&I) -> Result<T>) -> Result<T> {
+        let start = Instant::now();
+        let res = f(&self.indexer);
+        self.lookup_duration.observe(start.elapsed().as_secs_f64());
+        res
+    }
+
     pub fn insert(&self, labels: Labels, timepoint: TimePoint) -> Result<()> {
         let _m = self
             .mutex
             .write()
             .expect("Poisoned mutex when try to insert into chunk");
+        self.insert_locked(labels, timepoint).map(|_| ())
+    }
+
+    ///
+    /// Insert a time point, and write it into `target` with the same series id, like the in-memory
+    /// current chunk whose samples are written through into the chunk in backend.
+    ///
+    /// Series ids of `target` are assigned by this chunk, as if it's filled by `flush_into`.
+    pub fn insert_through<T: Storage, J: Indexer>(
+        &self,
+        target: &Chunk<T, J>,
+        labels: Labels,
+        timepoint: TimePoint,
+    ) -> Result<()> {
+        let _m = self
+            .mutex
+            .write()
+            .expect("Poisoned mutex when try to insert into chunk");
+        let _t = target
+            .mutex
+            .write()
+            .expect("Poisoned mutex when try to insert into chunk");
+        let (timestamp, value) = (timepoint.timestamp, timepoint.value);
+        let id = self.insert_locked(labels.clone(), timepoint)?;
+        if target
+            .indexer
+            .get_series_id_by_labels(labels.clone())?
+            .is_none()
+        {
+            target.indexer.create_index(labels, id)?;
+        }
+        target.storage.write_time_point(id, timestamp, value)
+    }
+
+    /// Insert a time point while holding the write lock, return the id of its series
+    fn insert_locked(&self, labels: Labels, timepoint: TimePoint) -> Result<TimeSeriesId> {
         if !self.is_in_range(&timepoint.timestamp) {
             info!(
                 "Chunk range {}, {}; but trying to insert {}",
@@ -120,20 +265,62 @@ impl<S: Storage, I: Indexer> Chunk<S, I> {
             return Err(MonolithErr::OutOfRangeErr(self.start_time, self.end_time));
         }
 
-        let id = self.indexer.get_series_id_by_labels(labels.clone())?;
-        if id.is_none() {
-            //insert new series
-            let new_id = self.id_generator.next();
-            self.indexer.create_index(labels.clone(), new_id)?;
-            self.storage
-                .write_time_point(new_id, timepoint.timestamp, timepoint.value)?;
-        } else {
-            //only one, skip the choose process
-            self.storage
-                .write_time_point(id.unwrap(), timepoint.timestamp, timepoint.value);
+        let id = self.lookup(|indexer| indexer.get_series_id_by_labels(labels.clone()))?;
+        match id {
+            None => {
+                //insert new series
+                self.series_limiter.add_series(&labels)?;
+                let new_id = self.id_generator.next();
+                self.indexer.create_index(labels.clone(), new_id)?;
+                self.series_count.fetch_add(1, Ordering::SeqCst);
+                self.storage
+                    .write_time_point(new_id, timepoint.timestamp, timepoint.value)?;
+                Ok(new_id)
+            }
+            Some(id) => {
+                //only one, skip the choose process
+                self.storage
+                    .write_time_point(id, timepoint.timestamp, timepoint.value)?;
+                Ok(id)
+            }
         }
+    }
 
-        Ok(())
+    ///
+    /// Copy all series of this chunk into `target` with the same ids, like the in-memory current chunk
+    /// into the chunk in backend, return the number of series.
+    ///
+    /// Data deleted by tombstones is copied as well, so that the tombstones of this chunk apply to
+    /// `target` as is. Series already in `target` are not indexed again, the same samples are overwritten.
+    pub fn flush_into<T: Storage, J: Indexer>(&self, target: &Chunk<T, J>) -> Result<usize> {
+        let _m = self
+            .mutex
+            .read()
+            .expect("Poisoned mutex when try to read from chunk");
+        let _t = target
+            .mutex
+            .write()
+            .expect("Poisoned mutex when try to insert into chunk");
+        let all = LabelMatcher::new(MatcherType::RegexMatch, "__name__", ".*")?;
+        let series = self.lookup(|indexer| indexer.get_series_metadata_by_matchers(&[all]))?;
+        let mut flushed = 0;
+        for (id, labels) in series {
+            let timepoints = match self.storage.read_time_series(id, 0, Timestamp::max_value()) {
+                Ok(timepoints) => timepoints,
+                Err(MonolithErr::NotFoundErr) => continue,
+                Err(err) => return Err(err),
+            };
+            if target
+                .indexer
+                .get_series_id_by_labels(labels.clone())?
+                .is_none()
+            {
+                target.indexer.create_index(labels, id)?;
+            }
+            target.storage.write_time_series(id, timepoints)?;
+            flushed += 1;
+        }
+        Ok(flushed)
     }
 
     pub fn query(
@@ -142,17 +329,210 @@ impl<S: Storage, I: Indexer> Chunk<S, I> {
         start_time: Timestamp,
         end_time: Timestamp,
     ) -> Result<Vec<TimeSeries>> {
-        self.mutex
+        let _m = self
+            .mutex
+            .read()
+            .expect("Poisoned mutex when try to read from chunk");
+        if !is_duration_overlap(self.start_time, self.end_time, start_time, end_time) {
+            return Err(OutOfRangeErr(self.start_time, self.end_time));
+        }
+        let candidates =
+            self.lookup(|indexer| indexer.get_series_metadata_contains_labels(labels))?;
+        self.read_candidates(candidates, start_time, end_time)
+    }
+
+    /// Query time series that satisfy all `matchers`
+    pub fn query_by_matchers(
+        &self,
+        matchers: &[LabelMatcher],
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<Vec<TimeSeries>> {
+        let _m = self
+            .mutex
             .read()
             .expect("Poisoned mutex when try to read from chunk");
         if !is_duration_overlap(self.start_time, self.end_time, start_time, end_time) {
             return Err(OutOfRangeErr(self.start_time, self.end_time));
         }
-        let candidates = self.indexer.get_series_metadata_contains_labels(labels)?;
+        let candidates =
+            self.lookup(|indexer| indexer.get_series_metadata_by_matchers(matchers))?;
+        self.read_candidates(candidates, start_time, end_time)
+    }
+
+    /// Read time points of the series with exactly `labels`, empty if there is no such series
+    pub fn query_by_labels(
+        &self,
+        labels: Labels,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<Vec<TimePoint>> {
+        let _m = self
+            .mutex
+            .read()
+            .expect("Poisoned mutex when try to read from chunk");
+        if !is_duration_overlap(self.start_time, self.end_time, start_time, end_time) {
+            return Ok(Vec::new());
+        }
+        let id = match self.lookup(|indexer| indexer.get_series_id_by_labels(labels.clone()))? {
+            Some(id) => id,
+            None => return Ok(Vec::new()),
+        };
+        Ok(self
+            .read_candidates(vec![(id, labels)], start_time, end_time)?
+            .pop()
+            .map(|series| series.time_points().clone())
+            .unwrap_or_default())
+    }
+
+    /// Get label sets of all time series that satisfy all `matchers`
+    ///
+    /// Time series deleted within the whole chunk range are excluded.
+    pub fn series_by_matchers(&self, matchers: &[LabelMatcher]) -> Result<Vec<Labels>> {
+        let _m = self
+            .mutex
+            .read()
+            .expect("Poisoned mutex when try to read from chunk");
+        let tombstones = self.tombstones.read().unwrap();
+        Ok(self
+            .lookup(|indexer| indexer.get_series_metadata_by_matchers(matchers))?
+            .into_iter()
+            .filter(|(id, _)| !tombstones.covers(*id, self.start_time, self.end_time))
+            .map(|(_, labels)| labels)
+            .collect())
+    }
+
+    /// Delete time series that satisfy all `matchers` within [`start_time`, `end_time`].
+    ///
+    /// Data is only marked deleted by tombstones and hidden from queries, it stays in storage until
+    /// `clean_tombstones`. Returns the number of time series deleted.
+    pub fn delete(
+        &self,
+        matchers: &[LabelMatcher],
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<usize> {
+        let _m = self
+            .mutex
+            .write()
+            .expect("Poisoned mutex when try to delete from chunk");
+        if !is_duration_overlap(self.start_time, self.end_time, start_time, end_time) {
+            return Ok(0);
+        }
+        let (start_time, end_time) = (start_time.max(self.start_time), end_time.min(self.end_time));
+        let ids = self.lookup(|indexer| indexer.get_series_metadata_by_matchers(matchers))?;
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let mut tombstones = self.tombstones.write().unwrap();
+        for (id, _) in ids.iter() {
+            tombstones.add(*id, start_time, end_time);
+        }
+        if let Some(path) = &self.tombstone_file {
+            tombstones.write_to_file(path)?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Purge tombstoned data from storage, and remove time series that have no data left from indexer.
+    pub fn clean_tombstones(&self) -> Result<()> {
+        let _m = self
+            .mutex
+            .write()
+            .expect("Poisoned mutex when try to clean tombstones of chunk");
+        self.take_tombstones(|_| Ok(()))
+    }
+
+    /// Like `clean_tombstones`, and purge the same data from `target` filled by `insert_through`, whose
+    /// series have the same ids
+    pub fn clean_tombstones_through<T: Storage, J: Indexer>(
+        &self,
+        target: &Chunk<T, J>,
+    ) -> Result<()> {
+        let _m = self
+            .mutex
+            .write()
+            .expect("Poisoned mutex when try to clean tombstones of chunk");
+        let _t = target
+            .mutex
+            .write()
+            .expect("Poisoned mutex when try to clean tombstones of chunk");
+        self.take_tombstones(|tombstones| target.purge(tombstones))
+    }
+
+    /// Purge all tombstones after `before` succeeds, and clear them
+    fn take_tombstones(&self, before: impl FnOnce(&Tombstones) -> Result<()>) -> Result<()> {
+        let mut tombstones = self.tombstones.write().unwrap();
+        if tombstones.is_empty() {
+            return Ok(());
+        }
+        before(&tombstones)?;
+        self.purge(&tombstones)?;
+        *tombstones = Tombstones::default();
+        if let Some(path) = &self.tombstone_file {
+            tombstones.write_to_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Purge data of `tombstones` from storage while holding the write lock
+    fn purge(&self, tombstones: &Tombstones) -> Result<()> {
+        for (id, intervals) in tombstones.iter() {
+            for (start_time, end_time) in intervals {
+                self.storage
+                    .delete_time_points(*id, *start_time, *end_time)?;
+            }
+            match self
+                .storage
+                .read_time_series(*id, 0, Timestamp::max_value())
+            {
+                Err(MonolithErr::NotFoundErr) => {
+                    self.indexer.delete_index(*id)?;
+                    // series of chunks read from disk are not counted
+                    self.series_count
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                        .ok();
+                }
+                Err(err) => return Err(err),
+                Ok(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Get names of all labels in this chunk
+    pub fn label_names(&self) -> Result<Vec<String>> {
+        let _m = self
+            .mutex
+            .read()
+            .expect("Poisoned mutex when try to read from chunk");
+        self.lookup(|indexer| indexer.get_label_names())
+    }
+
+    /// Get all values of label `name` in this chunk
+    pub fn label_values(&self, name: &str) -> Result<Vec<String>> {
+        let _m = self
+            .mutex
+            .read()
+            .expect("Poisoned mutex when try to read from chunk");
+        self.lookup(|indexer| indexer.get_label_values(name))
+    }
 
+    fn read_candidates(
+        &self,
+        candidates: Vec<(TimeSeriesId, Labels)>,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<Vec<TimeSeries>> {
+        let tombstones = self.tombstones.read().unwrap();
         let mut res = Vec::new();
         for (id, metadata) in candidates {
-            let data = self.storage.read_time_series(id, start_time, end_time)?;
+            let data = match self.storage.read_time_series(id, start_time, end_time) {
+                Ok(data) => tombstones.filter(id, data),
+                // series has no data within the range
+                Err(OutOfRangeErr(_, _)) | Err(MonolithErr::NotFoundErr) => continue,
+                Err(err) => return Err(err),
+            };
             if data.len() == 0 {
                 continue; //skip empty series
             }
@@ -170,8 +550,9 @@ impl<S: Storage, I: Indexer> Chunk<S, I> {
         (self.start_time, self.end_time)
     }
 
-    fn is_in_range(&self, timestamp: &Timestamp) -> bool {
-        return self.start_time < *timestamp && self.end_time > *timestamp;
+    /// Whether `timestamp` can be inserted into this chunk, which covers [`start_time`, `end_time`)
+    pub fn is_in_range(&self, timestamp: &Timestamp) -> bool {
+        self.start_time <= *timestamp && *timestamp < self.end_time
     }
 }
 
@@ -1,4 +1,6 @@
 pub mod chunk;
+pub mod tombstone;
 
 pub use chunk::Chunk;
 pub use chunk::ChunkOpts;
+pub use tombstone::Tombstones;
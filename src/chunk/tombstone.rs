@@ -0,0 +1,157 @@
+use crate::common::time_point::TimePoint;
+use crate::common::time_series::TimeSeriesId;
+use crate::{Result, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+///
+/// Time ranges of series that are deleted but still in storage.
+///
+/// Deleted points are hidden from queries until they are purged by `Chunk::clean_tombstones`.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Tombstones {
+    intervals: HashMap<TimeSeriesId, Vec<(Timestamp, Timestamp)>>,
+}
+
+impl Tombstones {
+    /// Mark [`start_time`, `end_time`] of series as deleted, overlapping ranges are merged.
+    pub fn add(
+        &mut self,
+        time_series_id: TimeSeriesId,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) {
+        let intervals = self.intervals.entry(time_series_id).or_default();
+        intervals.push((start_time, end_time));
+        intervals.sort();
+        let mut merged: Vec<(Timestamp, Timestamp)> = Vec::with_capacity(intervals.len());
+        for (start, end) in intervals.drain(..) {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *intervals = merged;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    /// Deleted ranges of each series
+    pub fn iter(&self) -> impl Iterator<Item = (&TimeSeriesId, &Vec<(Timestamp, Timestamp)>)> {
+        self.intervals.iter()
+    }
+
+    pub fn is_deleted(&self, time_series_id: TimeSeriesId, timestamp: Timestamp) -> bool {
+        match self.intervals.get(&time_series_id) {
+            Some(intervals) => intervals
+                .iter()
+                .any(|(start, end)| *start <= timestamp && timestamp <= *end),
+            None => false,
+        }
+    }
+
+    /// Whether the whole [`start_time`, `end_time`] of series is deleted
+    pub fn covers(
+        &self,
+        time_series_id: TimeSeriesId,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> bool {
+        match self.intervals.get(&time_series_id) {
+            Some(intervals) => intervals
+                .iter()
+                .any(|(start, end)| *start <= start_time && end_time <= *end),
+            None => false,
+        }
+    }
+
+    /// Remove deleted points of series
+    pub fn filter(&self, time_series_id: TimeSeriesId, points: Vec<TimePoint>) -> Vec<TimePoint> {
+        if !self.intervals.contains_key(&time_series_id) {
+            return points;
+        }
+        points
+            .into_iter()
+            .filter(|tp| !self.is_deleted(time_series_id, tp.timestamp))
+            .collect()
+    }
+
+    /// Read tombstones from file, empty if the file doesn't exist
+    pub fn read_from_file(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Tombstones::default());
+        }
+        let content = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(content.as_str())?)
+    }
+
+    /// Write tombstones into file, or remove the file if there is no tombstone
+    pub fn write_to_file(&self, path: &Path) -> Result<()> {
+        if self.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+            return Ok(());
+        }
+        // write to a temp file first, so that a crash never leaves a broken file
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chunk::Tombstones;
+    use crate::common::time_point::TimePoint;
+    use crate::Result;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_add_and_filter() {
+        let mut tombstones = Tombstones::default();
+        tombstones.add(1, 10, 20);
+        tombstones.add(1, 15, 30);
+        tombstones.add(1, 50, 60);
+        assert_eq!(
+            tombstones.iter().next().unwrap().1,
+            &vec![(10, 30), (50, 60)]
+        );
+        assert!(tombstones.covers(1, 12, 30));
+        assert!(!tombstones.covers(1, 12, 55));
+        assert!(!tombstones.covers(2, 12, 30));
+
+        let points = vec![5, 10, 30, 31, 55]
+            .into_iter()
+            .map(|ts| TimePoint::new(ts, 1.0))
+            .collect::<Vec<TimePoint>>();
+        let kept = tombstones
+            .filter(1, points.clone())
+            .into_iter()
+            .map(|tp| tp.timestamp)
+            .collect::<Vec<u64>>();
+        assert_eq!(kept, vec![5, 31]);
+        assert_eq!(tombstones.filter(2, points.clone()), points);
+    }
+
+    #[test]
+    fn test_read_write_file() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("tombstones.json");
+        assert!(Tombstones::read_from_file(&path)?.is_empty());
+
+        let mut tombstones = Tombstones::default();
+        tombstones.add(3, 100, 200);
+        tombstones.write_to_file(&path)?;
+        assert_eq!(Tombstones::read_from_file(&path)?, tombstones);
+
+        Tombstones::default().write_to_file(&path)?;
+        assert!(!path.exists());
+        Ok(())
+    }
+}
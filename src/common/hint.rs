@@ -0,0 +1,178 @@
+use crate::common::time_point::TimePoint;
+use crate::proto::ReadHints;
+use crate::{Timestamp, Value};
+
+/// Aggregation applied to each step window of a downsampled query
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AggregateFunc {
+    Avg,
+    Min,
+    Max,
+    Count,
+    Sum,
+    Last,
+}
+
+impl AggregateFunc {
+    /// Map the function name in Prometheus' hint to aggregation, both aggregation operators(e.g. `avg`)
+    /// and range functions(e.g. `avg_over_time`) are accepted.
+    ///
+    /// Return `None` if the function cannot be computed from the samples of one window, for example `rate`.
+    pub fn from_name(name: &str) -> Option<AggregateFunc> {
+        match name.trim_end_matches("_over_time") {
+            "avg" => Some(AggregateFunc::Avg),
+            "min" => Some(AggregateFunc::Min),
+            "max" => Some(AggregateFunc::Max),
+            "count" => Some(AggregateFunc::Count),
+            "sum" => Some(AggregateFunc::Sum),
+            "last" => Some(AggregateFunc::Last),
+            _ => None,
+        }
+    }
+
+    fn aggregate(self, points: &[TimePoint]) -> Value {
+        let values = points.iter().map(|tp| tp.value);
+        match self {
+            AggregateFunc::Avg => values.sum::<Value>() / points.len() as Value,
+            AggregateFunc::Min => values.fold(std::f64::INFINITY, Value::min),
+            AggregateFunc::Max => values.fold(std::f64::NEG_INFINITY, Value::max),
+            AggregateFunc::Count => points.len() as Value,
+            AggregateFunc::Sum => values.sum(),
+            AggregateFunc::Last => points.last().map(|tp| tp.value).unwrap_or(std::f64::NAN),
+        }
+    }
+}
+
+///
+/// Hint of query used to downsample the result on server side.
+///
+/// Time line is split into windows of `step` starting from `start`, each window is (`start` + (k-1) * `step`,
+/// `start` + k * `step`] and will be aggregated into one point at the right boundary.
+#[derive(Clone, Debug)]
+pub struct QueryHint {
+    start: Timestamp,
+    step: Timestamp,
+    func: AggregateFunc,
+}
+
+impl QueryHint {
+    pub fn new(start: Timestamp, step: Timestamp, func: AggregateFunc) -> QueryHint {
+        QueryHint { start, step, func }
+    }
+
+    /// Build hint from Prometheus' `ReadHints`, `query_start` will be used if the hint doesn't contain start time.
+    ///
+    /// Return `None` if the step or function is not set, or the function is not supported.
+    pub fn from_read_hints(hints: &ReadHints, query_start: Timestamp) -> Option<QueryHint> {
+        if hints.step_ms <= 0 {
+            return None;
+        }
+        let func = AggregateFunc::from_name(hints.func.as_str())?;
+        let start = if hints.start_ms > 0 {
+            hints.start_ms as Timestamp
+        } else {
+            query_start
+        };
+        Some(QueryHint::new(start, hints.step_ms as Timestamp, func))
+    }
+
+    pub fn step(&self) -> Timestamp {
+        self.step
+    }
+
+    pub fn func(&self) -> AggregateFunc {
+        self.func
+    }
+
+    /// Aggregate time points sorted by timestamp into one point per window.
+    pub fn downsample(&self, time_points: &[TimePoint]) -> Vec<TimePoint> {
+        let mut res = Vec::new();
+        let mut begin = 0;
+        while begin < time_points.len() {
+            let window = self.window_of(time_points[begin].timestamp);
+            let end = begin
+                + time_points[begin..]
+                    .iter()
+                    .take_while(|tp| self.window_of(tp.timestamp) == window)
+                    .count();
+            res.push(TimePoint::new(
+                window,
+                self.func.aggregate(&time_points[begin..end]),
+            ));
+            begin = end;
+        }
+        res
+    }
+
+    /// Right boundary of the window that `timestamp` belongs to
+    fn window_of(&self, timestamp: Timestamp) -> Timestamp {
+        if timestamp <= self.start {
+            return self.start;
+        }
+        let offset = timestamp - self.start;
+        self.start + (offset + self.step - 1) / self.step * self.step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::hint::{AggregateFunc, QueryHint};
+    use crate::common::time_point::TimePoint;
+    use crate::proto::ReadHints;
+
+    fn points() -> Vec<TimePoint> {
+        vec![
+            TimePoint::new(100, 1.0),
+            TimePoint::new(101, 3.0),
+            TimePoint::new(110, 2.0),
+            TimePoint::new(111, 4.0),
+            TimePoint::new(125, 6.0),
+        ]
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(AggregateFunc::from_name("avg"), Some(AggregateFunc::Avg));
+        assert_eq!(
+            AggregateFunc::from_name("max_over_time"),
+            Some(AggregateFunc::Max)
+        );
+        assert_eq!(AggregateFunc::from_name("rate"), None);
+    }
+
+    #[test]
+    fn test_downsample() {
+        let hint = QueryHint::new(100, 10, AggregateFunc::Avg);
+        let res = hint.downsample(points().as_slice());
+        let expected = vec![(100, 1.0), (110, 2.5), (120, 4.0), (130, 6.0)];
+        assert_eq!(res.len(), expected.len());
+        for (tp, (t, v)) in res.iter().zip(expected) {
+            assert_eq!(tp.timestamp, t);
+            assert!((tp.value - v).abs() < std::f64::EPSILON);
+        }
+
+        let hint = QueryHint::new(100, 30, AggregateFunc::Count);
+        let res = hint.downsample(points().as_slice());
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].timestamp, 130);
+        assert!((res[1].value - 4.0).abs() < std::f64::EPSILON);
+
+        let hint = QueryHint::new(100, 30, AggregateFunc::Last);
+        let res = hint.downsample(points().as_slice());
+        assert!((res[1].value - 6.0).abs() < std::f64::EPSILON);
+    }
+
+    #[test]
+    fn test_from_read_hints() {
+        let mut hints = ReadHints::new();
+        assert!(QueryHint::from_read_hints(&hints, 0).is_none());
+        hints.set_step_ms(10);
+        hints.set_func("rate".to_string());
+        assert!(QueryHint::from_read_hints(&hints, 0).is_none());
+        hints.set_func("sum".to_string());
+        let hint = QueryHint::from_read_hints(&hints, 50).unwrap();
+        assert_eq!(hint.step(), 10);
+        assert_eq!(hint.func(), AggregateFunc::Sum);
+        assert_eq!(hint.window_of(51), 60);
+    }
+}
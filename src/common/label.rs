@@ -1,5 +1,7 @@
-use crate::proto::LabelMatcher;
+use crate::proto::{LabelMatcher as ProtoLabelMatcher, LabelMatcher_Type};
+use crate::{MonolithErr, Result};
 use failure::_core::cmp::Ordering;
+use regex::Regex;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
@@ -16,7 +18,7 @@ impl Label {
     pub fn new(key: String, value: String) -> Label {
         Label { key, value }
     }
-    pub fn from_label_matcher(label_matcher: &LabelMatcher) -> Label {
+    pub fn from_label_matcher(label_matcher: &ProtoLabelMatcher) -> Label {
         Label {
             key: label_matcher.name.clone(),
             value: label_matcher.value.clone(),
@@ -98,6 +100,9 @@ impl Eq for Labels {}
 
 impl PartialEq for Labels {
     fn eq(&self, other: &Self) -> bool {
+        if self.len() != other.len() {
+            return false;
+        }
         for i in 0..self.len() {
             if !self.0.get(i).unwrap().eq(other.0.get(i).unwrap()) {
                 return false;
@@ -141,11 +146,155 @@ impl Labels {
     pub fn pop(&mut self) -> Option<Label> {
         self.0.pop()
     }
+
+    /// Compare name and then value of each label in order, used to sort label sets.
+    pub fn compare(&self, other: &Labels) -> Ordering {
+        for (l, r) in self.0.iter().zip(other.0.iter()) {
+            let ord = l.key.cmp(&r.key).then_with(|| l.value.cmp(&r.value));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        self.len().cmp(&other.len())
+    }
+}
+
+/// Format as `{name="value", ...}`
+impl std::fmt::Display for Labels {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let labels = self
+            .0
+            .iter()
+            .map(|l| format!("{}={:?}", l.key, l.value))
+            .collect::<Vec<String>>();
+        write!(f, "{{{}}}", labels.join(", "))
+    }
+}
+
+/// Type of `LabelMatcher`, same as the matcher types in Prometheus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatcherType {
+    Equal,         // =
+    NotEqual,      // !=
+    RegexMatch,    // =~
+    RegexNotMatch, // !~
+}
+
+impl From<LabelMatcher_Type> for MatcherType {
+    fn from(t: LabelMatcher_Type) -> Self {
+        match t {
+            LabelMatcher_Type::EQ => MatcherType::Equal,
+            LabelMatcher_Type::NEQ => MatcherType::NotEqual,
+            LabelMatcher_Type::RE => MatcherType::RegexMatch,
+            LabelMatcher_Type::NRE => MatcherType::RegexNotMatch,
+        }
+    }
+}
+
+///
+/// LabelMatcher select time series by the value of one label.
+///
+/// It follows Prometheus semantics:
+/// 1. A series without label `name` is treated as if it has label `name` with empty value. Thus `foo=""` matches series without `foo`.
+/// 2. Regex is fully anchored, `foo=~"ba"` will not match `foo="bar"`.
+#[derive(Clone, Debug)]
+pub struct LabelMatcher {
+    name: String,
+    value: String,
+    matcher_type: MatcherType,
+    regex: Option<Regex>,
+}
+
+impl LabelMatcher {
+    pub fn new(matcher_type: MatcherType, name: &str, value: &str) -> Result<LabelMatcher> {
+        let regex = match matcher_type {
+            MatcherType::RegexMatch | MatcherType::RegexNotMatch => Some(
+                Regex::new(format!("^(?:{})$", value).as_str())
+                    .map_err(|e| MonolithErr::InvalidMatcherErr(e.to_string()))?,
+            ),
+            _ => None,
+        };
+        Ok(LabelMatcher {
+            name: name.to_string(),
+            value: value.to_string(),
+            matcher_type,
+            regex,
+        })
+    }
+
+    pub fn from_label_matcher(label_matcher: &ProtoLabelMatcher) -> Result<LabelMatcher> {
+        LabelMatcher::new(
+            MatcherType::from(label_matcher.field_type),
+            label_matcher.name.as_str(),
+            label_matcher.value.as_str(),
+        )
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn value(&self) -> &String {
+        &self.value
+    }
+
+    pub fn matcher_type(&self) -> MatcherType {
+        self.matcher_type
+    }
+
+    /// Check if the label value matches. Use empty string if the label is not present.
+    pub fn matches(&self, value: &str) -> bool {
+        match self.matcher_type {
+            MatcherType::Equal => self.value == value,
+            MatcherType::NotEqual => self.value != value,
+            MatcherType::RegexMatch => self.regex.as_ref().unwrap().is_match(value),
+            MatcherType::RegexNotMatch => !self.regex.as_ref().unwrap().is_match(value),
+        }
+    }
+
+    /// If true, series that don't have this label will also be selected.
+    pub fn matches_empty(&self) -> bool {
+        self.matches("")
+    }
+
+    /// Check if the labels of one series matches.
+    pub fn matches_labels(&self, labels: &Labels) -> bool {
+        let value = labels
+            .vec()
+            .iter()
+            .find(|l| l.key == self.name)
+            .map(|l| l.value.as_str())
+            .unwrap_or("");
+        self.matches(value)
+    }
+}
+
+impl std::fmt::Display for LabelMatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let op = match self.matcher_type {
+            MatcherType::Equal => "=",
+            MatcherType::NotEqual => "!=",
+            MatcherType::RegexMatch => "=~",
+            MatcherType::RegexNotMatch => "!~",
+        };
+        write!(f, "{}{}{:?}", self.name, op, self.value)
+    }
+}
+
+impl From<&Label> for LabelMatcher {
+    fn from(l: &Label) -> Self {
+        LabelMatcher {
+            name: l.key.clone(),
+            value: l.value.clone(),
+            matcher_type: MatcherType::Equal,
+            regex: None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::common::label::{Label, Labels};
+    use crate::common::label::{Label, LabelMatcher, Labels, MatcherType};
 
     #[test]
     fn get_hash() {
@@ -165,4 +314,43 @@ mod test {
 
         assert_eq!(labels.0.get(0).unwrap().key, "test1")
     }
+
+    #[test]
+    fn label_matcher_matches() -> crate::Result<()> {
+        let eq = LabelMatcher::new(MatcherType::Equal, "job", "api")?;
+        assert!(eq.matches("api"));
+        assert!(!eq.matches("api-server"));
+        assert!(!eq.matches_empty());
+
+        let neq = LabelMatcher::new(MatcherType::NotEqual, "job", "api")?;
+        assert!(!neq.matches("api"));
+        assert!(neq.matches("db"));
+        assert!(neq.matches_empty());
+
+        // regex must match the whole value
+        let re = LabelMatcher::new(MatcherType::RegexMatch, "job", "api|db")?;
+        assert!(re.matches("api"));
+        assert!(re.matches("db"));
+        assert!(!re.matches("api-server"));
+        assert!(!re.matches_empty());
+
+        let nre = LabelMatcher::new(MatcherType::RegexNotMatch, "job", "a.*")?;
+        assert!(!nre.matches("api"));
+        assert!(nre.matches("db"));
+        assert!(nre.matches_empty());
+
+        assert!(LabelMatcher::new(MatcherType::RegexMatch, "job", "a(").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn label_matcher_matches_labels() -> crate::Result<()> {
+        let labels = Labels::from_vec(vec![Label::from_key_value("job", "api")]);
+        assert!(LabelMatcher::new(MatcherType::Equal, "job", "api")?.matches_labels(&labels));
+        // series without the label is treated as empty value
+        assert!(LabelMatcher::new(MatcherType::Equal, "env", "")?.matches_labels(&labels));
+        assert!(LabelMatcher::new(MatcherType::NotEqual, "env", "prod")?.matches_labels(&labels));
+        assert!(!LabelMatcher::new(MatcherType::RegexMatch, "env", ".+")?.matches_labels(&labels));
+        Ok(())
+    }
 }
@@ -8,9 +8,11 @@ use std::path::Path;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 
+pub mod hint;
 pub mod label;
 pub mod metadata;
 pub mod option;
+pub mod selector;
 pub mod time_point;
 pub mod time_series;
 pub mod utils;
@@ -76,6 +78,9 @@ pub trait Builder<T> {
 
     /// Read additional config or metadata information from db dir.
     fn read_config(&self, dir: &Path) -> Result<()>;
+
+    /// Builder for the chunks of `tenant`, whose data must be isolated from other tenants.
+    fn tenant_builder(&self, tenant: &str) -> Box<dyn Builder<T> + Sync + Send>;
 }
 
 pub trait HasTypeName {
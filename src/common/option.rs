@@ -1,13 +1,25 @@
+use crate::limits::Limits;
+use crate::wal::FlushPolicy;
 use crate::{
-    MonolithErr, Result, CHUNK_SIZE, DEFAULT_CHUNK_SIZE, DEFAULT_PORT, DEFAULT_READ_PATH,
-    DEFAULT_WORKER_NUM, DEFAULT_WRITE_PATH, FILE_DIR_ARG, INDEXER_ARG, PORT, READ_PATH,
-    SLED_BACKEND, STORAGE_ARG, TIKV_CONFIG, WORKER_NUM, WRITE_PATH,
+    MonolithErr, Result, BASIC_AUTH_FILE, BEARER_TOKEN_FILE, CHUNK_SIZE, DEFAULT_CHUNK_SIZE,
+    DEFAULT_PORT, DEFAULT_READ_PATH, DEFAULT_REPLICA_MAX_LAG, DEFAULT_WAL_FLUSH,
+    DEFAULT_WORKER_NUM, DEFAULT_WRITE_PATH, ENABLE_ADMIN_API, FILE_DIR_ARG, GRAPHITE_PICKLE_PORT,
+    GRAPHITE_PORT, GRAPHITE_TEMPLATE, INDEXER_ARG, INGESTION_BURST, INGESTION_RATE, LISTEN_ADDRESS,
+    MAX_LABELS_PER_SERIES, MAX_LABEL_NAME_LENGTH, MAX_LABEL_VALUE_LENGTH, MAX_SERIES,
+    MAX_SERIES_PER_METRIC, MAX_TENANTS, OUT_OF_ORDER_WINDOW, PORT, READ_PATH, REMOTE_WRITE_CONFIG,
+    REPLICATE_FROM, REPLICA_MAX_LAG, RETENTION, RETENTION_SIZE, SLED_BACKEND, STORAGE_ARG,
+    TENANT_CONFIG, TENANT_DIR, TIKV_CONFIG, TLS_CERT_FILE, TLS_KEY_FILE, WAL_DISABLED, WAL_FLUSH,
+    WORKER_NUM, WRITE_PATH,
 };
 use clap::ArgMatches;
 use failure::_core::fmt::Formatter;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::env::current_dir;
 use std::fmt;
-use std::path::PathBuf;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -18,22 +30,152 @@ pub struct DbOpts {
     pub base_dir: PathBuf,
     pub chunk_size: Duration,
     pub tikv_config: Option<PathBuf>,
+    /// Chunks ended longer than `retention` ago are removed, keep all chunks if not set
+    pub retention: Option<Duration>,
+    /// Oldest chunks are removed until the chunk dirs take no more than `retention_size` bytes,
+    /// the current chunk is always kept. Keep all chunks if not set
+    pub retention_size: Option<u64>,
+    /// Samples up to `out_of_order_window` older than the current chunk are written into the earlier
+    /// chunk that covers them, older ones are rejected. Zero accepts only the current chunk.
+    pub out_of_order_window: Duration,
+    /// Samples are logged ahead in `<base_dir>/wal` and replayed on start if set, otherwise samples of the
+    /// current chunk are written through into the backend. Enabled with `DEFAULT_WAL_FLUSH` by default
+    pub wal_flush_policy: Option<FlushPolicy>,
+    /// Db is a read replica, which rejects writes and swaps chunks like its leader instead of by time
+    pub replica: bool,
+    /// Options that override the default ones for each tenant
+    pub tenants: HashMap<String, TenantOpts>,
+    /// Requests of new tenants are rejected once this many tenants are opened, unlimited if not set
+    pub max_tenants: Option<usize>,
+    pub limits: Limits,
 }
 
 impl DbOpts {
     pub fn get_config(matches: &ArgMatches) -> Result<DbOpts> {
         let chunk_size_str = matches.value_of(CHUNK_SIZE).unwrap();
         let chunk_size_in_sec: u64 = String::from(chunk_size_str).parse()?;
+        let retention = match matches.value_of(RETENTION) {
+            Some(retention) => Some(Duration::from_secs(retention.parse()?)),
+            None => None,
+        };
+        let retention_size = match matches.value_of(RETENTION_SIZE) {
+            Some(size) => Some(size.parse()?),
+            None => None,
+        };
+        let out_of_order_window = match matches.value_of(OUT_OF_ORDER_WINDOW) {
+            Some(window) => Duration::from_secs(window.parse()?),
+            None => Duration::from_secs(0),
+        };
+        let wal_flush_policy = match matches.value_of(WAL_FLUSH).unwrap() {
+            WAL_DISABLED => None,
+            policy => Some(policy.parse()?),
+        };
+        let tenants = match matches.value_of(TENANT_CONFIG) {
+            Some(path) => TenantOpts::from_file(PathBuf::from(path).as_path())?,
+            None => HashMap::new(),
+        };
+        let max_tenants = match matches.value_of(MAX_TENANTS) {
+            Some(max) => Some(max.parse()?),
+            None => None,
+        };
         let config = DbOpts {
             storage: matches.value_of(STORAGE_ARG).unwrap().to_string(),
             indexer: matches.value_of(INDEXER_ARG).unwrap().to_string(),
             base_dir: PathBuf::from_str(matches.value_of(FILE_DIR_ARG).unwrap())?,
             chunk_size: Duration::from_secs(chunk_size_in_sec),
             tikv_config: matches.value_of(TIKV_CONFIG).map(PathBuf::from),
+            retention,
+            retention_size,
+            out_of_order_window,
+            wal_flush_policy,
+            replica: matches.is_present(REPLICATE_FROM),
+            tenants,
+            max_tenants,
+            limits: Self::get_limits(matches)?,
         };
 
         Ok(config)
     }
+
+    fn get_limits(matches: &ArgMatches) -> Result<Limits> {
+        let parse = |name: &str| -> Result<Option<usize>> {
+            match matches.value_of(name) {
+                Some(value) => Ok(Some(value.parse::<usize>()?)),
+                None => Ok(None),
+            }
+        };
+        let ingestion_rate = match matches.value_of(INGESTION_RATE) {
+            Some(value) => Some(value.parse::<f64>()?),
+            None => None,
+        };
+        Ok(Limits {
+            max_series: parse(MAX_SERIES)?,
+            max_series_per_metric: parse(MAX_SERIES_PER_METRIC)?,
+            max_labels_per_series: parse(MAX_LABELS_PER_SERIES)?,
+            max_label_name_length: parse(MAX_LABEL_NAME_LENGTH)?,
+            max_label_value_length: parse(MAX_LABEL_VALUE_LENGTH)?,
+            ingestion_rate,
+            ingestion_burst: parse(INGESTION_BURST)?,
+        })
+    }
+
+    /// Options of the db of `tenant`, which is under `<base_dir>/tenants/<tenant>`
+    pub fn for_tenant(&self, tenant: &str) -> DbOpts {
+        let mut opts = self.clone();
+        opts.base_dir = self.base_dir.join(TENANT_DIR).join(tenant);
+        opts.tenants = HashMap::new();
+        if let Some(tenant_opts) = self.tenants.get(tenant) {
+            if let Some(chunk_size) = tenant_opts.chunk_size {
+                opts.chunk_size = Duration::from_secs(chunk_size);
+            }
+            if let Some(retention) = tenant_opts.retention {
+                opts.retention = Some(Duration::from_secs(retention));
+            }
+            if let Some(retention_size) = tenant_opts.retention_size {
+                opts.retention_size = Some(retention_size);
+            }
+            if let Some(window) = tenant_opts.out_of_order_window {
+                opts.out_of_order_window = Duration::from_secs(window);
+            }
+            opts.limits = self.limits.merge(&tenant_opts.limits);
+        }
+        opts
+    }
+}
+
+///
+/// Options of one tenant, durations are in seconds. Unset options use the value of command line.
+///
+/// Read from a yaml file that maps tenant id to its options, which may also contain any field of `Limits`:
+///
+/// ```yaml
+/// team-a:
+///   chunk_size: 3600
+///   retention: 604800
+///   retention_size: 10737418240
+///   out_of_order_window: 600
+///   max_series: 100000
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct TenantOpts {
+    #[serde(default)]
+    pub chunk_size: Option<u64>,
+    #[serde(default)]
+    pub retention: Option<u64>,
+    #[serde(default)]
+    pub retention_size: Option<u64>,
+    #[serde(default)]
+    pub out_of_order_window: Option<u64>,
+    #[serde(flatten)]
+    pub limits: Limits,
+}
+
+impl TenantOpts {
+    pub fn from_file(path: &Path) -> Result<HashMap<String, TenantOpts>> {
+        let content = fs::read(path)?;
+        let tenants: HashMap<String, TenantOpts> = serde_yaml::from_slice(content.as_slice())?;
+        Ok(tenants)
+    }
 }
 
 impl Default for DbOpts {
@@ -44,32 +186,163 @@ impl Default for DbOpts {
             base_dir: current_dir().unwrap(),
             chunk_size: Duration::from_secs(u64::from_str(DEFAULT_CHUNK_SIZE).unwrap()),
             tikv_config: None,
+            retention: None,
+            retention_size: None,
+            out_of_order_window: Duration::from_secs(0),
+            wal_flush_policy: Some(FlushPolicy::from_str(DEFAULT_WAL_FLUSH).unwrap()),
+            replica: false,
+            tenants: HashMap::new(),
+            max_tenants: None,
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// Prefix of `listen_address` to listen on unix socket
+const UNIX_SOCKET_PREFIX: &str = "unix:";
+
+/// Address that http server listens on
+#[derive(Clone, Debug, PartialEq)]
+pub enum ListenAddr {
+    /// `host:port`, IPv6 host must be enclosed in brackets like `[::1]:9001`
+    Tcp(String),
+    /// Path of unix domain socket
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    pub fn parse(addr: &str) -> Result<ListenAddr> {
+        if let Some(path) = addr.strip_prefix(UNIX_SOCKET_PREFIX) {
+            if path.is_empty() {
+                return Err(MonolithErr::OptionErr);
+            }
+            return Ok(ListenAddr::Unix(PathBuf::from(path)));
+        }
+        if addr.to_socket_addrs().is_err() {
+            error!("Invalid listen address {}", addr);
+            return Err(MonolithErr::OptionErr);
+        }
+        Ok(ListenAddr::Tcp(addr.to_string()))
+    }
+
+    /// Parse `address` if set, otherwise listen on `127.0.0.1:<port>`
+    pub fn from_opts(address: Option<&str>, port: i32) -> Result<ListenAddr> {
+        match address {
+            Some(addr) => ListenAddr::parse(addr),
+            None => Ok(ListenAddr::Tcp(format!("127.0.0.1:{}", port))),
+        }
+    }
+
+    /// Host used by other listeners like graphite, `127.0.0.1` if listening on unix socket
+    pub fn host(&self) -> &str {
+        match self {
+            ListenAddr::Tcp(addr) => addr.rsplit_once(':').map_or("127.0.0.1", |(host, _)| host),
+            ListenAddr::Unix(_) => "127.0.0.1",
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "{}{}", UNIX_SOCKET_PREFIX, path.display()),
         }
     }
 }
 
 pub struct ServerOpts<'a> {
     pub port: i32,
+    /// `host:port` or `unix:<path>` to listen on, overrides `port` if set
+    pub listen_address: Option<&'a str>,
     pub write_path: &'a str,
     pub read_path: &'a str,
     pub worker_num: usize,
+    /// Port of graphite plaintext listener, disabled if not set
+    pub graphite_port: Option<i32>,
+    /// Port of graphite pickle listener, disabled if not set
+    pub graphite_pickle_port: Option<i32>,
+    /// Rules to convert graphite path into labels, see `GraphiteTemplates`
+    pub graphite_templates: Vec<&'a str>,
+    /// Serve https if both certificate and private key in PEM format are set
+    pub tls_cert_file: Option<PathBuf>,
+    pub tls_key_file: Option<PathBuf>,
+    /// File of `username:password` lines for http basic authentication
+    pub basic_auth_file: Option<PathBuf>,
+    /// File of bearer tokens, one token per line
+    pub bearer_token_file: Option<PathBuf>,
+    /// Serve the admin APIs that delete data
+    pub enable_admin_api: bool,
+    /// File of downstream endpoints that accepted remote writes are forwarded to, see `RemoteWriteConfig`
+    pub remote_write_config: Option<PathBuf>,
+    /// Url of the leader to replicate from, the server is a read replica if set, see `Replicator`
+    pub replicate_from: Option<&'a str>,
+    /// Reads of replica are rejected while it lags behind the leader by more than this
+    pub replica_max_lag: Duration,
 }
 
 impl<'a> ServerOpts<'a> {
     pub fn get_config(matchers: &'a ArgMatches) -> Result<ServerOpts<'a>> {
         //unwrap matcher.value_of because all options has default value.
         let worker_num = matchers.value_of(WORKER_NUM).unwrap().parse::<usize>()?;
-        let port = matchers.value_of(PORT).unwrap().parse::<i32>()?;
-        if port > 65535 || port < 1024 {
-            return Err(MonolithErr::OptionErr);
+        let port = Self::parse_port(matchers.value_of(PORT).unwrap())?;
+        let graphite_port = match matchers.value_of(GRAPHITE_PORT) {
+            Some(port) => Some(Self::parse_port(port)?),
+            None => None,
         };
+        let graphite_pickle_port = match matchers.value_of(GRAPHITE_PICKLE_PORT) {
+            Some(port) => Some(Self::parse_port(port)?),
+            None => None,
+        };
+        let listen_address = matchers.value_of(LISTEN_ADDRESS);
+        if let Some(addr) = listen_address {
+            ListenAddr::parse(addr)?;
+        }
+        let replica_max_lag = matchers.value_of(REPLICA_MAX_LAG).unwrap().parse::<u64>()?;
+        let tls_cert_file = matchers.value_of(TLS_CERT_FILE).map(PathBuf::from);
+        let tls_key_file = matchers.value_of(TLS_KEY_FILE).map(PathBuf::from);
+        if tls_cert_file.is_some() != tls_key_file.is_some() {
+            error!(
+                "Both {} and {} are required to enable TLS",
+                TLS_CERT_FILE, TLS_KEY_FILE
+            );
+            return Err(MonolithErr::OptionErr);
+        }
         Ok(ServerOpts {
             port,
+            listen_address,
             worker_num,
             write_path: matchers.value_of(WRITE_PATH).unwrap(),
             read_path: matchers.value_of(READ_PATH).unwrap(),
+            graphite_port,
+            graphite_pickle_port,
+            graphite_templates: matchers
+                .values_of(GRAPHITE_TEMPLATE)
+                .map(|values| values.collect())
+                .unwrap_or_default(),
+            tls_cert_file,
+            tls_key_file,
+            basic_auth_file: matchers.value_of(BASIC_AUTH_FILE).map(PathBuf::from),
+            bearer_token_file: matchers.value_of(BEARER_TOKEN_FILE).map(PathBuf::from),
+            enable_admin_api: matchers.is_present(ENABLE_ADMIN_API),
+            remote_write_config: matchers.value_of(REMOTE_WRITE_CONFIG).map(PathBuf::from),
+            replicate_from: matchers.value_of(REPLICATE_FROM),
+            replica_max_lag: Duration::from_secs(replica_max_lag),
         })
     }
+
+    /// Address to listen on, `127.0.0.1:<port>` if `listen_address` is not set
+    pub fn listen_addr(&self) -> Result<ListenAddr> {
+        ListenAddr::from_opts(self.listen_address, self.port)
+    }
+
+    fn parse_port(port: &str) -> Result<i32> {
+        let port = port.parse::<i32>()?;
+        if port > 65535 || port < 1024 {
+            return Err(MonolithErr::OptionErr);
+        };
+        Ok(port)
+    }
 }
 
 impl<'a> fmt::Display for ServerOpts<'a> {
@@ -78,10 +351,31 @@ impl<'a> fmt::Display for ServerOpts<'a> {
             f,
             "Server options: \n \
         port: {} \n \
+        listen address: {:?} \n \
+        tls enabled: {} \n \
+        auth enabled: {} \n \
+        admin api enabled: {} \n \
+        remote write config: {:?} \n \
+        replicate from: {:?} \n \
         write_path: {} \n \
         read_path: {} \n \
-        num of worker: {} \n ",
-            self.port, self.write_path, self.read_path, self.worker_num
+        num of worker: {} \n \
+        graphite port: {:?} \n \
+        graphite pickle port: {:?} \n \
+        graphite templates: {:?} \n ",
+            self.port,
+            self.listen_address,
+            self.tls_cert_file.is_some(),
+            self.basic_auth_file.is_some() || self.bearer_token_file.is_some(),
+            self.enable_admin_api,
+            self.remote_write_config,
+            self.replicate_from,
+            self.write_path,
+            self.read_path,
+            self.worker_num,
+            self.graphite_port,
+            self.graphite_pickle_port,
+            self.graphite_templates
         )
     }
 }
@@ -90,9 +384,68 @@ impl<'a> Default for ServerOpts<'a> {
     fn default() -> ServerOpts<'a> {
         ServerOpts {
             port: DEFAULT_PORT,
+            listen_address: None,
             write_path: DEFAULT_WRITE_PATH,
             read_path: DEFAULT_READ_PATH,
             worker_num: DEFAULT_WORKER_NUM,
+            graphite_port: None,
+            graphite_pickle_port: None,
+            graphite_templates: Vec::new(),
+            tls_cert_file: None,
+            tls_key_file: None,
+            basic_auth_file: None,
+            bearer_token_file: None,
+            enable_admin_api: false,
+            remote_write_config: None,
+            replicate_from: None,
+            replica_max_lag: Duration::from_secs(u64::from_str(DEFAULT_REPLICA_MAX_LAG).unwrap()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::option::{DbOpts, ListenAddr};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn test_listen_addr() {
+        let addr = ListenAddr::parse("0.0.0.0:9001").unwrap();
+        assert_eq!(addr.host(), "0.0.0.0");
+        let addr = ListenAddr::parse("[::1]:9001").unwrap();
+        assert_eq!(addr.host(), "[::1]");
+        let addr = ListenAddr::parse("unix:/tmp/monolith.sock").unwrap();
+        assert_eq!(addr, ListenAddr::Unix(PathBuf::from("/tmp/monolith.sock")));
+        assert_eq!(addr.to_string(), "unix:/tmp/monolith.sock");
+        assert!(ListenAddr::parse("9001").is_err());
+        assert!(ListenAddr::parse("unix:").is_err());
+    }
+
+    #[test]
+    fn test_tenant_opts() {
+        let mut opts = DbOpts::default();
+        opts.base_dir = PathBuf::from("/data");
+        opts.tenants = serde_yaml::from_str(
+            "a:\n  chunk_size: 60\n  retention_size: 1024\n\
+             b:\n  retention: 120\n  out_of_order_window: 300\n  max_series: 10\n",
+        )
+        .unwrap();
+        opts.limits.max_labels_per_series = Some(20);
+
+        let a = opts.for_tenant("a");
+        assert_eq!(a.base_dir, PathBuf::from("/data/tenants/a"));
+        assert_eq!(a.chunk_size, Duration::from_secs(60));
+        assert_eq!(a.retention, None);
+        assert_eq!(a.retention_size, Some(1024));
+        assert!(a.tenants.is_empty());
+
+        let b = opts.for_tenant("b");
+        assert_eq!(b.chunk_size, opts.chunk_size);
+        assert_eq!(b.retention, Some(Duration::from_secs(120)));
+        assert_eq!(b.out_of_order_window, Duration::from_secs(300));
+        assert_eq!(b.limits.max_series, Some(10));
+        assert_eq!(b.limits.max_labels_per_series, Some(20));
+        assert!(!opts.tenants.contains_key("c"));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::common::label::{LabelMatcher, MatcherType};
+use crate::{MonolithErr, Result};
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Name of the label that holds metric name
+pub const METRIC_NAME_LABEL: &str = "__name__";
+
+///
+/// Parse Prometheus series selector, e.g. `http_requests_total{job="api", method=~"GET|POST"}`, into
+/// label matchers.
+///
+/// Metric name will be converted to an `Equal` matcher on `__name__`.
+pub fn parse_selector(input: &str) -> Result<Vec<LabelMatcher>> {
+    let mut parser = SelectorParser {
+        chars: input.trim().chars().peekable(),
+    };
+    let matchers = parser.parse()?;
+    if matchers.is_empty() {
+        return Err(invalid(input, "selector has no matcher"));
+    }
+    Ok(matchers)
+}
+
+/// Check if `name` is a valid label name, which must match `[a-zA-Z_][a-zA-Z0-9_]*`
+pub fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Check if `name` is a valid metric name, which must match `[a-zA-Z_:][a-zA-Z0-9_:]*`
+pub fn is_valid_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+/// Replace invalid characters with `_` to make a valid label name, `:` is kept if `is_metric` is set.
+pub fn sanitize_name(name: &str, is_metric: bool) -> String {
+    let mut res = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || (is_metric && c == ':') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if res.starts_with(|c: char| c.is_ascii_digit()) {
+        res.insert(0, '_');
+    }
+    res
+}
+
+fn invalid(input: &str, reason: &str) -> MonolithErr {
+    MonolithErr::InvalidMatcherErr(format!("cannot parse selector {}, {}", input, reason))
+}
+
+struct SelectorParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> SelectorParser<'a> {
+    fn parse(&mut self) -> Result<Vec<LabelMatcher>> {
+        let mut matchers = Vec::new();
+        let name = self.identifier(true);
+        if !name.is_empty() {
+            matchers.push(LabelMatcher::new(
+                MatcherType::Equal,
+                METRIC_NAME_LABEL,
+                name.as_str(),
+            )?);
+        }
+        self.skip_whitespace();
+        match self.chars.next() {
+            None => return Ok(matchers),
+            Some('{') => {}
+            Some(c) => return Err(self.error(format!("unexpected character {}", c))),
+        }
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() == Some(&'}') {
+                self.chars.next();
+                break;
+            }
+            matchers.push(self.matcher()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err(self.error("expect , or }".to_string())),
+            }
+        }
+        self.skip_whitespace();
+        if self.chars.next().is_some() {
+            return Err(self.error("unexpected content after }".to_string()));
+        }
+        Ok(matchers)
+    }
+
+    fn matcher(&mut self) -> Result<LabelMatcher> {
+        let name = self.identifier(false);
+        if !is_valid_label_name(name.as_str()) {
+            return Err(self.error(format!("invalid label name {}", name)));
+        }
+        self.skip_whitespace();
+        let matcher_type = match (self.chars.next(), self.chars.peek()) {
+            (Some('='), Some('~')) => {
+                self.chars.next();
+                MatcherType::RegexMatch
+            }
+            (Some('='), _) => MatcherType::Equal,
+            (Some('!'), Some('=')) => {
+                self.chars.next();
+                MatcherType::NotEqual
+            }
+            (Some('!'), Some('~')) => {
+                self.chars.next();
+                MatcherType::RegexNotMatch
+            }
+            _ => return Err(self.error(format!("invalid operator for label {}", name))),
+        };
+        self.skip_whitespace();
+        let value = self.string()?;
+        LabelMatcher::new(matcher_type, name.as_str(), value.as_str())
+    }
+
+    fn identifier(&mut self, allow_colon: bool) -> String {
+        let mut res = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' || (allow_colon && c == ':') {
+                res.push(c);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        res
+    }
+
+    /// Parse quoted string, escape sequences are not supported within back quote.
+    fn string(&mut self) -> Result<String> {
+        let quote = match self.chars.next() {
+            Some(c) if c == '"' || c == '\'' || c == '`' => c,
+            _ => return Err(self.error("expect quoted label value".to_string())),
+        };
+        let mut res = String::new();
+        loop {
+            match self.chars.next() {
+                None => return Err(self.error("unterminated string".to_string())),
+                Some(c) if c == quote => return Ok(res),
+                Some('\\') if quote != '`' => match self.chars.next() {
+                    Some('n') => res.push('\n'),
+                    Some('t') => res.push('\t'),
+                    Some('r') => res.push('\r'),
+                    Some(c) if c == '\\' || c == '"' || c == '\'' => res.push(c),
+                    _ => return Err(self.error("invalid escape sequence".to_string())),
+                },
+                Some(c) => res.push(c),
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.chars.peek() {
+            if c.is_whitespace() {
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn error(&mut self, reason: String) -> MonolithErr {
+        let rest: String = self.chars.clone().collect();
+        MonolithErr::InvalidMatcherErr(format!("cannot parse selector at `{}`, {}", rest, reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::MatcherType;
+    use crate::common::selector::{is_valid_label_name, parse_selector, sanitize_name};
+    use crate::Result;
+
+    #[test]
+    fn test_parse_selector() -> Result<()> {
+        let matchers = parse_selector(r#"http_requests_total{job="api", method=~"GET|POST",}"#)?;
+        assert_eq!(matchers.len(), 3);
+        assert_eq!(matchers[0].name(), "__name__");
+        assert_eq!(matchers[0].value(), "http_requests_total");
+        assert_eq!(matchers[2].matcher_type(), MatcherType::RegexMatch);
+        assert!(matchers[2].matches("POST"));
+
+        let matchers = parse_selector(r#"{env!="prod",job!~'api.*', path=`C:\dir`}"#)?;
+        assert_eq!(matchers.len(), 3);
+        assert_eq!(matchers[0].matcher_type(), MatcherType::NotEqual);
+        assert_eq!(matchers[1].matcher_type(), MatcherType::RegexNotMatch);
+        assert_eq!(matchers[2].value(), r"C:\dir");
+
+        let matchers = parse_selector("up")?;
+        assert_eq!(matchers.len(), 1);
+
+        let matchers = parse_selector(r#"{name="a\"b"}"#)?;
+        assert_eq!(matchers[0].value(), "a\"b");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid_selector() {
+        assert!(parse_selector("").is_err());
+        assert!(parse_selector("{}").is_err());
+        assert!(parse_selector(r#"up{job="api""#).is_err());
+        assert!(parse_selector(r#"up{job=api}"#).is_err());
+        assert!(parse_selector(r#"up{job=="api"}"#).is_err());
+        assert!(parse_selector(r#"up{1job="api"}"#).is_err());
+        assert!(parse_selector(r#"up{job="api"} extra"#).is_err());
+    }
+
+    #[test]
+    fn test_is_valid_label_name() {
+        assert!(is_valid_label_name("__name__"));
+        assert!(is_valid_label_name("job1"));
+        assert!(!is_valid_label_name("1job"));
+        assert!(!is_valid_label_name("job:name"));
+        assert!(!is_valid_label_name(""));
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("cpu.usage-idle", true), "cpu_usage_idle");
+        assert_eq!(sanitize_name("1min:load", true), "_1min:load");
+        assert_eq!(sanitize_name("a:b", false), "a_b");
+    }
+}
@@ -2,7 +2,7 @@ use crate::indexer::Indexer;
 use crate::storage::Storage;
 use std::path::Path;
 
-use crate::common::label::Label;
+use crate::common::label::{Label, LabelMatcher};
 use crate::common::time_point::TimePoint;
 use crate::common::time_series::TimeSeries;
 use crate::label::Labels;
@@ -39,6 +39,15 @@ impl Storage for StubStorage {
     ) -> Result<Vec<TimePoint>> {
         unimplemented!()
     }
+
+    fn delete_time_points(
+        &self,
+        _time_series_id: u64,
+        _start_time: u64,
+        _end_time: u64,
+    ) -> Result<()> {
+        unimplemented!()
+    }
 }
 
 impl Builder<StubStorage> for StubStorage {
@@ -66,6 +75,10 @@ impl Builder<StubStorage> for StubStorage {
     fn read_config(&self, _dir: &Path) -> Result<()> {
         unimplemented!()
     }
+
+    fn tenant_builder(&self, _tenant: &str) -> Box<dyn Builder<StubStorage> + Sync + Send> {
+        Box::new(StubStorage {})
+    }
 }
 
 ///Stub indexer for testing
@@ -82,10 +95,25 @@ impl Indexer for StubIndexer {
         unimplemented!()
     }
 
+    fn get_series_metadata_by_matchers(
+        &self,
+        _matchers: &[LabelMatcher],
+    ) -> Result<Vec<(u64, Labels)>> {
+        unimplemented!()
+    }
+
     fn get_series_id_contains_labels(&self, _labels: Labels) -> Result<Vec<u64>> {
         unimplemented!()
     }
 
+    fn get_label_names(&self) -> Result<Vec<String>> {
+        unimplemented!()
+    }
+
+    fn get_label_values(&self, _name: &str) -> Result<Vec<String>> {
+        unimplemented!()
+    }
+
     fn get_series_id_by_labels(&self, _labels: Labels) -> Result<Option<u64>> {
         unimplemented!()
     }
@@ -93,6 +121,10 @@ impl Indexer for StubIndexer {
     fn create_index(&self, _labels: Labels, _time_series_id: u64) -> Result<()> {
         unimplemented!()
     }
+
+    fn delete_index(&self, _time_series_id: u64) -> Result<()> {
+        unimplemented!()
+    }
 }
 
 impl Builder<StubIndexer> for StubIndexer {
@@ -120,6 +152,10 @@ impl Builder<StubIndexer> for StubIndexer {
     fn read_config(&self, _dir: &Path) -> Result<()> {
         unimplemented!()
     }
+
+    fn tenant_builder(&self, _tenant: &str) -> Box<dyn Builder<StubIndexer> + Sync + Send> {
+        Box::new(StubIndexer {})
+    }
 }
 
 //todo: add internal concurrent test function, use a closure as param for real test logic.
@@ -255,4 +291,25 @@ impl TiKvRawBackend for DummyTiKvBackend {
             Ok(None)
         }
     }
+
+    fn delete(&self, key: Vec<u8>) -> Result<()> {
+        let mut map = self.tree.lock().unwrap();
+        map.remove(&key);
+        Ok(())
+    }
+
+    fn scan_prefix(&self, prefix: Vec<u8>) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let map = self.tree.lock().unwrap();
+        Ok(map
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(prefix.as_slice()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn delete_prefix(&self, prefix: Vec<u8>) -> Result<()> {
+        let mut map = self.tree.lock().unwrap();
+        map.retain(|key, _| !key.starts_with(prefix.as_slice()));
+        Ok(())
+    }
 }
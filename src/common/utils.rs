@@ -127,6 +127,37 @@ pub fn intersect_time_series_id_vec(mut ts: Vec<Vec<TimeSeriesId>>) -> Result<Ve
     }
 }
 
+/// Merge multiple `TimeSeriesId` array into one array without duplicate id.
+///
+/// Note that the element in each `TimeSeriesId` array must be in __ascend order__, the result is also in ascend order.
+pub fn union_time_series_id_vec(ts: Vec<Vec<TimeSeriesId>>) -> Vec<TimeSeriesId> {
+    let mut res: Vec<TimeSeriesId> = ts.into_iter().flatten().collect();
+    res.sort();
+    res.dedup();
+    res
+}
+
+/// Remove all `TimeSeriesId` in `other` from `ts`.
+///
+/// Note that both arrays must be in __ascend order__.
+pub fn subtract_time_series_id_vec(
+    ts: Vec<TimeSeriesId>,
+    other: &[TimeSeriesId],
+) -> Vec<TimeSeriesId> {
+    let mut res = Vec::with_capacity(ts.len());
+    let mut j = 0;
+    for id in ts {
+        while j < other.len() && other[j] < id {
+            j += 1;
+        }
+        if j < other.len() && other[j] == id {
+            continue;
+        }
+        res.push(id);
+    }
+    res
+}
+
 /// Read file from dir, filename must be constant
 ///
 /// Return None if no such file found
@@ -143,9 +174,27 @@ pub fn get_file_from_dir(base_dir: &Path, filename: &'static str) -> Result<Opti
     };
 }
 
+/// Total size in bytes of files under `dir`, recursively
+pub fn dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(entry.path().as_path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::common::utils::{decode_chunk_dir, encode_chunk_dir, get_current_timestamp};
+    use crate::common::utils::{
+        decode_chunk_dir, encode_chunk_dir, get_current_timestamp, subtract_time_series_id_vec,
+        union_time_series_id_vec,
+    };
     use crate::Result;
 
     #[test]
@@ -179,4 +228,16 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_union_time_series_id_vec() {
+        let res = union_time_series_id_vec(vec![vec![1, 3, 5], vec![2, 3, 6], vec![]]);
+        assert_eq!(res, vec![1, 2, 3, 5, 6]);
+    }
+
+    #[test]
+    fn test_subtract_time_series_id_vec() {
+        let res = subtract_time_series_id_vec(vec![1, 2, 3, 5, 8], &[2, 4, 5, 9]);
+        assert_eq!(res, vec![1, 3, 8]);
+    }
 }
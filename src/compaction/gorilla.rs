@@ -101,7 +101,8 @@ impl GorillaCompactor {
             } else {
                 // single one bit
                 self.bstream.write_one();
-                let leading: u8 = xord.leading_zeros() as u8;
+                // leading zeros are stored in 5 bits, so at most 31 of them are recorded
+                let leading: u8 = std::cmp::min(xord.leading_zeros() as u8, 31);
                 let trailing: u8 = xord.trailing_zeros() as u8;
                 if self.len > 1 && leading >= self.leading && trailing >= self.tailing {
                     self.bstream.write_zero(); // write 10
@@ -117,8 +118,10 @@ impl GorillaCompactor {
                                               // Use the next 5 bits to store len of leading zeros
                     self.bstream.write_bits((leading as u64).to_be_bytes(), 5);
 
+                    // 64 significant bits overflows 6 bits as 0, which is never a valid length
                     let sigbits = value_size as u8 - leading - trailing;
-                    self.bstream.write_bits((sigbits as u64).to_be_bytes(), 6);
+                    self.bstream
+                        .write_bits(((sigbits % 64) as u64).to_be_bytes(), 6);
                     self.bstream
                         .write_bits((xord >> trailing as u64).to_be_bytes(), sigbits);
                 }
@@ -208,7 +211,10 @@ impl GorillaDecompactor {
             if l != 6 {
                 return None;
             }
-            let sig_len = u8::from_be_bytes((sigbits_len[0] >> 2).to_be_bytes());
+            let sig_len = match u8::from_be_bytes((sigbits_len[0] >> 2).to_be_bytes()) {
+                0 => value_size as u8,
+                len => len,
+            };
 
             let tailing = value_size as u8 - sig_len - leading;
 
@@ -391,9 +397,10 @@ fn vec_with_len(len: usize) -> Vec<u8> {
     v
 }
 
-// Find if the timestamp's meaningful in the `nbit` range.
+// Find if the timestamp's meaningful in the `nbit` range, which is the range of `nbit` signed integer.
 fn in_bit_range(t: i64, nbits: u8) -> bool {
-    return -((1 << (nbits - 1)) - 1) as i64 <= t && t <= 1 << (nbits - 1) as i64;
+    let bound = 1i64 << (nbits - 1);
+    -bound <= t && t < bound
 }
 
 #[cfg(test)]
@@ -587,4 +594,30 @@ mod tests {
                 .collect::<()>();
         }
     }
+
+    #[test]
+    pub fn test_gorilla_round_trip() {
+        // large deltas of timestamp, and values whose xor has many leading zeros or 64 bits
+        let input = vec![
+            (1_600_000_000_000u64, 0.0),
+            (1_600_000_015_000, f64::from_bits(1)),
+            (1_600_000_015_001, f64::from_bits(u64::max_value() >> 1)),
+            (1_600_010_000_000, -1.0),
+            (1_700_000_000_000, 1.0),
+            (1_700_000_000_000, 1.0),
+        ];
+        let mut compactor = GorillaCompactor::new();
+        for (t, v) in input.iter() {
+            compactor.compact(&TimePoint::new(*t, *v));
+        }
+        let res = GorillaDecompactor::new(compactor.bstream)
+            .into_iter()
+            .map(|tp| (tp.timestamp, tp.value.to_bits()))
+            .collect::<Vec<(u64, u64)>>();
+        let expected = input
+            .iter()
+            .map(|(t, v)| (*t, v.to_bits()))
+            .collect::<Vec<(u64, u64)>>();
+        assert_eq!(res, expected);
+    }
 }
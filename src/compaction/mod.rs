@@ -11,6 +11,7 @@ use crate::{MonolithErr, Result, Value};
 
 mod gorilla;
 mod simple;
+mod xor;
 
 use crate::compaction::gorilla::GorillaDecompactor;
 use crate::compaction::CompactionErr::CompactionTypeDontMatch;
@@ -20,6 +21,7 @@ pub use gorilla::GorillaCompactor;
 use std::convert::TryInto;
 use std::fmt::{Debug, Error};
 use std::iter::FromIterator;
+pub use xor::{encode_uvarint, XorChunk};
 
 macro_rules! compaction_error {
     ($err:expr) => {
@@ -0,0 +1,226 @@
+use crate::common::time_point::TimePoint;
+use crate::compaction::Bstream;
+use crate::Timestamp;
+
+/// Bytes used by the header which stores the num of samples
+const HEADER_SIZE: usize = 2;
+
+///
+/// XOR chunk that compatible with Prometheus' chunk encoding, used to return data for streamed remote read.
+///
+/// It's similar with `GorillaCompactor`, but follows the exact bit layout of Prometheus so that clients
+/// could decode it directly.
+/// Reference https://github.com/prometheus/prometheus/blob/master/tsdb/chunkenc/xor.go
+///
+/// | num of samples(u16) | first timestamp(varint) | first value(64 bits) | second timestamp delta(uvarint) | ...
+pub struct XorChunk {
+    bstream: Bstream,
+    num: u16,
+    min_time: Timestamp,
+    // last timestamp
+    t: i64,
+    // last value
+    v: u64,
+    // last delta of timestamp
+    t_delta: i64,
+    // leading zero of last xor-ed value, 0xff if not set
+    leading: u8,
+    // trailing zero of last xor-ed value
+    trailing: u8,
+}
+
+impl XorChunk {
+    pub fn new() -> XorChunk {
+        XorChunk {
+            bstream: Bstream::new(),
+            num: 0,
+            min_time: 0,
+            t: 0,
+            v: 0,
+            t_delta: 0,
+            leading: 0xff,
+            trailing: 0,
+        }
+    }
+
+    pub fn append(&mut self, tp: &TimePoint) {
+        let t = tp.timestamp as i64;
+        let v = tp.value.to_bits();
+        if self.num == 0 {
+            self.write_varint(t);
+            self.bstream.write_bits(v.to_be_bytes(), 64);
+            self.min_time = tp.timestamp;
+        } else if self.num == 1 {
+            let t_delta = t - self.t;
+            self.write_uvarint(t_delta as u64);
+            self.write_value_delta(v);
+            self.t_delta = t_delta;
+        } else {
+            let t_delta = t - self.t;
+            let dod = t_delta - self.t_delta;
+            if dod == 0 {
+                self.bstream.write_zero();
+            } else if in_bit_range(dod, 14) {
+                self.bstream.write_bits(0b10u64.to_be_bytes(), 2);
+                self.bstream.write_bits(dod.to_be_bytes(), 14);
+            } else if in_bit_range(dod, 17) {
+                self.bstream.write_bits(0b110u64.to_be_bytes(), 3);
+                self.bstream.write_bits(dod.to_be_bytes(), 17);
+            } else if in_bit_range(dod, 20) {
+                self.bstream.write_bits(0b1110u64.to_be_bytes(), 4);
+                self.bstream.write_bits(dod.to_be_bytes(), 20);
+            } else {
+                self.bstream.write_bits(0b1111u64.to_be_bytes(), 4);
+                self.bstream.write_bits(dod.to_be_bytes(), 64);
+            }
+            self.write_value_delta(v);
+            self.t_delta = t_delta;
+        }
+        self.t = t;
+        self.v = v;
+        self.num += 1;
+    }
+
+    /// Num of samples in chunk
+    pub fn len(&self) -> usize {
+        self.num as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num == 0
+    }
+
+    /// timestamp of the first and last samples
+    pub fn time_range(&self) -> (Timestamp, Timestamp) {
+        (self.min_time, self.t as Timestamp)
+    }
+
+    /// Encoded chunk, including the header
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(HEADER_SIZE + self.bstream.data.len());
+        res.extend_from_slice(&self.num.to_be_bytes()[..]);
+        res.extend_from_slice(self.bstream.data.as_slice());
+        res
+    }
+
+    fn write_value_delta(&mut self, v: u64) {
+        let delta = v ^ self.v;
+        if delta == 0 {
+            self.bstream.write_zero();
+            return;
+        }
+        self.bstream.write_one();
+
+        let mut leading = delta.leading_zeros() as u8;
+        let trailing = delta.trailing_zeros() as u8;
+        // only 5 bits are available to store the leading zero
+        if leading >= 32 {
+            leading = 31;
+        }
+
+        if self.leading != 0xff && leading >= self.leading && trailing >= self.trailing {
+            // reuse the previous window of meaningful bits
+            self.bstream.write_zero();
+            self.bstream.write_bits(
+                (delta >> self.trailing as u64).to_be_bytes(),
+                64 - self.leading - self.trailing,
+            );
+        } else {
+            self.leading = leading;
+            self.trailing = trailing;
+            self.bstream.write_one();
+            self.bstream.write_bits((leading as u64).to_be_bytes(), 5);
+            // 64 significant bits overflow into 0, which is what Prometheus expects
+            let sigbits = 64 - leading - trailing;
+            self.bstream.write_bits((sigbits as u64).to_be_bytes(), 6);
+            self.bstream
+                .write_bits((delta >> trailing as u64).to_be_bytes(), sigbits);
+        }
+    }
+
+    /// Write signed integer with zig-zag encoding, same as golang's `binary.PutVarint`
+    fn write_varint(&mut self, x: i64) {
+        let mut ux = (x as u64) << 1;
+        if x < 0 {
+            ux = !ux;
+        }
+        self.write_uvarint(ux);
+    }
+
+    /// Same as golang's `binary.PutUvarint`
+    fn write_uvarint(&mut self, mut x: u64) {
+        while x >= 0x80 {
+            self.bstream.append_bytes(&[(x as u8) | 0x80], 0);
+            x >>= 7;
+        }
+        self.bstream.append_bytes(&[x as u8], 0);
+    }
+}
+
+/// Encode data as uvarint, same as golang's `binary.PutUvarint`
+pub fn encode_uvarint(mut x: u64) -> Vec<u8> {
+    let mut res = Vec::new();
+    while x >= 0x80 {
+        res.push((x as u8) | 0x80);
+        x >>= 7;
+    }
+    res.push(x as u8);
+    res
+}
+
+// Find if the delta of delta can be stored in `nbits` bits.
+fn in_bit_range(x: i64, nbits: u8) -> bool {
+    -((1 << (nbits - 1)) - 1) <= x && x <= 1 << (nbits - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::time_point::TimePoint;
+    use crate::compaction::xor::{encode_uvarint, XorChunk};
+
+    #[test]
+    fn test_encode_uvarint() {
+        assert_eq!(encode_uvarint(1), vec![0x01]);
+        assert_eq!(encode_uvarint(300), vec![0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_xor_chunk() {
+        let mut chunk = XorChunk::new();
+        for t in vec![1000, 2000, 3000] {
+            chunk.append(&TimePoint::new(t, 1.0));
+        }
+        assert_eq!(chunk.len(), 3);
+        assert_eq!(chunk.time_range(), (1000, 3000));
+        assert_eq!(
+            chunk.bytes(),
+            vec![
+                0x00, 0x03, // num of samples
+                0xd0, 0x0f, // first timestamp 1000 as varint
+                0x3f, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // first value 1.0
+                0xe8, 0x07, // delta 1000 as uvarint
+                0x00, // 0 for second value, 0 for third timestamp, 0 for third value
+            ]
+        );
+    }
+
+    #[test]
+    fn test_xor_chunk_value_change() {
+        let mut chunk = XorChunk::new();
+        chunk.append(&TimePoint::new(1, 1.0));
+        chunk.append(&TimePoint::new(2, 1.5));
+        chunk.append(&TimePoint::new(4, 1.5));
+        // 1.0 ^ 1.5 = 0x0008000000000000, 12 leading zero and 51 trailing zero.
+        // second timestamp: 0x01
+        // second value: 11 01100 000001 1
+        // third timestamp: dod is 1, 10 00000000000001
+        // third value: 0
+        assert_eq!(
+            chunk.bytes(),
+            vec![
+                0x00, 0x03, 0x02, 0x3f, 0xf0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0b11011000,
+                0b00001110, 0b00000000, 0b00000100,
+            ]
+        );
+    }
+}
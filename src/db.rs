@@ -1,34 +1,87 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::channel;
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use std::{fs, thread};
 
-use crate::chunk::{Chunk, ChunkOpts};
-use crate::common::label::Labels;
+use crate::chunk::{Chunk, ChunkOpts, Tombstones};
+use crate::common::hint::QueryHint;
+use crate::common::label::{LabelMatcher, Labels, MatcherType};
 use crate::common::metadata::DbMetadata;
 use crate::common::time_point::TimePoint;
 use crate::common::time_series::LabelPointPairs;
-use crate::common::utils::{decode_chunk_dir, encode_chunk_dir, get_current_timestamp};
-use crate::indexer::Indexer;
+use crate::common::utils::{decode_chunk_dir, dir_size, encode_chunk_dir, get_current_timestamp};
+use crate::indexer::{Indexer, MemIndexer};
+use crate::limits::{LimitReason, RateLimiter, RejectedCounter};
+use crate::metrics::{Counter, CHUNK_COMPACTION_DURATION, CHUNK_SWAP_DURATION, EXPIRED_CHUNKS};
 use crate::option::DbOpts;
-use crate::storage::Storage;
-use crate::{Builder, MonolithErr, Result, Timestamp, DB_METADATA_FILENAME};
+use crate::storage::{MemStorage, Storage};
+use crate::wal::{check_series_size, WalReader, WalWriter};
+use crate::{
+    Builder, MonolithErr, Result, Timestamp, DB_METADATA_FILENAME, REPLICATED_FILENAME,
+    TOMBSTONES_FILENAME, WAL_DIR,
+};
 use std::fs::File;
 use std::io::BufWriter;
 
+/// How often chunks are checked against retention
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The current chunk, whose samples are kept in memory until it's swapped out
+type HeadChunk = Chunk<MemStorage, MemIndexer>;
+
+/// In-memory chunks and secondary chunks within a time range, see `chunks_within_range`
+type ChunksInRange<S, I> = (Vec<Arc<HeadChunk>>, Vec<Arc<Chunk<S, I>>>);
+
 /// MonolithDb is thread-safe
 pub struct MonolithDb<S: Storage, I: Indexer>
 where
     S: Storage + Send + Sync,
     I: Indexer + Send + Sync,
 {
-    current_chuck: RwLock<Arc<Chunk<S, I>>>,
+    current_chuck: RwLock<Arc<HeadChunk>>,
+    /// Chunk in backend of the same range as the current chunk, which the current chunk is flushed into.
+    /// Samples are also written through into it if write ahead log is disabled.
+    current_target: RwLock<Chunk<S, I>>,
+    /// Current chunk swapped out until it's flushed into backend, see `flush_swapped`
+    swapped: Mutex<Option<Swapped<S, I>>>,
+    /// Notified when the chunk swapped out is flushed or its flush fails
+    flushed: Condvar,
+    /// Swaps run one at a time
+    swap_lock: Mutex<()>,
     secondary_chunks: RwLock<Vec<Arc<Chunk<S, I>>>>,
     options: DbOpts,
     storage_builder: Box<dyn Builder<S> + Sync + Send>,
     indexer_builder: Box<dyn Builder<I> + Sync + Send>,
+    rate_limiter: Option<RateLimiter>,
+    rejected: RejectedCounter,
+    ingested: Counter,
+    wal: Option<WalWriter>,
+    load_state: Mutex<LoadState>,
+    loaded: Condvar,
+    closed: AtomicBool,
+    swap_stop: Mutex<Option<Sender<()>>>,
+    swap_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+///
+/// Current chunk swapped out, which is still queried until it's flushed into its chunk in backend.
+///
+/// The chunk in backend is taken out while it's being flushed, and put back if the flush fails, so that
+/// it's flushed again by the next swap or close.
+struct Swapped<S: Storage, I: Indexer> {
+    head: Arc<HeadChunk>,
+    target: Option<Chunk<S, I>>,
+}
+
+/// State of reading existing chunks when db is opened
+enum LoadState {
+    Loading,
+    Loaded,
+    Failed(String),
 }
 
 impl<S, I> MonolithDb<S, I>
@@ -36,6 +89,11 @@ where
     S: Storage + Send + Sync + 'static,
     I: Indexer + Send + Sync + 'static,
 {
+    /// Create a db in base dir of `ops`.
+    ///
+    /// Existing chunks in base dir are read in background, use `is_ready` or `wait_ready` to
+    /// know when they are available for queries. If write ahead log is enabled, samples logged
+    /// before are replayed into the chunks covering them once the existing chunks are read.
     pub fn new(
         ops: DbOpts,
         storage_builder: Box<dyn Builder<S> + Sync + Send>,
@@ -46,60 +104,270 @@ where
             storage_type: I::get_type_name().to_string(),
         };
         Self::read_or_create_metadata(&ops.base_dir, &db_metadata)?;
-        //read existing data
-        let existing_chunk =
-            Self::read_existing_chunk(&ops.base_dir, &storage_builder, &indexer_builder)?;
+        // list existing chunks before the new one is created, they are read in background
+        let existing_dirs = Self::existing_chunk_dirs(&ops.base_dir)?;
+        // the same for segments of write ahead log, new samples are logged in a new segment
+        let (wal, wal_reader) = match &ops.wal_flush_policy {
+            Some(flush_policy) => {
+                let wal_dir = ops.base_dir.join(WAL_DIR);
+                let reader = WalReader::open(&wal_dir)?;
+                let writer = WalWriter::open(&wal_dir, flush_policy.clone())?;
+                (Some(writer), Some(reader))
+            }
+            None => (None, None),
+        };
 
         // write custom config to db config
         storage_builder.write_config(&ops.base_dir)?;
         storage_builder.write_config(&ops.base_dir)?;
 
-        let chunk_size = ops.chunk_size.as_millis() as Timestamp;
         let current_time = get_current_timestamp();
-
-        let mut chunk_opt = ChunkOpts::default();
-        chunk_opt.start_time = Some(current_time);
-        chunk_opt.end_time = Some(current_time + ops.chunk_size.as_millis() as Timestamp);
-
-        let chunk_dir = ops.base_dir.as_path().join(encode_chunk_dir(
-            chunk_opt.start_time.unwrap(),
-            chunk_opt.end_time.unwrap(),
-        ));
-
-        //write metadata into chunk config
-        storage_builder.write_to_chunk(&chunk_dir);
-        indexer_builder.write_to_chunk(&chunk_dir);
-        if let Err(_) = chunk_opt.write_config_to_dir(chunk_dir.as_path()) {
-            error!("Cannot create metadata file for chunk");
-            return Err(MonolithErr::InternalErr(
-                "Cannot create metadata file for chunk".to_string(),
-            ));
-        }
-
-        let chunk_dir_str = chunk_dir.as_path().display().to_string();
-        let (storage, indexer) = (
-            storage_builder.build(chunk_dir_str.clone(), Some(&chunk_opt), Some(&ops))?,
-            indexer_builder.build(chunk_dir_str.clone(), Some(&chunk_opt), Some(&ops))?,
-        );
-        let chunk = Chunk::<S, I>::new(storage, indexer, &chunk_opt);
-        let (swap_tx, swap_rx) = channel::<Timestamp>();
+        let (head, target) = Self::create_head(
+            &ops,
+            &storage_builder,
+            &indexer_builder,
+            current_time,
+            current_time + ops.chunk_size.as_millis() as Timestamp,
+        )?;
         let db = Arc::new(MonolithDb {
-            current_chuck: RwLock::new(Arc::new(chunk)),
-            secondary_chunks: RwLock::new(existing_chunk),
+            current_chuck: RwLock::new(Arc::new(head)),
+            current_target: RwLock::new(target),
+            swapped: Mutex::new(None),
+            flushed: Condvar::new(),
+            swap_lock: Mutex::new(()),
+            secondary_chunks: RwLock::new(Vec::new()),
+            rate_limiter: RateLimiter::from_limits(&ops.limits),
+            rejected: RejectedCounter::default(),
+            ingested: Counter::default(),
+            wal,
+            load_state: Mutex::new(LoadState::Loading),
+            loaded: Condvar::new(),
+            closed: AtomicBool::new(false),
+            swap_stop: Mutex::new(None),
+            swap_thread: Mutex::new(None),
             options: ops,
             storage_builder,
             indexer_builder,
         });
-        thread::spawn(move || loop {
-            thread::sleep(Duration::from_millis(chunk_size));
-            swap_tx.send(get_current_timestamp());
-        });
         let _db = db.clone();
-        thread::spawn(move || loop {
-            let start_time = swap_rx.recv().unwrap() + 1;
-            _db.clone().swap(start_time);
+        thread::spawn(move || _db.load_existing_chunks(existing_dirs, wal_reader));
+        Self::start_swap_thread(&db);
+        Ok(db)
+    }
+
+    /// Swap chunks every `chunk_size`, and remove expired chunks every `RETENTION_INTERVAL`, until
+    /// the db is closed or dropped
+    fn start_swap_thread(db: &Arc<Self>) {
+        let (stop_tx, stop_rx) = channel::<()>();
+        let chunk_size = db.options.chunk_size;
+        let weak = Arc::downgrade(db);
+        let handle = thread::spawn(move || {
+            let mut next_swap = Instant::now() + chunk_size;
+            loop {
+                let timeout = next_swap
+                    .saturating_duration_since(Instant::now())
+                    .min(RETENTION_INTERVAL);
+                match stop_rx.recv_timeout(timeout) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    // stopped by close, or the db is dropped
+                    _ => return,
+                }
+                let db = match weak.upgrade() {
+                    Some(db) => db,
+                    None => return,
+                };
+                if Instant::now() >= next_swap {
+                    next_swap += chunk_size;
+                    // chunks of replica are swapped like the ones of its leader, see `follow_chunk`
+                    if !db.options.replica {
+                        if let Err(err) = db.swap(get_current_timestamp() + 1) {
+                            error!("Cannot swap chunk, {}", err);
+                        }
+                    }
+                }
+                if let Err(err) = db.remove_expired_chunks() {
+                    error!("Cannot remove expired chunks, {}", err);
+                }
+            }
         });
-        Ok(db.clone())
+        *db.swap_stop.lock().unwrap() = Some(stop_tx);
+        *db.swap_thread.lock().unwrap() = Some(handle);
+    }
+
+    /// Read existing chunks into secondary chunks, before the ones swapped since the db is opened,
+    /// then replay the write ahead log
+    fn load_existing_chunks(&self, dirs: Vec<PathBuf>, wal: Option<WalReader>) {
+        let state =
+            match Self::read_existing_chunk(dirs, &self.storage_builder, &self.indexer_builder) {
+                Ok(existing) => {
+                    info!(
+                        "Read {} existing chunks in {}",
+                        existing.len(),
+                        self.options.base_dir.display()
+                    );
+                    self.secondary_chunks
+                        .write()
+                        .unwrap()
+                        .splice(0..0, existing);
+                    if let Err(err) = self.remove_expired_chunks() {
+                        error!("Cannot remove expired chunks, {}", err);
+                    }
+                    match wal.map_or(Ok(()), |reader| self.replay_wal(&reader)) {
+                        Ok(()) => LoadState::Loaded,
+                        Err(err) => {
+                            error!(
+                                "Cannot replay write ahead log in {}, {}",
+                                self.options.base_dir.display(),
+                                err
+                            );
+                            LoadState::Failed(err.to_string())
+                        }
+                    }
+                }
+                Err(err) => {
+                    error!(
+                        "Cannot read existing chunks in {}, {}",
+                        self.options.base_dir.display(),
+                        err
+                    );
+                    LoadState::Failed(err.to_string())
+                }
+            };
+        *self.load_state.lock().unwrap() = state;
+        self.loaded.notify_all();
+        // chunks closed before they were compacted, like by a crash
+        let chunks = self.secondary_chunks.read().unwrap().clone();
+        for chunk in chunks.iter() {
+            Self::compact_chunk(chunk);
+        }
+    }
+
+    /// Write samples logged before the db is opened into the chunks covering them, samples of
+    /// removed chunks are dropped
+    fn replay_wal(&self, reader: &WalReader) -> Result<()> {
+        let replay_start = Instant::now();
+        let mut dropped = 0;
+        let mut result = Ok(());
+        let samples = reader.replay(|labels, points| {
+            if result.is_err() {
+                return;
+            }
+            match self.insert_covered(labels, points) {
+                Ok(n) => dropped += n,
+                Err(err) => result = Err(err),
+            }
+        })?;
+        result?;
+        info!(
+            "Replayed {} samples of {} write ahead log segments in {:?}, {} of them are dropped",
+            samples,
+            reader.segments().len(),
+            replay_start.elapsed(),
+            dropped
+        );
+        Ok(())
+    }
+
+    /// Insert `points` into the current chunk or the latest secondary chunk covering them, return the
+    /// number of points dropped because no chunk covers them or the series is rejected by limits
+    fn insert_covered(&self, labels: &Labels, points: Vec<TimePoint>) -> Result<usize> {
+        let current = self.current_chuck.read().unwrap();
+        let mut dropped = 0;
+        for tp in points {
+            let inserted = if current.is_in_range(&tp.timestamp) {
+                let target = self.current_target.read().unwrap();
+                Some(self.insert_head(&current, &target, labels, tp))
+            } else {
+                self.insert_late(labels, tp)
+            };
+            match inserted {
+                Some(Ok(())) => {}
+                None
+                | Some(Err(MonolithErr::LimitErr(_, _)))
+                | Some(Err(MonolithErr::OutOfRangeErr(_, _))) => dropped += 1,
+                Some(Err(err)) => return Err(err),
+            }
+        }
+        Ok(dropped)
+    }
+
+    /// Whether existing chunks have been read
+    pub fn is_ready(&self) -> bool {
+        matches!(*self.load_state.lock().unwrap(), LoadState::Loaded)
+    }
+
+    /// Block until existing chunks are read, return the error if they cannot be read
+    pub fn wait_ready(&self) -> Result<()> {
+        let mut state = self.load_state.lock().unwrap();
+        while let LoadState::Loading = *state {
+            state = self.loaded.wait(state).unwrap();
+        }
+        match &*state {
+            LoadState::Failed(msg) => Err(MonolithErr::InternalErr(msg.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stop swapping chunks, close the current chunk and flush all chunks to disk, the current chunk is
+    /// flushed into its chunk in backend. Segments of write ahead log are removed once all chunks are
+    /// flushed.
+    ///
+    /// Writes are rejected with `ClosedErr` after closed, while queries still work.
+    pub fn close(&self) -> Result<()> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        // dropping the sender wakes up the swap thread
+        self.swap_stop.lock().unwrap().take();
+        if let Some(handle) = self.swap_thread.lock().unwrap().take() {
+            if handle.join().is_err() {
+                error!(
+                    "Swap thread of db in {} panicked",
+                    self.options.base_dir.display()
+                );
+            }
+        }
+        {
+            // the chunk swapped out is left if its flush failed
+            let _swap = self.swap_lock.lock().unwrap();
+            self.flush_swapped()?;
+            let current = self.current_chuck.read().unwrap();
+            current.close();
+            self.flush_head(&current, &self.current_target.read().unwrap())?;
+        }
+        for chunk in self.secondary_chunks.read().unwrap().iter() {
+            chunk.flush()?;
+        }
+        if let Some(wal) = &self.wal {
+            // all samples are persisted in backend, so they are not replayed again on start
+            let seq = wal.rotate()?;
+            wal.truncate(seq)?;
+            wal.close()?;
+        }
+        info!("Db in {} is closed", self.options.base_dir.display());
+        Ok(())
+    }
+
+    /// Create or open the db of `tenant`.
+    ///
+    /// It's stored under `<base_dir>/tenants/<tenant>` with the tenant's options, and the storage and indexer
+    /// are built by the tenant builders so that it cannot see data of other tenants.
+    pub fn open_tenant(&self, tenant: &str) -> Result<Arc<Self>> {
+        let opts = self.options.for_tenant(tenant);
+        fs::create_dir_all(&opts.base_dir)?;
+        info!(
+            "Open db of tenant {} in {}",
+            tenant,
+            opts.base_dir.display()
+        );
+        let db = MonolithDb::new(
+            opts,
+            self.storage_builder.tenant_builder(tenant),
+            self.indexer_builder.tenant_builder(tenant),
+        )?;
+        // tenants are opened on their first request, which should see the existing data
+        db.wait_ready()?;
+        Ok(db)
     }
 
     /// Check if there is a metadata file in base_dir. If it does, then read file and check if the existing Indexer and Storage
@@ -123,26 +391,34 @@ where
         Ok(())
     }
 
-    ///Read the existing chunk in dir. If no chunk found, return an empty vec.
+    ///List the existing chunk dirs in dir. If no chunk found, return an empty vec.
+    fn existing_chunk_dirs(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut res = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_chunk = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => decode_chunk_dir(name.to_string()).is_ok(),
+                None => false,
+            };
+            if is_chunk {
+                res.push(path);
+            }
+        }
+        Ok(res)
+    }
+
+    ///Read the existing chunks in dirs.
     fn read_existing_chunk(
-        dir: &Path,
+        dirs: Vec<PathBuf>,
         storage_builder: &Box<dyn Builder<S> + Sync + Send>,
         indexer_builder: &Box<dyn Builder<I> + Sync + Send>,
     ) -> Result<Vec<Arc<Chunk<S, I>>>> {
         let mut res = Vec::new();
-        for entry in fs::read_dir(dir)? {
-            let path_str = entry?.path().into_os_string().into_string().unwrap();
-            let _slash = path_str.rfind(std::path::MAIN_SEPARATOR).unwrap() + 1;
-            let dir_name = path_str.clone();
-            let dir_name = dir_name
-                .chars()
-                .skip(_slash)
-                .take(dir_name.len() - _slash)
-                .collect();
+        for path in dirs {
+            let dir_name = path.file_name().unwrap().to_string_lossy().to_string();
             match decode_chunk_dir(dir_name) {
                 Ok((start_time, end_time)) => {
                     // Read Chunk Options from metadata.json
-                    let path = PathBuf::from(path_str.clone());
                     let mut chunk_opts = ChunkOpts::read_config_from_dir(&path)?;
                     let current_time = get_current_timestamp();
                     chunk_opts.start_time = Some(start_time);
@@ -159,7 +435,9 @@ where
                         .read_from_chunk(&path.join("indexer"), Some(&chunk_opts))?;
 
                     if storage.is_some() && indexer.is_some() {
-                        let chunk = Chunk::new(storage.unwrap(), indexer.unwrap(), &chunk_opts);
+                        let chunk = Chunk::new(storage.unwrap(), indexer.unwrap(), &chunk_opts)
+                            .with_tombstone_file(path.join(TOMBSTONES_FILENAME))?
+                            .with_existing_series()?;
                         chunk.close();
                         (&mut res).push(Arc::new(chunk));
                     }
@@ -173,81 +451,531 @@ where
     }
 
     /// Write time points with same labels into chunk.
+    ///
+    /// Points are rejected with `LimitErr` if the series or the ingestion rate exceeds the limits in options.
+    /// Points older than the current chunk are written into the chunk that covers them if they are within
+    /// `out_of_order_window`, the rest of points are written before `OutOfBounds` is returned for them.
     pub fn write_time_points(&self, labels: Labels, timepoints: Vec<TimePoint>) -> Result<()> {
+        self.write_points(labels, timepoints, None)
+    }
+
+    /// Write time points like `write_time_points`, and push the points written into `accepted`, which
+    /// are only part of them if the others are out of bounds or the write fails halfway.
+    pub fn write_time_points_accepted(
+        &self,
+        labels: Labels,
+        timepoints: Vec<TimePoint>,
+        accepted: &mut Vec<TimePoint>,
+    ) -> Result<()> {
+        self.write_points(labels, timepoints, Some(accepted))
+    }
+
+    fn write_points(
+        &self,
+        labels: Labels,
+        timepoints: Vec<TimePoint>,
+        mut accepted: Option<&mut Vec<TimePoint>>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let samples = timepoints.len();
+        if let Err(err) = self.check_limits(&labels, samples) {
+            self.count_rejected(&err, samples as u64);
+            return Err(err);
+        }
         let _c = &self.current_chuck.read().unwrap();
-        let (start_time, end_time) = _c.start_end_time();
-        let res = timepoints
-            .into_iter()
-            .filter(|tp| {
-                tp.timestamp >= start_time && tp.timestamp <= end_time && tp.timestamp != 0
-            })
-            .map(|tp| _c.insert(labels.clone(), tp))
-            .filter(|res| res.is_err())
-            .collect::<Vec<_>>();
-        if !res.is_empty() {
-            error!("{} time point failed to insert", res.len());
-            return Err(MonolithErr::InternalErr(
-                "multiple time point cannot insert".to_string(),
-            ));
+        let window_start = self.window_start(_c);
+        self.log_ahead(&labels, timepoints.as_slice(), window_start, _c)?;
+        let (mut inserted, mut out_of_bounds) = (0, 0);
+        for tp in timepoints.into_iter().filter(|tp| tp.timestamp != 0) {
+            let (timestamp, value) = (tp.timestamp, tp.value);
+            match self.insert_point(_c, &labels, tp, window_start) {
+                Ok(()) => {
+                    inserted += 1;
+                    if let Some(accepted) = accepted.as_mut() {
+                        accepted.push(TimePoint::new(timestamp, value));
+                    }
+                }
+                Err(MonolithErr::LimitErr(LimitReason::OutOfBounds, _))
+                | Err(MonolithErr::OutOfRangeErr(_, _)) => out_of_bounds += 1,
+                Err(err) => {
+                    self.ingested.inc_by(inserted);
+                    if let MonolithErr::LimitErr(_, _) = err {
+                        // the series is rejected at its first point, and so are the rest
+                        self.count_rejected(&err, samples as u64);
+                        return Err(err);
+                    }
+                    error!("Time points of {} failed to insert, {}", labels, err);
+                    return Err(err);
+                }
+            }
+        }
+        self.ingested.inc_by(inserted);
+        if out_of_bounds > 0 {
+            self.rejected.add(LimitReason::OutOfBounds, out_of_bounds);
+            return Err(Self::out_of_bounds(window_start, _c.start_end_time().1));
         }
         Ok(())
     }
 
+    /// Earliest timestamp accepted, which is `out_of_order_window` before the start of `current`
+    fn window_start(&self, current: &HeadChunk) -> Timestamp {
+        let window = self.options.out_of_order_window.as_millis() as Timestamp;
+        current.start_end_time().0.saturating_sub(window)
+    }
+
+    /// Insert `tp` into the current chunk, or the latest chunk that covers it if it's late
+    fn insert_point(
+        &self,
+        current: &HeadChunk,
+        labels: &Labels,
+        tp: TimePoint,
+        window_start: Timestamp,
+    ) -> Result<()> {
+        let (start_time, end_time) = current.start_end_time();
+        if current.is_in_range(&tp.timestamp) {
+            let target = self.current_target.read().unwrap();
+            return self.insert_head(current, &target, labels, tp);
+        }
+        if tp.timestamp >= window_start && tp.timestamp < start_time {
+            if let Some(res) = self.insert_late(labels, tp) {
+                return res;
+            }
+        }
+        Err(Self::out_of_bounds(window_start, end_time))
+    }
+
+    ///
+    /// Insert `tp` older than the current chunk into the latest chunk covering it, `None` if no chunk
+    /// covers it.
+    ///
+    /// If it's covered by the chunk swapped out, it waits for the flush of that chunk and goes into the
+    /// chunk in backend, so that it's not missed by the flush. If the flush failed, it goes into the chunk
+    /// swapped out, which is flushed again later.
+    fn insert_late(&self, labels: &Labels, tp: TimePoint) -> Option<Result<()>> {
+        {
+            let swapped = self.wait_flushed();
+            if let Some(Swapped {
+                head,
+                target: Some(target),
+            }) = swapped.as_ref()
+            {
+                if head.is_in_range(&tp.timestamp) {
+                    return Some(self.insert_head(head, target, labels, tp));
+                }
+            }
+        }
+        self.secondary_chunks
+            .read()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|c| c.is_in_range(&tp.timestamp))
+            .cloned()
+            .map(|chunk| chunk.insert(labels.clone(), tp))
+    }
+
+    /// Lock the chunk swapped out once it's not being flushed
+    fn wait_flushed(&self) -> MutexGuard<'_, Option<Swapped<S, I>>> {
+        let mut swapped = self.swapped.lock().unwrap();
+        while matches!(swapped.as_ref(), Some(s) if s.target.is_none()) {
+            swapped = self.flushed.wait(swapped).unwrap();
+        }
+        swapped
+    }
+
+    /// Insert `tp` into the in-memory `head`, and write it through into `target`, its chunk in backend,
+    /// if write ahead log is disabled, so that it's not lost if the process crashes.
+    fn insert_head(
+        &self,
+        head: &HeadChunk,
+        target: &Chunk<S, I>,
+        labels: &Labels,
+        tp: TimePoint,
+    ) -> Result<()> {
+        match &self.wal {
+            Some(_) => head.insert(labels.clone(), tp),
+            None => head.insert_through(target, labels.clone(), tp),
+        }
+    }
+
+    /// Log points that may be accepted before writing them, if write ahead log is enabled. Series too
+    /// large to be logged are rejected.
+    fn log_ahead(
+        &self,
+        labels: &Labels,
+        timepoints: &[TimePoint],
+        window_start: Timestamp,
+        current: &HeadChunk,
+    ) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            check_series_size(labels)?;
+            let end_time = current.start_end_time().1;
+            let points = timepoints
+                .iter()
+                .filter(|tp| tp.timestamp != 0)
+                .filter(|tp| tp.timestamp >= window_start && tp.timestamp < end_time)
+                .cloned()
+                .collect::<Vec<TimePoint>>();
+            wal.write(labels, points.as_slice())?;
+        }
+        Ok(())
+    }
+
+    fn out_of_bounds(window_start: Timestamp, end_time: Timestamp) -> MonolithErr {
+        MonolithErr::LimitErr(
+            LimitReason::OutOfBounds,
+            format!(
+                "samples out of the accepted range [{}, {}) are dropped",
+                window_start, end_time
+            ),
+        )
+    }
+
+    /// Writes are only accepted by an open db that is not a replica
+    fn check_writable(&self) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(MonolithErr::ClosedErr);
+        }
+        self.check_replica()
+    }
+
+    /// Data of replica is only changed by replication
+    fn check_replica(&self) -> Result<()> {
+        if self.options.replica {
+            return Err(MonolithErr::ReadOnlyErr);
+        }
+        Ok(())
+    }
+
+    fn check_limits(&self, labels: &Labels, samples: usize) -> Result<()> {
+        self.options.limits.validate_labels(labels)?;
+        match &self.rate_limiter {
+            Some(limiter) if !limiter.allow(samples) => Err(MonolithErr::LimitErr(
+                LimitReason::RateLimited,
+                format!(
+                    "ingestion rate limit of {} samples/s exceeded",
+                    self.options.limits.ingestion_rate.unwrap_or_default()
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn count_rejected(&self, err: &MonolithErr, samples: u64) {
+        if let MonolithErr::LimitErr(reason, _) = err {
+            self.rejected.add(*reason, samples);
+        }
+    }
+
+    /// Number of samples rejected by limits since started, for each reason
+    pub fn rejected_samples(&self) -> Vec<(LimitReason, u64)> {
+        LimitReason::ALL
+            .iter()
+            .map(|reason| (*reason, self.rejected.get(*reason)))
+            .collect()
+    }
+
     pub fn write_time_point(&self, labels: Labels, timepoint: TimePoint) -> Result<()> {
+        self.check_writable()?;
         let _c = &self.current_chuck.read().unwrap();
-        _c.insert(labels, timepoint)?;
+        let window_start = self.window_start(_c);
+        self.log_ahead(&labels, std::slice::from_ref(&timepoint), window_start, _c)?;
+        if let Err(err) = self.insert_point(_c, &labels, timepoint, window_start) {
+            self.count_rejected(&err, 1);
+            return Err(err);
+        }
+        self.ingested.inc();
         Ok(())
     }
 
+    /// Latest time point of each time series in the current chunk that satisfies all matchers of any
+    /// selector, sorted by labels
+    pub fn latest_samples(
+        &self,
+        selectors: &[Vec<LabelMatcher>],
+    ) -> Result<Vec<(Labels, TimePoint)>> {
+        let current = self.current_chuck.read().unwrap();
+        let (start_time, end_time) = current.start_end_time();
+        let mut res = HashMap::<Labels, TimePoint>::new();
+        for matchers in selectors {
+            for series in current.query_by_matchers(matchers.as_slice(), start_time, end_time)? {
+                let latest = series.time_points().iter().max_by_key(|tp| tp.timestamp);
+                if let Some(latest) = latest {
+                    res.insert(series.meta_data().clone(), latest.clone());
+                }
+            }
+        }
+        let mut res = res.into_iter().collect::<Vec<_>>();
+        res.sort_by(|a, b| a.0.compare(&b.0));
+        Ok(res)
+    }
+
+    pub fn base_dir(&self) -> &Path {
+        self.options.base_dir.as_path()
+    }
+
+    /// Number of samples written into chunks since started
+    pub fn ingested_samples(&self) -> u64 {
+        self.ingested.get()
+    }
+
+    /// Number of series in the current chunk
+    pub fn active_series(&self) -> usize {
+        self.current_chuck.read().unwrap().series_count()
+    }
+
+    /// Number of chunks, including the current one
+    pub fn chunk_count(&self) -> usize {
+        self.secondary_chunks.read().unwrap().len() + 1
+    }
+
+    /// Size in bytes of each chunk dir in base dir.
+    ///
+    /// Data of TiKV backend is not on local disk, so only the metadata files are counted.
+    pub fn chunk_disk_usage(&self) -> Result<Vec<(String, u64)>> {
+        let mut res = Vec::new();
+        for entry in fs::read_dir(&self.options.base_dir)? {
+            let path = entry?.path();
+            let dir_name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if path.is_dir() && decode_chunk_dir(dir_name.clone()).is_ok() {
+                res.push((dir_name, dir_size(path.as_path())?));
+            }
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    /// Whether the db is a read replica
+    pub fn is_replica(&self) -> bool {
+        self.options.replica
+    }
+
+    /// Max number of tenants opened from this db, unlimited if `None`
+    pub fn max_tenants(&self) -> Option<usize> {
+        self.options.max_tenants
+    }
+
+    /// Start and end time of the current chunk
+    pub fn current_chunk_range(&self) -> (Timestamp, Timestamp) {
+        self.current_chuck.read().unwrap().start_end_time()
+    }
+
+    /// Dir of the write ahead log segments, if write ahead log is enabled
+    pub fn wal_dir(&self) -> Option<PathBuf> {
+        self.wal
+            .as_ref()
+            .map(|_| self.options.base_dir.join(WAL_DIR))
+    }
+
+    /// Ranges of secondary chunks that never change again, because late samples are only written into
+    /// the part of chunks between `out_of_order_window` and the current chunk
+    pub fn finalized_chunks(&self) -> Vec<(Timestamp, Timestamp)> {
+        let (window_start, current_start) = {
+            let current = self.current_chuck.read().unwrap();
+            (self.window_start(&current), current.start_end_time().0)
+        };
+        self.secondary_chunks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|c| c.start_end_time())
+            .filter(|(_, end_time)| (*end_time).min(current_start.saturating_sub(1)) < window_start)
+            .collect()
+    }
+
+    ///
+    /// Series of the finalized chunk starting at `start_time`, sorted by labels, at most `limit` of them
+    /// from the `offset`th one. So that the chunk can be copied page by page, which is consistent since
+    /// the chunk never changes. Series deleted within the chunk have no samples.
+    pub fn export_chunk(
+        &self,
+        start_time: Timestamp,
+        offset: usize,
+        limit: usize,
+    ) -> Result<LabelPointPairs> {
+        if !self
+            .finalized_chunks()
+            .iter()
+            .any(|(s, _)| *s == start_time)
+        {
+            return Err(MonolithErr::NotFoundErr);
+        }
+        let chunk = self
+            .secondary_chunks
+            .read()
+            .unwrap()
+            .iter()
+            .find(|c| c.start_end_time().0 == start_time)
+            .cloned()
+            .ok_or(MonolithErr::NotFoundErr)?;
+        let (start_time, end_time) = chunk.start_end_time();
+        let all = LabelMatcher::new(MatcherType::RegexMatch, "__name__", ".*")?;
+        let mut series = chunk.series_by_matchers(&[all])?;
+        for labels in series.iter_mut() {
+            labels.sort();
+        }
+        series.sort_by(|a, b| a.compare(b));
+        let mut res = Vec::new();
+        for labels in series.into_iter().skip(offset).take(limit) {
+            let points = chunk.query_by_labels(labels.clone(), start_time, end_time)?;
+            res.push((labels, points));
+        }
+        Ok(res)
+    }
+
+    ///
+    /// Make the current chunk of replica cover [`start_time`, `end_time`], like the current chunk of
+    /// its leader.
+    ///
+    /// Chunks of replica overlapping the range are removed, like the one created when the replica is
+    /// opened, because the samples in the range are in the current chunk of leader and replicated again.
+    pub fn follow_chunk(&self, start_time: Timestamp, end_time: Timestamp) -> Result<()> {
+        if self.current_chunk_range().0 == start_time {
+            return Ok(());
+        }
+        let overlaps = |c: &Arc<Chunk<S, I>>| {
+            let (start, end) = c.start_end_time();
+            start <= end_time && end >= start_time
+        };
+        self.remove_chunks(overlaps)?;
+        let stale = self.swap_to(start_time, end_time)?;
+        if overlaps(&stale) {
+            drop(stale);
+            self.remove_chunks(overlaps)?;
+        } else {
+            thread::spawn(move || Self::compact_chunk(&stale));
+        }
+        Ok(())
+    }
+
+    /// Whether replica should copy the finalized chunk of [`start_time`, `end_time`] from its leader,
+    /// which is not copied yet and not expired
+    pub fn needs_chunk(&self, start_time: Timestamp, end_time: Timestamp) -> bool {
+        if self
+            .retention_deadline()
+            .map_or(false, |deadline| end_time < deadline)
+        {
+            return false;
+        }
+        !self
+            .options
+            .base_dir
+            .join(encode_chunk_dir(start_time, end_time))
+            .join(REPLICATED_FILENAME)
+            .exists()
+    }
+
+    ///
+    /// Replace the chunk of replica starting at `start_time` with `pages` of series copied from its
+    /// leader, so that only one page is in memory at a time.
+    ///
+    /// If any page fails, the chunk being copied is removed, and the chunk is copied again later.
+    pub fn import_chunk<P>(
+        &self,
+        start_time: Timestamp,
+        end_time: Timestamp,
+        pages: P,
+    ) -> Result<()>
+    where
+        P: IntoIterator<Item = Result<LabelPointPairs>>,
+    {
+        self.remove_chunks(|c| c.start_end_time().0 == start_time)?;
+        let chunk = self.create_chunk(start_time, end_time)?;
+        let chunk_dir = self
+            .options
+            .base_dir
+            .join(encode_chunk_dir(start_time, end_time));
+        if let Err(err) = Self::insert_pages(&chunk, pages) {
+            if let Err(err) = chunk.destroy() {
+                error!(
+                    "Cannot remove data of chunk {}-{}, {}",
+                    start_time, end_time, err
+                );
+            }
+            // close the storage and indexer before removing their files
+            drop(chunk);
+            fs::remove_dir_all(chunk_dir.as_path())?;
+            return Err(err);
+        }
+        chunk.close();
+        File::create(chunk_dir.join(REPLICATED_FILENAME))?;
+        let chunk = Arc::new(chunk);
+        {
+            let mut chunks = self.secondary_chunks.write().unwrap();
+            chunks.push(chunk.clone());
+            chunks.sort();
+        }
+        thread::spawn(move || Self::compact_chunk(&chunk));
+        Ok(())
+    }
+
+    fn insert_pages<P>(chunk: &Chunk<S, I>, pages: P) -> Result<()>
+    where
+        P: IntoIterator<Item = Result<LabelPointPairs>>,
+    {
+        for page in pages {
+            for (labels, points) in page? {
+                for tp in points {
+                    match chunk.insert(labels.clone(), tp) {
+                        Ok(()) | Err(MonolithErr::LimitErr(_, _)) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write samples replicated from the leader into the chunks covering them, return the number of
+    /// samples dropped because no chunk covers them
+    pub fn replicate(&self, labels: &Labels, points: Vec<TimePoint>) -> Result<usize> {
+        let samples = points.len();
+        let dropped = self.insert_covered(labels, points)?;
+        self.ingested.inc_by((samples - dropped) as u64);
+        Ok(dropped)
+    }
+
+    /// Query time series that satisfy all `matchers` within [`start_time`, `end_time`]
+    ///
+    /// If `hint` is given, each time series will be downsampled to one point per step window.
     pub fn query(
         &self,
-        labels: Labels,
+        matchers: &[LabelMatcher],
         start_time: Timestamp,
         end_time: Timestamp,
+        hint: Option<&QueryHint>,
     ) -> Result<LabelPointPairs> {
         let mut res = HashMap::<Labels, Vec<TimePoint>>::new();
-        let res_ref = &mut res;
+        let (heads, chunks) = self.chunks_within_range(start_time, end_time);
+        //check the current chunk and the one swapped out first, then previous chunks, normally users query
+        //more on recent data, so we go backwards
+        let mut series = Vec::new();
+        for _c in heads.iter() {
+            if let Ok(mut _res) = _c.query_by_matchers(matchers, start_time, end_time) {
+                series.append(&mut _res);
+            }
+        }
+        for _c in chunks.iter() {
+            if let Ok(mut _res) = _c.query_by_matchers(matchers, start_time, end_time) {
+                series.append(&mut _res);
+            }
+        }
         //insert in reverse time order in order to quickly append vec
-        {
-            //check current chunk
-            let _c = &self.current_chuck.read().unwrap();
-            let _res = _c.query(labels.clone(), start_time, end_time);
-            if _res.is_ok() {
-                for ref t in _res.unwrap() {
-                    res_ref.insert(
-                        t.meta_data().clone(),
-                        t.time_points().clone().into_iter().rev().collect(),
-                    );
+        for t in series {
+            let mut current_val = t
+                .time_points()
+                .iter()
+                .cloned()
+                .rev()
+                .collect::<Vec<TimePoint>>();
+            match res.get_mut(t.meta_data()) {
+                Some(val) => val.append(&mut current_val),
+                None => {
+                    res.insert(t.meta_data().clone(), current_val);
                 }
-            } else {
-            }
-        };
-        {
-            //chuck previous chunk, normally users query more on recent data, so we go backwards
-            let chunks = &self.secondary_chunks.read().unwrap();
-            for _c in chunks.iter().rev() {
-                if _c.is_with_range(start_time, end_time) {
-                    let _res = _c.query(labels.clone(), start_time, end_time);
-                    if _res.is_ok() {
-                        for ref t in _res.unwrap() {
-                            let mut current_val = t
-                                .time_points()
-                                .clone()
-                                .into_iter()
-                                .rev()
-                                .collect::<Vec<TimePoint>>();
-                            match res_ref.get_mut(t.meta_data()) {
-                                Some(val) => {
-                                    val.append(&mut current_val);
-                                }
-                                None => {
-                                    res_ref.insert(t.meta_data().clone(), current_val);
-                                }
-                            };
-                        }
-                    } else {
-                    }
-                };
             }
         }
         //reverse time again before return
@@ -257,30 +985,497 @@ where
             .filter(|o| o.is_some())
             .map(|o| o.unwrap())
             .map(|(labels, points)| {
-                (
-                    labels.clone(),
-                    points.iter().cloned().rev().collect::<Vec<TimePoint>>(),
-                )
+                let points = points.iter().cloned().rev().collect::<Vec<TimePoint>>();
+                match hint {
+                    Some(hint) => (labels.clone(), hint.downsample(points.as_slice())),
+                    None => (labels.clone(), points),
+                }
             })
             .collect();
         Ok(res_vec)
     }
 
+    /// Query the time series with exactly `labels` within [`start_time`, `end_time`], so that large
+    /// results can be read one series at a time. Empty if there is no such series.
+    ///
+    /// If `hint` is given, the time series will be downsampled to one point per step window.
+    pub fn query_series(
+        &self,
+        labels: &Labels,
+        start_time: Timestamp,
+        end_time: Timestamp,
+        hint: Option<&QueryHint>,
+    ) -> Result<Vec<TimePoint>> {
+        let mut points = Vec::new();
+        let (heads, chunks) = self.chunks_within_range(start_time, end_time);
+        // chunks never overlap, so points are sorted by reading the earliest chunk first
+        for chunk in chunks.iter().rev() {
+            points.append(&mut chunk.query_by_labels(labels.clone(), start_time, end_time)?);
+        }
+        for head in heads.iter().rev() {
+            points.append(&mut head.query_by_labels(labels.clone(), start_time, end_time)?);
+        }
+        match hint {
+            Some(hint) => Ok(hint.downsample(points.as_slice())),
+            None => Ok(points),
+        }
+    }
+
+    /// Get label sets of time series that satisfy any of the `selectors` within [`start_time`, `end_time`]
+    ///
+    /// A time series is included as long as its chunk overlaps with the time range. Result is sorted and
+    /// contains no duplication.
+    pub fn series(
+        &self,
+        selectors: &[Vec<LabelMatcher>],
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<Vec<Labels>> {
+        let (heads, chunks) = self.chunks_within_range(start_time, end_time);
+        let mut res = HashSet::<Labels>::new();
+        for matchers in selectors {
+            let mut series = Vec::new();
+            for head in heads.iter() {
+                series.extend(head.series_by_matchers(matchers.as_slice())?);
+            }
+            for chunk in chunks.iter() {
+                series.extend(chunk.series_by_matchers(matchers.as_slice())?);
+            }
+            for mut labels in series {
+                labels.sort();
+                res.insert(labels);
+            }
+        }
+        let mut res_vec = res.into_iter().collect::<Vec<Labels>>();
+        res_vec.sort_by(|a, b| a.compare(b));
+        Ok(res_vec)
+    }
+
+    /// Get names of all labels within [`start_time`, `end_time`], sorted in alphabet order
+    pub fn label_names(&self, start_time: Timestamp, end_time: Timestamp) -> Result<Vec<String>> {
+        let mut res = BTreeSet::new();
+        let (heads, chunks) = self.chunks_within_range(start_time, end_time);
+        for head in heads {
+            res.extend(head.label_names()?);
+        }
+        for chunk in chunks {
+            res.extend(chunk.label_names()?);
+        }
+        Ok(res.into_iter().collect())
+    }
+
+    /// Get all values of label `name` within [`start_time`, `end_time`], sorted in alphabet order
+    pub fn label_values(
+        &self,
+        name: &str,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<Vec<String>> {
+        let mut res = BTreeSet::new();
+        let (heads, chunks) = self.chunks_within_range(start_time, end_time);
+        for head in heads {
+            res.extend(head.label_values(name)?);
+        }
+        for chunk in chunks {
+            res.extend(chunk.label_values(name)?);
+        }
+        Ok(res.into_iter().collect())
+    }
+
+    /// Delete time series that satisfy any of the `selectors` within [`start_time`, `end_time`]
+    ///
+    /// Deleted data is hidden from queries at once, but only removed from disk by `clean_tombstones`.
+    pub fn delete_series(
+        &self,
+        selectors: &[Vec<LabelMatcher>],
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<()> {
+        self.check_replica()?;
+        // number of deleted series by chunk range
+        let mut deleted = Vec::new();
+        let current = self.current_chuck.read().unwrap();
+        // deletions of the chunk swapped out wait for its flush, so that its tombstones are loaded by its
+        // chunk in backend
+        let swapped = self.wait_flushed();
+        let (heads, chunks) = self.overlapping(&current, swapped.as_ref(), start_time, end_time);
+        for head in heads {
+            for matchers in selectors {
+                let series = head.delete(matchers.as_slice(), start_time, end_time)?;
+                deleted.push((head.start_end_time(), series));
+            }
+        }
+        for chunk in chunks {
+            for matchers in selectors {
+                let series = chunk.delete(matchers.as_slice(), start_time, end_time)?;
+                deleted.push((chunk.start_end_time(), series));
+            }
+        }
+        for ((chunk_start, chunk_end), series) in deleted {
+            if series > 0 {
+                info!(
+                    "Deleted {} series in chunk {}-{} within {}-{}",
+                    series, chunk_start, chunk_end, start_time, end_time
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove data deleted by `delete_series` from storage and indexer of all chunks
+    pub fn clean_tombstones(&self) -> Result<()> {
+        self.check_replica()?;
+        {
+            let current = self.current_chuck.read().unwrap();
+            match &self.wal {
+                Some(_) => current.clean_tombstones()?,
+                None => current.clean_tombstones_through(&self.current_target.read().unwrap())?,
+            }
+        }
+        let chunks = self.secondary_chunks.read().unwrap().clone();
+        for chunk in chunks {
+            chunk.clean_tombstones()?;
+        }
+        Ok(())
+    }
+
+    /// The current chunk and the chunk swapped out that overlap with [`start_time`, `end_time`], and the
+    /// secondary chunks that overlap with it, both latest first
+    fn chunks_within_range(
+        &self,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> ChunksInRange<S, I> {
+        let current = self.current_chuck.read().unwrap();
+        let swapped = self.swapped.lock().unwrap();
+        self.overlapping(&current, swapped.as_ref(), start_time, end_time)
+    }
+
+    /// See `chunks_within_range`, the caller holds the read lock of `current` and the lock of `swapped`, so
+    /// that they are consistent with each other and secondary chunks
+    fn overlapping(
+        &self,
+        current: &Arc<HeadChunk>,
+        swapped: Option<&Swapped<S, I>>,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> ChunksInRange<S, I> {
+        let mut heads = Vec::new();
+        for head in std::iter::once(current).chain(swapped.map(|s| &s.head)) {
+            if head.is_with_range(start_time, end_time) {
+                heads.push(Arc::clone(head));
+            }
+        }
+        // its chunk in backend is pushed into secondary chunks before the chunk swapped out is dropped
+        let swapped_start = swapped.map(|s| s.head.start_end_time().0);
+        let mut chunks = Vec::new();
+        for _c in self.secondary_chunks.read().unwrap().iter().rev() {
+            if _c.is_with_range(start_time, end_time)
+                && Some(_c.start_end_time().0) != swapped_start
+            {
+                chunks.push(Arc::clone(_c));
+            }
+        }
+        (heads, chunks)
+    }
+
+    ///
+    /// Remove secondary chunks that ended longer than `retention` ago, and the oldest ones while chunk
+    /// dirs are larger than `retention_size`, together with their dirs and data out of the dirs.
+    fn remove_expired_chunks(&self) -> Result<()> {
+        let deadline = self.retention_deadline();
+        let max_size = self.options.retention_size;
+        if deadline.is_none() && max_size.is_none() {
+            return Ok(());
+        }
+        // size of chunk dirs by start time, only needed by size based retention
+        let mut sizes = HashMap::new();
+        if max_size.is_some() {
+            for (dir_name, size) in self.chunk_disk_usage()? {
+                sizes.insert(decode_chunk_dir(dir_name)?.0, size);
+            }
+        }
+        let mut total_size: u64 = sizes.values().sum();
+        // secondary chunks are in time order, so the oldest ones are removed first
+        let expired = self.remove_chunks(|c| {
+            let (start_time, end_time) = c.start_end_time();
+            let too_old = deadline.map_or(false, |deadline| end_time < deadline);
+            let too_large = max_size.map_or(false, |max_size| total_size > max_size);
+            if too_old || too_large {
+                total_size -= sizes.get(&start_time).cloned().unwrap_or_default();
+            }
+            too_old || too_large
+        })?;
+        if expired > 0 {
+            EXPIRED_CHUNKS.with_label_values(&[]).inc_by(expired as u64);
+        }
+        Ok(())
+    }
+
+    /// Chunks ended before it are expired, if retention is set
+    fn retention_deadline(&self) -> Option<Timestamp> {
+        self.options.retention.map(|retention| {
+            get_current_timestamp().saturating_sub(retention.as_millis() as Timestamp)
+        })
+    }
+
+    /// Remove secondary chunks that `f` returns true for, together with their dirs and data out of the
+    /// dirs, return the number of removed chunks
+    fn remove_chunks<F>(&self, f: F) -> Result<usize>
+    where
+        F: FnMut(&Arc<Chunk<S, I>>) -> bool,
+    {
+        let removed = {
+            let mut chunks = self.secondary_chunks.write().unwrap();
+            let (removed, kept): (Vec<_>, Vec<_>) = chunks.drain(..).partition(f);
+            *chunks = kept;
+            removed
+        };
+        if removed.is_empty() {
+            return Ok(0);
+        }
+        for chunk in removed.iter() {
+            let (start_time, end_time) = chunk.start_end_time();
+            if let Err(err) = chunk.destroy() {
+                error!(
+                    "Cannot remove data of chunk {}-{}, {}",
+                    start_time, end_time, err
+                );
+            }
+        }
+        let count = removed.len();
+        // end time of chunks read from disk may be reset, so match dirs by start time
+        let start_times = removed
+            .iter()
+            .map(|c| c.start_end_time().0)
+            .collect::<HashSet<Timestamp>>();
+        // close the storage and indexer before removing their files
+        drop(removed);
+        for entry in fs::read_dir(&self.options.base_dir)? {
+            let path = entry?.path();
+            let dir_name = match path.file_name().and_then(|name| name.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            if let Ok((start_time, _)) = decode_chunk_dir(dir_name) {
+                if start_times.contains(&start_time) {
+                    info!("Remove chunk {}", path.display());
+                    fs::remove_dir_all(&path)?;
+                }
+            }
+        }
+        Ok(count)
+    }
+
     fn swap(&self, start_time: Timestamp) -> Result<()> {
+        let end_time = start_time + self.options.chunk_size.as_millis() as Timestamp;
+        let stale = self.swap_to(start_time, end_time)?;
+        thread::spawn(move || Self::compact_chunk(&stale));
+        Ok(())
+    }
+
+    ///
+    /// Replace the current chunk with a new one of [`start_time`, `end_time`], return the chunk in backend
+    /// that the one swapped out is flushed into.
+    ///
+    /// Writes of the current chunk only wait for the pointer to the new chunk to be swapped, the chunk
+    /// swapped out is still queried while it's flushed, see `flush_swapped`. If anything fails before the
+    /// swap, the current chunk is kept.
+    fn swap_to(&self, start_time: Timestamp, end_time: Timestamp) -> Result<Arc<Chunk<S, I>>> {
+        let _swap = self.swap_lock.lock().unwrap();
+        // the chunk swapped out last time is flushed first if its flush failed
+        if let Some(stale) = self.flush_swapped()? {
+            thread::spawn(move || Self::compact_chunk(&stale));
+        }
         info!("Chunk swap, new chunk with start time {}", start_time);
+        let swap_start = Instant::now();
+        let wal_seq = self.swap_head(start_time, end_time)?;
+        let stale = self
+            .flush_swapped()?
+            .ok_or_else(|| MonolithErr::InternalErr("swapped out chunk is missing".to_string()))?;
+        if let (Some(wal), Some(seq)) = (&self.wal, wal_seq) {
+            // samples in older segments are persisted once secondary chunks are flushed
+            for chunk in self.secondary_chunks.read().unwrap().iter() {
+                chunk.flush()?;
+            }
+            wal.truncate(seq)?;
+        }
+        CHUNK_SWAP_DURATION.observe_duration(&[], swap_start.elapsed());
+        Ok(stale)
+    }
+
+    /// Make a new chunk of [`start_time`, `end_time`] the current one, and keep the old one as the chunk
+    /// swapped out, return the sequence number of the new segment of write ahead log if it's enabled
+    fn swap_head(&self, start_time: Timestamp, end_time: Timestamp) -> Result<Option<u64>> {
+        // samples logged from now on are written into the new segment, which is kept after the chunk
+        // swapped out is flushed, so it's fine to rotate before the swap
+        let wal_seq = match &self.wal {
+            Some(wal) => Some(wal.rotate()?),
+            None => None,
+        };
+        let (head, target) = Self::create_head(
+            &self.options,
+            &self.storage_builder,
+            &self.indexer_builder,
+            start_time,
+            end_time,
+        )?;
+        // wait for the writes of the current chunk
+        let mut current = self.current_chuck.write().unwrap();
+        let target = std::mem::replace(&mut *self.current_target.write().unwrap(), target);
+        let stale = std::mem::replace(&mut *current, Arc::new(head));
+        info!(
+            "The old chunk with end time {} is closing",
+            stale.start_end_time().1
+        );
+        *self.swapped.lock().unwrap() = Some(Swapped {
+            head: stale,
+            target: Some(target),
+        });
+        Ok(wal_seq)
+    }
+
+    ///
+    /// Flush the chunk swapped out into its chunk in backend, which then replaces it in secondary chunks
+    /// and is returned, `None` if there is no chunk swapped out. The caller holds `swap_lock`.
+    ///
+    /// Queries read the chunk swapped out in the meantime, while late samples and deletions of it wait
+    /// for the flush. If the flush fails, the chunk swapped out is kept readable and writable, and its
+    /// samples are still in write ahead log or written through into backend.
+    fn flush_swapped(&self) -> Result<Option<Arc<Chunk<S, I>>>> {
+        let (head, target) = {
+            let mut swapped = self.swapped.lock().unwrap();
+            match swapped.as_mut() {
+                Some(s) => match s.target.take() {
+                    Some(target) => (s.head.clone(), target),
+                    None => return Ok(None),
+                },
+                None => return Ok(None),
+            }
+        };
+        let (start_time, end_time) = head.start_end_time();
+        // series are flushed with their ids, so the tombstones of the chunk swapped out apply as is
+        let tombstone_file = self
+            .options
+            .base_dir
+            .join(encode_chunk_dir(start_time, end_time))
+            .join(TOMBSTONES_FILENAME);
+        head.close();
+        let flushed = self.flush_head(&head, &target).and_then(|()| {
+            let tombstones = Tombstones::read_from_file(&tombstone_file)?;
+            Ok((tombstones, target.series_ids()?))
+        });
+        match flushed {
+            Ok((tombstones, ids)) => {
+                let stale = Arc::new(
+                    target
+                        .with_tombstones(tombstone_file, tombstones)
+                        .with_series_ids(ids.as_slice()),
+                );
+                stale.close();
+                // pushed before the chunk swapped out is dropped, so that its samples are always readable
+                self.secondary_chunks.write().unwrap().push(stale.clone());
+                *self.swapped.lock().unwrap() = None;
+                self.flushed.notify_all();
+                Ok(Some(stale))
+            }
+            Err(err) => {
+                error!(
+                    "Cannot flush chunk {}-{}, it's flushed again by the next swap, {}",
+                    start_time, end_time, err
+                );
+                if let Some(s) = self.swapped.lock().unwrap().as_mut() {
+                    s.target = Some(target);
+                }
+                self.flushed.notify_all();
+                Err(err)
+            }
+        }
+    }
+
+    ///
+    /// Write samples of the current chunk into its chunk in backend, and flush them to disk.
+    ///
+    /// Without write ahead log the samples have been written through with the same series ids, so it's
+    /// only flushed.
+    fn flush_head(&self, head: &HeadChunk, target: &Chunk<S, I>) -> Result<()> {
+        let (start_time, end_time) = head.start_end_time();
+        if self.wal.is_none() {
+            return target.flush();
+        }
+        let flush_start = Instant::now();
+        let series = head.flush_into(target)?;
+        target.flush()?;
+        info!(
+            "Flushed {} series of chunk {}-{} into backend in {:?}",
+            series,
+            start_time,
+            end_time,
+            flush_start.elapsed()
+        );
+        Ok(())
+    }
+
+    /// Create a chunk of [`start_time`, `end_time`] in its dir under base dir
+    fn create_chunk(&self, start_time: Timestamp, end_time: Timestamp) -> Result<Chunk<S, I>> {
+        let chunk_dir = self
+            .options
+            .base_dir
+            .join(encode_chunk_dir(start_time, end_time));
+        Self::new_chunk(
+            &self.options,
+            &self.storage_builder,
+            &self.indexer_builder,
+            start_time,
+            end_time,
+        )?
+        .with_limits(&self.options.limits)
+        .with_tombstone_file(chunk_dir.join(TOMBSTONES_FILENAME))
+    }
+
+    ///
+    /// Create the in-memory current chunk of [`start_time`, `end_time`], along with the chunk in backend
+    /// it's flushed into.
+    ///
+    /// The chunk in backend is created at once, so that samples replayed from write ahead log after a
+    /// crash are covered by it. Tombstones of the current chunk are persisted in its dir, and loaded by
+    /// the chunk in backend once the current chunk is flushed.
+    fn create_head(
+        opts: &DbOpts,
+        storage_builder: &Box<dyn Builder<S> + Sync + Send>,
+        indexer_builder: &Box<dyn Builder<I> + Sync + Send>,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<(HeadChunk, Chunk<S, I>)> {
+        let target = Self::new_chunk(opts, storage_builder, indexer_builder, start_time, end_time)?;
         let mut chunk_opt = ChunkOpts::default();
         chunk_opt.start_time = Some(start_time);
-        chunk_opt.end_time = Some(start_time + self.options.chunk_size.as_millis() as Timestamp);
+        chunk_opt.end_time = Some(end_time);
+        let chunk_dir = opts.base_dir.join(encode_chunk_dir(start_time, end_time));
+        let head = HeadChunk::new(MemStorage::new(), MemIndexer::new(), &chunk_opt)
+            .with_limits(&opts.limits)
+            .with_tombstone_file(chunk_dir.join(TOMBSTONES_FILENAME))?;
+        Ok((head, target))
+    }
 
-        let chunk_dir = self.options.base_dir.join(encode_chunk_dir(
-            chunk_opt.start_time.unwrap(),
-            chunk_opt.end_time.unwrap(),
-        ));
+    /// Create a chunk in backend of [`start_time`, `end_time`] in its dir under base dir of `opts`
+    fn new_chunk(
+        opts: &DbOpts,
+        storage_builder: &Box<dyn Builder<S> + Sync + Send>,
+        indexer_builder: &Box<dyn Builder<I> + Sync + Send>,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<Chunk<S, I>> {
+        let mut chunk_opt = ChunkOpts::default();
+        chunk_opt.start_time = Some(start_time);
+        chunk_opt.end_time = Some(end_time);
+
+        let chunk_dir = opts.base_dir.join(encode_chunk_dir(start_time, end_time));
         let chunk_dir_str = chunk_dir.as_path().display().to_string();
 
         // write metadata into chunk
-        self.storage_builder.write_to_chunk(&chunk_dir)?;
-        self.indexer_builder.write_to_chunk(&chunk_dir)?;
+        storage_builder.write_to_chunk(&chunk_dir)?;
+        indexer_builder.write_to_chunk(&chunk_dir)?;
         if let Err(_) = chunk_opt.write_config_to_dir(chunk_dir.as_path()) {
             error!("Cannot create metadata file for chunk");
             return Err(MonolithErr::InternalErr(
@@ -290,44 +1485,53 @@ where
 
         // build storage and indexer instance
         let (storage, indexer) = (
-            self.storage_builder.build(
-                chunk_dir_str.clone(),
-                Some(&chunk_opt),
-                Some(&self.options),
-            )?,
-            self.indexer_builder.build(
-                chunk_dir_str.clone(),
-                Some(&chunk_opt),
-                Some(&self.options),
-            )?,
+            storage_builder.build(chunk_dir_str.clone(), Some(&chunk_opt), Some(opts))?,
+            indexer_builder.build(chunk_dir_str.clone(), Some(&chunk_opt), Some(opts))?,
         );
 
-        let chunk = Chunk::<S, I>::new(storage, indexer, &chunk_opt);
-        {
-            let stale = self.current_chuck.read().unwrap();
-            self.secondary_chunks.write().unwrap().push(stale.clone());
-        }
-        {
-            let mut current = self.current_chuck.write().unwrap(); //will also block all read util swap finish
-            let (_, end_time) = current.start_end_time();
-            info!("The old chunk with end time {} is closing", end_time);
-            current.close(); //close the stale one
-            *current = Arc::new(chunk); //crate new one
+        Ok(Chunk::<S, I>::new(storage, indexer, &chunk_opt))
+    }
+
+    /// Compact series of a closed chunk, which is still readable and writable in the meantime
+    fn compact_chunk(chunk: &Chunk<S, I>) {
+        let (start_time, end_time) = chunk.start_end_time();
+        let compaction_start = Instant::now();
+        match chunk.compact() {
+            Ok(series) => {
+                CHUNK_COMPACTION_DURATION.observe_duration(&[], compaction_start.elapsed());
+                info!(
+                    "Compacted {} series of chunk {}-{} in {:?}",
+                    series,
+                    start_time,
+                    end_time,
+                    compaction_start.elapsed()
+                );
+            }
+            Err(err) => error!("Cannot compact chunk {}-{}, {}", start_time, end_time, err),
         }
-        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::common::metadata::DbMetadata;
-    use crate::{MonolithDb, Result, DB_METADATA_FILENAME};
+    use crate::{MonolithDb, MonolithErr, Result, Timestamp, DB_METADATA_FILENAME};
     use tempfile::TempDir;
 
+    use crate::common::label::{Label, LabelMatcher, Labels, MatcherType};
     use crate::common::test_utils::{StubIndexer, StubStorage};
-    use std::fs::File;
-    use std::io::BufWriter;
+    use crate::common::time_point::TimePoint;
+    use crate::indexer::{SledIndexer, SledIndexerBuilder};
+    use crate::limits::LimitReason;
+    use crate::option::DbOpts;
+    use crate::storage::{SledStorage, SledStorageBuilder};
+    use crate::utils::get_current_timestamp;
+    use crate::wal::{FlushPolicy, WalReader, WalWriter};
+    use crate::WAL_DIR;
+    use std::fs::{File, OpenOptions};
+    use std::io::{BufWriter, Write};
     use std::path::PathBuf;
+    use std::time::Duration;
 
     #[test]
     fn test_read_metadata() -> Result<()> {
@@ -366,4 +1570,525 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_delete_series() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        let now = get_current_timestamp() + 1000;
+        for job in vec!["a", "b"] {
+            let labels = Labels::from_vec(vec![
+                Label::from_key_value("__name__", "up"),
+                Label::from_key_value("job", job),
+            ]);
+            let points = (0..3).map(|i| TimePoint::new(now + i, 1.0)).collect();
+            db.write_time_points(labels, points)?;
+        }
+        let job_a = vec![LabelMatcher::new(MatcherType::Equal, "job", "a")?];
+        let all = vec![LabelMatcher::new(MatcherType::Equal, "__name__", "up")?];
+
+        db.delete_series(&[job_a.clone()], now + 1, now + 1)?;
+        let res = db.query(job_a.as_slice(), 0, now + 10, None)?;
+        let timestamps = res[0].1.iter().map(|tp| tp.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![now, now + 2]);
+
+        db.delete_series(&[job_a.clone()], 0, u64::max_value())?;
+        assert!(db.query(job_a.as_slice(), 0, now + 10, None)?.is_empty());
+        assert_eq!(db.series(&[all.clone()], 0, now + 10)?.len(), 1);
+        // labels are kept until tombstones are cleaned
+        assert_eq!(db.label_values("job", 0, now + 10)?, vec!["a", "b"]);
+
+        db.clean_tombstones()?;
+        assert_eq!(db.label_values("job", 0, now + 10)?, vec!["b"]);
+        assert_eq!(db.query(all.as_slice(), 0, now + 10, None)?[0].1.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_close() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        db.wait_ready()?;
+        assert!(db.is_ready());
+        let labels = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        let now = get_current_timestamp() + 1000;
+        db.write_time_point(labels.clone(), TimePoint::new(now, 1.0))?;
+
+        db.close()?;
+        // closing twice is fine
+        db.close()?;
+        match db.write_time_point(labels, TimePoint::new(now + 1, 1.0)) {
+            Err(MonolithErr::ClosedErr) => {}
+            res => panic!("write after close should fail, got {:?}", res),
+        }
+        let all = vec![LabelMatcher::new(MatcherType::Equal, "__name__", "up")?];
+        assert_eq!(db.query(all.as_slice(), 0, now + 10, None)?[0].1.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_out_of_order_window() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        opts.out_of_order_window = Duration::from_secs(60);
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        db.wait_ready()?;
+        let start = db.current_chuck.read().unwrap().start_end_time().0;
+        let labels = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        db.write_time_points(labels.clone(), vec![TimePoint::new(start + 30, 3.0)])?;
+        db.swap(start + 1000)?;
+
+        // late samples go to the swapped out chunk, and are sorted there
+        db.write_time_points(
+            labels.clone(),
+            vec![
+                TimePoint::new(start + 1500, 5.0),
+                TimePoint::new(start + 20, 2.0),
+                TimePoint::new(start + 10, 1.0),
+            ],
+        )?;
+        // no chunk covers it, even though it's within the window
+        match db.write_time_points(
+            labels.clone(),
+            vec![
+                TimePoint::new(start - 10, 0.0),
+                TimePoint::new(start + 40, 4.0),
+            ],
+        ) {
+            Err(MonolithErr::LimitErr(LimitReason::OutOfBounds, _)) => {}
+            res => panic!("sample out of bounds should be rejected, got {:?}", res),
+        }
+        let rejected = db.rejected_samples();
+        assert!(rejected.contains(&(LimitReason::OutOfBounds, 1)));
+
+        let all = vec![LabelMatcher::new(MatcherType::Equal, "__name__", "up")?];
+        let res = db.query(all.as_slice(), 0, start + 2000, None)?;
+        let timestamps = res[0]
+            .1
+            .iter()
+            .map(|tp| tp.timestamp - start)
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![10, 20, 30, 40, 1500]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunk_boundary() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        opts.out_of_order_window = Duration::from_secs(60);
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        db.wait_ready()?;
+        let (start, end) = db.current_chuck.read().unwrap().start_end_time();
+        let up = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        let down = Labels::from_vec(vec![Label::from_key_value("__name__", "down")]);
+
+        // chunks cover [start, end), the sample at the end is rejected without failing the others
+        db.write_time_points(up.clone(), vec![TimePoint::new(start, 1.0)])?;
+        let mut accepted = Vec::new();
+        match db.write_time_points_accepted(
+            up.clone(),
+            vec![TimePoint::new(end, 0.0), TimePoint::new(start + 10, 2.0)],
+            &mut accepted,
+        ) {
+            Err(MonolithErr::LimitErr(LimitReason::OutOfBounds, _)) => {}
+            res => panic!("sample at the end should be rejected, got {:?}", res),
+        }
+        let accepted = accepted.iter().map(|tp| tp.timestamp - start);
+        assert_eq!(accepted.collect::<Vec<_>>(), vec![10]);
+        assert!(db
+            .rejected_samples()
+            .contains(&(LimitReason::OutOfBounds, 1)));
+
+        db.swap(start + 1000)?;
+        db.write_time_points(up.clone(), vec![TimePoint::new(start + 1000, 3.0)])?;
+        // late sample at the start of the swapped out chunk
+        db.write_time_points(down.clone(), vec![TimePoint::new(start, 4.0)])?;
+
+        let timestamps = |name: &str| -> Result<Vec<Timestamp>> {
+            let matchers = vec![LabelMatcher::new(MatcherType::Equal, "__name__", name)?];
+            Ok(db.query(matchers.as_slice(), 0, end, None)?[0]
+                .1
+                .iter()
+                .map(|tp| tp.timestamp - start)
+                .collect())
+        };
+        assert_eq!(timestamps("up")?, vec![0, 10, 1000]);
+        assert_eq!(timestamps("down")?, vec![0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay_wal() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        opts.wal_flush_policy = Some(FlushPolicy::Immediate);
+        let open = || {
+            MonolithDb::<SledStorage, SledIndexer>::new(
+                opts.clone(),
+                Box::new(SledStorageBuilder::new()),
+                Box::new(SledIndexerBuilder::new()),
+            )
+        };
+        let labels = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        let start = {
+            let db = open()?;
+            db.wait_ready()?;
+            let start = db.current_chuck.read().unwrap().start_end_time().0;
+            db.write_time_points(labels.clone(), vec![TimePoint::new(start + 10, 1.0)])?;
+            db.close()?;
+            start
+        };
+        // samples only in the log, like the ones in memory when crashed, with a torn record after them
+        {
+            let writer = WalWriter::open(&dir.path().join(WAL_DIR), FlushPolicy::Immediate)?;
+            writer.write(&labels, &[TimePoint::new(start + 20, 2.0)])?;
+            let segment = dir
+                .path()
+                .join(WAL_DIR)
+                .join(format!("{:020}.wal", writer.active_segment()));
+            OpenOptions::new()
+                .append(true)
+                .open(segment)?
+                .write_all(&[0, 0, 0])?;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+
+        let db = open()?;
+        db.wait_ready()?;
+        let matchers = vec![LabelMatcher::new(MatcherType::Equal, "__name__", "up")?];
+        let res = db.query(matchers.as_slice(), 0, start + 1000, None)?;
+        let points = res[0]
+            .1
+            .iter()
+            .map(|tp| (tp.timestamp - start, tp.value))
+            .collect::<Vec<_>>();
+        assert_eq!(points, vec![(10, 1.0), (20, 2.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_through_without_wal() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        opts.wal_flush_policy = None;
+        let open = || {
+            MonolithDb::<SledStorage, SledIndexer>::new(
+                opts.clone(),
+                Box::new(SledStorageBuilder::new()),
+                Box::new(SledIndexerBuilder::new()),
+            )
+        };
+        let up = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        let down = Labels::from_vec(vec![Label::from_key_value("__name__", "down")]);
+        let start = {
+            let db = open()?;
+            db.wait_ready()?;
+            let start = db.current_chuck.read().unwrap().start_end_time().0;
+            db.write_time_points(up.clone(), vec![TimePoint::new(start + 1, 1.0)])?;
+            db.write_time_points(down.clone(), vec![TimePoint::new(start + 1, 1.0)])?;
+            let down_matchers = vec![LabelMatcher::new(MatcherType::Equal, "__name__", "down")?];
+            db.delete_series(&[down_matchers], 0, start + 100)?;
+            // dropped without close, like a crash
+            start
+        };
+        std::thread::sleep(Duration::from_millis(50));
+
+        let db = open()?;
+        db.wait_ready()?;
+        let all = vec![LabelMatcher::new(
+            MatcherType::RegexMatch,
+            "__name__",
+            ".*",
+        )?];
+        let res = db.query(all.as_slice(), 0, start + 1000, None)?;
+        assert_eq!(res.len(), 1);
+        assert!(res[0].0 == up);
+        assert_eq!(res[0].1, vec![TimePoint::new(start + 1, 1.0)]);
+        db.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncate_wal_after_swap() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        opts.wal_flush_policy = Some(FlushPolicy::Immediate);
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        db.wait_ready()?;
+        let start = db.current_chuck.read().unwrap().start_end_time().0;
+        let labels = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        db.write_time_points(labels.clone(), vec![TimePoint::new(start + 10, 1.0)])?;
+        db.swap(start + 1000)?;
+        db.write_time_points(labels, vec![TimePoint::new(start + 1010, 2.0)])?;
+
+        // samples of the swapped out chunk are persisted, only the new segment is kept
+        let wal_dir = dir.path().join(WAL_DIR);
+        let mut files = std::fs::read_dir(&wal_dir)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<Vec<String>>>()?;
+        files.sort();
+        assert_eq!(
+            files,
+            vec![format!("{:020}.checkpoint", 1), format!("{:020}.wal", 1)]
+        );
+        let mut replayed = Vec::new();
+        WalReader::open(&wal_dir)?.replay(|_, points| replayed.extend(points))?;
+        assert_eq!(replayed.len(), 1);
+        assert_eq!(replayed[0].timestamp, start + 1010);
+        db.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_swapped_chunk() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        opts.out_of_order_window = Duration::from_secs(3600);
+        opts.wal_flush_policy = Some(FlushPolicy::Immediate);
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        db.wait_ready()?;
+        let start = db.current_chuck.read().unwrap().start_end_time().0;
+        let labels = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        db.write_time_points(labels.clone(), vec![TimePoint::new(start + 10, 1.0)])?;
+        let all = vec![LabelMatcher::new(
+            MatcherType::RegexMatch,
+            "__name__",
+            ".*",
+        )?];
+        let timestamps = |db: &MonolithDb<SledStorage, SledIndexer>| -> Result<Vec<Timestamp>> {
+            let res = db.query(all.as_slice(), 0, start + 2000, None)?;
+            assert_eq!(res.len(), 1);
+            Ok(res[0].1.iter().map(|tp| tp.timestamp - start).collect())
+        };
+
+        // the chunk swapped out is readable before it's flushed, and takes late samples if its flush
+        // hasn't started or failed
+        db.swap_head(start + 1000, start + 2000)?;
+        assert_eq!(timestamps(&db)?, vec![10]);
+        db.write_time_points(labels.clone(), vec![TimePoint::new(start + 1010, 2.0)])?;
+        db.write_time_points(labels.clone(), vec![TimePoint::new(start + 20, 2.0)])?;
+        assert_eq!(timestamps(&db)?, vec![10, 20, 1010]);
+        assert_eq!(db.series(&[all.clone()], 0, start + 100)?.len(), 1);
+
+        // once flushed, it's replaced by its chunk in backend without duplicated samples
+        let stale = db.flush_swapped()?.unwrap();
+        assert_eq!(stale.start_end_time().0, start);
+        assert!(db.swapped.lock().unwrap().is_none());
+        assert!(db.flush_swapped()?.is_none());
+        assert_eq!(timestamps(&db)?, vec![10, 20, 1010]);
+        // late samples go into the chunk in backend now
+        db.write_time_points(labels, vec![TimePoint::new(start + 30, 3.0)])?;
+        assert_eq!(timestamps(&db)?, vec![10, 20, 30, 1010]);
+        db.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_flush_head() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        let open = |opts: DbOpts| {
+            MonolithDb::<SledStorage, SledIndexer>::new(
+                opts,
+                Box::new(SledStorageBuilder::new()),
+                Box::new(SledIndexerBuilder::new()),
+            )
+        };
+        let db = open(opts.clone())?;
+        db.wait_ready()?;
+        let start = db.current_chuck.read().unwrap().start_end_time().0;
+        let up = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        let down = Labels::from_vec(vec![Label::from_key_value("__name__", "down")]);
+        db.write_time_points(up.clone(), vec![TimePoint::new(start + 10, 1.0)])?;
+        db.write_time_points(down.clone(), vec![TimePoint::new(start + 10, 1.0)])?;
+        let left = Labels::from_vec(vec![Label::from_key_value("__name__", "left")]);
+        db.write_time_points(left.clone(), vec![TimePoint::new(start + 10, 1.0)])?;
+        let down_matchers = vec![LabelMatcher::new(MatcherType::Equal, "__name__", "down")?];
+        db.delete_series(&[down_matchers], 0, start + 100)?;
+        db.swap(start + 1000)?;
+
+        // the swapped out chunk is in backend, without deleted data
+        let chunk = db.secondary_chunks.read().unwrap()[0].clone();
+        assert_eq!(chunk.start_end_time().0, start);
+        let all = vec![LabelMatcher::new(
+            MatcherType::RegexMatch,
+            "__name__",
+            ".*",
+        )?];
+        let series = chunk.query_by_matchers(all.as_slice(), 0, start + 2000)?;
+        assert_eq!(series.len(), 2);
+        assert_eq!(db.active_series(), 0);
+        // deletion in the chunk in backend is persisted as well
+        let left_matchers = vec![LabelMatcher::new(MatcherType::Equal, "__name__", "left")?];
+        db.delete_series(&[left_matchers], 0, start + 100)?;
+
+        // the current chunk is flushed on close, and the log is removed so it's not replayed twice
+        db.write_time_points(up.clone(), vec![TimePoint::new(start + 1010, 2.0)])?;
+        db.close()?;
+        drop(db);
+        let db = open(opts)?;
+        db.wait_ready()?;
+        let res = db.query(all.as_slice(), 0, start + 2000, None)?;
+        assert_eq!(res.len(), 1);
+        let timestamps = res[0].1.iter().map(|tp| tp.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![start + 10, start + 1010]);
+        db.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_replicate() -> Result<()> {
+        let open = |replica| -> Result<_> {
+            let dir = TempDir::new()?;
+            let mut opts = DbOpts::default();
+            opts.base_dir = dir.path().to_path_buf();
+            opts.replica = replica;
+            let db = MonolithDb::<SledStorage, SledIndexer>::new(
+                opts,
+                Box::new(SledStorageBuilder::new()),
+                Box::new(SledIndexerBuilder::new()),
+            )?;
+            db.wait_ready()?;
+            Ok((dir, db))
+        };
+        let (_leader_dir, leader) = open(false)?;
+        let (_replica_dir, replica) = open(true)?;
+        let labels = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        let start = leader.current_chunk_range().0;
+        leader.write_time_points(labels.clone(), vec![TimePoint::new(start + 10, 1.0)])?;
+        leader.swap(start + 1000)?;
+        let finalized = leader.finalized_chunks();
+        assert_eq!(finalized.len(), 1);
+        assert_eq!(finalized[0].0, start);
+
+        // the chunk created when replica is opened is replaced by the one of leader
+        let (current_start, current_end) = leader.current_chunk_range();
+        replica.follow_chunk(current_start, current_end)?;
+        assert_eq!(replica.current_chunk_range(), (current_start, current_end));
+        assert_eq!(replica.chunk_count(), 1);
+
+        let (chunk_start, chunk_end) = finalized[0];
+        assert!(replica.needs_chunk(chunk_start, chunk_end));
+        // a failed page leaves nothing behind
+        let pages = vec![
+            leader.export_chunk(chunk_start, 0, 1),
+            Err(MonolithErr::IoError("leader is gone".to_string())),
+        ];
+        assert!(replica.import_chunk(chunk_start, chunk_end, pages).is_err());
+        assert!(replica.needs_chunk(chunk_start, chunk_end));
+        assert_eq!(replica.chunk_count(), 1);
+
+        assert_eq!(leader.export_chunk(chunk_start, 0, 1)?.len(), 1);
+        assert!(leader.export_chunk(chunk_start, 1, 1)?.is_empty());
+        let pages = vec![
+            leader.export_chunk(chunk_start, 0, 1),
+            leader.export_chunk(chunk_start, 1, 1),
+        ];
+        replica.import_chunk(chunk_start, chunk_end, pages)?;
+        assert!(!replica.needs_chunk(chunk_start, chunk_end));
+        match leader.export_chunk(current_start, 0, 1) {
+            Err(MonolithErr::NotFoundErr) => {}
+            _ => panic!("current chunk is not finalized"),
+        }
+
+        let points = vec![TimePoint::new(start + 1010, 2.0), TimePoint::new(1, 0.0)];
+        assert_eq!(replica.replicate(&labels, points)?, 1);
+        match replica.write_time_points(labels, vec![TimePoint::new(start + 1020, 3.0)]) {
+            Err(MonolithErr::ReadOnlyErr) => {}
+            res => panic!("replica should reject writes, got {:?}", res),
+        }
+        let all = vec![LabelMatcher::new(MatcherType::Equal, "__name__", "up")?];
+        let res = replica.query(all.as_slice(), 0, start + 2000, None)?;
+        let timestamps = res[0]
+            .1
+            .iter()
+            .map(|tp| tp.timestamp - start)
+            .collect::<Vec<_>>();
+        assert_eq!(timestamps, vec![10, 1010]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_retention() -> Result<()> {
+        let labels = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        let all = vec![LabelMatcher::new(MatcherType::Equal, "__name__", "up")?];
+        let open = |retention, retention_size| -> Result<_> {
+            let dir = TempDir::new()?;
+            let mut opts = DbOpts::default();
+            opts.base_dir = dir.path().to_path_buf();
+            opts.chunk_size = Duration::from_secs(1000);
+            opts.retention = retention;
+            opts.retention_size = retention_size;
+            let db = MonolithDb::<SledStorage, SledIndexer>::new(
+                opts,
+                Box::new(SledStorageBuilder::new()),
+                Box::new(SledIndexerBuilder::new()),
+            )?;
+            db.wait_ready()?;
+            // chunks start 2 hours ago, 1 second ago and now, besides the one created by `new`
+            let start = db.current_chuck.read().unwrap().start_end_time().0;
+            for (idx, chunk_start) in vec![start - 7_200_000, start - 1000, start + 1]
+                .into_iter()
+                .enumerate()
+            {
+                db.swap(chunk_start)?;
+                db.write_time_point(labels.clone(), TimePoint::new(chunk_start + 1, idx as f64))?;
+            }
+            assert_eq!(db.chunk_count(), 4);
+            Ok((dir, db, start))
+        };
+
+        // the chunk ended before an hour ago is removed
+        let (_dir, db, start) = open(Some(Duration::from_secs(3600)), None)?;
+        db.remove_expired_chunks()?;
+        assert_eq!(db.chunk_count(), 3);
+        assert_eq!(db.chunk_disk_usage()?.len(), 3);
+        let res = db.query(all.as_slice(), 0, start + 1000, None)?;
+        assert_eq!(res[0].1.len(), 2);
+
+        // chunks are removed from the oldest one, but the current chunk is always kept
+        let (_dir, db, start) = open(None, Some(1))?;
+        db.remove_expired_chunks()?;
+        assert_eq!(db.chunk_count(), 1);
+        assert_eq!(db.chunk_disk_usage()?.len(), 1);
+        let res = db.query(all.as_slice(), 0, start + 1000, None)?;
+        assert_eq!(res[0].1, vec![TimePoint::new(start + 2, 2.0)]);
+        Ok(())
+    }
 }
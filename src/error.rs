@@ -16,8 +16,31 @@ pub enum MonolithErr {
     OptionErr,
     #[fail(display = "Parse error")]
     ParseErr,
+    #[fail(display = "Invalid label matcher, {}", _0)]
+    InvalidMatcherErr(String),
+    #[fail(display = "Cannot parse query, {}", _0)]
+    QueryParseErr(String),
+    #[fail(display = "Error when executing query, {}", _0)]
+    QueryExecErr(String),
+    #[fail(display = "Invalid graphite input, {}", _0)]
+    GraphiteErr(String),
+    #[fail(display = "Invalid time series, {}", _0)]
+    InvalidSeriesErr(String),
+    #[fail(display = "Request body is larger than {} bytes", _0)]
+    RequestTooLargeErr(usize),
+    #[fail(display = "Invalid tenant, {}", _0)]
+    InvalidTenantErr(String),
+    /// Rejected by ingestion limits
+    #[fail(display = "Limit exceeded, {}", _1)]
+    LimitErr(crate::limits::LimitReason, String),
     #[fail(display = "Not found")]
     NotFoundErr,
+    /// Db is closed and cannot accept writes
+    #[fail(display = "Db is closed")]
+    ClosedErr,
+    /// Db is a read replica, which is only written by replication
+    #[fail(display = "Db is a read-only replica")]
+    ReadOnlyErr,
     /// Out of the target range, the two param shows the target range.
     #[fail(display = "Out of range, target range is {}, {}", _0, _1)]
     OutOfRangeErr(u64, u64),
@@ -44,6 +67,12 @@ impl std::convert::From<std::io::Error> for MonolithErr {
     }
 }
 
+impl std::convert::From<crate::wal::WalErr> for MonolithErr {
+    fn from(err: crate::wal::WalErr) -> Self {
+        MonolithErr::WalErr(err)
+    }
+}
+
 impl std::convert::From<std::convert::Infallible> for MonolithErr {
     //didn't implement because Infallible should never happen
     fn from(_: Infallible) -> Self {
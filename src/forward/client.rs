@@ -0,0 +1,128 @@
+use crate::{MonolithErr, Result};
+use openssl::ssl::{SslConnector, SslMethod};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use url::Url;
+
+/// Max size of the response header that is read for the status code
+const MAX_STATUS_LINE_SIZE: u64 = 1024;
+
+///
+/// Client that posts remote write requests to one endpoint.
+///
+/// Each request is sent on a new connection, which is closed once the status code is received.
+pub struct RemoteWriteClient {
+    host: String,
+    port: u16,
+    path: String,
+    tls: Option<SslConnector>,
+    timeout: Duration,
+    headers: HashMap<String, String>,
+}
+
+impl RemoteWriteClient {
+    pub fn new(
+        url: &str,
+        timeout: Duration,
+        headers: HashMap<String, String>,
+    ) -> Result<RemoteWriteClient> {
+        let parsed = Url::parse(url)
+            .map_err(|e| MonolithErr::IoError(format!("Invalid url {}, {}", url, e)))?;
+        let tls = match parsed.scheme() {
+            "http" => None,
+            "https" => Some(
+                SslConnector::builder(SslMethod::tls())
+                    .map_err(|e| MonolithErr::IoError(e.to_string()))?
+                    .build(),
+            ),
+            scheme => {
+                return Err(MonolithErr::IoError(format!(
+                    "Unsupported scheme {} of {}",
+                    scheme, url
+                )))
+            }
+        };
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| MonolithErr::IoError(format!("No host in {}", url)))?
+            .to_string();
+        let mut path = parsed.path().to_string();
+        if let Some(query) = parsed.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        Ok(RemoteWriteClient {
+            host,
+            port: parsed.port_or_known_default().unwrap_or(80),
+            path,
+            tls,
+            timeout,
+            headers,
+        })
+    }
+
+    /// Post snappy compressed `body` on behalf of `tenant`, return the status code of response
+    pub fn send(&self, tenant: Option<&str>, body: &[u8]) -> Result<u16> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| MonolithErr::IoError(format!("Cannot resolve {}", self.host)))?;
+        let stream = TcpStream::connect_timeout(&addr, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        match &self.tls {
+            Some(connector) => {
+                let stream = connector
+                    .connect(self.host.as_str(), stream)
+                    .map_err(|e| MonolithErr::IoError(format!("TLS handshake failed, {}", e)))?;
+                self.exchange(stream, tenant, body)
+            }
+            None => self.exchange(stream, tenant, body),
+        }
+    }
+
+    fn exchange<T: Read + Write>(
+        &self,
+        mut stream: T,
+        tenant: Option<&str>,
+        body: &[u8],
+    ) -> Result<u16> {
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\n\
+             Host: {}:{}\r\n\
+             User-Agent: monolith\r\n\
+             Content-Type: application/x-protobuf\r\n\
+             Content-Encoding: snappy\r\n\
+             X-Prometheus-Remote-Write-Version: 0.1.0\r\n\
+             Connection: close\r\n\
+             Content-Length: {}\r\n",
+            self.path,
+            self.host,
+            self.port,
+            body.len()
+        );
+        if let Some(tenant) = tenant {
+            request.push_str(format!("{}: {}\r\n", crate::server::TENANT_HEADER, tenant).as_str());
+        }
+        for (name, value) in self.headers.iter() {
+            request.push_str(format!("{}: {}\r\n", name, value).as_str());
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream.take(MAX_STATUS_LINE_SIZE)).read_line(&mut status_line)?;
+        // HTTP/1.1 200 OK
+        status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| {
+                MonolithErr::IoError(format!("Invalid status line {}", status_line.trim()))
+            })
+    }
+}
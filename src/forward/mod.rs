@@ -0,0 +1,408 @@
+use crate::common::label::Labels;
+use crate::common::time_point::TimePoint;
+use crate::metrics::{FORWARD_DROPPED_REQUESTS, FORWARD_REQUESTS};
+use crate::proto::WriteRequest;
+use crate::{MonolithErr, Result};
+use protobuf::{Message, RepeatedField};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+mod client;
+mod queue;
+mod relabel;
+
+pub use client::RemoteWriteClient;
+pub use queue::DiskQueue;
+pub use relabel::{RelabelAction, RelabelConfig, Relabeler};
+
+/// Delay of the first retry, which doubles on each failure up to `max_backoff`
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+/// How long a sender waits for new records before checking if it should stop
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+///
+/// A downstream endpoint of remote write, durations are in seconds.
+///
+/// Read from a yaml file with a list of endpoints:
+///
+/// ```yaml
+/// - url: http://central:9001/write
+///   shards: 4
+///   headers:
+///     Authorization: Bearer token
+///   write_relabel_configs:
+///     - source_labels: [__name__]
+///       regex: "go_.*"
+///       action: drop
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RemoteWriteConfig {
+    pub url: String,
+    /// Name of the queue dir and the metrics label, `host:port` of url by default
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Series are split into shards by labels, each shard has its own queue and sender
+    #[serde(default = "default_shards")]
+    pub shards: usize,
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+    /// Max bytes of unsent requests in the queue of each shard, newer requests are dropped beyond it
+    #[serde(default = "default_max_queue_size")]
+    pub max_queue_size: u64,
+    /// Extra headers of each request, like `Authorization`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub write_relabel_configs: Vec<RelabelConfig>,
+}
+
+fn default_shards() -> usize {
+    1
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+fn default_max_backoff() -> u64 {
+    30
+}
+
+fn default_max_queue_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+impl RemoteWriteConfig {
+    pub fn from_file(path: &Path) -> Result<Vec<RemoteWriteConfig>> {
+        let content = fs::read(path)?;
+        let configs: Vec<RemoteWriteConfig> = serde_yaml::from_slice(content.as_slice())?;
+        Ok(configs)
+    }
+
+    fn name(&self) -> Result<String> {
+        if let Some(name) = &self.name {
+            return Ok(name.clone());
+        }
+        let url = url::Url::parse(self.url.as_str())
+            .map_err(|e| MonolithErr::IoError(format!("Invalid url {}, {}", self.url, e)))?;
+        Ok(format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default().unwrap_or_default()
+        ))
+    }
+}
+
+///
+/// Forward accepted remote write requests to downstream endpoints.
+///
+/// Series are relabeled and pushed into the on-disk queue of their shard, so forwarding never waits
+/// for the endpoints, nor for fsync of the queues, which is done by the senders. A sender thread of each shard posts the queued requests in order, and retries
+/// with exponential backoff if the endpoint is unavailable or responds 5xx or 429. Requests rejected
+/// with other status are dropped, the same as Prometheus does.
+///
+/// Queues are kept in `<dir>/<name>/<shard>`, so that unsent requests are sent after restart.
+pub struct Forwarder {
+    endpoints: Vec<Endpoint>,
+    stopped: Arc<AtomicBool>,
+    senders: Mutex<Vec<JoinHandle<()>>>,
+}
+
+struct Endpoint {
+    name: String,
+    relabeler: Relabeler,
+    queues: Vec<Arc<DiskQueue>>,
+}
+
+impl Forwarder {
+    pub fn new(configs: Vec<RemoteWriteConfig>, dir: &Path) -> Result<Forwarder> {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let mut endpoints = Vec::new();
+        let mut senders = Vec::new();
+        for config in configs {
+            let name = config.name()?;
+            if config.shards == 0 {
+                error!("Shards of remote write endpoint {} must be positive", name);
+                return Err(MonolithErr::OptionErr);
+            }
+            let client = Arc::new(RemoteWriteClient::new(
+                config.url.as_str(),
+                Duration::from_secs(config.timeout),
+                config.headers.clone(),
+            )?);
+            let mut queues = Vec::new();
+            for shard in 0..config.shards {
+                let queue = Arc::new(DiskQueue::open(
+                    dir.join(&name).join(shard.to_string()).as_path(),
+                    config.max_queue_size,
+                )?);
+                let sender = Sender {
+                    name: name.clone(),
+                    queue: Arc::clone(&queue),
+                    client: Arc::clone(&client),
+                    max_backoff: Duration::from_secs(config.max_backoff),
+                    stopped: Arc::clone(&stopped),
+                };
+                senders.push(thread::spawn(move || sender.run()));
+                queues.push(queue);
+            }
+            info!(
+                "Forward remote write requests to {} with {} shards",
+                config.url, config.shards
+            );
+            endpoints.push(Endpoint {
+                name,
+                relabeler: Relabeler::new(config.write_relabel_configs.as_slice())?,
+                queues,
+            });
+        }
+        Ok(Forwarder {
+            endpoints,
+            stopped,
+            senders: Mutex::new(senders),
+        })
+    }
+
+    /// Queue `series` of `tenant` for each endpoint. Failures are logged instead of returned,
+    /// because the series have been accepted by the local db.
+    pub fn forward(&self, tenant: Option<&str>, series: &[(Labels, Vec<TimePoint>)]) {
+        for endpoint in self.endpoints.iter() {
+            if let Err(err) = endpoint.push(tenant, series) {
+                FORWARD_REQUESTS
+                    .with_label_values(&[endpoint.name.as_str(), "failed"])
+                    .inc();
+                error!("Cannot queue requests for {}, {}", endpoint.name, err);
+            }
+        }
+    }
+
+    /// Stop senders, requests not yet sent are kept in queues
+    pub fn close(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        for handle in self.senders.lock().unwrap().drain(..) {
+            if handle.join().is_err() {
+                error!("Sender of remote write panicked");
+            }
+        }
+        for endpoint in self.endpoints.iter() {
+            for queue in endpoint.queues.iter() {
+                if let Err(err) = queue.sync() {
+                    error!("Cannot sync queue of {}, {}", endpoint.name, err);
+                }
+            }
+        }
+    }
+}
+
+impl Endpoint {
+    fn push(&self, tenant: Option<&str>, series: &[(Labels, Vec<TimePoint>)]) -> Result<()> {
+        let mut shards = vec![Vec::new(); self.queues.len()];
+        for (labels, points) in series {
+            if let Some(labels) = self.relabeler.process(labels) {
+                let shard = crc::crc32::checksum_ieee(labels.to_string().as_bytes()) as usize
+                    % shards.len();
+                shards[shard].push(crate::proto::TimeSeries::from(&(labels, points.clone())));
+            }
+        }
+        for (queue, timeseries) in self.queues.iter().zip(shards) {
+            if timeseries.is_empty() {
+                continue;
+            }
+            let mut request = WriteRequest::new();
+            request.set_timeseries(RepeatedField::from_vec(timeseries));
+            let content = request
+                .write_to_bytes()
+                .map_err(|e| MonolithErr::InternalErr(e.to_string()))?;
+            let body = snap::raw::Encoder::new()
+                .compress_vec(content.as_slice())
+                .map_err(|e| MonolithErr::InternalErr(e.to_string()))?;
+            if !queue.push(encode_record(tenant, body.as_slice()).as_slice())? {
+                FORWARD_DROPPED_REQUESTS
+                    .with_label_values(&[self.name.as_str()])
+                    .inc();
+                warn!("Queue of {} is full, drop the request", self.name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sends requests in a queue to an endpoint
+struct Sender {
+    name: String,
+    queue: Arc<DiskQueue>,
+    client: Arc<RemoteWriteClient>,
+    max_backoff: Duration,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Sender {
+    fn run(self) {
+        let mut backoff = MIN_BACKOFF;
+        while !self.stopped.load(Ordering::SeqCst) {
+            self.sync();
+            let record = match self.queue.peek(POLL_INTERVAL) {
+                Ok(Some(record)) => record,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!("Cannot read queue of {}, {}", self.name, err);
+                    self.sleep(backoff);
+                    continue;
+                }
+            };
+            let result = match decode_record(record.as_slice()) {
+                Ok((tenant, body)) => self.send(tenant.as_deref(), body),
+                Err(err) => {
+                    error!("Drop corrupt record in queue of {}, {}", self.name, err);
+                    "dropped"
+                }
+            };
+            FORWARD_REQUESTS
+                .with_label_values(&[self.name.as_str(), result])
+                .inc();
+            if result == "retried" {
+                self.sleep(backoff);
+                backoff = std::cmp::min(backoff * 2, self.max_backoff);
+                continue;
+            }
+            backoff = MIN_BACKOFF;
+            if let Err(err) = self.queue.ack() {
+                error!("Cannot ack queue of {}, {}", self.name, err);
+            }
+        }
+    }
+
+    /// Post a request to the endpoint, return the result label of `FORWARD_REQUESTS`
+    fn send(&self, tenant: Option<&str>, body: &[u8]) -> &'static str {
+        match self.client.send(tenant, body) {
+            Ok(status) if status / 100 == 2 => "success",
+            Ok(status) if status == 429 || status >= 500 => {
+                warn!(
+                    "Remote write to {} responded {}, retry later",
+                    self.name, status
+                );
+                "retried"
+            }
+            Ok(status) => {
+                warn!(
+                    "Remote write to {} rejected with {}, drop it",
+                    self.name, status
+                );
+                "dropped"
+            }
+            Err(err) => {
+                warn!("Cannot remote write to {}, {}, retry later", self.name, err);
+                "retried"
+            }
+        }
+    }
+
+    /// Sync records pushed since the last time, so that requests share fsyncs off their threads
+    fn sync(&self) {
+        if let Err(err) = self.queue.sync() {
+            error!("Cannot sync queue of {}, {}", self.name, err);
+        }
+    }
+
+    /// Sleep for `duration` unless stopped in the meantime, the queue is still synced meanwhile
+    fn sleep(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while !self.stopped.load(Ordering::SeqCst) && Instant::now() < deadline {
+            thread::sleep(std::cmp::min(POLL_INTERVAL, deadline - Instant::now()));
+            self.sync();
+        }
+    }
+}
+
+/// Record is the length of tenant id in one byte, the tenant id and the request body
+fn encode_record(tenant: Option<&str>, body: &[u8]) -> Vec<u8> {
+    let tenant = tenant.unwrap_or("");
+    let mut record = Vec::with_capacity(1 + tenant.len() + body.len());
+    record.push(tenant.len() as u8);
+    record.extend_from_slice(tenant.as_bytes());
+    record.extend_from_slice(body);
+    record
+}
+
+/// Split a record into tenant id and request body, fail if the record is shorter than its header
+fn decode_record(record: &[u8]) -> Result<(Option<String>, &[u8])> {
+    let len = match record.first() {
+        Some(len) => *len as usize,
+        None => return Err(MonolithErr::IoError("Empty record in queue".to_string())),
+    };
+    if record.len() < 1 + len {
+        return Err(MonolithErr::IoError(format!(
+            "Record of {} bytes is shorter than tenant id of {} bytes",
+            record.len(),
+            len
+        )));
+    }
+    let tenant = String::from_utf8_lossy(&record[1..=len]).to_string();
+    let tenant = if tenant.is_empty() {
+        None
+    } else {
+        Some(tenant)
+    };
+    Ok((tenant, &record[1 + len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::forward::{decode_record, encode_record, RemoteWriteConfig};
+    use crate::Result;
+
+    #[test]
+    fn test_record() -> Result<()> {
+        let record = encode_record(Some("team-a"), b"body");
+        assert_eq!(
+            decode_record(record.as_slice())?,
+            (Some("team-a".to_string()), &b"body"[..])
+        );
+        let record = encode_record(None, b"body");
+        assert_eq!(decode_record(record.as_slice())?, (None, &b"body"[..]));
+        let record = encode_record(Some("team-a"), b"");
+        assert_eq!(
+            decode_record(record.as_slice())?,
+            (Some("team-a".to_string()), &b""[..])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt_record() {
+        assert!(decode_record(&[]).is_err());
+        // tenant id is cut off
+        let record = encode_record(Some("team-a"), b"");
+        assert!(decode_record(&record[..4]).is_err());
+        assert!(decode_record(&[1]).is_err());
+    }
+
+    #[test]
+    fn test_config() -> Result<()> {
+        let configs: Vec<RemoteWriteConfig> = serde_yaml::from_str(
+            r#"
+- url: http://central:9001/write
+  shards: 2
+  max_queue_size: 1024
+- url: https://store.example.com/api/v1/push
+  name: store
+"#,
+        )?;
+        assert_eq!(configs[0].name()?, "central:9001");
+        assert_eq!(configs[0].shards, 2);
+        assert_eq!(configs[0].max_queue_size, 1024);
+        assert_eq!(configs[1].name()?, "store");
+        assert_eq!(configs[1].shards, 1);
+        assert_eq!(configs[1].timeout, 30);
+        assert_eq!(configs[1].max_queue_size, 1024 * 1024 * 1024);
+        Ok(())
+    }
+}
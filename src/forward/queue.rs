@@ -0,0 +1,346 @@
+use crate::{MonolithErr, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// A new segment is started once the current one is larger than this
+const MAX_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+const SEGMENT_SUFFIX: &str = ".seg";
+const CURSOR_FILENAME: &str = "cursor";
+/// Length and CRC32 of each record, in big endian
+const RECORD_HEADER_SIZE: u64 = 8;
+
+///
+/// A FIFO queue of records on disk, which survives restarts.
+///
+/// Records are appended to segment files, and the position of the first record not yet acked is
+/// kept in the cursor file. Segments are removed once all of their records are acked. Segments
+/// written before restart are never appended again, so a record torn by a crash can only be at the
+/// end of an old segment, where it is skipped.
+///
+/// `push` only writes records into the page cache, they are synced to disk by `sync`, so that
+/// records pushed in the meantime share one fsync. Records are dropped once the unacked records
+/// would take more than `max_size` bytes.
+///
+/// ```text
+/// | len(u32) | crc32(u32) | data | len(u32) | crc32(u32) | data | ...
+/// ```
+pub struct DiskQueue {
+    dir: PathBuf,
+    max_size: u64,
+    state: Mutex<QueueState>,
+    pushed: Condvar,
+}
+
+struct QueueState {
+    write_file: File,
+    write_seq: u64,
+    write_size: u64,
+    /// Whether records are written since the last sync
+    dirty: bool,
+    /// Size of unacked records, including their headers
+    size: u64,
+    read_seq: u64,
+    read_offset: u64,
+    /// Size of the record returned by `peek`, which is skipped by `ack`
+    peeked: Option<u64>,
+}
+
+impl DiskQueue {
+    pub fn open(dir: &Path, max_size: u64) -> Result<DiskQueue> {
+        fs::create_dir_all(dir)?;
+        let segments = list_segments(dir)?;
+        let (mut read_seq, mut read_offset) = read_cursor(dir)?;
+        if !segments.contains(&read_seq) {
+            read_seq = segments.first().cloned().unwrap_or(0);
+            read_offset = 0;
+        }
+        for seq in segments.iter().filter(|seq| **seq < read_seq) {
+            fs::remove_file(segment_path(dir, *seq))?;
+        }
+        let write_seq = segments.last().map(|seq| seq + 1).unwrap_or(0);
+        if segments.is_empty() {
+            read_seq = write_seq;
+        }
+        let mut size = 0;
+        for seq in segments.iter().filter(|seq| **seq >= read_seq) {
+            size += fs::metadata(segment_path(dir, *seq))?.len();
+        }
+        Ok(DiskQueue {
+            dir: dir.to_path_buf(),
+            max_size,
+            state: Mutex::new(QueueState {
+                write_file: create_segment(dir, write_seq)?,
+                write_seq,
+                write_size: 0,
+                dirty: false,
+                size: size.saturating_sub(read_offset),
+                read_seq,
+                read_offset,
+                peeked: None,
+            }),
+            pushed: Condvar::new(),
+        })
+    }
+
+    /// Append `data` to the queue, return false if it's dropped because the queue is full
+    pub fn push(&self, data: &[u8]) -> Result<bool> {
+        let mut state = self.state.lock().unwrap();
+        if state.size + RECORD_HEADER_SIZE + data.len() as u64 > self.max_size {
+            return Ok(false);
+        }
+        if state.write_size >= MAX_SEGMENT_SIZE {
+            // the segment is not written anymore, sync it now instead of tracking it
+            if state.dirty {
+                state.write_file.sync_data()?;
+                state.dirty = false;
+            }
+            state.write_seq += 1;
+            state.write_file = create_segment(&self.dir, state.write_seq)?;
+            state.write_size = 0;
+        }
+        let mut record = Vec::with_capacity(data.len() + RECORD_HEADER_SIZE as usize);
+        record.extend_from_slice(&(data.len() as u32).to_be_bytes()[..]);
+        record.extend_from_slice(&crc::crc32::checksum_ieee(data).to_be_bytes()[..]);
+        record.extend_from_slice(data);
+        state.write_file.write_all(record.as_slice())?;
+        state.write_size += record.len() as u64;
+        state.size += record.len() as u64;
+        state.dirty = true;
+        self.pushed.notify_all();
+        Ok(true)
+    }
+
+    /// Sync records pushed since the last sync to disk, pushes are not blocked during fsync
+    pub fn sync(&self) -> Result<()> {
+        let file = {
+            let mut state = self.state.lock().unwrap();
+            if !state.dirty {
+                return Ok(());
+            }
+            state.dirty = false;
+            state.write_file.try_clone()?
+        };
+        if let Err(err) = file.sync_data() {
+            self.state.lock().unwrap().dirty = true;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    ///
+    /// Return the first record not yet acked, wait up to `timeout` if the queue is empty.
+    ///
+    /// The same record is returned until `ack` is called.
+    pub fn peek(&self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        if state.is_empty() {
+            state = self.pushed.wait_timeout(state, timeout).unwrap().0;
+        }
+        while !state.is_empty() {
+            if state.read_seq == state.write_seq {
+                let (offset, seq) = (state.read_offset, state.read_seq);
+                let data = read_record(&segment_path(&self.dir, seq), offset)?
+                    .ok_or_else(|| MonolithErr::IoError(format!("Broken queue segment {}", seq)))?;
+                state.peeked = Some(RECORD_HEADER_SIZE + data.len() as u64);
+                return Ok(Some(data));
+            }
+            let path = segment_path(&self.dir, state.read_seq);
+            match read_record(&path, state.read_offset) {
+                Ok(Some(data)) => {
+                    state.peeked = Some(RECORD_HEADER_SIZE + data.len() as u64);
+                    return Ok(Some(data));
+                }
+                Ok(None) => {}
+                Err(err) => warn!("Skip the rest of queue segment {}, {}", path.display(), err),
+            }
+            // old segment is finished
+            let rest = fs::metadata(&path)?.len().saturating_sub(state.read_offset);
+            state.size = state.size.saturating_sub(rest);
+            fs::remove_file(&path)?;
+            state.read_seq += 1;
+            state.read_offset = 0;
+            write_cursor(&self.dir, state.read_seq, state.read_offset)?;
+        }
+        Ok(None)
+    }
+
+    /// Remove the record returned by the last `peek`
+    pub fn ack(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(size) = state.peeked.take() {
+            state.read_offset += size;
+            state.size = state.size.saturating_sub(size);
+            write_cursor(&self.dir, state.read_seq, state.read_offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl QueueState {
+    fn is_empty(&self) -> bool {
+        self.read_seq == self.write_seq && self.read_offset >= self.write_size
+    }
+}
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}{}", seq, SEGMENT_SUFFIX))
+}
+
+fn create_segment(dir: &Path, seq: u64) -> Result<File> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment_path(dir, seq))?)
+}
+
+/// Sequence numbers of segments in dir, in ascending order
+fn list_segments(dir: &Path) -> Result<Vec<u64>> {
+    let mut res = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if let Some(seq) = name.strip_suffix(SEGMENT_SUFFIX) {
+            if let Ok(seq) = seq.parse::<u64>() {
+                res.push(seq);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// Read the record at `offset`, return `None` at the end of segment
+fn read_record(path: &Path, offset: u64) -> Result<Option<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    if offset >= file.metadata()?.len() {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+    file.read_exact(&mut header)?;
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[..4]);
+    let mut crc = [0u8; 4];
+    crc.copy_from_slice(&header[4..]);
+    let mut data = vec![0u8; u32::from_be_bytes(len) as usize];
+    file.read_exact(data.as_mut_slice())?;
+    if crc::crc32::checksum_ieee(data.as_slice()) != u32::from_be_bytes(crc) {
+        return Err(MonolithErr::IoError(format!(
+            "Checksum mismatch at offset {}",
+            offset
+        )));
+    }
+    Ok(Some(data))
+}
+
+fn read_cursor(dir: &Path) -> Result<(u64, u64)> {
+    let content = match fs::read_to_string(dir.join(CURSOR_FILENAME)) {
+        Ok(content) => content,
+        Err(_) => return Ok((0, 0)),
+    };
+    let mut parts = content.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some(seq), Some(offset)) => Ok((seq.parse()?, offset.parse()?)),
+        _ => Err(MonolithErr::ParseErr),
+    }
+}
+
+/// Write cursor to a temp file then rename it, so that the cursor is never half written
+fn write_cursor(dir: &Path, seq: u64, offset: u64) -> Result<()> {
+    let tmp = dir.join(format!("{}.tmp", CURSOR_FILENAME));
+    fs::write(&tmp, format!("{} {}", seq, offset))?;
+    fs::rename(&tmp, dir.join(CURSOR_FILENAME))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::forward::DiskQueue;
+    use crate::Result;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+    const MAX_SIZE: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_push_peek_ack() -> Result<()> {
+        let dir = TempDir::new()?;
+        let queue = DiskQueue::open(dir.path(), MAX_SIZE)?;
+        assert_eq!(queue.peek(TIMEOUT)?, None);
+        queue.push(b"a")?;
+        queue.push(b"bc")?;
+        assert_eq!(queue.peek(TIMEOUT)?, Some(b"a".to_vec()));
+        // not acked yet
+        assert_eq!(queue.peek(TIMEOUT)?, Some(b"a".to_vec()));
+        queue.ack()?;
+        assert_eq!(queue.peek(TIMEOUT)?, Some(b"bc".to_vec()));
+        queue.ack()?;
+        assert_eq!(queue.peek(TIMEOUT)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen() -> Result<()> {
+        let dir = TempDir::new()?;
+        {
+            let queue = DiskQueue::open(dir.path(), MAX_SIZE)?;
+            queue.push(b"a")?;
+            queue.push(b"b")?;
+            queue.sync()?;
+            queue.peek(TIMEOUT)?;
+            queue.ack()?;
+        }
+        let queue = DiskQueue::open(dir.path(), MAX_SIZE)?;
+        queue.push(b"c")?;
+        assert_eq!(queue.peek(TIMEOUT)?, Some(b"b".to_vec()));
+        queue.ack()?;
+        assert_eq!(queue.peek(TIMEOUT)?, Some(b"c".to_vec()));
+        queue.ack()?;
+        assert_eq!(queue.peek(TIMEOUT)?, None);
+
+        // finished segments are removed, only the one for writing is left
+        drop(queue);
+        let queue = DiskQueue::open(dir.path(), MAX_SIZE)?;
+        assert_eq!(queue.peek(TIMEOUT)?, None);
+        let segments = std::fs::read_dir(dir.path())?
+            .filter(|e| {
+                e.as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .ends_with(".seg")
+            })
+            .count();
+        assert_eq!(segments, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_size() -> Result<()> {
+        let dir = TempDir::new()?;
+        // room for two records of 1 byte with their headers
+        let queue = DiskQueue::open(dir.path(), 18)?;
+        assert!(queue.push(b"a")?);
+        assert!(queue.push(b"b")?);
+        assert!(!queue.push(b"c")?);
+        queue.sync()?;
+        assert_eq!(queue.peek(TIMEOUT)?, Some(b"a".to_vec()));
+        queue.ack()?;
+        assert!(queue.push(b"d")?);
+        drop(queue);
+
+        // size of unacked records is restored after reopen
+        let queue = DiskQueue::open(dir.path(), 18)?;
+        assert!(!queue.push(b"e")?);
+        assert_eq!(queue.peek(TIMEOUT)?, Some(b"b".to_vec()));
+        queue.ack()?;
+        assert!(queue.push(b"e")?);
+        assert_eq!(queue.peek(TIMEOUT)?, Some(b"d".to_vec()));
+        queue.ack()?;
+        assert_eq!(queue.peek(TIMEOUT)?, Some(b"e".to_vec()));
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+use crate::common::label::{Label, Labels};
+use crate::{MonolithErr, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelabelAction {
+    /// Set `target_label` to `replacement` if `regex` matches the source value
+    Replace,
+    /// Drop series whose source value doesn't match `regex`
+    Keep,
+    /// Drop series whose source value matches `regex`
+    Drop,
+    /// Remove labels whose name matches `regex`
+    LabelDrop,
+    /// Remove labels whose name doesn't match `regex`
+    LabelKeep,
+}
+
+impl Default for RelabelAction {
+    fn default() -> Self {
+        RelabelAction::Replace
+    }
+}
+
+///
+/// A relabeling rule, with the same fields and defaults as `write_relabel_configs` of Prometheus.
+///
+/// The source value is the values of `source_labels` joined by `separator`, and `regex` must match
+/// the whole value.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RelabelConfig {
+    #[serde(default)]
+    pub source_labels: Vec<String>,
+    #[serde(default = "default_separator")]
+    pub separator: String,
+    #[serde(default = "default_regex")]
+    pub regex: String,
+    #[serde(default)]
+    pub target_label: Option<String>,
+    #[serde(default = "default_replacement")]
+    pub replacement: String,
+    #[serde(default)]
+    pub action: RelabelAction,
+}
+
+fn default_separator() -> String {
+    ";".to_string()
+}
+
+fn default_regex() -> String {
+    "(.*)".to_string()
+}
+
+fn default_replacement() -> String {
+    "$1".to_string()
+}
+
+/// Apply relabeling rules in order
+pub struct Relabeler {
+    rules: Vec<(RelabelConfig, Regex)>,
+}
+
+impl Relabeler {
+    pub fn new(configs: &[RelabelConfig]) -> Result<Relabeler> {
+        let mut rules = Vec::new();
+        for config in configs {
+            let regex = Regex::new(format!("^(?:{})$", config.regex).as_str()).map_err(|e| {
+                MonolithErr::InvalidMatcherErr(format!("invalid relabel regex, {}", e))
+            })?;
+            if config.action == RelabelAction::Replace && config.target_label.is_none() {
+                return Err(MonolithErr::InvalidMatcherErr(
+                    "target_label is required by replace action".to_string(),
+                ));
+            }
+            rules.push((config.clone(), regex));
+        }
+        Ok(Relabeler { rules })
+    }
+
+    /// Relabel `labels`, return `None` if the series is dropped
+    pub fn process(&self, labels: &Labels) -> Option<Labels> {
+        if self.rules.is_empty() {
+            return Some(labels.clone());
+        }
+        let mut map = labels
+            .vec()
+            .iter()
+            .map(|l| (l.key().clone(), l.value().clone()))
+            .collect::<BTreeMap<String, String>>();
+        for (config, regex) in self.rules.iter() {
+            let value = config
+                .source_labels
+                .iter()
+                .map(|name| map.get(name).map(|v| v.as_str()).unwrap_or(""))
+                .collect::<Vec<&str>>()
+                .join(config.separator.as_str());
+            match config.action {
+                RelabelAction::Keep if !regex.is_match(value.as_str()) => return None,
+                RelabelAction::Drop if regex.is_match(value.as_str()) => return None,
+                RelabelAction::Replace => {
+                    if let Some(captures) = regex.captures(value.as_str()) {
+                        let mut replaced = String::new();
+                        captures.expand(config.replacement.as_str(), &mut replaced);
+                        // checked in `new`
+                        let target = config.target_label.clone().unwrap();
+                        if replaced.is_empty() {
+                            map.remove(&target);
+                        } else {
+                            map.insert(target, replaced);
+                        }
+                    }
+                }
+                RelabelAction::LabelDrop => map.retain(|name, _| !regex.is_match(name)),
+                RelabelAction::LabelKeep => map.retain(|name, _| regex.is_match(name)),
+                _ => {}
+            }
+        }
+        if map.is_empty() {
+            return None;
+        }
+        Some(Labels::from_vec(
+            map.into_iter()
+                .map(|(key, value)| Label::new(key, value))
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::{Label, Labels};
+    use crate::forward::{RelabelConfig, Relabeler};
+    use crate::Result;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        Labels::from_vec(
+            pairs
+                .iter()
+                .map(|(k, v)| Label::from_key_value(k, v))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_relabel() -> Result<()> {
+        let configs: Vec<RelabelConfig> = serde_yaml::from_str(
+            r#"
+- source_labels: [__name__]
+  regex: "go_.*"
+  action: drop
+- source_labels: [job, instance]
+  regex: "(.*);(.*):.*"
+  target_label: node
+  replacement: "$1-$2"
+- regex: instance
+  action: labeldrop
+"#,
+        )?;
+        let relabeler = Relabeler::new(configs.as_slice())?;
+        assert!(relabeler
+            .process(&labels(&[("__name__", "go_goroutines")]))
+            .is_none());
+        let res = relabeler.process(&labels(&[
+            ("__name__", "up"),
+            ("instance", "host:9100"),
+            ("job", "node"),
+        ]));
+        let expected = labels(&[("__name__", "up"), ("job", "node"), ("node", "node-host")]);
+        assert_eq!(res.unwrap().to_string(), expected.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn test_relabel_keep() -> Result<()> {
+        let configs: Vec<RelabelConfig> = serde_yaml::from_str(
+            r#"
+- source_labels: [__name__]
+  regex: "up|node_.*"
+  action: keep
+"#,
+        )?;
+        let relabeler = Relabeler::new(configs.as_slice())?;
+        assert!(relabeler.process(&labels(&[("__name__", "up")])).is_some());
+        assert!(relabeler
+            .process(&labels(&[("__name__", "upstream")]))
+            .is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace_without_target() -> Result<()> {
+        let configs: Vec<RelabelConfig> = serde_yaml::from_str("- source_labels: [job]\n")?;
+        assert!(Relabeler::new(configs.as_slice()).is_err());
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
-use crate::common::label::Labels;
+use crate::common::label::{Label, LabelMatcher, Labels, MatcherType};
 
 use crate::common::time_series::TimeSeriesId;
+use crate::common::utils::{
+    intersect_time_series_id_vec, subtract_time_series_id_vec, union_time_series_id_vec,
+};
 use crate::{HasTypeName, Result};
 
 ///
@@ -20,6 +23,20 @@ pub trait Indexer: Sized + HasTypeName {
     /// Note that the result time series may contains other labels
     fn get_series_id_contains_labels(&self, labels: Labels) -> Result<Vec<TimeSeriesId>>;
 
+    /// Get all time series and their meta data that satisfy __all__ matchers
+    ///
+    /// Return empty result if no matcher provided.
+    fn get_series_metadata_by_matchers(
+        &self,
+        matchers: &[LabelMatcher],
+    ) -> Result<Vec<(TimeSeriesId, Labels)>>;
+
+    /// Get names of all labels in indexer, sorted in alphabet order
+    fn get_label_names(&self) -> Result<Vec<String>>;
+
+    /// Get all values of label `name`, sorted in alphabet order
+    fn get_label_values(&self, name: &str) -> Result<Vec<String>>;
+
     /// Get time series that match exactly with the labels
     fn get_series_id_by_labels(&self, labels: Labels) -> Result<Option<TimeSeriesId>>;
 
@@ -31,6 +48,106 @@ pub trait Indexer: Sized + HasTypeName {
     /// 2. mapping form label set to time series id, used to find target series id by complete label set
     /// 3. mapping from time series id to label set, used to get all meta data from time series id.
     fn create_index(&self, labels: Labels, time_series_id: TimeSeriesId) -> Result<()>;
+
+    /// Remove all three kinds of mapping of the time series, the opposite of `create_index`.
+    ///
+    /// Label whose posting list becomes empty is removed as well. Removing a time series that is not
+    /// found is not an error.
+    fn delete_index(&self, time_series_id: TimeSeriesId) -> Result<()>;
+
+    /// Flush buffered writes to disk, indexer that writes through does nothing
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Remove all data of the indexer when its chunk expires, indexer in chunk dir does nothing
+    /// because the dir is removed
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Postings(the ascending list of time series id) lookups that key-value based indexer provides.
+///
+/// Used to implement `LabelMatcher` based search in one place.
+pub(crate) trait PostingsReader {
+    /// Get ids of all time series in indexer
+    fn all_postings(&self) -> Result<Vec<TimeSeriesId>>;
+
+    /// Get ids of time series that has `label`
+    fn label_postings(&self, label: &Label) -> Result<Vec<TimeSeriesId>>;
+
+    /// Get all values of label `name`, along with ids of time series that has the value
+    fn label_value_postings(&self, name: &str) -> Result<Vec<(String, Vec<TimeSeriesId>)>>;
+}
+
+/// Get ids of time series that satisfy all matchers, follows the same algorithm as Prometheus.
+///
+/// Matcher that matches empty value(like `foo!="bar"` or `foo=""`) cannot be used to select series directly
+/// because series without the label also satisfy it. Instead, we remove the series that has a value
+/// not matching it from the result of other matchers.
+pub(crate) fn postings_for_matchers<P: PostingsReader>(
+    reader: &P,
+    matchers: &[LabelMatcher],
+) -> Result<Vec<TimeSeriesId>> {
+    if matchers.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut intersections = Vec::new();
+    let mut subtractions = Vec::new();
+    if matchers.iter().all(|m| m.matches_empty()) {
+        // nothing to subtract from, start with all series
+        intersections.push(reader.all_postings()?);
+    }
+    for matcher in matchers {
+        if matcher.matches_empty() {
+            subtractions.push(postings_for_unmatched(reader, matcher)?);
+        } else {
+            intersections.push(postings_for_matched(reader, matcher)?);
+        }
+    }
+
+    let mut res = intersect_time_series_id_vec(intersections)?;
+    for ids in subtractions {
+        res = subtract_time_series_id_vec(res, ids.as_slice());
+    }
+    Ok(res)
+}
+
+/// Ids of series that has label `matcher.name` and the value matches
+fn postings_for_matched<P: PostingsReader>(
+    reader: &P,
+    matcher: &LabelMatcher,
+) -> Result<Vec<TimeSeriesId>> {
+    if matcher.matcher_type() == MatcherType::Equal {
+        return reader.label_postings(&Label::from_key_value(matcher.name(), matcher.value()));
+    }
+    Ok(union_time_series_id_vec(
+        reader
+            .label_value_postings(matcher.name())?
+            .into_iter()
+            .filter(|(value, _)| matcher.matches(value))
+            .map(|(_, ids)| ids)
+            .collect(),
+    ))
+}
+
+/// Ids of series that has label `matcher.name` but the value doesn't match
+fn postings_for_unmatched<P: PostingsReader>(
+    reader: &P,
+    matcher: &LabelMatcher,
+) -> Result<Vec<TimeSeriesId>> {
+    if matcher.matcher_type() == MatcherType::NotEqual {
+        return reader.label_postings(&Label::from_key_value(matcher.name(), matcher.value()));
+    }
+    Ok(union_time_series_id_vec(
+        reader
+            .label_value_postings(matcher.name())?
+            .into_iter()
+            .filter(|(value, _)| !matcher.matches(value))
+            .map(|(_, ids)| ids)
+            .collect(),
+    ))
 }
 
 #[cfg(test)]
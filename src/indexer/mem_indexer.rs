@@ -0,0 +1,266 @@
+use crate::common::label::{Label, LabelMatcher, Labels};
+use crate::common::time_series::TimeSeriesId;
+use crate::indexer::common::{postings_for_matchers, PostingsReader};
+use crate::indexer::Indexer;
+use crate::utils::intersect_time_series_id_vec;
+use crate::{HasTypeName, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+///
+/// In-memory indexer of the current chunk, whose series are indexed again in the indexer of db when
+/// the chunk is swapped out.
+///
+/// Keeps the same three kinds of mapping as `SledIndexer`, with posting lists sorted by label name
+/// and value so that label names and values are listed in alphabet order.
+#[derive(Default)]
+pub struct MemIndexer {
+    index: RwLock<MemIndex>,
+}
+
+#[derive(Default)]
+struct MemIndex {
+    /// Sorted label set of each series
+    series: HashMap<TimeSeriesId, Labels>,
+    /// Series of each sorted label set
+    ids: HashMap<Labels, TimeSeriesId>,
+    /// Ascending ids of series that has the label, by label name and value
+    postings: BTreeMap<String, BTreeMap<String, Vec<TimeSeriesId>>>,
+}
+
+impl MemIndexer {
+    pub fn new() -> MemIndexer {
+        MemIndexer::default()
+    }
+
+    fn get_series_metadata_by_ids(&self, ids: Vec<TimeSeriesId>) -> Vec<(TimeSeriesId, Labels)> {
+        let index = self.index.read().unwrap();
+        ids.into_iter()
+            .filter_map(|id| index.series.get(&id).map(|labels| (id, labels.clone())))
+            .collect()
+    }
+
+    fn sorted(mut labels: Labels) -> Labels {
+        labels.sort();
+        labels
+    }
+}
+
+impl PostingsReader for MemIndexer {
+    fn all_postings(&self) -> Result<Vec<TimeSeriesId>> {
+        let mut res = self
+            .index
+            .read()
+            .unwrap()
+            .series
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        res.sort();
+        Ok(res)
+    }
+
+    fn label_postings(&self, label: &Label) -> Result<Vec<TimeSeriesId>> {
+        Ok(self
+            .index
+            .read()
+            .unwrap()
+            .postings
+            .get(label.key())
+            .and_then(|values| values.get(label.value()))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    fn label_value_postings(&self, name: &str) -> Result<Vec<(String, Vec<TimeSeriesId>)>> {
+        Ok(self
+            .index
+            .read()
+            .unwrap()
+            .postings
+            .get(name)
+            .map(|values| {
+                values
+                    .iter()
+                    .map(|(value, ids)| (value.clone(), ids.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+impl HasTypeName for MemIndexer {
+    fn get_type_name() -> &'static str {
+        "MemIndexer"
+    }
+}
+
+impl Indexer for MemIndexer {
+    fn get_series_metadata_contains_labels(
+        &self,
+        labels: Labels,
+    ) -> Result<Vec<(TimeSeriesId, Labels)>> {
+        let ids = self.get_series_id_contains_labels(labels)?;
+        Ok(self.get_series_metadata_by_ids(ids))
+    }
+
+    fn get_series_id_contains_labels(&self, labels: Labels) -> Result<Vec<TimeSeriesId>> {
+        let mut postings = Vec::new();
+        for label in labels.vec() {
+            postings.push(self.label_postings(label)?);
+        }
+        intersect_time_series_id_vec(postings)
+    }
+
+    fn get_series_metadata_by_matchers(
+        &self,
+        matchers: &[LabelMatcher],
+    ) -> Result<Vec<(TimeSeriesId, Labels)>> {
+        let ids = postings_for_matchers(self, matchers)?;
+        Ok(self.get_series_metadata_by_ids(ids))
+    }
+
+    fn get_label_names(&self) -> Result<Vec<String>> {
+        Ok(self
+            .index
+            .read()
+            .unwrap()
+            .postings
+            .keys()
+            .cloned()
+            .collect())
+    }
+
+    fn get_label_values(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self
+            .index
+            .read()
+            .unwrap()
+            .postings
+            .get(name)
+            .map(|values| values.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn get_series_id_by_labels(&self, labels: Labels) -> Result<Option<TimeSeriesId>> {
+        let labels = MemIndexer::sorted(labels);
+        Ok(self.index.read().unwrap().ids.get(&labels).cloned())
+    }
+
+    fn create_index(&self, labels: Labels, time_series_id: TimeSeriesId) -> Result<()> {
+        let labels = MemIndexer::sorted(labels);
+        let mut index = self.index.write().unwrap();
+        for label in labels.vec() {
+            index
+                .postings
+                .entry(label.key().clone())
+                .or_default()
+                .entry(label.value().clone())
+                .or_default()
+                .push(time_series_id);
+        }
+        index.ids.insert(labels.clone(), time_series_id);
+        index.series.insert(time_series_id, labels);
+        Ok(())
+    }
+
+    fn delete_index(&self, time_series_id: TimeSeriesId) -> Result<()> {
+        let mut index = self.index.write().unwrap();
+        let labels = match index.series.remove(&time_series_id) {
+            Some(labels) => labels,
+            None => return Ok(()),
+        };
+        index.ids.remove(&labels);
+        for label in labels.vec() {
+            let values = match index.postings.get_mut(label.key()) {
+                Some(values) => values,
+                None => continue,
+            };
+            if let Some(ids) = values.get_mut(label.value()) {
+                ids.retain(|id| *id != time_series_id);
+                if ids.is_empty() {
+                    values.remove(label.value());
+                }
+            }
+            if values.is_empty() {
+                index.postings.remove(label.key());
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop all series, the chunk is not readable after it
+    fn destroy(&self) -> Result<()> {
+        *self.index.write().unwrap() = MemIndex::default();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::{Label, LabelMatcher, Labels, MatcherType};
+    use crate::indexer::{Indexer, MemIndexer};
+    use crate::Result;
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        Labels::from_vec(
+            pairs
+                .iter()
+                .map(|(k, v)| Label::from_key_value(k, v))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_create_index() -> Result<()> {
+        let indexer = MemIndexer::new();
+        indexer.create_index(labels(&[("__name__", "up"), ("job", "a")]), 1)?;
+        indexer.create_index(labels(&[("job", "b"), ("__name__", "up")]), 2)?;
+        indexer.create_index(labels(&[("__name__", "down"), ("job", "a")]), 3)?;
+
+        // label sets are matched regardless of label order
+        assert_eq!(
+            indexer.get_series_id_by_labels(labels(&[("__name__", "up"), ("job", "b")]))?,
+            Some(2)
+        );
+        assert_eq!(
+            indexer.get_series_id_by_labels(labels(&[("__name__", "up")]))?,
+            None
+        );
+        assert_eq!(
+            indexer.get_series_id_contains_labels(labels(&[("job", "a")]))?,
+            vec![1, 3]
+        );
+        assert_eq!(indexer.get_label_names()?, vec!["__name__", "job"]);
+        assert_eq!(indexer.get_label_values("__name__")?, vec!["down", "up"]);
+
+        let matchers = vec![
+            LabelMatcher::new(MatcherType::Equal, "__name__", "up")?,
+            LabelMatcher::new(MatcherType::NotEqual, "job", "a")?,
+        ];
+        let res = indexer.get_series_metadata_by_matchers(matchers.as_slice())?;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].0, 2);
+        assert!(res[0].1 == labels(&[("__name__", "up"), ("job", "b")]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_index() -> Result<()> {
+        let indexer = MemIndexer::new();
+        indexer.create_index(labels(&[("__name__", "up"), ("job", "a")]), 1)?;
+        indexer.create_index(labels(&[("__name__", "up"), ("job", "b")]), 2)?;
+        indexer.delete_index(1)?;
+        // deleting a missing series is not an error
+        indexer.delete_index(1)?;
+
+        assert_eq!(
+            indexer.get_series_id_by_labels(labels(&[("__name__", "up"), ("job", "a")]))?,
+            None
+        );
+        assert_eq!(indexer.get_label_values("job")?, vec!["b"]);
+        indexer.delete_index(2)?;
+        assert!(indexer.get_label_names()?.is_empty());
+        Ok(())
+    }
+}
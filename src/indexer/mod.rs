@@ -1,8 +1,10 @@
 mod common;
+mod mem_indexer;
 mod sled_indexer;
 mod tikv_indexer;
 
 pub use common::*;
+pub use mem_indexer::MemIndexer;
 pub use sled_indexer::SledIndexer;
 pub use sled_indexer::SledIndexerBuilder;
 pub use tikv_indexer::TiKvIndexer;
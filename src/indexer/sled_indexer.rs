@@ -1,6 +1,6 @@
 use sled::Db;
 
-use crate::common::label::{Label, Labels};
+use crate::common::label::{Label, LabelMatcher, Labels};
 
 use crate::common::time_series::TimeSeriesId;
 use crate::{Builder, HasTypeName, MonolithErr, Result};
@@ -9,6 +9,7 @@ use std::path::{Path, PathBuf};
 
 use crate::chunk::ChunkOpts;
 use crate::common::option::DbOpts;
+use crate::indexer::common::{postings_for_matchers, PostingsReader};
 use crate::indexer::Indexer;
 use crate::utils::intersect_time_series_id_vec;
 
@@ -58,6 +59,55 @@ impl SledIndexer {
             Ok(None)
         };
     }
+
+    fn get_series_metadata_by_ids(
+        &self,
+        ids: Vec<TimeSeriesId>,
+    ) -> Result<Vec<(TimeSeriesId, Labels)>> {
+        let mut res = Vec::new();
+        for time_series_id in ids {
+            let labels_str =
+                self.get(&KvIndexerProcessor::encode_time_series_id(time_series_id))?;
+            if labels_str.is_some() {
+                let labels = KvIndexerProcessor::decode_labels(labels_str.unwrap(), false)?;
+                res.push((time_series_id, labels))
+            }
+        }
+        Ok(res)
+    }
+}
+
+impl PostingsReader for SledIndexer {
+    fn all_postings(&self) -> Result<Vec<TimeSeriesId>> {
+        let mut res = Vec::new();
+        for item in self.storage.scan_prefix(ID_PREFIX) {
+            let (key, _) = item?;
+            let key_str = String::from_utf8(AsRef::<[u8]>::as_ref(&key).to_vec())?;
+            res.push(KvIndexerProcessor::decode_time_series_id(key_str)?);
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    fn label_postings(&self, label: &Label) -> Result<Vec<TimeSeriesId>> {
+        Ok(self.get_id(label)?.unwrap_or_default())
+    }
+
+    fn label_value_postings(&self, name: &str) -> Result<Vec<(String, Vec<TimeSeriesId>)>> {
+        let prefix = KvIndexerProcessor::encode_label_name(name);
+        let mut res = Vec::new();
+        for item in self.storage.scan_prefix(prefix.as_str()) {
+            let (key, val) = item?;
+            let key_str = String::from_utf8(AsRef::<[u8]>::as_ref(&key).to_vec())?;
+            let val_str = String::from_utf8(AsRef::<[u8]>::as_ref(&val).to_vec())?;
+            let mut ids = Vec::new();
+            for id in val_str.split(",") {
+                ids.push(id.parse::<TimeSeriesId>()?);
+            }
+            res.push((key_str[prefix.len()..].to_string(), ids));
+        }
+        Ok(res)
+    }
 }
 
 impl HasTypeName for SledIndexer {
@@ -72,18 +122,40 @@ impl Indexer for SledIndexer {
         labels: Labels,
     ) -> Result<Vec<(TimeSeriesId, Labels)>> {
         let ids = self.get_series_id_contains_labels(labels)?;
-        let mut res = Vec::new();
-        for time_series_id in ids {
-            let labels_str =
-                self.get(&KvIndexerProcessor::encode_time_series_id(time_series_id))?;
-            if labels_str.is_some() {
-                let labels = KvIndexerProcessor::decode_labels(labels_str.unwrap(), false)?;
-                res.push((time_series_id, labels))
+        self.get_series_metadata_by_ids(ids)
+    }
+
+    fn get_series_metadata_by_matchers(
+        &self,
+        matchers: &[LabelMatcher],
+    ) -> Result<Vec<(TimeSeriesId, Labels)>> {
+        let ids = postings_for_matchers(self, matchers)?;
+        self.get_series_metadata_by_ids(ids)
+    }
+
+    fn get_label_names(&self) -> Result<Vec<String>> {
+        let mut res: Vec<String> = Vec::new();
+        let prefix = KvIndexerProcessor::encode_label_prefix();
+        for item in self.storage.scan_prefix(prefix.as_str()) {
+            let (key, _) = item?;
+            let key_str = String::from_utf8(AsRef::<[u8]>::as_ref(&key).to_vec())?;
+            let name = KvIndexerProcessor::decode_label_name(key_str.as_str())?;
+            // keys are sorted, so the same names are next to each other
+            if res.last() != Some(&name) {
+                res.push(name);
             }
         }
         Ok(res)
     }
 
+    fn get_label_values(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self
+            .label_value_postings(name)?
+            .into_iter()
+            .map(|(value, _)| value)
+            .collect())
+    }
+
     fn get_series_id_contains_labels(&self, labels: Labels) -> Result<Vec<TimeSeriesId>> {
         let mut ts_vec = Vec::new();
         for label in labels.vec() {
@@ -145,6 +217,44 @@ impl Indexer for SledIndexer {
 
         Ok(())
     }
+
+    fn delete_index(&self, time_series_id: u64) -> Result<()> {
+        let tree = &self.storage;
+        let id_key = KvIndexerProcessor::encode_time_series_id(time_series_id);
+        let labels = match self.get(&id_key)? {
+            Some(labels_str) => KvIndexerProcessor::decode_labels(labels_str, false)?,
+            None => return Ok(()),
+        };
+
+        // from label to time series ids
+        for label in labels.vec() {
+            let key = KvIndexerProcessor::encode_label(label);
+            let ids = match self.get_id(label)? {
+                Some(ids) => ids,
+                None => continue,
+            };
+            let kept = ids
+                .into_iter()
+                .filter(|id| *id != time_series_id)
+                .map(|id| id.to_string())
+                .collect::<Vec<String>>();
+            if kept.is_empty() {
+                tree.del(&key)?;
+            } else {
+                tree.set(&key, kept.join(",").into_bytes())?;
+            }
+        }
+
+        tree.del(KvIndexerProcessor::encode_labels(&labels, true))?;
+        tree.del(id_key)?;
+        tree.flush()?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.storage.flush()?;
+        Ok(())
+    }
 }
 
 pub struct SledIndexerBuilder {}
@@ -176,6 +286,11 @@ impl Builder<SledIndexer> for SledIndexerBuilder {
     fn read_config(&self, _dir: &Path) -> Result<()> {
         Ok(())
     }
+
+    /// Chunks of tenants are in their own db dir, so no difference with other builders
+    fn tenant_builder(&self, _tenant: &str) -> Box<dyn Builder<SledIndexer> + Sync + Send> {
+        Box::new(SledIndexerBuilder::new())
+    }
 }
 
 impl SledIndexerBuilder {
@@ -186,7 +301,7 @@ impl SledIndexerBuilder {
 
 #[cfg(test)]
 mod tests {
-    use crate::common::label::{Label, Labels};
+    use crate::common::label::{Label, LabelMatcher, Labels, MatcherType};
     use crate::indexer::sled_indexer::{KvIndexerProcessor, SledIndexer};
     use crate::Result;
     use tempfile::TempDir;
@@ -238,6 +353,120 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_delete_index() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let indexer = SledIndexer::new(temp_dir.path())?;
+        let labels = Labels::from_vec(vec![
+            Label::from_key_value("test1", "test1value"),
+            Label::from_key_value("test2", "test1value"),
+        ]);
+        let another_labels = Labels::from_vec(vec![Label::from_key_value("test1", "test1value")]);
+        indexer.create_index(labels.clone(), 1)?;
+        indexer.create_index(another_labels.clone(), 2)?;
+
+        indexer.delete_index(1)?;
+        assert_eq!(
+            indexer.get(&"LRtest1=test1value".to_string())?.unwrap(),
+            "2"
+        );
+        assert!(indexer.get(&"LRtest2=test1value".to_string())?.is_none());
+        assert!(indexer.get(&"I1".to_string())?.is_none());
+        assert_eq!(indexer.get_series_id_by_labels(labels)?, None);
+        assert_eq!(indexer.get_series_id_by_labels(another_labels)?, Some(2));
+        assert_eq!(indexer.get_label_names()?, vec!["test1"]);
+        // deleting a missing series is fine
+        indexer.delete_index(3)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_series_metadata_by_matchers() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let indexer = SledIndexer::new(temp_dir.path())?;
+        let data = vec![
+            vec![("__name__", "up"), ("job", "api")],
+            vec![("__name__", "up"), ("job", "db"), ("env", "prod")],
+            vec![("__name__", "up"), ("job", "api-canary"), ("env", "dev")],
+        ];
+        for (idx, labels) in data.into_iter().enumerate() {
+            let labels = labels
+                .into_iter()
+                .map(|(k, v)| Label::from_key_value(k, v))
+                .collect();
+            indexer.create_index(Labels::from_vec(labels), idx as u64 + 1)?;
+        }
+
+        let query = |matchers: Vec<(MatcherType, &str, &str)>| -> Result<Vec<u64>> {
+            let mut label_matchers = Vec::new();
+            for (t, name, value) in matchers {
+                label_matchers.push(LabelMatcher::new(t, name, value)?);
+            }
+            Ok(indexer
+                .get_series_metadata_by_matchers(label_matchers.as_slice())?
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect())
+        };
+
+        assert_eq!(query(vec![(MatcherType::Equal, "job", "api")])?, vec![1]);
+        assert_eq!(
+            query(vec![
+                (MatcherType::Equal, "__name__", "up"),
+                (MatcherType::NotEqual, "job", "api")
+            ])?,
+            vec![2, 3]
+        );
+        assert_eq!(
+            query(vec![(MatcherType::RegexMatch, "job", "api.*")])?,
+            vec![1, 3]
+        );
+        assert_eq!(
+            query(vec![(MatcherType::RegexMatch, "job", "api")])?,
+            vec![1]
+        );
+        assert_eq!(
+            query(vec![
+                (MatcherType::Equal, "__name__", "up"),
+                (MatcherType::RegexNotMatch, "job", "api.*")
+            ])?,
+            vec![2]
+        );
+        // empty value matches series without the label
+        assert_eq!(
+            query(vec![
+                (MatcherType::Equal, "__name__", "up"),
+                (MatcherType::Equal, "env", "")
+            ])?,
+            vec![1]
+        );
+        assert_eq!(
+            query(vec![(MatcherType::NotEqual, "env", "prod")])?,
+            vec![1, 3]
+        );
+        assert_eq!(query(vec![(MatcherType::NotEqual, "env", "")])?, vec![2, 3]);
+        assert!(query(vec![])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_label_names_and_values() -> Result<()> {
+        let temp_dir = TempDir::new().unwrap();
+        let indexer = SledIndexer::new(temp_dir.path())?;
+        let mut labels = Labels::new();
+        labels.add(Label::from_key_value("job", "db"));
+        labels.add(Label::from_key_value("env", "prod"));
+        indexer.create_index(labels, 1)?;
+        let mut labels = Labels::new();
+        labels.add(Label::from_key_value("job", "api"));
+        indexer.create_index(labels, 2)?;
+
+        assert_eq!(indexer.get_label_names()?, vec!["env", "job"]);
+        assert_eq!(indexer.get_label_values("job")?, vec!["api", "db"]);
+        assert!(indexer.get_label_values("not_exist")?.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_decode_labels() -> Result<()> {
         let labels_str = "Lkey1=value1,key2=value2";
@@ -258,6 +487,25 @@ impl KvIndexerProcessor {
         format!("{}={}", label.key(), label.value())
     }
 
+    /// prefix of all keys that map single label to ids
+    pub fn encode_label_prefix() -> String {
+        LABEL_REVERSE_PREFIX.to_string()
+    }
+
+    /// encode label name as the prefix of all keys that has this label
+    pub fn encode_label_name(name: &str) -> String {
+        format!("{}{}=", LABEL_REVERSE_PREFIX, name)
+    }
+
+    /// decode label name from the key of single label
+    pub fn decode_label_name(key: &str) -> Result<String> {
+        let label_str = key
+            .get(LABEL_REVERSE_PREFIX.len()..)
+            .ok_or(MonolithErr::ParseErr)?;
+        let equal_idx = label_str.find("=").ok_or(MonolithErr::ParseErr)?;
+        Ok(label_str[..equal_idx].to_string())
+    }
+
     /// encode single label as key
     pub fn encode_label(label: &Label) -> String {
         format!(
@@ -318,6 +566,11 @@ impl KvIndexerProcessor {
         Ok(Labels::from_vec(res))
     }
 
+    /// prefix of all keys that map time series id to labels
+    pub fn encode_time_series_id_prefix() -> String {
+        ID_PREFIX.to_string()
+    }
+
     pub fn encode_time_series_id(id: TimeSeriesId) -> String {
         format!("{}{}", ID_PREFIX, id)
     }
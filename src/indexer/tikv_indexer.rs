@@ -1,9 +1,10 @@
 use crate::backend::tikv::TiKvRawBackend;
 use crate::chunk::ChunkOpts;
-use crate::common::label::Labels;
+use crate::common::label::{Label, LabelMatcher, Labels};
 use crate::common::option::DbOpts;
 use crate::common::time_series::TimeSeriesId;
 use crate::common::utils::intersect_time_series_id_vec;
+use crate::indexer::common::{postings_for_matchers, PostingsReader};
 use crate::indexer::sled_indexer::KvIndexerProcessor;
 use crate::indexer::Indexer;
 use crate::{Builder, HasTypeName, MonolithErr, Result, TiKvRawBackendSingleton};
@@ -23,15 +24,20 @@ impl TiKvIndexer {
         res.append(key);
         res
     }
-}
 
-impl Indexer for TiKvIndexer {
-    fn get_series_metadata_contains_labels(
+    fn decode_ids(val: &[u8]) -> Vec<TimeSeriesId> {
+        val.chunks(std::mem::size_of::<TimeSeriesId>())
+            .map(|raw| {
+                //todo: error handling for try into
+                TimeSeriesId::from_be_bytes(raw.try_into().unwrap())
+            })
+            .collect::<Vec<TimeSeriesId>>()
+    }
+
+    fn get_series_metadata_by_ids(
         &self,
-        labels: Labels,
+        ids: Vec<TimeSeriesId>,
     ) -> Result<Vec<(TimeSeriesId, Labels)>> {
-        let ids = self.get_series_id_contains_labels(labels.clone())?;
-
         //todo: refactor this.
         Ok(ids
             .iter()
@@ -66,6 +72,94 @@ impl Indexer for TiKvIndexer {
             .map(|(id, res)| (id, res.unwrap()))
             .collect())
     }
+}
+
+impl PostingsReader for TiKvIndexer {
+    fn all_postings(&self) -> Result<Vec<TimeSeriesId>> {
+        let prefix = self.add_indexer_id(
+            KvIndexerProcessor::encode_time_series_id_prefix()
+                .into_bytes()
+                .as_mut(),
+        );
+        let mut res = Vec::new();
+        for (key, _) in self.client.scan_prefix(prefix.clone())? {
+            let id_str = String::from_utf8(key[self.indexer_identifier.len()..].to_vec())?;
+            res.push(KvIndexerProcessor::decode_time_series_id(id_str)?);
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    fn label_postings(&self, label: &Label) -> Result<Vec<TimeSeriesId>> {
+        let key = self.add_indexer_id(
+            KvIndexerProcessor::encode_label(label)
+                .into_bytes()
+                .as_mut(),
+        );
+        Ok(self
+            .client
+            .get(key)?
+            .map(|val| Self::decode_ids(val.as_slice()))
+            .unwrap_or_default())
+    }
+
+    fn label_value_postings(&self, name: &str) -> Result<Vec<(String, Vec<TimeSeriesId>)>> {
+        let prefix = self.add_indexer_id(
+            KvIndexerProcessor::encode_label_name(name)
+                .into_bytes()
+                .as_mut(),
+        );
+        let mut res = Vec::new();
+        for (key, val) in self.client.scan_prefix(prefix.clone())? {
+            let value = String::from_utf8(key[prefix.len()..].to_vec())?;
+            res.push((value, Self::decode_ids(val.as_slice())));
+        }
+        Ok(res)
+    }
+}
+
+impl Indexer for TiKvIndexer {
+    fn get_series_metadata_contains_labels(
+        &self,
+        labels: Labels,
+    ) -> Result<Vec<(TimeSeriesId, Labels)>> {
+        let ids = self.get_series_id_contains_labels(labels.clone())?;
+        self.get_series_metadata_by_ids(ids)
+    }
+
+    fn get_series_metadata_by_matchers(
+        &self,
+        matchers: &[LabelMatcher],
+    ) -> Result<Vec<(TimeSeriesId, Labels)>> {
+        let ids = postings_for_matchers(self, matchers)?;
+        self.get_series_metadata_by_ids(ids)
+    }
+
+    fn get_label_names(&self) -> Result<Vec<String>> {
+        let prefix = self.add_indexer_id(
+            KvIndexerProcessor::encode_label_prefix()
+                .into_bytes()
+                .as_mut(),
+        );
+        let mut res: Vec<String> = Vec::new();
+        for (key, _) in self.client.scan_prefix(prefix)? {
+            let key_str = String::from_utf8(key[self.indexer_identifier.len()..].to_vec())?;
+            let name = KvIndexerProcessor::decode_label_name(key_str.as_str())?;
+            // keys are sorted, so the same names are next to each other
+            if res.last() != Some(&name) {
+                res.push(name);
+            }
+        }
+        Ok(res)
+    }
+
+    fn get_label_values(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self
+            .label_value_postings(name)?
+            .into_iter()
+            .map(|(value, _)| value)
+            .collect())
+    }
 
     fn get_series_id_contains_labels(&self, labels: Labels) -> Result<Vec<TimeSeriesId>> {
         // Get raw value from tikv
@@ -88,14 +182,7 @@ impl Indexer for TiKvIndexer {
         // convert into time series id
         let mut time_series_ids: Vec<Vec<TimeSeriesId>> = vec![];
         for val in query_res {
-            let ids = val
-                .chunks(std::mem::size_of::<TimeSeriesId>())
-                .map(|raw| {
-                    //todo: error handling for try into
-                    TimeSeriesId::from_be_bytes(raw.try_into().unwrap())
-                })
-                .collect::<Vec<TimeSeriesId>>();
-            time_series_ids.push(ids);
+            time_series_ids.push(Self::decode_ids(val.as_slice()));
         }
         Ok(intersect_time_series_id_vec(time_series_ids)?)
     }
@@ -163,6 +250,56 @@ impl Indexer for TiKvIndexer {
 
         Ok(())
     }
+
+    fn delete_index(&self, time_series_id: TimeSeriesId) -> Result<()> {
+        let id_key = self.add_indexer_id(
+            KvIndexerProcessor::encode_time_series_id(time_series_id)
+                .into_bytes()
+                .as_mut(),
+        );
+        let labels = match self.client.get(id_key.clone())? {
+            Some(raw) => KvIndexerProcessor::decode_labels(
+                String::from_utf8_lossy(raw.as_slice()).to_string(),
+                false,
+            )?,
+            None => return Ok(()),
+        };
+
+        // Remove from reverse search
+        for label in labels.vec() {
+            let key = self.add_indexer_id(
+                KvIndexerProcessor::encode_label(label)
+                    .into_bytes()
+                    .as_mut(),
+            );
+            let ids = match self.client.get(key.clone())? {
+                Some(val) => Self::decode_ids(val.as_slice()),
+                None => continue,
+            };
+            let kept = ids
+                .into_iter()
+                .filter(|id| *id != time_series_id)
+                .flat_map(|id| id.to_be_bytes().to_vec())
+                .collect::<Vec<u8>>();
+            if kept.is_empty() {
+                self.client.delete(key)?;
+            } else {
+                self.client.set(key, kept)?;
+            }
+        }
+
+        let key = KvIndexerProcessor::encode_labels(&labels, true);
+        self.client
+            .delete(self.add_indexer_id(key.into_bytes().as_mut()))?;
+        self.client.delete(id_key)?;
+        Ok(())
+    }
+
+    /// Delete all indexes and the mapping from chunk to components
+    fn destroy(&self) -> Result<()> {
+        self.client.delete_prefix(self.indexer_identifier.clone())?;
+        self.client.delete(self.chunk_identifier.clone())
+    }
 }
 
 impl HasTypeName for TiKvIndexer {
@@ -231,6 +368,12 @@ impl Builder<TiKvIndexer> for TiKvIndexerBuilder {
     fn read_config(&self, _dir: &Path) -> Result<()> {
         Ok(())
     }
+
+    fn tenant_builder(&self, tenant: &str) -> Box<dyn Builder<TiKvIndexer> + Sync + Send> {
+        Box::new(TiKvIndexerBuilder {
+            backend_builder: self.backend_builder.for_tenant(tenant),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -240,7 +383,7 @@ mod tests {
     use crate::common::test_utils::DummyTiKvBackend;
     use crate::indexer::sled_indexer::KvIndexerProcessor;
     use crate::indexer::{Indexer, TiKvIndexer};
-    use crate::label::{Label, Labels};
+    use crate::label::{Label, LabelMatcher, Labels, MatcherType};
     use crate::Result;
 
     /// Get test data for this unit test
@@ -389,4 +532,79 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_get_series_metadata_by_matchers() -> Result<()> {
+        let dummy_backend = DummyTiKvBackend::new();
+        let indexer = TiKvIndexer {
+            client: Box::new(dummy_backend.clone()),
+            chunk_identifier: "whatever".to_string().into_bytes(),
+            indexer_identifier: "indexer".to_string().into_bytes(),
+        };
+        indexer.create_index(get_data(0), 1u64)?;
+        indexer.create_index(get_data(1), 2u64)?;
+
+        let ids = |matchers: Vec<LabelMatcher>| -> Result<Vec<u64>> {
+            Ok(indexer
+                .get_series_metadata_by_matchers(matchers.as_slice())?
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect())
+        };
+
+        let not_equal = LabelMatcher::new(MatcherType::NotEqual, "key2", "value2")?;
+        assert_eq!(ids(vec![not_equal])?, vec![2]);
+        let regex = LabelMatcher::new(MatcherType::RegexMatch, "key2", "value.")?;
+        assert_eq!(ids(vec![regex])?, vec![1, 2]);
+        let absent = LabelMatcher::new(MatcherType::Equal, "key4", "")?;
+        let equal = LabelMatcher::new(MatcherType::Equal, "key2", "value1")?;
+        assert_eq!(ids(vec![absent, equal])?, vec![2]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_label_names_and_values() -> Result<()> {
+        let dummy_backend = DummyTiKvBackend::new();
+        let indexer = TiKvIndexer {
+            client: Box::new(dummy_backend.clone()),
+            chunk_identifier: "whatever".to_string().into_bytes(),
+            indexer_identifier: "indexer".to_string().into_bytes(),
+        };
+        indexer.create_index(get_data(0), 1u64)?;
+        indexer.create_index(get_data(1), 2u64)?;
+
+        assert_eq!(indexer.get_label_names()?, vec!["key1", "key2", "key3"]);
+        assert_eq!(indexer.get_label_values("key2")?, vec!["value1", "value2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_index() -> Result<()> {
+        let dummy_backend = DummyTiKvBackend::new();
+        let indexer = TiKvIndexer {
+            client: Box::new(dummy_backend.clone()),
+            chunk_identifier: "whatever".to_string().into_bytes(),
+            indexer_identifier: "indexer".to_string().into_bytes(),
+        };
+        indexer.create_index(get_data(0), 1u64)?;
+        indexer.create_index(get_data(1), 2u64)?;
+
+        indexer.delete_index(1)?;
+        assert_eq!(indexer.get_series_id_by_labels(get_data(0))?, None);
+        assert_eq!(indexer.get_series_id_by_labels(get_data(1))?, Some(2));
+        assert_eq!(
+            indexer.get_series_id_contains_labels(Labels::from_vec(vec![
+                Label::from_key_value("key1", "value1")
+            ]))?,
+            vec![2]
+        );
+        assert_eq!(indexer.get_label_values("key2")?, vec!["value1"]);
+
+        indexer.delete_index(2)?;
+        assert!(dummy_backend.scan_prefix(b"indexer".to_vec())?.is_empty());
+        // deleting a missing series is fine
+        indexer.delete_index(3)?;
+        Ok(())
+    }
 }
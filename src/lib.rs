@@ -5,14 +5,20 @@ use std::time::Duration;
 mod common;
 mod error;
 mod backend;
-mod wal;
 
 pub mod compaction;
 pub mod chunk;
 pub mod db;
+pub mod forward;
+pub mod promql;
+pub mod replica;
 pub mod server;
 pub mod indexer;
+pub mod limits;
+pub mod metrics;
 pub mod storage;
+pub mod tenant;
+pub mod wal;
 
 
 /// Generated proto definition
@@ -37,6 +43,31 @@ pub const READ_PATH: &str = "read_path";
 pub const WRITE_PATH: &str = "write_path";
 pub const WORKER_NUM: &str = "worker_num";
 pub const TIKV_CONFIG: &str = "tikv_config"; // tikv config file path
+pub const GRAPHITE_PORT: &str = "graphite_port";
+pub const GRAPHITE_PICKLE_PORT: &str = "graphite_pickle_port";
+pub const GRAPHITE_TEMPLATE: &str = "graphite_template";
+pub const LISTEN_ADDRESS: &str = "listen_address";
+pub const TLS_CERT_FILE: &str = "tls_cert_file";
+pub const TLS_KEY_FILE: &str = "tls_key_file";
+pub const BASIC_AUTH_FILE: &str = "basic_auth_file";
+pub const BEARER_TOKEN_FILE: &str = "bearer_token_file";
+pub const ENABLE_ADMIN_API: &str = "enable_admin_api";
+pub const REMOTE_WRITE_CONFIG: &str = "remote_write_config"; // downstream remote write endpoints file path
+pub const RETENTION: &str = "retention";
+pub const RETENTION_SIZE: &str = "retention_size"; // in bytes
+pub const OUT_OF_ORDER_WINDOW: &str = "out_of_order_window"; // in seconds
+pub const TENANT_CONFIG: &str = "tenant_config"; // per tenant options file path
+pub const MAX_TENANTS: &str = "max_tenants";
+pub const WAL_FLUSH: &str = "wal_flush"; // flush policy of write ahead log, disabled if `none`
+pub const REPLICATE_FROM: &str = "replicate_from"; // url of the leader, run as a read replica if set
+pub const REPLICA_MAX_LAG: &str = "replica_max_lag"; // in seconds
+pub const MAX_SERIES: &str = "max_series";
+pub const MAX_SERIES_PER_METRIC: &str = "max_series_per_metric";
+pub const MAX_LABELS_PER_SERIES: &str = "max_labels_per_series";
+pub const MAX_LABEL_NAME_LENGTH: &str = "max_label_name_length";
+pub const MAX_LABEL_VALUE_LENGTH: &str = "max_label_value_length";
+pub const INGESTION_RATE: &str = "ingestion_rate";
+pub const INGESTION_BURST: &str = "ingestion_burst";
 
 pub const TIME_UNIT: Duration = Duration::from_micros(1);
 
@@ -46,9 +77,22 @@ pub const DEFAULT_PORT: i32 = 9001;
 pub const DEFAULT_READ_PATH: &str = "/read";
 pub const DEFAULT_WRITE_PATH: &str = "/write";
 pub const DEFAULT_WORKER_NUM: usize = 8;
+pub const DEFAULT_REPLICA_MAX_LAG: &str = "60"; //in seconds
+pub const DEFAULT_WAL_FLUSH: &str = "interval:1000";
+pub const WAL_DISABLED: &str = "none";
 
 pub const DB_METADATA_FILENAME: &'static str = "metadata.json";
 pub const CHUNK_METADATA_FILENAME: &'static str = "metadata.json";
+/// File in chunk dir that records deleted but not yet purged data
+pub const TOMBSTONES_FILENAME: &str = "tombstones.json";
+/// File in chunk dir of a replica, which marks that the chunk is copied from the leader
+pub const REPLICATED_FILENAME: &str = "replicated";
+/// Dir under base dir that contains the db of each tenant
+pub const TENANT_DIR: &str = "tenants";
+/// Dir under base dir that contains the queues of remote write forwarding
+pub const FORWARD_DIR: &str = "forward";
+/// Dir under base dir that contains the segments of write ahead log
+pub const WAL_DIR: &str = "wal";
 
 // Storage backend
 pub const SLED_BACKEND: &str = "sled";
@@ -57,4 +101,6 @@ pub const TIKV_BACKEND: &str = "tikv";
 #[macro_use]
 extern crate log;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate failure;
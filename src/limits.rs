@@ -0,0 +1,328 @@
+use crate::common::label::Labels;
+use crate::{MonolithErr, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+const METRIC_NAME_LABEL: &str = "__name__";
+
+///
+/// Limits of ingestion, unset limits are not enforced.
+///
+/// Limits from command line apply to the default db and every tenant, and can be overridden for each tenant
+/// in the tenant config file. Series limits count the active series, i.e. series in the current chunk.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct Limits {
+    #[serde(default)]
+    pub max_series: Option<usize>,
+    #[serde(default)]
+    pub max_series_per_metric: Option<usize>,
+    #[serde(default)]
+    pub max_labels_per_series: Option<usize>,
+    #[serde(default)]
+    pub max_label_name_length: Option<usize>,
+    #[serde(default)]
+    pub max_label_value_length: Option<usize>,
+    /// Samples per second
+    #[serde(default)]
+    pub ingestion_rate: Option<f64>,
+    /// Max samples accepted at once, default to `ingestion_rate`
+    #[serde(default)]
+    pub ingestion_burst: Option<usize>,
+}
+
+impl Limits {
+    /// Limits set in `other` override the ones in `self`
+    pub fn merge(&self, other: &Limits) -> Limits {
+        Limits {
+            max_series: other.max_series.or(self.max_series),
+            max_series_per_metric: other.max_series_per_metric.or(self.max_series_per_metric),
+            max_labels_per_series: other.max_labels_per_series.or(self.max_labels_per_series),
+            max_label_name_length: other.max_label_name_length.or(self.max_label_name_length),
+            max_label_value_length: other.max_label_value_length.or(self.max_label_value_length),
+            ingestion_rate: other.ingestion_rate.or(self.ingestion_rate),
+            ingestion_burst: other.ingestion_burst.or(self.ingestion_burst),
+        }
+    }
+
+    /// Check the number of labels and length of label names and values
+    pub fn validate_labels(&self, labels: &Labels) -> Result<()> {
+        if let Some(max) = self.max_labels_per_series {
+            if labels.len() > max {
+                return Err(MonolithErr::LimitErr(
+                    LimitReason::MaxLabelsPerSeries,
+                    format!("series {} has more than {} labels", labels, max),
+                ));
+            }
+        }
+        for label in labels.vec() {
+            match self.max_label_name_length {
+                Some(max) if label.key().len() > max => {
+                    return Err(MonolithErr::LimitErr(
+                        LimitReason::LabelNameTooLong,
+                        format!("label name {} is longer than {}", label.key(), max),
+                    ));
+                }
+                _ => {}
+            }
+            match self.max_label_value_length {
+                Some(max) if label.value().len() > max => {
+                    return Err(MonolithErr::LimitErr(
+                        LimitReason::LabelValueTooLong,
+                        format!("value of label {} is longer than {}", label.key(), max),
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reason that samples are rejected by limits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitReason {
+    RateLimited,
+    MaxSeries,
+    MaxSeriesPerMetric,
+    MaxLabelsPerSeries,
+    LabelNameTooLong,
+    LabelValueTooLong,
+    /// Older than the out-of-order window, or not in any chunk
+    OutOfBounds,
+}
+
+impl LimitReason {
+    pub const ALL: [LimitReason; 7] = [
+        LimitReason::RateLimited,
+        LimitReason::MaxSeries,
+        LimitReason::MaxSeriesPerMetric,
+        LimitReason::MaxLabelsPerSeries,
+        LimitReason::LabelNameTooLong,
+        LimitReason::LabelValueTooLong,
+        LimitReason::OutOfBounds,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LimitReason::RateLimited => "rate_limited",
+            LimitReason::MaxSeries => "max_series",
+            LimitReason::MaxSeriesPerMetric => "max_series_per_metric",
+            LimitReason::MaxLabelsPerSeries => "max_labels_per_series",
+            LimitReason::LabelNameTooLong => "label_name_too_long",
+            LimitReason::LabelValueTooLong => "label_value_too_long",
+            LimitReason::OutOfBounds => "out_of_bounds",
+        }
+    }
+}
+
+impl fmt::Display for LimitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Number of samples rejected for each `LimitReason`
+#[derive(Default)]
+pub struct RejectedCounter([AtomicU64; 7]);
+
+impl RejectedCounter {
+    pub fn add(&self, reason: LimitReason, samples: u64) {
+        self.0[reason as usize].fetch_add(samples, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, reason: LimitReason) -> u64 {
+        self.0[reason as usize].load(Ordering::Relaxed)
+    }
+}
+
+///
+/// Token bucket that refills `rate` tokens per second up to `burst`.
+///
+/// One sample takes one token.
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    // available tokens and the last time of refilling
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: usize) -> RateLimiter {
+        RateLimiter {
+            rate,
+            burst: burst as f64,
+            state: Mutex::new((burst as f64, Instant::now())),
+        }
+    }
+
+    pub fn from_limits(limits: &Limits) -> Option<RateLimiter> {
+        limits.ingestion_rate.map(|rate| {
+            let burst = limits.ingestion_burst.unwrap_or(rate.ceil() as usize);
+            RateLimiter::new(rate, burst)
+        })
+    }
+
+    /// Take `n` tokens if there are enough, otherwise take nothing and return false
+    pub fn allow(&self, n: usize) -> bool {
+        self.allow_at(n, Instant::now())
+    }
+
+    fn allow_at(&self, n: usize, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = now.saturating_duration_since(state.1).as_secs_f64();
+        let tokens = (state.0 + elapsed * self.rate).min(self.burst);
+        *state = (tokens, now);
+        if tokens < n as f64 {
+            return false;
+        }
+        state.0 = tokens - n as f64;
+        true
+    }
+}
+
+/// Limit the number of series in one chunk, totally and of each metric name
+#[derive(Default)]
+pub struct SeriesLimiter {
+    max_series: Option<usize>,
+    max_series_per_metric: Option<usize>,
+    // number of all series and series of each metric name
+    counts: Mutex<(usize, HashMap<String, usize>)>,
+}
+
+impl SeriesLimiter {
+    pub fn new(limits: &Limits) -> SeriesLimiter {
+        SeriesLimiter {
+            max_series: limits.max_series,
+            max_series_per_metric: limits.max_series_per_metric,
+            counts: Mutex::new((0, HashMap::new())),
+        }
+    }
+
+    /// Count a new series if it's within the limits
+    pub fn add_series(&self, labels: &Labels) -> Result<()> {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(max) = self.max_series {
+            if counts.0 >= max {
+                return Err(MonolithErr::LimitErr(
+                    LimitReason::MaxSeries,
+                    format!("series limit of {} exceeded", max),
+                ));
+            }
+        }
+        if let Some(max) = self.max_series_per_metric {
+            let name = labels
+                .vec()
+                .iter()
+                .find(|l| l.key() == METRIC_NAME_LABEL)
+                .map(|l| l.value().as_str())
+                .unwrap_or("");
+            let count = counts.1.entry(name.to_string()).or_insert(0);
+            if *count >= max {
+                return Err(MonolithErr::LimitErr(
+                    LimitReason::MaxSeriesPerMetric,
+                    format!("series limit of {} exceeded for metric {}", max, name),
+                ));
+            }
+            *count += 1;
+        }
+        counts.0 += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::{Label, Labels};
+    use crate::limits::{LimitReason, Limits, RateLimiter, SeriesLimiter};
+    use crate::MonolithErr;
+    use std::time::{Duration, Instant};
+
+    fn labels(pairs: &[(&str, &str)]) -> Labels {
+        Labels::from_vec(
+            pairs
+                .iter()
+                .map(|(k, v)| Label::from_key_value(k, v))
+                .collect(),
+        )
+    }
+
+    fn reason(err: MonolithErr) -> LimitReason {
+        match err {
+            MonolithErr::LimitErr(reason, _) => reason,
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+
+    #[test]
+    fn test_validate_labels() {
+        let mut limits = Limits::default();
+        let series = labels(&[("__name__", "up"), ("instance", "localhost:9090")]);
+        assert!(limits.validate_labels(&series).is_ok());
+
+        limits.max_labels_per_series = Some(1);
+        let err = limits.validate_labels(&series).unwrap_err();
+        assert_eq!(reason(err), LimitReason::MaxLabelsPerSeries);
+
+        limits.max_labels_per_series = None;
+        limits.max_label_name_length = Some(6);
+        let err = limits.validate_labels(&series).unwrap_err();
+        assert_eq!(reason(err), LimitReason::LabelNameTooLong);
+
+        limits.max_label_name_length = Some(8);
+        limits.max_label_value_length = Some(10);
+        let err = limits.validate_labels(&series).unwrap_err();
+        assert_eq!(reason(err), LimitReason::LabelValueTooLong);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut global = Limits::default();
+        global.max_series = Some(100);
+        global.ingestion_rate = Some(10.0);
+        let mut tenant = Limits::default();
+        tenant.max_series = Some(10);
+        let merged = global.merge(&tenant);
+        assert_eq!(merged.max_series, Some(10));
+        assert_eq!(merged.ingestion_rate, Some(10.0));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(10.0, 20);
+        let start = Instant::now();
+        assert!(limiter.allow_at(15, start));
+        assert!(!limiter.allow_at(10, start));
+        assert!(limiter.allow_at(5, start));
+        // refilled 10 tokens after one second
+        let later = start + Duration::from_secs(1);
+        assert!(!limiter.allow_at(11, later));
+        assert!(limiter.allow_at(10, later));
+        // never more than burst
+        let much_later = start + Duration::from_secs(100);
+        assert!(!limiter.allow_at(21, much_later));
+        assert!(limiter.allow_at(20, much_later));
+    }
+
+    #[test]
+    fn test_series_limiter() {
+        let mut limits = Limits::default();
+        limits.max_series = Some(3);
+        limits.max_series_per_metric = Some(2);
+        let limiter = SeriesLimiter::new(&limits);
+        assert!(limiter.add_series(&labels(&[("__name__", "a")])).is_ok());
+        assert!(limiter.add_series(&labels(&[("__name__", "a")])).is_ok());
+        let err = limiter
+            .add_series(&labels(&[("__name__", "a")]))
+            .unwrap_err();
+        assert_eq!(reason(err), LimitReason::MaxSeriesPerMetric);
+        assert!(limiter.add_series(&labels(&[("__name__", "b")])).is_ok());
+        let err = limiter
+            .add_series(&labels(&[("__name__", "c")]))
+            .unwrap_err();
+        assert_eq!(reason(err), LimitReason::MaxSeries);
+    }
+}
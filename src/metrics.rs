@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of histogram buckets in seconds, the same as the default of Prometheus client libraries
+pub const DEFAULT_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+lazy_static! {
+    /// Latency of http requests, by the kind of request
+    pub static ref REQUEST_DURATION: HistogramVec = HistogramVec::new(
+        "monolith_request_duration_seconds",
+        "Latency of http requests",
+        &["handler"],
+    );
+    /// Latency of indexer lookups, by the type of indexer
+    pub static ref INDEXER_LOOKUP_DURATION: HistogramVec = HistogramVec::new(
+        "monolith_indexer_lookup_duration_seconds",
+        "Latency of looking up series in indexer",
+        &["backend"],
+    );
+    /// Time taken to swap the current chunk with a new one
+    pub static ref CHUNK_SWAP_DURATION: HistogramVec = HistogramVec::new(
+        "monolith_chunk_swap_duration_seconds",
+        "Time taken to swap chunks",
+        &[],
+    );
+    /// Time taken to compact the series of a closed chunk
+    pub static ref CHUNK_COMPACTION_DURATION: HistogramVec = HistogramVec::new(
+        "monolith_chunk_compaction_duration_seconds",
+        "Time taken to compact closed chunks",
+        &[],
+    );
+    /// Chunks removed by retention
+    pub static ref EXPIRED_CHUNKS: CounterVec = CounterVec::new(
+        "monolith_expired_chunks_total",
+        "Number of chunks removed by retention",
+        &[],
+    );
+    /// Failed requests to TiKV, by the operation
+    pub static ref TIKV_ERRORS: CounterVec = CounterVec::new(
+        "monolith_tikv_errors_total",
+        "Number of failed requests to TiKV",
+        &["operation"],
+    );
+    /// Remote write requests forwarded to downstream endpoints, by the endpoint and the result
+    pub static ref FORWARD_REQUESTS: CounterVec = CounterVec::new(
+        "monolith_forward_requests_total",
+        "Number of remote write requests forwarded to downstream endpoints",
+        &["endpoint", "result"],
+    );
+    /// Remote write requests dropped because the queue of the endpoint is full, by the endpoint
+    pub static ref FORWARD_DROPPED_REQUESTS: CounterVec = CounterVec::new(
+        "monolith_forward_dropped_requests_total",
+        "Number of remote write requests dropped because the forward queue is full",
+        &["endpoint"],
+    );
+}
+
+/// Write metrics registered in this module
+pub fn encode_global(encoder: &mut TextEncoder) {
+    REQUEST_DURATION.encode(encoder);
+    INDEXER_LOOKUP_DURATION.encode(encoder);
+    CHUNK_SWAP_DURATION.encode(encoder);
+    CHUNK_COMPACTION_DURATION.encode(encoder);
+    EXPIRED_CHUNKS.encode(encoder);
+    TIKV_ERRORS.encode(encoder);
+    FORWARD_REQUESTS.encode(encoder);
+    FORWARD_DROPPED_REQUESTS.encode(encoder);
+}
+
+/// Observe time elapsed since `start` in `histogram` of `label_values`
+pub fn observe_since(histogram: &HistogramVec, label_values: &[&str], start: Instant) {
+    histogram.observe_duration(label_values, start.elapsed());
+}
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+///
+/// Histogram with fixed buckets.
+///
+/// Only the count of each bucket is kept, they are made cumulative when encoded.
+pub struct Histogram {
+    bounds: &'static [f64],
+    // one more bucket for +Inf
+    counts: Vec<AtomicU64>,
+    sum: Mutex<f64>,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: Mutex::new(0.0),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let idx = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[idx].fetch_add(1, Ordering::Relaxed);
+        *self.sum.lock().unwrap() += value;
+    }
+
+    /// Cumulative count of each bucket, the last one is +Inf
+    fn cumulative_counts(&self) -> Vec<u64> {
+        let mut total = 0;
+        self.counts
+            .iter()
+            .map(|count| {
+                total += count.load(Ordering::Relaxed);
+                total
+            })
+            .collect()
+    }
+}
+
+/// Counters of the same name, one for each combination of label values
+pub struct CounterVec {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    counters: RwLock<BTreeMap<Vec<String>, Arc<Counter>>>,
+}
+
+impl CounterVec {
+    pub fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+    ) -> CounterVec {
+        CounterVec {
+            name,
+            help,
+            label_names,
+            counters: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn with_label_values(&self, label_values: &[&str]) -> Arc<Counter> {
+        get_or_create(&self.counters, label_values, Counter::default)
+    }
+
+    pub fn encode(&self, encoder: &mut TextEncoder) {
+        let counters = self.counters.read().unwrap();
+        encoder.header(self.name, self.help, "counter");
+        for (values, counter) in counters.iter() {
+            let labels = zip_labels(self.label_names, values);
+            encoder.sample(self.name, labels.as_slice(), counter.get() as f64);
+        }
+    }
+}
+
+/// Histograms of the same name, one for each combination of label values
+pub struct HistogramVec {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    histograms: RwLock<BTreeMap<Vec<String>, Arc<Histogram>>>,
+}
+
+impl HistogramVec {
+    pub fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+    ) -> HistogramVec {
+        HistogramVec {
+            name,
+            help,
+            label_names,
+            histograms: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn with_label_values(&self, label_values: &[&str]) -> Arc<Histogram> {
+        get_or_create(&self.histograms, label_values, || {
+            Histogram::new(&DEFAULT_BUCKETS)
+        })
+    }
+
+    pub fn observe_duration(&self, label_values: &[&str], duration: Duration) {
+        self.with_label_values(label_values)
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn encode(&self, encoder: &mut TextEncoder) {
+        let histograms = self.histograms.read().unwrap();
+        encoder.header(self.name, self.help, "histogram");
+        for (values, histogram) in histograms.iter() {
+            let labels = zip_labels(self.label_names, values);
+            encoder.histogram(self.name, labels.as_slice(), histogram);
+        }
+    }
+}
+
+fn get_or_create<T, F: FnOnce() -> T>(
+    map: &RwLock<BTreeMap<Vec<String>, Arc<T>>>,
+    label_values: &[&str],
+    create: F,
+) -> Arc<T> {
+    let key = label_values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<String>>();
+    if let Some(metric) = map.read().unwrap().get(&key) {
+        return Arc::clone(metric);
+    }
+    Arc::clone(
+        map.write()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(create())),
+    )
+}
+
+fn zip_labels<'a>(names: &[&'a str], values: &'a [String]) -> Vec<(&'a str, &'a str)> {
+    names
+        .iter()
+        .cloned()
+        .zip(values.iter().map(|v| v.as_str()))
+        .collect()
+}
+
+/// Encoder of the Prometheus text exposition format
+#[derive(Default)]
+pub struct TextEncoder {
+    buf: String,
+}
+
+impl TextEncoder {
+    pub fn new() -> TextEncoder {
+        TextEncoder::default()
+    }
+
+    /// Write `HELP` and `TYPE` lines, which must come before the samples of metric
+    pub fn header(&mut self, name: &str, help: &str, metric_type: &str) {
+        writeln!(self.buf, "# HELP {} {}", name, help.replace('\\', "\\\\")).unwrap();
+        writeln!(self.buf, "# TYPE {} {}", name, metric_type).unwrap();
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.buf.push_str(name);
+        if !labels.is_empty() {
+            self.buf.push('{');
+            for (idx, (label, value)) in labels.iter().enumerate() {
+                if idx > 0 {
+                    self.buf.push(',');
+                }
+                write!(self.buf, "{}=\"{}\"", label, escape_label_value(value)).unwrap();
+            }
+            self.buf.push('}');
+        }
+        writeln!(self.buf, " {}", format_value(value)).unwrap();
+    }
+
+    /// Write `_bucket`, `_sum` and `_count` samples of histogram
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let bucket_name = format!("{}_bucket", name);
+        let counts = histogram.cumulative_counts();
+        for (idx, count) in counts.iter().enumerate() {
+            let le = match histogram.bounds.get(idx) {
+                Some(bound) => format_value(*bound),
+                None => "+Inf".to_string(),
+            };
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", le.as_str()));
+            self.sample(
+                bucket_name.as_str(),
+                bucket_labels.as_slice(),
+                *count as f64,
+            );
+        }
+        let sum = *histogram.sum.lock().unwrap();
+        self.sample(format!("{}_sum", name).as_str(), labels, sum);
+        let count = counts.last().cloned().unwrap_or(0);
+        self.sample(format!("{}_count", name).as_str(), labels, count as f64);
+    }
+
+    pub fn finish(self) -> String {
+        self.buf
+    }
+}
+
+pub(crate) fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub(crate) fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::{CounterVec, HistogramVec, TextEncoder};
+
+    #[test]
+    fn test_encode_counter() {
+        let counters = CounterVec::new("test_total", "Test counter", &["reason"]);
+        counters.with_label_values(&["a\"b"]).inc_by(3);
+        counters.with_label_values(&["c"]).inc();
+        let mut encoder = TextEncoder::new();
+        counters.encode(&mut encoder);
+        assert_eq!(
+            encoder.finish(),
+            "# HELP test_total Test counter\n\
+             # TYPE test_total counter\n\
+             test_total{reason=\"a\\\"b\"} 3\n\
+             test_total{reason=\"c\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_histogram() {
+        let histograms = HistogramVec::new("test_seconds", "Test histogram", &[]);
+        let histogram = histograms.with_label_values(&[]);
+        histogram.observe(0.003);
+        histogram.observe(0.2);
+        histogram.observe(20.0);
+        let mut encoder = TextEncoder::new();
+        histograms.encode(&mut encoder);
+        let text = encoder.finish();
+        assert!(text.contains("# TYPE test_seconds histogram\n"), "{}", text);
+        assert!(
+            text.contains("test_seconds_bucket{le=\"0.005\"} 1\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("test_seconds_bucket{le=\"0.25\"} 2\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("test_seconds_bucket{le=\"10\"} 2\n"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("test_seconds_bucket{le=\"+Inf\"} 3\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("test_seconds_sum 20.2"), "{}", text);
+        assert!(text.ends_with("test_seconds_count 3\n"), "{}", text);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::common::label::LabelMatcher;
+use crate::Timestamp;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eql,
+    Neq,
+    Lss,
+    Gtr,
+    Lte,
+    Gte,
+    And,
+    Or,
+    Unless,
+}
+
+impl BinaryOp {
+    /// Higher value binds tighter
+    pub fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And | BinaryOp::Unless => 2,
+            BinaryOp::Eql
+            | BinaryOp::Neq
+            | BinaryOp::Lss
+            | BinaryOp::Gtr
+            | BinaryOp::Lte
+            | BinaryOp::Gte => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 5,
+            BinaryOp::Pow => 6,
+        }
+    }
+
+    pub fn is_right_associative(self) -> bool {
+        self == BinaryOp::Pow
+    }
+
+    pub fn is_comparison(self) -> bool {
+        self.precedence() == 3
+    }
+
+    pub fn is_set_operator(self) -> bool {
+        match self {
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Unless => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AggregateOp {
+    Sum,
+    Avg,
+    Count,
+    Min,
+    Max,
+    Stddev,
+    Stdvar,
+    Group,
+    Topk,
+    Bottomk,
+    Quantile,
+    CountValues,
+}
+
+impl AggregateOp {
+    pub fn from_name(name: &str) -> Option<AggregateOp> {
+        match name {
+            "sum" => Some(AggregateOp::Sum),
+            "avg" => Some(AggregateOp::Avg),
+            "count" => Some(AggregateOp::Count),
+            "min" => Some(AggregateOp::Min),
+            "max" => Some(AggregateOp::Max),
+            "stddev" => Some(AggregateOp::Stddev),
+            "stdvar" => Some(AggregateOp::Stdvar),
+            "group" => Some(AggregateOp::Group),
+            "topk" => Some(AggregateOp::Topk),
+            "bottomk" => Some(AggregateOp::Bottomk),
+            "quantile" => Some(AggregateOp::Quantile),
+            "count_values" => Some(AggregateOp::CountValues),
+            _ => None,
+        }
+    }
+
+    /// Whether the aggregation takes a parameter before the expression, e.g. `topk(5, ...)`
+    pub fn has_param(self) -> bool {
+        match self {
+            AggregateOp::Topk
+            | AggregateOp::Bottomk
+            | AggregateOp::Quantile
+            | AggregateOp::CountValues => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cardinality {
+    OneToOne,
+    ManyToOne,
+    OneToMany,
+    ManyToMany,
+}
+
+/// How samples of two vectors are matched in binary operation
+#[derive(Clone, Debug)]
+pub struct VectorMatching {
+    pub card: Cardinality,
+    /// `true` if `on` is used, otherwise labels are the `ignoring` list
+    pub on: bool,
+    pub labels: Vec<String>,
+    /// Labels to copy from the "one" side in `group_left` or `group_right`
+    pub include: Vec<String>,
+}
+
+impl Default for VectorMatching {
+    fn default() -> Self {
+        VectorMatching {
+            card: Cardinality::OneToOne,
+            on: false,
+            labels: Vec::new(),
+            include: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct VectorSelector {
+    pub matchers: Vec<LabelMatcher>,
+    /// In milliseconds
+    pub offset: Timestamp,
+}
+
+impl fmt::Display for VectorSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let matchers = self
+            .matchers
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "{{{}}}", matchers.join(","))?;
+        if self.offset > 0 {
+            write!(f, " offset {}ms", self.offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    NumberLiteral(f64),
+    StringLiteral(String),
+    VectorSelector(VectorSelector),
+    /// Vector selector with a range in milliseconds
+    MatrixSelector(VectorSelector, Timestamp),
+    Call(String, Vec<Expr>),
+    Aggregate {
+        op: AggregateOp,
+        expr: Box<Expr>,
+        param: Option<Box<Expr>>,
+        grouping: Vec<String>,
+        without: bool,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        return_bool: bool,
+        matching: VectorMatching,
+    },
+    Negative(Box<Expr>),
+    Paren(Box<Expr>),
+}
@@ -0,0 +1,356 @@
+use crate::common::label::Labels;
+use crate::common::time_point::TimePoint;
+use crate::indexer::Indexer;
+use crate::promql::ast::{Expr, VectorSelector};
+use crate::promql::functions::{call, drop_metric_name, takes_range_vector, Window};
+use crate::promql::operators::{aggregate, binary};
+use crate::promql::parser::parse;
+use crate::storage::Storage;
+use crate::{MonolithDb, MonolithErr, Result, Timestamp};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+/// Samples older than this will not be selected by instant vector selector
+pub const DEFAULT_LOOKBACK_DELTA: Timestamp = 5 * 60 * 1000;
+
+/// Label set of series, sorted by label name
+pub type Metric = BTreeMap<String, String>;
+
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub metric: Metric,
+    pub timestamp: Timestamp,
+    pub value: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Series {
+    pub metric: Metric,
+    pub points: Vec<TimePoint>,
+}
+
+/// Result of evaluating an expression
+#[derive(Clone, Debug)]
+pub enum Value {
+    Scalar(f64),
+    String(String),
+    Vector(Vec<Sample>),
+    Matrix(Vec<Series>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Scalar(_) => "scalar",
+            Value::String(_) => "string",
+            Value::Vector(_) => "vector",
+            Value::Matrix(_) => "matrix",
+        }
+    }
+}
+
+///
+/// PromQL engine that evaluates queries against `MonolithDb`.
+///
+/// All timestamps are in milliseconds.
+pub struct Engine<'a, S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    db: &'a MonolithDb<S, I>,
+    lookback_delta: Timestamp,
+}
+
+impl<'a, S, I> Engine<'a, S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    pub fn new(db: &'a MonolithDb<S, I>) -> Self {
+        Engine {
+            db,
+            lookback_delta: DEFAULT_LOOKBACK_DELTA,
+        }
+    }
+
+    /// Evaluate `query` at `time`
+    pub fn instant_query(&self, query: &str, time: Timestamp) -> Result<Value> {
+        let expr = parse(query)?;
+        let evaluator = Evaluator::new(self.db, time, time, self.lookback_delta);
+        evaluator.eval(&expr, time)
+    }
+
+    /// Evaluate `query` at every `step` from `start` to `end`
+    pub fn range_query(
+        &self,
+        query: &str,
+        start: Timestamp,
+        end: Timestamp,
+        step: Timestamp,
+    ) -> Result<Vec<Series>> {
+        if step == 0 {
+            return Err(MonolithErr::QueryParseErr(
+                "step must be positive".to_string(),
+            ));
+        }
+        let expr = parse(query)?;
+        let evaluator = Evaluator::new(self.db, start, end, self.lookback_delta);
+        let mut res: BTreeMap<Metric, Vec<TimePoint>> = BTreeMap::new();
+        let mut t = start;
+        while t <= end {
+            match evaluator.eval(&expr, t)? {
+                Value::Scalar(v) => res
+                    .entry(Metric::new())
+                    .or_insert_with(Vec::new)
+                    .push(TimePoint::new(t, v)),
+                Value::Vector(samples) => {
+                    for sample in samples {
+                        res.entry(sample.metric)
+                            .or_insert_with(Vec::new)
+                            .push(TimePoint::new(t, sample.value));
+                    }
+                }
+                other => {
+                    return Err(MonolithErr::QueryExecErr(format!(
+                        "range query expects scalar or instant vector but got {}",
+                        other.type_name()
+                    )))
+                }
+            }
+            t += step;
+        }
+        Ok(res
+            .into_iter()
+            .map(|(metric, points)| Series { metric, points })
+            .collect())
+    }
+}
+
+struct Evaluator<'a, S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    db: &'a MonolithDb<S, I>,
+    start: Timestamp,
+    end: Timestamp,
+    lookback_delta: Timestamp,
+    /// Data of selectors loaded for the whole query range, so each step doesn't need to hit db
+    cache: RefCell<HashMap<String, Rc<Vec<Series>>>>,
+}
+
+impl<'a, S, I> Evaluator<'a, S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    fn new(
+        db: &'a MonolithDb<S, I>,
+        start: Timestamp,
+        end: Timestamp,
+        lookback_delta: Timestamp,
+    ) -> Self {
+        Evaluator {
+            db,
+            start,
+            end,
+            lookback_delta,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn eval(&self, expr: &Expr, t: Timestamp) -> Result<Value> {
+        match expr {
+            Expr::NumberLiteral(v) => Ok(Value::Scalar(*v)),
+            Expr::StringLiteral(s) => Ok(Value::String(s.clone())),
+            Expr::Paren(expr) => self.eval(expr, t),
+            Expr::Negative(expr) => match self.eval(expr, t)? {
+                Value::Scalar(v) => Ok(Value::Scalar(-v)),
+                Value::Vector(samples) => Ok(Value::Vector(
+                    samples
+                        .into_iter()
+                        .map(|s| Sample {
+                            metric: drop_metric_name(s.metric),
+                            timestamp: s.timestamp,
+                            value: -s.value,
+                        })
+                        .collect(),
+                )),
+                other => Err(MonolithErr::QueryExecErr(format!(
+                    "unary minus not allowed on {}",
+                    other.type_name()
+                ))),
+            },
+            Expr::VectorSelector(selector) => self.instant_vector(selector, t),
+            Expr::MatrixSelector(selector, range) => self.range_vector(selector, *range, t),
+            Expr::Call(name, args) => {
+                let mut window = None;
+                let mut values = Vec::new();
+                for (idx, arg) in args.iter().enumerate() {
+                    if takes_range_vector(name.as_str(), idx) {
+                        match arg {
+                            Expr::MatrixSelector(selector, range) => {
+                                let end = t.saturating_sub(selector.offset);
+                                window = Some(Window {
+                                    start: end.saturating_sub(*range),
+                                    end,
+                                });
+                            }
+                            _ => {
+                                return Err(MonolithErr::QueryExecErr(format!(
+                                    "{} expects range vector selector",
+                                    name
+                                )))
+                            }
+                        }
+                    }
+                    values.push(self.eval(arg, t)?);
+                }
+                call(name.as_str(), values, t, window)
+            }
+            Expr::Aggregate {
+                op,
+                expr,
+                param,
+                grouping,
+                without,
+            } => {
+                let vector = match self.eval(expr, t)? {
+                    Value::Vector(v) => v,
+                    other => {
+                        return Err(MonolithErr::QueryExecErr(format!(
+                            "aggregation expects instant vector but got {}",
+                            other.type_name()
+                        )))
+                    }
+                };
+                let param = match param {
+                    Some(param) => Some(self.eval(param, t)?),
+                    None => None,
+                };
+                Ok(Value::Vector(aggregate(
+                    *op,
+                    vector,
+                    param,
+                    grouping.as_slice(),
+                    *without,
+                    t,
+                )?))
+            }
+            Expr::Binary {
+                op,
+                lhs,
+                rhs,
+                return_bool,
+                matching,
+            } => {
+                let lhs = self.eval(lhs, t)?;
+                let rhs = self.eval(rhs, t)?;
+                binary(*op, lhs, rhs, *return_bool, matching)
+            }
+        }
+    }
+
+    /// Select the latest sample within lookback delta of each series
+    fn instant_vector(&self, selector: &VectorSelector, t: Timestamp) -> Result<Value> {
+        let series = self.fetch(selector, self.lookback_delta)?;
+        let end = t.saturating_sub(selector.offset);
+        let start = end.saturating_sub(self.lookback_delta);
+        let mut res = Vec::new();
+        for s in series.iter() {
+            let idx = s.points.partition_point_by(end);
+            if idx == 0 {
+                continue;
+            }
+            let point = &s.points[idx - 1];
+            if point.timestamp > start {
+                res.push(Sample {
+                    metric: s.metric.clone(),
+                    timestamp: t,
+                    value: point.value,
+                });
+            }
+        }
+        Ok(Value::Vector(res))
+    }
+
+    /// Select samples within (t - range, t] of each series
+    fn range_vector(
+        &self,
+        selector: &VectorSelector,
+        range: Timestamp,
+        t: Timestamp,
+    ) -> Result<Value> {
+        let series = self.fetch(selector, range)?;
+        let end = t.saturating_sub(selector.offset);
+        let start = end.saturating_sub(range);
+        let mut res = Vec::new();
+        for s in series.iter() {
+            let from = s.points.partition_point_by(start);
+            let to = s.points.partition_point_by(end);
+            if from < to {
+                res.push(Series {
+                    metric: s.metric.clone(),
+                    points: s.points[from..to].to_vec(),
+                });
+            }
+        }
+        Ok(Value::Matrix(res))
+    }
+
+    /// Load series of `selector` that covers all steps in query range.
+    fn fetch(&self, selector: &VectorSelector, range: Timestamp) -> Result<Rc<Vec<Series>>> {
+        let key = format!("{}[{}]", selector, range);
+        if let Some(res) = self.cache.borrow().get(&key) {
+            return Ok(Rc::clone(res));
+        }
+        let start = self.start.saturating_sub(selector.offset + range);
+        let end = self.end.saturating_sub(selector.offset);
+        let res = Rc::new(
+            self.db
+                .query(selector.matchers.as_slice(), start, end, None)?
+                .into_iter()
+                .map(|(labels, mut points)| {
+                    points.sort();
+                    Series {
+                        metric: to_metric(&labels),
+                        points,
+                    }
+                })
+                .collect::<Vec<Series>>(),
+        );
+        self.cache.borrow_mut().insert(key, Rc::clone(&res));
+        Ok(res)
+    }
+}
+
+pub fn to_metric(labels: &Labels) -> Metric {
+    labels
+        .vec()
+        .iter()
+        .map(|l| (l.key().clone(), l.value().clone()))
+        .collect()
+}
+
+trait PartitionPoint {
+    /// Num of points whose timestamp is not greater than `timestamp`
+    fn partition_point_by(&self, timestamp: Timestamp) -> usize;
+}
+
+impl PartitionPoint for Vec<TimePoint> {
+    fn partition_point_by(&self, timestamp: Timestamp) -> usize {
+        let mut low = 0;
+        let mut high = self.len();
+        while low < high {
+            let mid = (low + high) / 2;
+            if self[mid].timestamp <= timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+}
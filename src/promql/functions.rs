@@ -0,0 +1,621 @@
+use crate::common::selector::METRIC_NAME_LABEL;
+use crate::common::time_point::TimePoint;
+use crate::promql::engine::{Metric, Sample, Series, Value};
+use crate::{MonolithErr, Result, Timestamp};
+use std::collections::BTreeMap;
+
+/// Label that holds upper bound of histogram bucket
+const BUCKET_LABEL: &str = "le";
+
+/// Time range covered by the range vector argument, start is exclusive and end is inclusive.
+#[derive(Clone, Copy, Debug)]
+pub struct Window {
+    pub start: Timestamp,
+    pub end: Timestamp,
+}
+
+fn exec_err(reason: String) -> MonolithErr {
+    MonolithErr::QueryExecErr(reason)
+}
+
+///
+/// Call function `name` at time `t`.
+///
+/// `window` must be provided if one of the argument is range vector.
+pub fn call(
+    name: &str,
+    mut args: Vec<Value>,
+    t: Timestamp,
+    window: Option<Window>,
+) -> Result<Value> {
+    let range_fn: Option<fn(&[TimePoint], Window) -> Option<f64>> = match name {
+        "rate" => Some(|points, window| extrapolated_rate(points, window, true, true)),
+        "increase" => Some(|points, window| extrapolated_rate(points, window, true, false)),
+        "delta" => Some(|points, window| extrapolated_rate(points, window, false, false)),
+        "irate" => Some(|points, _| instant_value(points, true)),
+        "idelta" => Some(|points, _| instant_value(points, false)),
+        "changes" => Some(|points, _| {
+            Some(
+                points
+                    .windows(2)
+                    .filter(|w| w[0].value != w[1].value)
+                    .count() as f64,
+            )
+        }),
+        "resets" => Some(|points, _| {
+            Some(
+                points
+                    .windows(2)
+                    .filter(|w| w[1].value < w[0].value)
+                    .count() as f64,
+            )
+        }),
+        "avg_over_time" => Some(|points, _| Some(mean(points))),
+        "min_over_time" => Some(|points, _| {
+            points
+                .iter()
+                .map(|tp| tp.value)
+                .fold(None, |acc: Option<f64>, v| match acc {
+                    Some(a) if !a.is_nan() && !(v < a) => Some(a),
+                    _ => Some(v),
+                })
+        }),
+        "max_over_time" => Some(|points, _| {
+            points
+                .iter()
+                .map(|tp| tp.value)
+                .fold(None, |acc: Option<f64>, v| match acc {
+                    Some(a) if !a.is_nan() && !(v > a) => Some(a),
+                    _ => Some(v),
+                })
+        }),
+        "sum_over_time" => Some(|points, _| Some(points.iter().map(|tp| tp.value).sum())),
+        "count_over_time" => Some(|points, _| Some(points.len() as f64)),
+        "last_over_time" => Some(|points, _| points.last().map(|tp| tp.value)),
+        "stddev_over_time" => Some(|points, _| Some(variance(points).sqrt())),
+        "stdvar_over_time" => Some(|points, _| Some(variance(points))),
+        _ => None,
+    };
+    if let Some(f) = range_fn {
+        check_args(name, &args, 1)?;
+        let window = window.ok_or(exec_err(format!("{} expects a range vector", name)))?;
+        let matrix = take_matrix(name, args.remove(0))?;
+        let keep_name = name == "last_over_time";
+        return Ok(Value::Vector(
+            matrix
+                .into_iter()
+                .filter(|series| !series.points.is_empty())
+                .filter_map(|series| {
+                    f(series.points.as_slice(), window).map(|value| Sample {
+                        metric: if keep_name {
+                            series.metric
+                        } else {
+                            drop_metric_name(series.metric)
+                        },
+                        timestamp: t,
+                        value,
+                    })
+                })
+                .collect(),
+        ));
+    }
+
+    let math_fn: Option<fn(f64) -> f64> = match name {
+        "abs" => Some(f64::abs),
+        "ceil" => Some(f64::ceil),
+        "floor" => Some(f64::floor),
+        "exp" => Some(f64::exp),
+        "ln" => Some(f64::ln),
+        "log2" => Some(f64::log2),
+        "log10" => Some(f64::log10),
+        "sqrt" => Some(f64::sqrt),
+        _ => None,
+    };
+    if let Some(f) = math_fn {
+        check_args(name, &args, 1)?;
+        let vector = take_vector(name, args.remove(0))?;
+        return Ok(Value::Vector(map_vector(vector, f)));
+    }
+
+    match name {
+        "quantile_over_time" => {
+            check_args(name, &args, 2)?;
+            let q = take_scalar(name, args.remove(0))?;
+            let matrix = take_matrix(name, args.remove(0))?;
+            Ok(Value::Vector(
+                matrix
+                    .into_iter()
+                    .filter(|series| !series.points.is_empty())
+                    .map(|series| Sample {
+                        metric: drop_metric_name(series.metric),
+                        timestamp: t,
+                        value: quantile(q, series.points.iter().map(|tp| tp.value).collect()),
+                    })
+                    .collect(),
+            ))
+        }
+        "round" => {
+            if args.is_empty() || args.len() > 2 {
+                return Err(exec_err("round expects 1 or 2 arguments".to_string()));
+            }
+            let to_nearest = if args.len() == 2 {
+                take_scalar(name, args.remove(1))?
+            } else {
+                1.0
+            };
+            let vector = take_vector(name, args.remove(0))?;
+            let inverse = 1.0 / to_nearest;
+            Ok(Value::Vector(
+                vector
+                    .into_iter()
+                    .map(|s| Sample {
+                        metric: drop_metric_name(s.metric),
+                        timestamp: s.timestamp,
+                        value: (s.value * inverse + 0.5).floor() / inverse,
+                    })
+                    .collect(),
+            ))
+        }
+        "clamp_min" | "clamp_max" => {
+            check_args(name, &args, 2)?;
+            let bound = take_scalar(name, args.remove(1))?;
+            let vector = take_vector(name, args.remove(0))?;
+            let is_min = name == "clamp_min";
+            Ok(Value::Vector(
+                vector
+                    .into_iter()
+                    .map(|s| Sample {
+                        metric: drop_metric_name(s.metric),
+                        timestamp: s.timestamp,
+                        value: if is_min {
+                            s.value.max(bound)
+                        } else {
+                            s.value.min(bound)
+                        },
+                    })
+                    .collect(),
+            ))
+        }
+        "histogram_quantile" => {
+            check_args(name, &args, 2)?;
+            let q = take_scalar(name, args.remove(0))?;
+            let vector = take_vector(name, args.remove(0))?;
+            Ok(Value::Vector(histogram_quantile(q, vector, t)))
+        }
+        "scalar" => {
+            check_args(name, &args, 1)?;
+            let vector = take_vector(name, args.remove(0))?;
+            Ok(Value::Scalar(if vector.len() == 1 {
+                vector[0].value
+            } else {
+                std::f64::NAN
+            }))
+        }
+        "vector" => {
+            check_args(name, &args, 1)?;
+            let value = take_scalar(name, args.remove(0))?;
+            Ok(Value::Vector(vec![Sample {
+                metric: Metric::new(),
+                timestamp: t,
+                value,
+            }]))
+        }
+        "time" => {
+            check_args(name, &args, 0)?;
+            Ok(Value::Scalar(t as f64 / 1000.0))
+        }
+        _ => Err(MonolithErr::QueryParseErr(format!(
+            "unknown function {}",
+            name
+        ))),
+    }
+}
+
+/// Check if the function is the one that takes range vector at `idx` argument
+pub fn takes_range_vector(name: &str, idx: usize) -> bool {
+    match name {
+        "quantile_over_time" => idx == 1,
+        "rate" | "increase" | "delta" | "irate" | "idelta" | "changes" | "resets" => idx == 0,
+        name => name.ends_with("_over_time") && idx == 0,
+    }
+}
+
+fn check_args(name: &str, args: &[Value], expected: usize) -> Result<()> {
+    if args.len() != expected {
+        return Err(exec_err(format!(
+            "{} expects {} arguments but got {}",
+            name,
+            expected,
+            args.len()
+        )));
+    }
+    Ok(())
+}
+
+fn take_scalar(name: &str, value: Value) -> Result<f64> {
+    match value {
+        Value::Scalar(v) => Ok(v),
+        other => Err(exec_err(format!(
+            "{} expects scalar but got {}",
+            name,
+            other.type_name()
+        ))),
+    }
+}
+
+fn take_vector(name: &str, value: Value) -> Result<Vec<Sample>> {
+    match value {
+        Value::Vector(v) => Ok(v),
+        other => Err(exec_err(format!(
+            "{} expects instant vector but got {}",
+            name,
+            other.type_name()
+        ))),
+    }
+}
+
+fn take_matrix(name: &str, value: Value) -> Result<Vec<Series>> {
+    match value {
+        Value::Matrix(m) => Ok(m),
+        other => Err(exec_err(format!(
+            "{} expects range vector but got {}",
+            name,
+            other.type_name()
+        ))),
+    }
+}
+
+pub fn drop_metric_name(mut metric: Metric) -> Metric {
+    metric.remove(METRIC_NAME_LABEL);
+    metric
+}
+
+fn map_vector(vector: Vec<Sample>, f: fn(f64) -> f64) -> Vec<Sample> {
+    vector
+        .into_iter()
+        .map(|s| Sample {
+            metric: drop_metric_name(s.metric),
+            timestamp: s.timestamp,
+            value: f(s.value),
+        })
+        .collect()
+}
+
+fn mean(points: &[TimePoint]) -> f64 {
+    points.iter().map(|tp| tp.value).sum::<f64>() / points.len() as f64
+}
+
+fn variance(points: &[TimePoint]) -> f64 {
+    let mean = mean(points);
+    points
+        .iter()
+        .map(|tp| (tp.value - mean).powi(2))
+        .sum::<f64>()
+        / points.len() as f64
+}
+
+///
+/// Calculate rate or delta of samples in `window`, and extrapolate the result to the edges of window.
+///
+/// Same as Prometheus, the result will only be extrapolated to the window edge if the first or last sample
+/// is close enough to it, otherwise only half of the average sample interval will be extrapolated.
+/// For counter, decrease of value is treated as reset and will be compensated.
+fn extrapolated_rate(
+    points: &[TimePoint],
+    window: Window,
+    is_counter: bool,
+    is_rate: bool,
+) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let first = &points[0];
+    let last = &points[points.len() - 1];
+    let mut result = last.value - first.value;
+    if is_counter {
+        for w in points.windows(2) {
+            if w[1].value < w[0].value {
+                result += w[0].value;
+            }
+        }
+    }
+
+    let mut duration_to_start = (first.timestamp - window.start) as f64 / 1000.0;
+    let duration_to_end = (window.end - last.timestamp) as f64 / 1000.0;
+    let sampled_interval = (last.timestamp - first.timestamp) as f64 / 1000.0;
+    let average_interval = sampled_interval / (points.len() - 1) as f64;
+
+    if is_counter && result > 0.0 && first.value >= 0.0 {
+        // counter can't go below zero, don't extrapolate beyond the point it would be zero
+        let duration_to_zero = sampled_interval * (first.value / result);
+        if duration_to_zero < duration_to_start {
+            duration_to_start = duration_to_zero;
+        }
+    }
+
+    let threshold = average_interval * 1.1;
+    let mut extrapolate_to = sampled_interval;
+    extrapolate_to += if duration_to_start < threshold {
+        duration_to_start
+    } else {
+        average_interval / 2.0
+    };
+    extrapolate_to += if duration_to_end < threshold {
+        duration_to_end
+    } else {
+        average_interval / 2.0
+    };
+    result *= extrapolate_to / sampled_interval;
+    if is_rate {
+        result /= (window.end - window.start) as f64 / 1000.0;
+    }
+    Some(result)
+}
+
+/// Per-second rate(or delta) of the last two samples
+fn instant_value(points: &[TimePoint], is_rate: bool) -> Option<f64> {
+    if points.len() < 2 {
+        return None;
+    }
+    let last = &points[points.len() - 1];
+    let previous = &points[points.len() - 2];
+    let mut result = if is_rate && last.value < previous.value {
+        // counter reset
+        last.value
+    } else {
+        last.value - previous.value
+    };
+    if is_rate {
+        let interval = (last.timestamp - previous.timestamp) as f64 / 1000.0;
+        if interval == 0.0 {
+            return None;
+        }
+        result /= interval;
+    }
+    Some(result)
+}
+
+/// φ-quantile of values using linear interpolation between ranks
+pub fn quantile(q: f64, mut values: Vec<f64>) -> f64 {
+    if values.is_empty() || q.is_nan() {
+        return std::f64::NAN;
+    }
+    if q < 0.0 {
+        return std::f64::NEG_INFINITY;
+    }
+    if q > 1.0 {
+        return std::f64::INFINITY;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = q * (values.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = std::cmp::min(lower + 1, values.len() - 1);
+    let weight = rank - rank.floor();
+    values[lower] * (1.0 - weight) + values[upper] * weight
+}
+
+/// Calculate quantile from buckets of Prometheus histogram, buckets are grouped by labels other than `le`.
+fn histogram_quantile(q: f64, vector: Vec<Sample>, t: Timestamp) -> Vec<Sample> {
+    let mut groups: BTreeMap<Metric, Vec<(f64, f64)>> = BTreeMap::new();
+    for sample in vector {
+        let upper_bound = match sample.metric.get(BUCKET_LABEL).map(|le| parse_float(le)) {
+            Some(Ok(upper_bound)) => upper_bound,
+            // ignore series that is not bucket
+            _ => continue,
+        };
+        let mut metric = drop_metric_name(sample.metric);
+        metric.remove(BUCKET_LABEL);
+        groups
+            .entry(metric)
+            .or_insert_with(Vec::new)
+            .push((upper_bound, sample.value));
+    }
+    groups
+        .into_iter()
+        .map(|(metric, buckets)| Sample {
+            metric,
+            timestamp: t,
+            value: bucket_quantile(q, buckets),
+        })
+        .collect()
+}
+
+/// Parse float in the format of Prometheus, which uses `+Inf`, `-Inf` and `NaN` for special values
+pub fn parse_float(s: &str) -> Result<f64> {
+    match s {
+        "+Inf" | "Inf" => Ok(std::f64::INFINITY),
+        "-Inf" => Ok(std::f64::NEG_INFINITY),
+        "NaN" => Ok(std::f64::NAN),
+        s => Ok(s.parse::<f64>()?),
+    }
+}
+
+/// Format float in the same way as Prometheus
+pub fn format_float(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v == std::f64::INFINITY {
+        "+Inf".to_string()
+    } else if v == std::f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        v.to_string()
+    }
+}
+
+/// Reference `bucketQuantile` in Prometheus' `promql/quantile.go`
+fn bucket_quantile(q: f64, mut buckets: Vec<(f64, f64)>) -> f64 {
+    if q < 0.0 {
+        return std::f64::NEG_INFINITY;
+    }
+    if q > 1.0 {
+        return std::f64::INFINITY;
+    }
+    buckets.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    if buckets.len() < 2 || buckets[buckets.len() - 1].0 != std::f64::INFINITY {
+        return std::f64::NAN;
+    }
+    // counts may be not monotonic because of the precision of float
+    let mut max = std::f64::NEG_INFINITY;
+    for bucket in buckets.iter_mut() {
+        if bucket.1 > max {
+            max = bucket.1;
+        } else {
+            bucket.1 = max;
+        }
+    }
+
+    let observations = buckets[buckets.len() - 1].1;
+    if observations == 0.0 {
+        return std::f64::NAN;
+    }
+    let mut rank = q * observations;
+    let b = buckets
+        .iter()
+        .position(|bucket| bucket.1 >= rank)
+        .unwrap_or(buckets.len() - 1);
+    if b == buckets.len() - 1 {
+        return buckets[buckets.len() - 2].0;
+    }
+    if b == 0 && buckets[0].0 <= 0.0 {
+        return buckets[0].0;
+    }
+    let mut bucket_start = 0.0;
+    let bucket_end = buckets[b].0;
+    let mut count = buckets[b].1;
+    if b > 0 {
+        bucket_start = buckets[b - 1].0;
+        count -= buckets[b - 1].1;
+        rank -= buckets[b - 1].1;
+    }
+    bucket_start + (bucket_end - bucket_start) * (rank / count)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::time_point::TimePoint;
+    use crate::promql::engine::{Metric, Sample, Value};
+    use crate::promql::functions::{
+        bucket_quantile, call, extrapolated_rate, format_float, quantile, Window,
+    };
+    use crate::Result;
+
+    fn assert_float_eq(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_extrapolated_rate() {
+        let window = Window {
+            start: 0,
+            end: 60000,
+        };
+        // counter increases 1 per second, sampled every 15s
+        let points = (1..5)
+            .map(|i| TimePoint::new(i * 15000, (i * 15) as f64))
+            .collect::<Vec<TimePoint>>();
+        // the first point is 15s away from start, which is close enough to be extrapolated
+        assert_float_eq(extrapolated_rate(&points, window, true, true).unwrap(), 1.0);
+        assert_float_eq(
+            extrapolated_rate(&points, window, true, false).unwrap(),
+            60.0,
+        );
+
+        // counter reset from 45 to 5
+        let points = vec![
+            TimePoint::new(15000, 15.0),
+            TimePoint::new(30000, 30.0),
+            TimePoint::new(45000, 45.0),
+            TimePoint::new(60000, 5.0),
+        ];
+        assert_float_eq(
+            extrapolated_rate(&points, window, true, false).unwrap(),
+            (45.0 - 15.0 + 5.0) * 60.0 / 45.0,
+        );
+        assert!(extrapolated_rate(&points[..1], window, true, true).is_none());
+    }
+
+    #[test]
+    fn test_irate() -> Result<()> {
+        let mut metric = Metric::new();
+        metric.insert("__name__".to_string(), "requests".to_string());
+        let series = crate::promql::engine::Series {
+            metric,
+            points: vec![
+                TimePoint::new(0, 10.0),
+                TimePoint::new(10000, 20.0),
+                TimePoint::new(20000, 5.0),
+            ],
+        };
+        let window = Window {
+            start: 0,
+            end: 20000,
+        };
+        match call(
+            "irate",
+            vec![Value::Matrix(vec![series])],
+            20000,
+            Some(window),
+        )? {
+            Value::Vector(v) => {
+                assert_eq!(v.len(), 1);
+                assert!(v[0].metric.is_empty());
+                assert_float_eq(v[0].value, 0.5);
+            }
+            _ => panic!("irate should return vector"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantile() {
+        assert_float_eq(quantile(0.5, vec![3.0, 1.0, 2.0]), 2.0);
+        assert_float_eq(quantile(0.75, vec![1.0, 2.0, 3.0, 4.0, 5.0]), 4.0);
+        assert_float_eq(quantile(0.1, vec![1.0, 2.0]), 1.1);
+        assert!(quantile(0.5, vec![]).is_nan());
+    }
+
+    #[test]
+    fn test_histogram_quantile() -> Result<()> {
+        let buckets = vec![
+            (0.1, 10.0),
+            (0.5, 60.0),
+            (1.0, 90.0),
+            (std::f64::INFINITY, 100.0),
+        ];
+        // rank 50 is in bucket (0.1, 0.5], 40 of 50 observations
+        assert_float_eq(bucket_quantile(0.5, buckets.clone()), 0.1 + 0.4 * 0.8);
+        // rank 99 is in the +Inf bucket
+        assert_float_eq(bucket_quantile(0.99, buckets.clone()), 1.0);
+        assert!(bucket_quantile(0.5, buckets[..2].to_vec()).is_nan());
+
+        let vector = buckets
+            .iter()
+            .map(|(le, count)| {
+                let mut metric = Metric::new();
+                metric.insert("__name__".to_string(), "latency_bucket".to_string());
+                metric.insert("le".to_string(), format_float(*le));
+                metric.insert("job".to_string(), "api".to_string());
+                Sample {
+                    metric,
+                    timestamp: 0,
+                    value: *count,
+                }
+            })
+            .collect();
+        match call(
+            "histogram_quantile",
+            vec![Value::Scalar(0.5), Value::Vector(vector)],
+            0,
+            None,
+        )? {
+            Value::Vector(v) => {
+                assert_eq!(v.len(), 1);
+                assert_eq!(v[0].metric.len(), 1);
+                assert_float_eq(v[0].value, 0.42);
+            }
+            _ => panic!("histogram_quantile should return vector"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,283 @@
+use crate::{MonolithErr, Result, Timestamp};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    /// Metric name, label name, function name or keyword
+    Identifier(String),
+    Number(f64),
+    /// Duration in milliseconds
+    Duration(Timestamp),
+    Str(String),
+    LeftParen,
+    RightParen,
+    LeftBrace,
+    RightBrace,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Eql,
+    Neq,
+    Lss,
+    Gtr,
+    Lte,
+    Gte,
+    Assign,
+    EqlRegex,
+    NeqRegex,
+    Eof,
+}
+
+/// Split the query into tokens, the last token is always `Token::Eof`
+pub fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars = input.chars().collect::<Vec<char>>();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+        if c == '#' {
+            // comment till end of line
+            while pos < chars.len() && chars[pos] != '\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let next = chars.get(pos + 1).cloned();
+        let (token, len) = match c {
+            '(' => (Token::LeftParen, 1),
+            ')' => (Token::RightParen, 1),
+            '{' => (Token::LeftBrace, 1),
+            '}' => (Token::RightBrace, 1),
+            '[' => (Token::LeftBracket, 1),
+            ']' => (Token::RightBracket, 1),
+            ',' => (Token::Comma, 1),
+            '+' => (Token::Add, 1),
+            '-' => (Token::Sub, 1),
+            '*' => (Token::Mul, 1),
+            '/' => (Token::Div, 1),
+            '%' => (Token::Mod, 1),
+            '^' => (Token::Pow, 1),
+            '=' => match next {
+                Some('=') => (Token::Eql, 2),
+                Some('~') => (Token::EqlRegex, 2),
+                _ => (Token::Assign, 1),
+            },
+            '!' => match next {
+                Some('=') => (Token::Neq, 2),
+                Some('~') => (Token::NeqRegex, 2),
+                _ => {
+                    return Err(parse_err(format!(
+                        "unexpected character after ! at {}",
+                        pos
+                    )))
+                }
+            },
+            '<' => match next {
+                Some('=') => (Token::Lte, 2),
+                _ => (Token::Lss, 1),
+            },
+            '>' => match next {
+                Some('=') => (Token::Gte, 2),
+                _ => (Token::Gtr, 1),
+            },
+            '"' | '\'' | '`' => lex_string(&chars[pos..])?,
+            c if c.is_ascii_digit()
+                || (c == '.' && next.map(|n| n.is_ascii_digit()).unwrap_or(false)) =>
+            {
+                lex_number_or_duration(&chars[pos..])?
+            }
+            c if c.is_ascii_alphabetic() || c == '_' || c == ':' => {
+                let len = chars[pos..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_' || **c == ':')
+                    .count();
+                let ident = chars[pos..pos + len].iter().collect::<String>();
+                (Token::Identifier(ident), len)
+            }
+            c => return Err(parse_err(format!("unexpected character {} at {}", c, pos))),
+        };
+        tokens.push(token);
+        pos += len;
+    }
+    tokens.push(Token::Eof);
+    Ok(tokens)
+}
+
+///
+/// Parse duration like `1h30m`, units are ms, s, m, h, d, w and y.
+pub fn parse_duration(s: &str) -> Result<Timestamp> {
+    let chars = s.chars().collect::<Vec<char>>();
+    match lex_number_or_duration(chars.as_slice())? {
+        (Token::Duration(d), len) if len == chars.len() => Ok(d),
+        _ => Err(parse_err(format!("invalid duration {}", s))),
+    }
+}
+
+fn parse_err(reason: String) -> MonolithErr {
+    MonolithErr::QueryParseErr(reason)
+}
+
+fn lex_string(chars: &[char]) -> Result<(Token, usize)> {
+    let quote = chars[0];
+    let mut res = String::new();
+    let mut pos = 1;
+    loop {
+        match chars.get(pos) {
+            None => return Err(parse_err("unterminated string".to_string())),
+            Some(c) if *c == quote => return Ok((Token::Str(res), pos + 1)),
+            Some('\\') if quote != '`' => {
+                pos += 1;
+                match chars.get(pos) {
+                    Some('n') => res.push('\n'),
+                    Some('t') => res.push('\t'),
+                    Some('r') => res.push('\r'),
+                    Some(c) if *c == '\\' || *c == '"' || *c == '\'' => res.push(*c),
+                    _ => return Err(parse_err("invalid escape sequence in string".to_string())),
+                }
+            }
+            Some(c) => res.push(*c),
+        }
+        pos += 1;
+    }
+}
+
+fn duration_unit(chars: &[char]) -> Option<(Timestamp, usize)> {
+    match (chars.get(0), chars.get(1)) {
+        (Some('m'), Some('s')) => Some((1, 2)),
+        (Some('s'), _) => Some((1000, 1)),
+        (Some('m'), _) => Some((60 * 1000, 1)),
+        (Some('h'), _) => Some((60 * 60 * 1000, 1)),
+        (Some('d'), _) => Some((24 * 60 * 60 * 1000, 1)),
+        (Some('w'), _) => Some((7 * 24 * 60 * 60 * 1000, 1)),
+        (Some('y'), _) => Some((365 * 24 * 60 * 60 * 1000, 1)),
+        _ => None,
+    }
+}
+
+fn lex_number_or_duration(chars: &[char]) -> Result<(Token, usize)> {
+    let digits = |from: usize| {
+        chars[from..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count()
+    };
+    // hex number
+    if chars.len() > 2 && chars[0] == '0' && (chars[1] == 'x' || chars[1] == 'X') {
+        let len = chars[2..]
+            .iter()
+            .take_while(|c| c.is_ascii_hexdigit())
+            .count();
+        let s = chars[2..2 + len].iter().collect::<String>();
+        let v = u64::from_str_radix(s.as_str(), 16)
+            .map_err(|_| parse_err(format!("invalid number 0x{}", s)))?;
+        return Ok((Token::Number(v as f64), len + 2));
+    }
+
+    let int_len = digits(0);
+    // duration, e.g. 5m or 1h30m
+    if int_len > 0 && duration_unit(&chars[int_len..]).is_some() {
+        let mut pos = 0;
+        let mut total = 0;
+        loop {
+            let len = digits(pos);
+            if len == 0 {
+                break;
+            }
+            let num = chars[pos..pos + len]
+                .iter()
+                .collect::<String>()
+                .parse::<Timestamp>()
+                .map_err(|_| parse_err("invalid duration".to_string()))?;
+            match duration_unit(&chars[pos + len..]) {
+                Some((unit, unit_len)) => {
+                    total += num * unit;
+                    pos += len + unit_len;
+                }
+                None => return Err(parse_err("invalid duration".to_string())),
+            }
+        }
+        return Ok((Token::Duration(total), pos));
+    }
+
+    let mut len = int_len;
+    if chars.get(len) == Some(&'.') {
+        len += 1 + digits(len + 1);
+    }
+    if chars.get(len) == Some(&'e') || chars.get(len) == Some(&'E') {
+        let mut exp_len = 1;
+        if chars.get(len + 1) == Some(&'+') || chars.get(len + 1) == Some(&'-') {
+            exp_len += 1;
+        }
+        let exp_digits = digits(len + exp_len);
+        if exp_digits > 0 {
+            len += exp_len + exp_digits;
+        }
+    }
+    let s = chars[..len].iter().collect::<String>();
+    let v = s
+        .parse::<f64>()
+        .map_err(|_| parse_err(format!("invalid number {}", s)))?;
+    Ok((Token::Number(v), len))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::promql::lexer::{parse_duration, tokenize, Token};
+    use crate::Result;
+
+    #[test]
+    fn test_tokenize() -> Result<()> {
+        let tokens = tokenize(r#"rate(http_requests_total{job=~"api"}[5m]) >= 0.5e1"#)?;
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Identifier("rate".to_string()),
+                Token::LeftParen,
+                Token::Identifier("http_requests_total".to_string()),
+                Token::LeftBrace,
+                Token::Identifier("job".to_string()),
+                Token::EqlRegex,
+                Token::Str("api".to_string()),
+                Token::RightBrace,
+                Token::LeftBracket,
+                Token::Duration(300000),
+                Token::RightBracket,
+                Token::RightParen,
+                Token::Gte,
+                Token::Number(5.0),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            tokenize("0x1f != .5")?,
+            vec![
+                Token::Number(31.0),
+                Token::Neq,
+                Token::Number(0.5),
+                Token::Eof
+            ]
+        );
+        assert!(tokenize("a ! b").is_err());
+        assert!(tokenize(r#"a{b="c}"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        assert_eq!(parse_duration("1h30m")?, 5400000);
+        assert_eq!(parse_duration("100ms")?, 100);
+        assert_eq!(parse_duration("15s")?, 15000);
+        assert!(parse_duration("15").is_err());
+        assert!(parse_duration("15x").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+//!
+//! PromQL support, used to serve `/api/v1/query` and `/api/v1/query_range`.
+//!
+//! Subqueries and the `@` modifier are not supported.
+
+pub mod ast;
+mod engine;
+mod functions;
+mod lexer;
+mod operators;
+mod parser;
+
+pub use engine::{Engine, Metric, Sample, Series, Value, DEFAULT_LOOKBACK_DELTA};
+pub use functions::format_float;
+pub use lexer::parse_duration;
+pub use parser::parse;
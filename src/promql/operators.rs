@@ -0,0 +1,608 @@
+use crate::common::selector::METRIC_NAME_LABEL;
+use crate::promql::ast::{AggregateOp, BinaryOp, Cardinality, VectorMatching};
+use crate::promql::engine::{Metric, Sample, Value};
+use crate::promql::functions::{drop_metric_name, format_float, quantile};
+use crate::{MonolithErr, Result, Timestamp};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+fn exec_err(reason: String) -> MonolithErr {
+    MonolithErr::QueryExecErr(reason)
+}
+
+fn arithmetic(op: BinaryOp, l: f64, r: f64) -> f64 {
+    match op {
+        BinaryOp::Add => l + r,
+        BinaryOp::Sub => l - r,
+        BinaryOp::Mul => l * r,
+        BinaryOp::Div => l / r,
+        BinaryOp::Mod => l % r,
+        BinaryOp::Pow => l.powf(r),
+        _ => unreachable!("{:?} is not arithmetic operator", op),
+    }
+}
+
+fn compare(op: BinaryOp, l: f64, r: f64) -> bool {
+    match op {
+        BinaryOp::Eql => l == r,
+        BinaryOp::Neq => l != r,
+        BinaryOp::Lss => l < r,
+        BinaryOp::Gtr => l > r,
+        BinaryOp::Lte => l <= r,
+        BinaryOp::Gte => l >= r,
+        _ => unreachable!("{:?} is not comparison operator", op),
+    }
+}
+
+/// Apply `op` on two values, return `None` if the comparison fails and it's used as filter.
+fn apply(op: BinaryOp, l: f64, r: f64, return_bool: bool) -> Option<f64> {
+    if op.is_comparison() {
+        let res = compare(op, l, r);
+        if return_bool {
+            Some(if res { 1.0 } else { 0.0 })
+        } else if res {
+            Some(l)
+        } else {
+            None
+        }
+    } else {
+        Some(arithmetic(op, l, r))
+    }
+}
+
+/// Evaluate binary operation between two values
+pub fn binary(
+    op: BinaryOp,
+    lhs: Value,
+    rhs: Value,
+    return_bool: bool,
+    matching: &VectorMatching,
+) -> Result<Value> {
+    match (lhs, rhs) {
+        (Value::Scalar(l), Value::Scalar(r)) => {
+            if op.is_set_operator() {
+                return Err(exec_err(format!(
+                    "set operator {:?} not allowed between scalars",
+                    op
+                )));
+            }
+            if op.is_comparison() && !return_bool {
+                return Err(exec_err(
+                    "comparisons between scalars must use bool modifier".to_string(),
+                ));
+            }
+            Ok(Value::Scalar(apply(op, l, r, return_bool).unwrap()))
+        }
+        (Value::Vector(l), Value::Scalar(r)) => vector_scalar(op, l, r, false, return_bool),
+        (Value::Scalar(l), Value::Vector(r)) => vector_scalar(op, r, l, true, return_bool),
+        (Value::Vector(l), Value::Vector(r)) => {
+            if op.is_set_operator() {
+                Ok(Value::Vector(set_operation(op, l, r, matching)))
+            } else {
+                Ok(Value::Vector(vector_vector(
+                    op,
+                    l,
+                    r,
+                    return_bool,
+                    matching,
+                )?))
+            }
+        }
+        (l, r) => Err(exec_err(format!(
+            "binary operator {:?} not allowed between {} and {}",
+            op,
+            l.type_name(),
+            r.type_name()
+        ))),
+    }
+}
+
+/// `swap` is true if the scalar is on the left side
+fn vector_scalar(
+    op: BinaryOp,
+    vector: Vec<Sample>,
+    scalar: f64,
+    swap: bool,
+    return_bool: bool,
+) -> Result<Value> {
+    if op.is_set_operator() {
+        return Err(exec_err(format!(
+            "set operator {:?} not allowed between vector and scalar",
+            op
+        )));
+    }
+    let mut res = Vec::new();
+    for sample in vector {
+        let (l, r) = if swap {
+            (scalar, sample.value)
+        } else {
+            (sample.value, scalar)
+        };
+        if let Some(mut value) = apply(op, l, r, return_bool) {
+            // always keep the value of vector for filtering comparison
+            if op.is_comparison() && !return_bool {
+                value = sample.value;
+            }
+            let metric = if !op.is_comparison() || return_bool {
+                drop_metric_name(sample.metric)
+            } else {
+                sample.metric
+            };
+            res.push(Sample {
+                metric,
+                timestamp: sample.timestamp,
+                value,
+            });
+        }
+    }
+    Ok(Value::Vector(res))
+}
+
+/// Labels used to match samples from two sides
+fn signature(metric: &Metric, matching: &VectorMatching) -> Vec<(String, String)> {
+    metric
+        .iter()
+        .filter(|(k, _)| {
+            if matching.on {
+                matching.labels.contains(k)
+            } else {
+                k.as_str() != METRIC_NAME_LABEL && !matching.labels.contains(k)
+            }
+        })
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+fn set_operation(
+    op: BinaryOp,
+    lhs: Vec<Sample>,
+    rhs: Vec<Sample>,
+    matching: &VectorMatching,
+) -> Vec<Sample> {
+    let rhs_sigs = rhs
+        .iter()
+        .map(|s| signature(&s.metric, matching))
+        .collect::<HashSet<_>>();
+    match op {
+        BinaryOp::And => lhs
+            .into_iter()
+            .filter(|s| rhs_sigs.contains(&signature(&s.metric, matching)))
+            .collect(),
+        BinaryOp::Unless => lhs
+            .into_iter()
+            .filter(|s| !rhs_sigs.contains(&signature(&s.metric, matching)))
+            .collect(),
+        BinaryOp::Or => {
+            let lhs_sigs = lhs
+                .iter()
+                .map(|s| signature(&s.metric, matching))
+                .collect::<HashSet<_>>();
+            let mut res = lhs;
+            res.extend(
+                rhs.into_iter()
+                    .filter(|s| !lhs_sigs.contains(&signature(&s.metric, matching))),
+            );
+            res
+        }
+        _ => unreachable!("{:?} is not set operator", op),
+    }
+}
+
+fn vector_vector(
+    op: BinaryOp,
+    lhs: Vec<Sample>,
+    rhs: Vec<Sample>,
+    return_bool: bool,
+    matching: &VectorMatching,
+) -> Result<Vec<Sample>> {
+    // "one" side of the matching, which must have unique signature
+    let swapped = matching.card == Cardinality::OneToMany;
+    let (many, one) = if swapped { (rhs, lhs) } else { (lhs, rhs) };
+
+    let mut one_side = HashMap::new();
+    for sample in one.iter() {
+        if one_side
+            .insert(signature(&sample.metric, matching), sample)
+            .is_some()
+        {
+            return Err(exec_err(
+                "found duplicate series for the match group, many-to-many matching not allowed"
+                    .to_string(),
+            ));
+        }
+    }
+
+    let mut matched_sigs = HashSet::new();
+    let mut result_metrics = HashSet::new();
+    let mut res = Vec::new();
+    for sample in many {
+        let sig = signature(&sample.metric, matching);
+        let other = match one_side.get(&sig) {
+            Some(other) => other,
+            None => continue,
+        };
+        if matching.card == Cardinality::OneToOne && !matched_sigs.insert(sig) {
+            return Err(exec_err(
+                "multiple matches for labels, many-to-one matching must be explicit (group_left/group_right)"
+                    .to_string(),
+            ));
+        }
+        let (l, r) = if swapped {
+            (other.value, sample.value)
+        } else {
+            (sample.value, other.value)
+        };
+        let value = match apply(op, l, r, return_bool) {
+            Some(value) => value,
+            None => continue,
+        };
+        let metric = result_metric(sample.metric, &other.metric, op, return_bool, matching);
+        if !result_metrics.insert(metric.clone()) {
+            return Err(exec_err(
+                "multiple matches for labels, grouping labels must ensure unique matches"
+                    .to_string(),
+            ));
+        }
+        res.push(Sample {
+            metric,
+            timestamp: sample.timestamp,
+            value,
+        });
+    }
+    Ok(res)
+}
+
+fn result_metric(
+    mut metric: Metric,
+    other: &Metric,
+    op: BinaryOp,
+    return_bool: bool,
+    matching: &VectorMatching,
+) -> Metric {
+    if !op.is_comparison() || return_bool {
+        metric.remove(METRIC_NAME_LABEL);
+    }
+    if matching.card == Cardinality::OneToOne {
+        if matching.on {
+            metric.retain(|k, _| matching.labels.contains(k));
+        } else {
+            for label in matching.labels.iter() {
+                metric.remove(label);
+            }
+        }
+    } else {
+        for label in matching.include.iter() {
+            match other.get(label) {
+                Some(value) if !value.is_empty() => {
+                    metric.insert(label.clone(), value.clone());
+                }
+                _ => {
+                    metric.remove(label);
+                }
+            }
+        }
+    }
+    metric
+}
+
+/// Evaluate aggregation at time `t`
+pub fn aggregate(
+    op: AggregateOp,
+    vector: Vec<Sample>,
+    param: Option<Value>,
+    grouping: &[String],
+    without: bool,
+    t: Timestamp,
+) -> Result<Vec<Sample>> {
+    let mut groups: BTreeMap<Metric, Vec<Sample>> = BTreeMap::new();
+    for sample in vector {
+        let key = sample
+            .metric
+            .iter()
+            .filter(|(k, _)| {
+                if without {
+                    k.as_str() != METRIC_NAME_LABEL && !grouping.contains(k)
+                } else {
+                    grouping.contains(k)
+                }
+            })
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Metric>();
+        groups.entry(key).or_insert_with(Vec::new).push(sample);
+    }
+
+    let scalar_param = || match &param {
+        Some(Value::Scalar(v)) => Ok(*v),
+        _ => Err(exec_err(format!("{:?} expects scalar parameter", op))),
+    };
+
+    let mut res = Vec::new();
+    for (metric, samples) in groups {
+        let values = samples.iter().map(|s| s.value).collect::<Vec<f64>>();
+        let value = match op {
+            AggregateOp::Sum => values.iter().sum(),
+            AggregateOp::Avg => values.iter().sum::<f64>() / values.len() as f64,
+            AggregateOp::Count => values.len() as f64,
+            AggregateOp::Group => 1.0,
+            AggregateOp::Min => values.iter().cloned().fold(std::f64::NAN, f64::min),
+            AggregateOp::Max => values.iter().cloned().fold(std::f64::NAN, f64::max),
+            AggregateOp::Stddev | AggregateOp::Stdvar => {
+                let mean = values.iter().sum::<f64>() / values.len() as f64;
+                let var =
+                    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+                if op == AggregateOp::Stddev {
+                    var.sqrt()
+                } else {
+                    var
+                }
+            }
+            AggregateOp::Quantile => quantile(scalar_param()?, values),
+            AggregateOp::Topk | AggregateOp::Bottomk => {
+                let k = scalar_param()?;
+                if k < 1.0 {
+                    continue;
+                }
+                let mut samples = samples;
+                samples.sort_by(|a, b| {
+                    let ord = a
+                        .value
+                        .partial_cmp(&b.value)
+                        .unwrap_or(std::cmp::Ordering::Equal);
+                    if op == AggregateOp::Topk {
+                        ord.reverse()
+                    } else {
+                        ord
+                    }
+                });
+                res.extend(samples.into_iter().take(k as usize));
+                continue;
+            }
+            AggregateOp::CountValues => {
+                let label = match &param {
+                    Some(Value::String(label)) => label.clone(),
+                    _ => {
+                        return Err(exec_err(
+                            "count_values expects string parameter".to_string(),
+                        ))
+                    }
+                };
+                let mut counts: BTreeMap<String, f64> = BTreeMap::new();
+                for v in values {
+                    *counts.entry(format_float(v)).or_insert(0.0) += 1.0;
+                }
+                for (v, count) in counts {
+                    let mut metric = metric.clone();
+                    metric.insert(label.clone(), v);
+                    res.push(Sample {
+                        metric,
+                        timestamp: t,
+                        value: count,
+                    });
+                }
+                continue;
+            }
+        };
+        res.push(Sample {
+            metric,
+            timestamp: t,
+            value,
+        });
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::promql::ast::{AggregateOp, BinaryOp, Cardinality, VectorMatching};
+    use crate::promql::engine::{Metric, Sample, Value};
+    use crate::promql::operators::{aggregate, binary};
+    use crate::Result;
+
+    fn sample(labels: Vec<(&str, &str)>, value: f64) -> Sample {
+        Sample {
+            metric: labels
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Metric>(),
+            timestamp: 0,
+            value,
+        }
+    }
+
+    fn vector(value: Value) -> Vec<Sample> {
+        match value {
+            Value::Vector(v) => v,
+            _ => panic!("expect vector"),
+        }
+    }
+
+    #[test]
+    fn test_vector_scalar() -> Result<()> {
+        let v = vec![
+            sample(vec![("__name__", "up"), ("job", "a")], 1.0),
+            sample(vec![("__name__", "up"), ("job", "b")], 0.0),
+        ];
+        let res = vector(binary(
+            BinaryOp::Mul,
+            Value::Vector(v.clone()),
+            Value::Scalar(2.0),
+            false,
+            &VectorMatching::default(),
+        )?);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].value, 2.0);
+        assert!(res[0].metric.get("__name__").is_none());
+
+        let res = vector(binary(
+            BinaryOp::Lss,
+            Value::Scalar(0.5),
+            Value::Vector(v.clone()),
+            false,
+            &VectorMatching::default(),
+        )?);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].value, 1.0);
+        assert_eq!(res[0].metric.get("__name__").unwrap(), "up");
+
+        let res = vector(binary(
+            BinaryOp::Gtr,
+            Value::Vector(v),
+            Value::Scalar(0.5),
+            true,
+            &VectorMatching::default(),
+        )?);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[1].value, 0.0);
+        assert!(binary(
+            BinaryOp::Gtr,
+            Value::Scalar(1.0),
+            Value::Scalar(0.5),
+            false,
+            &VectorMatching::default()
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_vector_matching() -> Result<()> {
+        let errors = vec![
+            sample(
+                vec![("__name__", "errors"), ("job", "a"), ("code", "500")],
+                2.0,
+            ),
+            sample(
+                vec![("__name__", "errors"), ("job", "a"), ("code", "503")],
+                4.0,
+            ),
+            sample(
+                vec![("__name__", "errors"), ("job", "b"), ("code", "500")],
+                1.0,
+            ),
+        ];
+        let total = vec![
+            sample(
+                vec![("__name__", "total"), ("job", "a"), ("version", "1")],
+                10.0,
+            ),
+            sample(
+                vec![("__name__", "total"), ("job", "b"), ("version", "2")],
+                5.0,
+            ),
+        ];
+        let on_job = VectorMatching {
+            card: Cardinality::OneToOne,
+            on: true,
+            labels: vec!["job".to_string()],
+            include: vec![],
+        };
+        // one-to-one matching fails because there are two errors series for job a
+        assert!(binary(
+            BinaryOp::Div,
+            Value::Vector(errors.clone()),
+            Value::Vector(total.clone()),
+            false,
+            &on_job
+        )
+        .is_err());
+
+        let group_left = VectorMatching {
+            card: Cardinality::ManyToOne,
+            include: vec!["version".to_string()],
+            ..on_job.clone()
+        };
+        let res = vector(binary(
+            BinaryOp::Div,
+            Value::Vector(errors.clone()),
+            Value::Vector(total.clone()),
+            false,
+            &group_left,
+        )?);
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[1].value, 0.4);
+        assert_eq!(res[1].metric.get("code").unwrap(), "503");
+        assert_eq!(res[1].metric.get("version").unwrap(), "1");
+        assert!(res[1].metric.get("__name__").is_none());
+
+        let group_right = VectorMatching {
+            card: Cardinality::OneToMany,
+            include: vec![],
+            ..on_job.clone()
+        };
+        let res = vector(binary(
+            BinaryOp::Sub,
+            Value::Vector(total.clone()),
+            Value::Vector(errors.clone()),
+            false,
+            &group_right,
+        )?);
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].value, 8.0);
+
+        let set = VectorMatching {
+            card: Cardinality::ManyToMany,
+            ..on_job
+        };
+        let res = vector(binary(
+            BinaryOp::Unless,
+            Value::Vector(total),
+            Value::Vector(errors[2..].to_vec()),
+            false,
+            &set,
+        )?);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].metric.get("job").unwrap(), "a");
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate() -> Result<()> {
+        let v = vec![
+            sample(
+                vec![("__name__", "up"), ("job", "a"), ("instance", "1")],
+                1.0,
+            ),
+            sample(
+                vec![("__name__", "up"), ("job", "a"), ("instance", "2")],
+                3.0,
+            ),
+            sample(
+                vec![("__name__", "up"), ("job", "b"), ("instance", "1")],
+                5.0,
+            ),
+        ];
+        let by_job = vec!["job".to_string()];
+        let res = aggregate(AggregateOp::Sum, v.clone(), None, &by_job, false, 0)?;
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].value, 4.0);
+        assert_eq!(res[0].metric.len(), 1);
+
+        let instance = vec!["instance".to_string()];
+        let res = aggregate(AggregateOp::Avg, v.clone(), None, &instance, true, 0)?;
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].value, 2.0);
+
+        let res = aggregate(
+            AggregateOp::Topk,
+            v.clone(),
+            Some(Value::Scalar(1.0)),
+            &[],
+            false,
+            0,
+        )?;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].value, 5.0);
+        assert_eq!(res[0].metric.len(), 3);
+
+        let res = aggregate(
+            AggregateOp::CountValues,
+            v,
+            Some(Value::String("value".to_string())),
+            &[],
+            false,
+            0,
+        )?;
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].metric.get("value").unwrap(), "1");
+        Ok(())
+    }
+}
@@ -0,0 +1,517 @@
+use crate::common::label::{LabelMatcher, MatcherType};
+use crate::common::selector::{is_valid_label_name, METRIC_NAME_LABEL};
+use crate::promql::ast::{
+    AggregateOp, BinaryOp, Cardinality, Expr, VectorMatching, VectorSelector,
+};
+use crate::promql::lexer::{tokenize, Token};
+use crate::{MonolithErr, Result};
+
+/// Parse PromQL expression into syntax tree
+pub fn parse(input: &str) -> Result<Expr> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let expr = parser.expr(0)?;
+    match parser.peek() {
+        Token::Eof => Ok(expr),
+        token => Err(parse_err(format!("unexpected {:?}", token))),
+    }
+}
+
+fn parse_err(reason: String) -> MonolithErr {
+    MonolithErr::QueryParseErr(reason)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        let token = self.next();
+        if token != expected {
+            return Err(parse_err(format!(
+                "expect {:?} but got {:?}",
+                expected, token
+            )));
+        }
+        Ok(())
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Token::Identifier(ident) => ident == keyword,
+            _ => false,
+        }
+    }
+
+    fn binary_op(&self) -> Option<BinaryOp> {
+        match self.peek() {
+            Token::Add => Some(BinaryOp::Add),
+            Token::Sub => Some(BinaryOp::Sub),
+            Token::Mul => Some(BinaryOp::Mul),
+            Token::Div => Some(BinaryOp::Div),
+            Token::Mod => Some(BinaryOp::Mod),
+            Token::Pow => Some(BinaryOp::Pow),
+            Token::Eql => Some(BinaryOp::Eql),
+            Token::Neq => Some(BinaryOp::Neq),
+            Token::Lss => Some(BinaryOp::Lss),
+            Token::Gtr => Some(BinaryOp::Gtr),
+            Token::Lte => Some(BinaryOp::Lte),
+            Token::Gte => Some(BinaryOp::Gte),
+            Token::Identifier(ident) => match ident.as_str() {
+                "and" => Some(BinaryOp::And),
+                "or" => Some(BinaryOp::Or),
+                "unless" => Some(BinaryOp::Unless),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Parse binary expression whose operator precedence is at least `min_precedence`
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.binary_op() {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.next();
+            let return_bool = if self.is_keyword("bool") {
+                if !op.is_comparison() {
+                    return Err(parse_err(
+                        "bool modifier can only be used on comparison operators".to_string(),
+                    ));
+                }
+                self.next();
+                true
+            } else {
+                false
+            };
+            let matching = self.vector_matching(op)?;
+            let next_precedence = if op.is_right_associative() {
+                op.precedence()
+            } else {
+                op.precedence() + 1
+            };
+            let rhs = self.expr(next_precedence)?;
+            lhs = Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+                return_bool,
+                matching,
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Token::Sub => {
+                self.next();
+                // unary operators bind looser than ^, -2^2 is -(2^2)
+                let expr = self.expr(BinaryOp::Pow.precedence())?;
+                Ok(match expr {
+                    Expr::NumberLiteral(v) => Expr::NumberLiteral(-v),
+                    expr => Expr::Negative(Box::new(expr)),
+                })
+            }
+            Token::Add => {
+                self.next();
+                self.expr(BinaryOp::Pow.precedence())
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn vector_matching(&mut self, op: BinaryOp) -> Result<VectorMatching> {
+        let mut matching = VectorMatching::default();
+        if op.is_set_operator() {
+            matching.card = Cardinality::ManyToMany;
+        }
+        if self.is_keyword("on") || self.is_keyword("ignoring") {
+            matching.on = self.is_keyword("on");
+            self.next();
+            matching.labels = self.label_list()?;
+            if self.is_keyword("group_left") || self.is_keyword("group_right") {
+                if op.is_set_operator() {
+                    return Err(parse_err(
+                        "no grouping allowed for set operations".to_string(),
+                    ));
+                }
+                matching.card = if self.is_keyword("group_left") {
+                    Cardinality::ManyToOne
+                } else {
+                    Cardinality::OneToMany
+                };
+                self.next();
+                if *self.peek() == Token::LeftParen {
+                    matching.include = self.label_list()?;
+                }
+            }
+        }
+        Ok(matching)
+    }
+
+    /// Parse `(label1, label2, ...)`
+    fn label_list(&mut self) -> Result<Vec<String>> {
+        self.expect(Token::LeftParen)?;
+        let mut res = Vec::new();
+        loop {
+            match self.next() {
+                Token::RightParen => break,
+                Token::Identifier(name) if is_valid_label_name(name.as_str()) => {
+                    res.push(name);
+                    match self.next() {
+                        Token::Comma => continue,
+                        Token::RightParen => break,
+                        token => {
+                            return Err(parse_err(format!("unexpected {:?} in label list", token)))
+                        }
+                    }
+                }
+                token => return Err(parse_err(format!("unexpected {:?} in label list", token))),
+            }
+        }
+        Ok(res)
+    }
+
+    /// Parse primary expression followed by range and offset modifiers
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            if *self.peek() == Token::LeftBracket {
+                self.next();
+                let range = match self.next() {
+                    Token::Duration(d) => d,
+                    token => {
+                        return Err(parse_err(format!(
+                            "expect duration in range but got {:?}",
+                            token
+                        )))
+                    }
+                };
+                self.expect(Token::RightBracket)?;
+                expr = match expr {
+                    Expr::VectorSelector(selector) => Expr::MatrixSelector(selector, range),
+                    _ => {
+                        return Err(parse_err(
+                            "range can only be used on vector selector".to_string(),
+                        ))
+                    }
+                };
+            } else if self.is_keyword("offset") {
+                self.next();
+                let offset = match self.next() {
+                    Token::Duration(d) => d,
+                    token => {
+                        return Err(parse_err(format!(
+                            "expect duration after offset but got {:?}",
+                            token
+                        )))
+                    }
+                };
+                match &mut expr {
+                    Expr::VectorSelector(selector) | Expr::MatrixSelector(selector, _) => {
+                        selector.offset = offset
+                    }
+                    _ => return Err(parse_err("offset can only be used on selector".to_string())),
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        if *self.peek() == Token::LeftBrace {
+            return self.vector_selector(None);
+        }
+        match self.next() {
+            Token::Number(v) => Ok(Expr::NumberLiteral(v)),
+            Token::Str(s) => Ok(Expr::StringLiteral(s)),
+            Token::LeftParen => {
+                let expr = self.expr(0)?;
+                self.expect(Token::RightParen)?;
+                Ok(Expr::Paren(Box::new(expr)))
+            }
+            Token::Identifier(ident) => {
+                let lower = ident.to_lowercase();
+                if lower == "inf" {
+                    return Ok(Expr::NumberLiteral(std::f64::INFINITY));
+                }
+                if lower == "nan" {
+                    return Ok(Expr::NumberLiteral(std::f64::NAN));
+                }
+                if let Some(op) = AggregateOp::from_name(ident.as_str()) {
+                    if *self.peek() == Token::LeftParen
+                        || self.is_keyword("by")
+                        || self.is_keyword("without")
+                    {
+                        return self.aggregate(op);
+                    }
+                }
+                if *self.peek() == Token::LeftParen {
+                    return self.call(ident);
+                }
+                self.vector_selector(Some(ident))
+            }
+            token => Err(parse_err(format!("unexpected {:?}", token))),
+        }
+    }
+
+    /// Parse optional label matchers in `{}`, with the metric name before it if any
+    fn vector_selector(&mut self, name: Option<String>) -> Result<Expr> {
+        let mut matchers = Vec::new();
+        if let Some(name) = name {
+            matchers.push(LabelMatcher::new(
+                MatcherType::Equal,
+                METRIC_NAME_LABEL,
+                name.as_str(),
+            )?);
+        }
+        if *self.peek() == Token::LeftBrace {
+            self.next();
+            self.label_matchers(&mut matchers)?;
+        }
+        if matchers.is_empty() || matchers.iter().all(|m| m.matches_empty()) {
+            return Err(parse_err(
+                "vector selector must contain at least one non-empty matcher".to_string(),
+            ));
+        }
+        Ok(Expr::VectorSelector(VectorSelector {
+            matchers,
+            offset: 0,
+        }))
+    }
+
+    /// Parse label matchers after `{` till `}`
+    fn label_matchers(&mut self, matchers: &mut Vec<LabelMatcher>) -> Result<()> {
+        loop {
+            let label = match self.next() {
+                Token::RightBrace => break,
+                Token::Identifier(label) if is_valid_label_name(label.as_str()) => label,
+                token => return Err(parse_err(format!("unexpected {:?} in selector", token))),
+            };
+            let matcher_type = match self.next() {
+                Token::Assign => MatcherType::Equal,
+                Token::Neq => MatcherType::NotEqual,
+                Token::EqlRegex => MatcherType::RegexMatch,
+                Token::NeqRegex => MatcherType::RegexNotMatch,
+                token => {
+                    return Err(parse_err(format!(
+                        "unexpected {:?} after label {}",
+                        token, label
+                    )))
+                }
+            };
+            let value = match self.next() {
+                Token::Str(value) => value,
+                token => {
+                    return Err(parse_err(format!(
+                        "expect string for label {} but got {:?}",
+                        label, token
+                    )))
+                }
+            };
+            matchers.push(LabelMatcher::new(
+                matcher_type,
+                label.as_str(),
+                value.as_str(),
+            )?);
+            match self.next() {
+                Token::Comma => continue,
+                Token::RightBrace => break,
+                token => return Err(parse_err(format!("unexpected {:?} in selector", token))),
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, name: String) -> Result<Expr> {
+        self.expect(Token::LeftParen)?;
+        let mut args = Vec::new();
+        if *self.peek() == Token::RightParen {
+            self.next();
+            return Ok(Expr::Call(name, args));
+        }
+        loop {
+            args.push(self.expr(0)?);
+            match self.next() {
+                Token::Comma => continue,
+                Token::RightParen => break,
+                token => {
+                    return Err(parse_err(format!(
+                        "unexpected {:?} in arguments of {}",
+                        token, name
+                    )))
+                }
+            }
+        }
+        Ok(Expr::Call(name, args))
+    }
+
+    /// Parse `op [by|without (labels)] ([param,] expr) [by|without (labels)]`
+    fn aggregate(&mut self, op: AggregateOp) -> Result<Expr> {
+        let mut grouping = None;
+        if self.is_keyword("by") || self.is_keyword("without") {
+            grouping = Some(self.grouping()?);
+        }
+        self.expect(Token::LeftParen)?;
+        let param = if op.has_param() {
+            let param = self.expr(0)?;
+            self.expect(Token::Comma)?;
+            Some(Box::new(param))
+        } else {
+            None
+        };
+        let expr = self.expr(0)?;
+        self.expect(Token::RightParen)?;
+        if grouping.is_none() && (self.is_keyword("by") || self.is_keyword("without")) {
+            grouping = Some(self.grouping()?);
+        }
+        let (without, grouping) = grouping.unwrap_or((false, Vec::new()));
+        Ok(Expr::Aggregate {
+            op,
+            expr: Box::new(expr),
+            param,
+            grouping,
+            without,
+        })
+    }
+
+    fn grouping(&mut self) -> Result<(bool, Vec<String>)> {
+        let without = self.is_keyword("without");
+        self.next();
+        Ok((without, self.label_list()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::promql::ast::{AggregateOp, BinaryOp, Cardinality, Expr};
+    use crate::promql::parser::parse;
+    use crate::Result;
+
+    #[test]
+    fn test_parse_selector() -> Result<()> {
+        match parse(r#"http_requests_total{job="api"}[5m] offset 1m"#)? {
+            Expr::MatrixSelector(selector, range) => {
+                assert_eq!(range, 300000);
+                assert_eq!(selector.offset, 60000);
+                assert_eq!(selector.matchers.len(), 2);
+            }
+            expr => panic!("unexpected {:?}", expr),
+        }
+        match parse("up")? {
+            Expr::VectorSelector(selector) => assert_eq!(selector.matchers.len(), 1),
+            expr => panic!("unexpected {:?}", expr),
+        }
+        assert!(parse(r#"{job=""}"#).is_err());
+        assert!(parse("rate(up)[5m]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_precedence() -> Result<()> {
+        // 1 + (2 * 3)
+        match parse("1 + 2 * 3")? {
+            Expr::Binary { op, rhs, .. } => {
+                assert_eq!(op, BinaryOp::Add);
+                match *rhs {
+                    Expr::Binary { op, .. } => assert_eq!(op, BinaryOp::Mul),
+                    expr => panic!("unexpected {:?}", expr),
+                }
+            }
+            expr => panic!("unexpected {:?}", expr),
+        }
+        // -(2 ^ (3 ^ 2))
+        match parse("-2 ^ 3 ^ 2")? {
+            Expr::Negative(expr) => match *expr {
+                Expr::Binary { op, rhs, .. } => {
+                    assert_eq!(op, BinaryOp::Pow);
+                    match *rhs {
+                        Expr::Binary { op, .. } => assert_eq!(op, BinaryOp::Pow),
+                        expr => panic!("unexpected {:?}", expr),
+                    }
+                }
+                expr => panic!("unexpected {:?}", expr),
+            },
+            expr => panic!("unexpected {:?}", expr),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_aggregate() -> Result<()> {
+        for query in vec![
+            "sum by (job) (rate(http_requests_total[5m]))",
+            "sum(rate(http_requests_total[5m])) by (job)",
+        ] {
+            match parse(query)? {
+                Expr::Aggregate {
+                    op,
+                    grouping,
+                    without,
+                    ..
+                } => {
+                    assert_eq!(op, AggregateOp::Sum);
+                    assert_eq!(grouping, vec!["job"]);
+                    assert!(!without);
+                }
+                expr => panic!("unexpected {:?}", expr),
+            }
+        }
+        match parse("topk without (instance) (3, up)")? {
+            Expr::Aggregate { param, without, .. } => {
+                assert!(param.is_some());
+                assert!(without);
+            }
+            expr => panic!("unexpected {:?}", expr),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_vector_matching() -> Result<()> {
+        match parse("a / on (job) group_left (version) b")? {
+            Expr::Binary { matching, .. } => {
+                assert_eq!(matching.card, Cardinality::ManyToOne);
+                assert!(matching.on);
+                assert_eq!(matching.labels, vec!["job"]);
+                assert_eq!(matching.include, vec!["version"]);
+            }
+            expr => panic!("unexpected {:?}", expr),
+        }
+        match parse("a > bool ignoring (instance) b")? {
+            Expr::Binary {
+                return_bool,
+                matching,
+                ..
+            } => {
+                assert!(return_bool);
+                assert!(!matching.on);
+            }
+            expr => panic!("unexpected {:?}", expr),
+        }
+        assert!(parse("a and on (job) group_left b").is_err());
+        assert!(parse("a + bool b").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+use crate::common::label::Labels;
+use crate::common::time_series::LabelPointPairs;
+use crate::indexer::Indexer;
+use crate::proto::WriteRequest;
+use crate::server::REPLICATION_PREFIX;
+use crate::storage::Storage;
+use crate::wal::{Entry, Record, SegmentDecoder, WalErr};
+use crate::{MonolithDb, MonolithErr, Result, Timestamp, WAL_FLUSH};
+use protobuf::Message;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+mod client;
+
+pub use client::LeaderClient;
+
+/// How often the replica pulls from the leader once it has caught up
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Timeout of each request to the leader
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+///
+/// Keep the db of a read replica in sync with its leader over HTTP, see `replication` of the server.
+///
+/// A worker thread pulls from the leader every `POLL_INTERVAL`. The current chunk of the replica always
+/// covers the same range as the one of the leader, and finalized chunks are copied once into the
+/// replica, replacing the samples replicated into them before. Samples are replicated by tailing the
+/// write ahead log of leader, so the leader must have write ahead log enabled. Finalized chunks are
+/// copied page by page, so that neither side holds a whole chunk in memory.
+///
+/// Segments are read from the offset where the last pull stopped. Samples are written again if a
+/// segment is read from the start, like after the replica restarts or fails to apply a segment, which
+/// overwrite the same samples. If the segment being read is truncated by the leader, the replica
+/// skips to the next segment left.
+pub struct Replicator {
+    stopped: Arc<AtomicBool>,
+    /// When the replica last read the whole write ahead log of leader
+    last_synced: Arc<Mutex<Option<Instant>>>,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Replicator {
+    pub fn start<S, I>(leader: &str, db: Arc<MonolithDb<S, I>>) -> Result<Replicator>
+    where
+        S: Sync + Storage + Send + 'static,
+        I: Sync + Indexer + Send + 'static,
+    {
+        if !db.is_replica() {
+            error!(
+                "Db must be opened as a replica to replicate from {}",
+                leader
+            );
+            return Err(MonolithErr::OptionErr);
+        }
+        let stopped = Arc::new(AtomicBool::new(false));
+        let last_synced = Arc::new(Mutex::new(None));
+        let mut follower = Follower {
+            client: LeaderClient::new(leader, REQUEST_TIMEOUT)?,
+            db,
+            position: None,
+            stopped: Arc::clone(&stopped),
+            last_synced: Arc::clone(&last_synced),
+        };
+        // a leader that is not up yet is retried by the worker, but one without log never works
+        match follower.get_json::<SegmentsData>("wal") {
+            Ok(wal) if !wal.enabled => {
+                error!(
+                    "Leader {} has write ahead log disabled, start it with --{}",
+                    leader, WAL_FLUSH
+                );
+                return Err(MonolithErr::OptionErr);
+            }
+            Ok(_) => {}
+            Err(err) => warn!("Cannot reach leader {} yet, {}", leader, err),
+        }
+        info!("Replicate from leader {}", leader);
+        let worker = thread::spawn(move || follower.run());
+        Ok(Replicator {
+            stopped,
+            last_synced,
+            worker: Mutex::new(Some(worker)),
+        })
+    }
+
+    /// How long ago the replica has caught up with the leader, `None` if it has never caught up
+    pub fn lag(&self) -> Option<Duration> {
+        self.last_synced.lock().unwrap().map(|t| t.elapsed())
+    }
+
+    /// Stop pulling from the leader
+    pub fn close(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.worker.lock().unwrap().take() {
+            if handle.join().is_err() {
+                error!("Replicator panicked");
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChunkRange {
+    start: Timestamp,
+    end: Timestamp,
+}
+
+#[derive(Deserialize)]
+struct ChunksData {
+    current: ChunkRange,
+    finalized: Vec<ChunkRange>,
+}
+
+#[derive(Deserialize)]
+struct SegmentsData {
+    enabled: bool,
+    segments: Vec<u64>,
+}
+
+#[derive(Deserialize)]
+struct Data<T> {
+    data: T,
+}
+
+/// Where the replica is reading the write ahead log of leader
+struct Position {
+    seq: u64,
+    offset: u64,
+    decoder: SegmentDecoder,
+    /// Series refs of the segment, seeded from its checkpoint
+    series: HashMap<u64, Labels>,
+}
+
+struct Follower<S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    client: LeaderClient,
+    db: Arc<MonolithDb<S, I>>,
+    position: Option<Position>,
+    stopped: Arc<AtomicBool>,
+    last_synced: Arc<Mutex<Option<Instant>>>,
+}
+
+impl<S, I> Follower<S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    fn run(&mut self) {
+        if let Err(err) = self.db.wait_ready() {
+            error!("Cannot replicate into db that failed to load, {}", err);
+            return;
+        }
+        while !self.stopped.load(Ordering::SeqCst) {
+            let start = Instant::now();
+            match self.pull() {
+                Ok(()) => *self.last_synced.lock().unwrap() = Some(start),
+                Err(err) => warn!("Cannot replicate from leader, {}", err),
+            }
+            self.sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Sleep for `duration` unless stopped in the meantime
+    fn sleep(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while !self.stopped.load(Ordering::SeqCst) && Instant::now() < deadline {
+            thread::sleep(std::cmp::min(
+                Duration::from_millis(100),
+                deadline - Instant::now(),
+            ));
+        }
+    }
+
+    /// Follow the chunks of leader and read its write ahead log to the end
+    fn pull(&mut self) -> Result<()> {
+        self.sync_chunks()?;
+        // segments are listed before reading, so that a listed segment before the last is complete
+        let wal = self.get_json::<SegmentsData>("wal")?;
+        if !wal.enabled {
+            return Err(MonolithErr::IoError(
+                "write ahead log of leader is disabled".to_string(),
+            ));
+        }
+        let segments = wal.segments;
+        let mut position = self.position.take();
+        let res = self.tail(&mut position, segments.as_slice());
+        self.position = position;
+        res
+    }
+
+    /// Read segments from `position`, which is reset if the data read cannot be applied
+    fn tail(&self, position: &mut Option<Position>, segments: &[u64]) -> Result<()> {
+        while !self.stopped.load(Ordering::SeqCst) {
+            if position.is_none() {
+                match segments.first() {
+                    Some(seq) => *position = Some(self.open_segment(*seq)?),
+                    None => return Ok(()),
+                }
+            }
+            let current = position.as_mut().unwrap();
+            let next = segments.iter().find(|seq| **seq > current.seq).cloned();
+            let path = format!("wal/segment?seq={}&offset={}", current.seq, current.offset);
+            let data = match self.get(path.as_str())? {
+                Some(data) => data,
+                // truncated by the leader, samples in it are in the finalized chunks
+                None => match next.or_else(|| segments.first().cloned()) {
+                    Some(seq) if seq != current.seq => {
+                        warn!("Segment {} is truncated by leader", current.seq);
+                        *position = Some(self.open_segment(seq)?);
+                        continue;
+                    }
+                    _ => return Ok(()),
+                },
+            };
+            if data.is_empty() {
+                match next {
+                    Some(seq) => {
+                        // the leader swapped chunks before starting the next segment
+                        self.sync_chunks()?;
+                        *position = Some(self.open_segment(seq)?);
+                        continue;
+                    }
+                    None => return Ok(()),
+                }
+            }
+            current.offset += data.len() as u64;
+            let applied = current
+                .decoder
+                .push(data.as_slice())
+                .and_then(|entries| self.apply(current, entries));
+            if let Err(err) = applied {
+                *position = None;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, position: &mut Position, entries: Vec<Entry>) -> Result<()> {
+        for entry in entries {
+            match Record::from_entry(&entry)? {
+                Record::Series(series_ref, labels) => {
+                    position.series.insert(series_ref, labels);
+                }
+                Record::Samples(series_ref, points) => {
+                    let labels = position.series.get(&series_ref).ok_or_else(|| {
+                        WalErr::CorruptedErr(
+                            position.seq.to_string(),
+                            format!("unknown series {}", series_ref),
+                        )
+                    })?;
+                    self.db.replicate(labels, points)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Start reading segment `seq` with the series in its checkpoint
+    fn open_segment(&self, seq: u64) -> Result<Position> {
+        let mut series = HashMap::new();
+        if let Some(data) = self.get(format!("wal/checkpoint?seq={}", seq).as_str())? {
+            let name = format!("checkpoint {}", seq);
+            for entry in SegmentDecoder::new(name.as_str()).push(data.as_slice())? {
+                if let Record::Series(series_ref, labels) = Record::from_entry(&entry)? {
+                    series.insert(series_ref, labels);
+                }
+            }
+        }
+        Ok(Position {
+            seq,
+            offset: 0,
+            decoder: SegmentDecoder::new(seq.to_string().as_str()),
+            series,
+        })
+    }
+
+    /// Follow the current chunk of leader, and copy the finalized chunks not copied yet
+    fn sync_chunks(&self) -> Result<()> {
+        let chunks = self.get_json::<ChunksData>("chunks")?;
+        self.db
+            .follow_chunk(chunks.current.start, chunks.current.end)?;
+        for chunk in chunks.finalized {
+            if self.stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            if !self.db.needs_chunk(chunk.start, chunk.end) {
+                continue;
+            }
+            // the chunk may be removed by retention of leader in the meantime
+            if let Some(page) = self.get_chunk_page(chunk.start, 0)? {
+                let mut first = Some(page);
+                let mut offset = 0;
+                // pages are fetched while importing, until an empty one
+                let pages = std::iter::from_fn(|| {
+                    let page = match first.take() {
+                        Some(page) => Ok(page),
+                        None => self.get_chunk_page(chunk.start, offset).and_then(|page| {
+                            page.ok_or_else(|| {
+                                MonolithErr::IoError("chunk removed by leader".to_string())
+                            })
+                        }),
+                    };
+                    match page {
+                        Ok(page) if page.is_empty() => None,
+                        Ok(page) => {
+                            offset += page.len();
+                            Some(Ok(page))
+                        }
+                        Err(err) => Some(Err(err)),
+                    }
+                });
+                self.db.import_chunk(chunk.start, chunk.end, pages)?;
+                info!("Copied chunk {}-{} from leader", chunk.start, chunk.end);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get series of the finalized chunk starting at `start` from `offset`, `None` if it's not found
+    fn get_chunk_page(&self, start: Timestamp, offset: usize) -> Result<Option<LabelPointPairs>> {
+        let path = format!("chunk?start={}&offset={}", start, offset);
+        let body = match self.get(path.as_str())? {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+        let content = snap::raw::Decoder::new()
+            .decompress_vec(body.as_slice())
+            .map_err(|e| MonolithErr::IoError(format!("Cannot decompress chunk, {}", e)))?;
+        let mut request = WriteRequest::new();
+        request
+            .merge_from_bytes(content.as_slice())
+            .map_err(|e| MonolithErr::IoError(format!("Cannot decode chunk, {}", e)))?;
+        Ok(Some(
+            request
+                .timeseries
+                .iter()
+                .map(crate::common::time_series::TimeSeries::from)
+                .map(|ts| (ts.meta_data().clone(), ts.time_points().clone()))
+                .collect(),
+        ))
+    }
+
+    fn get(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        self.client
+            .get(format!("{}{}", REPLICATION_PREFIX, path).as_str())
+    }
+
+    fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let body = self
+            .get(path)?
+            .ok_or_else(|| MonolithErr::IoError(format!("{} not found on leader", path)))?;
+        let res: Data<T> = serde_json::from_slice(body.as_slice())
+            .map_err(|e| MonolithErr::IoError(format!("Invalid response of {}, {}", path, e)))?;
+        Ok(res.data)
+    }
+}
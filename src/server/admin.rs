@@ -0,0 +1,64 @@
+use crate::indexer::Indexer;
+use crate::server::api::{parse_match_params, parse_time_range, ApiResponse, Params};
+use crate::server::http::{Method, Response};
+use crate::storage::Storage;
+use crate::{MonolithDb, MonolithErr, Result};
+
+/// Prefix of admin API, the same as Prometheus TSDB admin API
+pub const ADMIN_PREFIX: &str = "/api/v1/admin/tsdb/";
+
+///
+/// Serve requests under `ADMIN_PREFIX`, which are only accepted with POST or PUT.
+///
+/// `delete_series` marks series matching any `match[]` within [`start`, `end`] as deleted, and
+/// `clean_tombstones` removes the deleted data from disk. Both respond 204 on success.
+pub fn handle<S, I>(
+    db: &MonolithDb<S, I>,
+    method: &Method,
+    path: &str,
+    params: &Params,
+    enabled: bool,
+) -> Response
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    if !enabled {
+        return ApiResponse::error(503, "unavailable", "admin APIs disabled".to_string())
+            .into_response();
+    }
+    if *method != Method::POST && *method != Method::PUT {
+        return ApiResponse::error(405, "bad_data", format!("method {} not allowed", method))
+            .into_response();
+    }
+    let res = match path.trim_start_matches(ADMIN_PREFIX) {
+        "delete_series" => delete_series(db, params),
+        "clean_tombstones" => db.clean_tombstones(),
+        _ => {
+            return ApiResponse::error(404, "not_found", format!("Unknown API {}", path))
+                .into_response()
+        }
+    };
+    match res {
+        Ok(()) => Response::empty(204),
+        Err(err) => {
+            error!("Error when process request to {}, {}", path, err);
+            ApiResponse::from(err).into_response()
+        }
+    }
+}
+
+fn delete_series<S, I>(db: &MonolithDb<S, I>, params: &Params) -> Result<()>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let selectors = parse_match_params(params)?;
+    if selectors.is_empty() {
+        return Err(MonolithErr::InvalidMatcherErr(
+            "no match[] parameter provided".to_string(),
+        ));
+    }
+    let (start, end) = parse_time_range(params)?;
+    db.delete_series(selectors.as_slice(), start, end)
+}
@@ -0,0 +1,375 @@
+use crate::common::label::{LabelMatcher, Labels};
+use crate::common::selector::{is_valid_label_name, parse_selector};
+use crate::common::utils::get_current_timestamp;
+use crate::indexer::Indexer;
+use crate::promql::{format_float, parse_duration, Engine, Metric, Series, Value as QueryValue};
+use crate::server::http::{status_code, Method, Request, Response};
+use crate::storage::Storage;
+use crate::{MonolithDb, MonolithErr, Result, Timestamp};
+use serde_json::{json, Map, Value};
+
+/// Prefix of Prometheus compatible HTTP API
+pub const API_V1_PREFIX: &str = "/api/v1/";
+
+const MIN_TIME: Timestamp = 0;
+const MAX_TIME: Timestamp = std::i64::MAX as Timestamp;
+/// Max num of points per series that a range query can return, same as Prometheus
+const MAX_POINTS_PER_SERIES: Timestamp = 11000;
+
+/// Parameters from url query string and url-encoded form body
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    /// Parse parameters from query string of `url`, and the body if it's an url-encoded form.
+    pub fn from_request(request: &Request) -> Result<Params> {
+        let mut params = Vec::new();
+        if let Some(idx) = request.url().find('?') {
+            params.extend(
+                url::form_urlencoded::parse(request.url()[idx + 1..].as_bytes()).into_owned(),
+            );
+        }
+        let is_form = request.header("Content-Type").map_or(false, |t| {
+            t.starts_with("application/x-www-form-urlencoded")
+        });
+        let has_body = *request.method() == Method::POST || *request.method() == Method::PUT;
+        if has_body && is_form {
+            params.extend(url::form_urlencoded::parse(request.body()).into_owned());
+        }
+        Ok(Params(params))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+}
+
+/// Response in Prometheus envelope format
+pub struct ApiResponse {
+    status: u16,
+    body: Value,
+}
+
+impl ApiResponse {
+    pub fn success(data: Value) -> ApiResponse {
+        ApiResponse {
+            status: 200,
+            body: json!({"status": "success", "data": data}),
+        }
+    }
+
+    pub fn error(status: u16, error_type: &str, error: String) -> ApiResponse {
+        ApiResponse {
+            status,
+            body: json!({"status": "error", "errorType": error_type, "error": error}),
+        }
+    }
+
+    pub fn bad_data(error: String) -> ApiResponse {
+        ApiResponse::error(400, "bad_data", error)
+    }
+
+    pub fn into_response(self) -> Response {
+        Response::from_string(self.body.to_string())
+            .with_status_code(self.status)
+            .with_header("Content-Type", "application/json")
+    }
+}
+
+impl From<MonolithErr> for ApiResponse {
+    fn from(err: MonolithErr) -> Self {
+        match status_code(&err) {
+            422 => ApiResponse::error(422, "execution", err.to_string()),
+            503 => ApiResponse::error(503, "unavailable", err.to_string()),
+            status if status < 500 => ApiResponse::error(status, "bad_data", err.to_string()),
+            status => ApiResponse::error(status, "internal", err.to_string()),
+        }
+    }
+}
+
+/// Serve requests under `API_V1_PREFIX`
+pub fn handle<S, I>(db: &MonolithDb<S, I>, path: &str, params: &Params) -> ApiResponse
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let res = match path.trim_start_matches(API_V1_PREFIX) {
+        "query" => query(db, params),
+        "query_range" => query_range(db, params),
+        "series" => series(db, params),
+        "labels" => labels(db, params),
+        other if other.starts_with("label/") && other.ends_with("/values") => {
+            let name = &other["label/".len()..other.len() - "/values".len()];
+            label_values(db, name, params)
+        }
+        _ => Ok(ApiResponse::error(
+            404,
+            "not_found",
+            format!("Unknown API {}", path),
+        )),
+    };
+    res.unwrap_or_else(ApiResponse::from)
+}
+
+fn query<S, I>(db: &MonolithDb<S, I>, params: &Params) -> Result<ApiResponse>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let query = params
+        .get("query")
+        .ok_or(MonolithErr::QueryParseErr("no query provided".to_string()))?;
+    let time = match params.get("time") {
+        Some(time) => parse_time(time)?,
+        None => get_current_timestamp(),
+    };
+    let (result_type, result) = match Engine::new(db).instant_query(query, time)? {
+        QueryValue::Scalar(v) => ("scalar", json!([format_timestamp(time), format_float(v)])),
+        QueryValue::String(s) => ("string", json!([format_timestamp(time), s])),
+        QueryValue::Vector(samples) => (
+            "vector",
+            Value::from(
+                samples
+                    .iter()
+                    .map(|s| {
+                        json!({
+                            "metric": metric_to_json(&s.metric),
+                            "value": [format_timestamp(s.timestamp), format_float(s.value)],
+                        })
+                    })
+                    .collect::<Vec<Value>>(),
+            ),
+        ),
+        QueryValue::Matrix(series) => ("matrix", matrix_to_json(series.as_slice())),
+    };
+    Ok(ApiResponse::success(
+        json!({"resultType": result_type, "result": result}),
+    ))
+}
+
+fn query_range<S, I>(db: &MonolithDb<S, I>, params: &Params) -> Result<ApiResponse>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let query = params
+        .get("query")
+        .ok_or(MonolithErr::QueryParseErr("no query provided".to_string()))?;
+    let start = parse_time(
+        params
+            .get("start")
+            .ok_or(MonolithErr::QueryParseErr("no start provided".to_string()))?,
+    )?;
+    let end = parse_time(
+        params
+            .get("end")
+            .ok_or(MonolithErr::QueryParseErr("no end provided".to_string()))?,
+    )?;
+    if end < start {
+        return Ok(ApiResponse::bad_data(
+            "end timestamp must not be before start time".to_string(),
+        ));
+    }
+    let step = params
+        .get("step")
+        .ok_or(MonolithErr::QueryParseErr("no step provided".to_string()))?;
+    // step can be either float seconds or duration
+    let step = match step.parse::<f64>() {
+        Ok(seconds) => (seconds * 1000.0).round() as Timestamp,
+        Err(_) => parse_duration(step)?,
+    };
+    if step == 0 {
+        return Ok(ApiResponse::bad_data(
+            "zero or negative query resolution step widths are not accepted".to_string(),
+        ));
+    }
+    if (end - start) / step > MAX_POINTS_PER_SERIES {
+        return Ok(ApiResponse::bad_data(
+            "exceeded maximum resolution of 11,000 points per timeseries".to_string(),
+        ));
+    }
+    let series = Engine::new(db).range_query(query, start, end, step)?;
+    Ok(ApiResponse::success(json!({
+        "resultType": "matrix",
+        "result": matrix_to_json(series.as_slice()),
+    })))
+}
+
+fn series<S, I>(db: &MonolithDb<S, I>, params: &Params) -> Result<ApiResponse>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let selectors = parse_match_params(params)?;
+    if selectors.is_empty() {
+        return Ok(ApiResponse::bad_data(
+            "no match[] parameter provided".to_string(),
+        ));
+    }
+    let (start, end) = parse_time_range(params)?;
+    let data = db
+        .series(selectors.as_slice(), start, end)?
+        .iter()
+        .map(labels_to_json)
+        .collect::<Vec<Value>>();
+    Ok(ApiResponse::success(Value::from(data)))
+}
+
+fn labels<S, I>(db: &MonolithDb<S, I>, params: &Params) -> Result<ApiResponse>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let (start, end) = parse_time_range(params)?;
+    Ok(ApiResponse::success(Value::from(
+        db.label_names(start, end)?,
+    )))
+}
+
+fn label_values<S, I>(db: &MonolithDb<S, I>, name: &str, params: &Params) -> Result<ApiResponse>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    if !is_valid_label_name(name) {
+        return Ok(ApiResponse::bad_data(format!(
+            "invalid label name: {}",
+            name
+        )));
+    }
+    let (start, end) = parse_time_range(params)?;
+    Ok(ApiResponse::success(Value::from(
+        db.label_values(name, start, end)?,
+    )))
+}
+
+pub(crate) fn parse_match_params(params: &Params) -> Result<Vec<Vec<LabelMatcher>>> {
+    let mut res = Vec::new();
+    for selector in params.get_all("match[]") {
+        let matchers = parse_selector(selector)?;
+        if matchers.iter().all(|m| m.matches_empty()) {
+            return Err(MonolithErr::InvalidMatcherErr(format!(
+                "match[] must contain at least one non-empty matcher, got {}",
+                selector
+            )));
+        }
+        res.push(matchers);
+    }
+    Ok(res)
+}
+
+pub(crate) fn parse_time_range(params: &Params) -> Result<(Timestamp, Timestamp)> {
+    let start = match params.get("start") {
+        Some(s) => parse_time(s)?,
+        None => MIN_TIME,
+    };
+    let end = match params.get("end") {
+        Some(s) => parse_time(s)?,
+        None => MAX_TIME,
+    };
+    if end < start {
+        return Err(MonolithErr::InvalidMatcherErr(
+            "end timestamp must not be before start time".to_string(),
+        ));
+    }
+    Ok((start, end))
+}
+
+/// Parse time in unix seconds with optional decimal places, or RFC3339 format, into milliseconds.
+pub fn parse_time(s: &str) -> Result<Timestamp> {
+    if let Ok(seconds) = s.parse::<f64>() {
+        if seconds < 0.0 {
+            return Ok(MIN_TIME);
+        }
+        return Ok((seconds * 1000.0).round() as Timestamp);
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(s).map_err(|_| MonolithErr::ParseErr)?;
+    Ok(time.timestamp_millis().max(0) as Timestamp)
+}
+
+/// Timestamp in seconds, which is used by Prometheus API
+fn format_timestamp(timestamp: Timestamp) -> f64 {
+    timestamp as f64 / 1000.0
+}
+
+fn metric_to_json(metric: &Metric) -> Value {
+    Value::Object(
+        metric
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::from(v.clone())))
+            .collect(),
+    )
+}
+
+fn matrix_to_json(series: &[Series]) -> Value {
+    Value::from(
+        series
+            .iter()
+            .map(|s| {
+                json!({
+                    "metric": metric_to_json(&s.metric),
+                    "values": s.points
+                        .iter()
+                        .map(|tp| json!([format_timestamp(tp.timestamp), format_float(tp.value)]))
+                        .collect::<Vec<Value>>(),
+                })
+            })
+            .collect::<Vec<Value>>(),
+    )
+}
+
+fn labels_to_json(labels: &Labels) -> Value {
+    let mut map = Map::new();
+    for label in labels.vec() {
+        map.insert(label.key().clone(), Value::from(label.value().clone()));
+    }
+    Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::api::{parse_time, ApiResponse, Params};
+    use crate::{MonolithErr, Result};
+
+    #[test]
+    fn test_parse_time() -> Result<()> {
+        assert_eq!(parse_time("1500000000")?, 1500000000000);
+        assert_eq!(parse_time("1500000000.123")?, 1500000000123);
+        assert_eq!(parse_time("2017-07-14T02:40:00.5Z")?, 1500000000500);
+        assert!(parse_time("yesterday").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_params() {
+        let params = Params(vec![
+            ("match[]".to_string(), "up".to_string()),
+            ("match[]".to_string(), "down".to_string()),
+            ("start".to_string(), "1".to_string()),
+        ]);
+        assert_eq!(params.get("start"), Some("1"));
+        assert_eq!(params.get("end"), None);
+        assert_eq!(params.get_all("match[]"), vec!["up", "down"]);
+    }
+
+    #[test]
+    fn test_api_response() {
+        let res = ApiResponse::success(serde_json::json!(["a"]));
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body.to_string(), r#"{"data":["a"],"status":"success"}"#);
+
+        let res = ApiResponse::from(MonolithErr::InvalidMatcherErr("bad".to_string()));
+        assert_eq!(res.status, 400);
+        assert_eq!(res.body["errorType"], "bad_data");
+    }
+}
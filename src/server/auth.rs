@@ -0,0 +1,170 @@
+use crate::server::http::{Request, Response};
+use crate::{MonolithErr, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+///
+/// Check `Authorization` header of requests by http basic auth or bearer tokens.
+///
+/// Users are loaded from lines of `username:password` and tokens are loaded one per line, empty lines
+/// and lines starting with `#` are ignored. A request is accepted if it matches either of them.
+pub struct Authenticator {
+    users: HashMap<String, String>,
+    tokens: Vec<String>,
+}
+
+impl Authenticator {
+    /// Return `None` if neither file is given, which means authentication is disabled
+    pub fn from_files(
+        basic_auth_file: Option<&Path>,
+        bearer_token_file: Option<&Path>,
+    ) -> Result<Option<Authenticator>> {
+        if basic_auth_file.is_none() && bearer_token_file.is_none() {
+            return Ok(None);
+        }
+        let users = match basic_auth_file {
+            Some(path) => Authenticator::parse_users(fs::read_to_string(path)?.as_str())?,
+            None => HashMap::new(),
+        };
+        let tokens = match bearer_token_file {
+            Some(path) => Authenticator::parse_tokens(fs::read_to_string(path)?.as_str()),
+            None => Vec::new(),
+        };
+        if users.is_empty() && tokens.is_empty() {
+            error!("No user or token found in authentication files");
+            return Err(MonolithErr::OptionErr);
+        }
+        Ok(Some(Authenticator { users, tokens }))
+    }
+
+    fn parse_users(content: &str) -> Result<HashMap<String, String>> {
+        let mut users = HashMap::new();
+        for (idx, line) in Authenticator::lines(content).enumerate() {
+            let mut kv = line.splitn(2, ':');
+            match (kv.next(), kv.next()) {
+                (Some(user), Some(password)) if !user.is_empty() && !password.is_empty() => {
+                    users.insert(user.to_string(), password.to_string());
+                }
+                _ => {
+                    error!("Invalid line {} in basic auth file", idx + 1);
+                    return Err(MonolithErr::OptionErr);
+                }
+            }
+        }
+        Ok(users)
+    }
+
+    fn parse_tokens(content: &str) -> Vec<String> {
+        Authenticator::lines(content).map(String::from).collect()
+    }
+
+    fn lines(content: &str) -> impl Iterator<Item = &str> {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+    }
+
+    pub fn authenticate(&self, request: &Request) -> bool {
+        self.check(request.header("Authorization"))
+    }
+
+    fn check(&self, authorization: Option<&str>) -> bool {
+        let authorization = match authorization {
+            Some(authorization) => authorization.trim(),
+            None => return false,
+        };
+        let mut parts = authorization.splitn(2, ' ');
+        let (scheme, credentials) = match (parts.next(), parts.next()) {
+            (Some(scheme), Some(credentials)) => (scheme, credentials.trim()),
+            _ => return false,
+        };
+        if scheme.eq_ignore_ascii_case("Bearer") {
+            // check all tokens to not leak which one matches by time
+            return self.tokens.iter().fold(false, |res, token| {
+                constant_time_eq(token, credentials) | res
+            });
+        }
+        if scheme.eq_ignore_ascii_case("Basic") {
+            let decoded = base64::decode(credentials)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok());
+            if let Some(decoded) = decoded {
+                let mut kv = decoded.splitn(2, ':');
+                if let (Some(user), Some(password)) = (kv.next(), kv.next()) {
+                    return self
+                        .users
+                        .get(user)
+                        .map_or(false, |expected| constant_time_eq(expected, password));
+                }
+            }
+        }
+        false
+    }
+
+    /// 401 response with challenges of enabled schemes
+    pub fn unauthorized(&self) -> Response {
+        let mut response = Response::from_string("unauthorized").with_status_code(401);
+        if !self.users.is_empty() {
+            response.add_header("WWW-Authenticate", "Basic realm=\"monolith\"");
+        }
+        if !self.tokens.is_empty() {
+            response.add_header("WWW-Authenticate", "Bearer realm=\"monolith\"");
+        }
+        response
+    }
+}
+
+/// Compare in time that only depends on length, to not leak secrets by timing
+fn constant_time_eq(expected: &str, actual: &str) -> bool {
+    if expected.len() != actual.len() {
+        return false;
+    }
+    expected
+        .bytes()
+        .zip(actual.bytes())
+        .fold(0u8, |res, (l, r)| res | (l ^ r))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::auth::Authenticator;
+
+    fn authenticator() -> Authenticator {
+        Authenticator {
+            users: Authenticator::parse_users("# users\nprometheus:secret\n\nadmin:pa:ss\n")
+                .unwrap(),
+            tokens: Authenticator::parse_tokens("token-1\n  token-2  \n"),
+        }
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        let auth = authenticator();
+        let header = |s: &str| format!("Basic {}", base64::encode(s));
+        assert!(auth.check(Some(header("prometheus:secret").as_str())));
+        assert!(auth.check(Some(header("admin:pa:ss").as_str())));
+        assert!(!auth.check(Some(header("prometheus:wrong").as_str())));
+        assert!(!auth.check(Some(header("unknown:secret").as_str())));
+        assert!(!auth.check(Some("Basic !!!")));
+        assert!(!auth.check(None));
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let auth = authenticator();
+        assert!(auth.check(Some("Bearer token-1")));
+        assert!(auth.check(Some("bearer token-2")));
+        assert!(!auth.check(Some("Bearer token-3")));
+        assert!(!auth.check(Some("Bearer")));
+    }
+
+    #[test]
+    fn test_parse_users() {
+        assert!(Authenticator::parse_users("prometheus").is_err());
+        assert!(Authenticator::parse_users("prometheus:").is_err());
+        assert!(Authenticator::from_files(None, None).unwrap().is_none());
+    }
+}
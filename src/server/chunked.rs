@@ -0,0 +1,250 @@
+use crate::common::hint::QueryHint;
+use crate::common::label::LabelMatcher;
+use crate::common::time_point::TimePoint;
+use crate::compaction::{encode_uvarint, XorChunk};
+use crate::indexer::Indexer;
+use crate::proto::{
+    Chunk as ProtoChunk, Chunk_Encoding, ChunkedReadResponse, ChunkedSeries, ReadRequest,
+};
+use crate::server::http::Response;
+use crate::storage::Storage;
+use crate::{MonolithDb, MonolithErr, Result, Timestamp};
+use protobuf::{Message, RepeatedField};
+use std::io::Write;
+use std::sync::Arc;
+
+pub const STREAMED_CONTENT_TYPE: &str =
+    "application/x-streamed-protobuf; proto=prometheus.ChunkedReadResponse";
+
+/// Max num of samples in one XOR chunk, same as Prometheus
+const SAMPLES_PER_CHUNK: usize = 120;
+/// Flush the frame once it grows beyond this size
+const MAX_BYTES_IN_FRAME: usize = 1024 * 1024;
+
+///
+/// Write frames using the format of Prometheus' streamed remote read.
+///
+/// | length of message(uvarint) | message | CRC32 Castagnoli of message(big endian u32) |
+pub struct ChunkedWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(writer: W) -> ChunkedWriter<W> {
+        ChunkedWriter { writer }
+    }
+
+    pub fn write_frame(&mut self, msg: &[u8]) -> Result<()> {
+        self.writer
+            .write_all(encode_uvarint(msg.len() as u64).as_slice())?;
+        self.writer.write_all(msg)?;
+        self.writer
+            .write_all(&crc::crc32::checksum_castagnoli(msg).to_be_bytes()[..])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Encode time points into XOR chunks, each chunk contains at most `SAMPLES_PER_CHUNK` samples.
+pub fn encode_chunks(time_points: &[TimePoint]) -> Vec<ProtoChunk> {
+    time_points
+        .chunks(SAMPLES_PER_CHUNK)
+        .map(|tps| {
+            let mut chunk = XorChunk::new();
+            for tp in tps {
+                chunk.append(tp);
+            }
+            let (min_time, max_time) = chunk.time_range();
+            ProtoChunk {
+                min_time_ms: min_time as i64,
+                max_time_ms: max_time as i64,
+                field_type: Chunk_Encoding::XOR,
+                data: chunk.bytes(),
+                unknown_fields: Default::default(),
+                cached_size: Default::default(),
+            }
+        })
+        .collect()
+}
+
+///
+/// Response that queries `db` and streams the result as `ChunkedReadResponse` frames.
+///
+/// Only label sets of the matched series are loaded at first, then series are read one by one and
+/// each frame is written once it's full, so that memory is bounded by the frame instead of the result.
+/// Queries run on the worker that responds, and stop at the next write if the client has gone.
+pub fn stream_read<S, I>(db: Arc<MonolithDb<S, I>>, read_req: ReadRequest) -> Response
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    Response::from_writer(move |writer| {
+        write_chunked_response(db.as_ref(), &read_req, &mut ChunkedWriter::new(writer))
+    })
+}
+
+fn write_chunked_response<S, I, W>(
+    db: &MonolithDb<S, I>,
+    read_req: &ReadRequest,
+    writer: &mut ChunkedWriter<W>,
+) -> Result<()>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+    W: Write,
+{
+    for (idx, q) in read_req.queries.iter().enumerate() {
+        let mut matchers = Vec::new();
+        for m in q.matchers.iter() {
+            matchers.push(LabelMatcher::from_label_matcher(m)?);
+        }
+        let hint = q
+            .hints
+            .as_ref()
+            .and_then(|h| QueryHint::from_read_hints(h, q.start_timestamp_ms as Timestamp));
+        let (start_time, end_time) = (
+            q.start_timestamp_ms as Timestamp,
+            q.end_timestamp_ms as Timestamp,
+        );
+        // Prometheus expects both series and labels inside series are sorted, which `series` returns
+        let series = db.series(&[matchers], start_time, end_time)?;
+
+        let mut frame = Vec::new();
+        let mut frame_size = 0;
+        for labels in series.iter() {
+            let time_points = db.query_series(labels, start_time, end_time, hint.as_ref())?;
+            if time_points.is_empty() {
+                continue;
+            }
+            let chunked_series = ChunkedSeries {
+                labels: labels.vec().iter().map(crate::proto::Label::from).collect(),
+                chunks: RepeatedField::from(encode_chunks(time_points.as_slice())),
+                unknown_fields: Default::default(),
+                cached_size: Default::default(),
+            };
+            frame_size += chunked_series.compute_size() as usize;
+            frame.push(chunked_series);
+            if frame_size >= MAX_BYTES_IN_FRAME {
+                write_frame(writer, std::mem::replace(&mut frame, Vec::new()), idx)?;
+                frame_size = 0;
+            }
+        }
+        if !frame.is_empty() {
+            write_frame(writer, frame, idx)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_frame<W: Write>(
+    writer: &mut ChunkedWriter<W>,
+    series: Vec<ChunkedSeries>,
+    query_index: usize,
+) -> Result<()> {
+    let response = ChunkedReadResponse {
+        chunked_series: RepeatedField::from(series),
+        query_index: query_index as i64,
+        unknown_fields: Default::default(),
+        cached_size: Default::default(),
+    };
+    let bytes = response
+        .write_to_bytes()
+        .map_err(|err| MonolithErr::InternalErr(err.to_string()))?;
+    writer.write_frame(bytes.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::{Label, Labels};
+    use crate::common::time_point::TimePoint;
+    use crate::indexer::{SledIndexer, SledIndexerBuilder};
+    use crate::option::DbOpts;
+    use crate::proto::{ChunkedReadResponse, LabelMatcher, LabelMatcher_Type, Query, ReadRequest};
+    use crate::server::chunked::{encode_chunks, write_chunked_response, ChunkedWriter};
+    use crate::storage::{SledStorage, SledStorageBuilder};
+    use crate::{MonolithDb, Result};
+    use protobuf::RepeatedField;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_chunked_writer() -> Result<()> {
+        let mut res = Vec::new();
+        {
+            let mut writer = ChunkedWriter::new(&mut res);
+            writer.write_frame(&[1, 2, 3])?;
+        }
+        let crc = crc::crc32::checksum_castagnoli(&[1, 2, 3]).to_be_bytes();
+        assert_eq!(res, vec![3, 1, 2, 3, crc[0], crc[1], crc[2], crc[3]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_chunks() {
+        let tps = (0..250)
+            .map(|i| TimePoint::new(i * 1000, i as f64))
+            .collect::<Vec<TimePoint>>();
+        let chunks = encode_chunks(tps.as_slice());
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].min_time_ms, 0);
+        assert_eq!(chunks[0].max_time_ms, 119000);
+        assert_eq!(chunks[2].min_time_ms, 240000);
+        assert_eq!(chunks[2].max_time_ms, 249000);
+        assert_eq!(&chunks[2].data[..2], &[0, 10]);
+    }
+
+    #[test]
+    fn test_write_chunked_response() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        db.wait_ready()?;
+        let start = db.current_chunk_range().0;
+        let up = Labels::from_vec(vec![
+            Label::from_key_value("job", "a"),
+            Label::from_key_value("__name__", "up"),
+        ]);
+        let points = (1..=150)
+            .map(|i| TimePoint::new(start + i, i as f64))
+            .collect::<Vec<_>>();
+        db.write_time_points(up, points)?;
+        let down = Labels::from_vec(vec![Label::from_key_value("__name__", "down")]);
+        db.write_time_points(down, vec![TimePoint::new(start + 1, 1.0)])?;
+
+        let mut matcher = LabelMatcher::new();
+        matcher.set_field_type(LabelMatcher_Type::RE);
+        matcher.set_name("__name__".to_string());
+        matcher.set_value(".*".to_string());
+        let mut query = Query::new();
+        query.set_start_timestamp_ms((start + 1) as i64);
+        query.set_end_timestamp_ms((start + 100) as i64);
+        query.set_matchers(RepeatedField::from_vec(vec![matcher]));
+        let mut read_req = ReadRequest::new();
+        read_req.set_queries(RepeatedField::from_vec(vec![query]));
+
+        let mut res = Vec::new();
+        write_chunked_response(db.as_ref(), &read_req, &mut ChunkedWriter::new(&mut res))?;
+        // one frame with a 2 bytes length
+        let len = (res[0] & 0x7f) as usize | (res[1] as usize) << 7;
+        assert_eq!(res.len(), 2 + len + 4);
+        let response: ChunkedReadResponse = protobuf::parse_from_bytes(&res[2..2 + len])
+            .map_err(|e| crate::MonolithErr::InternalErr(e.to_string()))?;
+        let series = response.get_chunked_series();
+        assert_eq!(series.len(), 2);
+        // series and their labels are sorted
+        assert_eq!(series[0].get_labels()[0].get_value(), "down");
+        assert_eq!(series[1].get_labels()[0].get_name(), "__name__");
+        assert_eq!(series[1].get_labels()[1].get_name(), "job");
+        // samples out of the range are excluded
+        let chunks = series[1].get_chunks();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].min_time_ms, (start + 1) as i64);
+        assert_eq!(chunks[0].max_time_ms, (start + 100) as i64);
+        db.close()?;
+        Ok(())
+    }
+}
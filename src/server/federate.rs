@@ -0,0 +1,149 @@
+use crate::common::label::Labels;
+use crate::common::time_point::TimePoint;
+use crate::indexer::Indexer;
+use crate::metrics::{escape_label_value, format_value};
+use crate::server::api::{parse_match_params, ApiResponse, Params};
+use crate::server::http::Response;
+use crate::storage::Storage;
+use crate::{MonolithDb, MonolithErr, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Path of federation, the same as Prometheus
+pub const FEDERATE_PATH: &str = "/federate";
+
+const TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+///
+/// Respond the latest sample of each series in the current chunk that matches any `match[]`.
+///
+/// Samples are in OpenMetrics format if the client accepts `application/openmetrics-text`, otherwise
+/// in Prometheus text format. Series are grouped by metric name and typed `untyped`(`unknown` in
+/// OpenMetrics), because the type of metrics is not stored. Series without metric name are skipped.
+pub fn handle<S, I>(db: &MonolithDb<S, I>, params: &Params, accept: Option<&str>) -> Response
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let openmetrics = accept.map_or(false, |accept| {
+        accept.contains("application/openmetrics-text")
+    });
+    match latest_samples(db, params) {
+        Ok(samples) => {
+            let (body, content_type) = if openmetrics {
+                (encode(samples, true), OPENMETRICS_CONTENT_TYPE)
+            } else {
+                (encode(samples, false), TEXT_CONTENT_TYPE)
+            };
+            Response::from_string(body).with_header("Content-Type", content_type)
+        }
+        Err(err) => ApiResponse::from(err).into_response(),
+    }
+}
+
+fn latest_samples<S, I>(db: &MonolithDb<S, I>, params: &Params) -> Result<Vec<(Labels, TimePoint)>>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let selectors = parse_match_params(params)?;
+    if selectors.is_empty() {
+        return Err(MonolithErr::InvalidMatcherErr(
+            "no match[] parameter provided".to_string(),
+        ));
+    }
+    db.latest_samples(selectors.as_slice())
+}
+
+/// Timestamps are in milliseconds in Prometheus text format, and in seconds in OpenMetrics
+fn encode(samples: Vec<(Labels, TimePoint)>, openmetrics: bool) -> String {
+    let mut families = BTreeMap::<String, Vec<(Labels, TimePoint)>>::new();
+    for (labels, point) in samples {
+        let name = labels
+            .vec()
+            .iter()
+            .find(|l| l.key() == "__name__")
+            .map(|l| l.value().clone());
+        if let Some(name) = name {
+            families.entry(name).or_default().push((labels, point));
+        }
+    }
+    let mut buf = String::new();
+    for (name, samples) in families {
+        let metric_type = if openmetrics { "unknown" } else { "untyped" };
+        writeln!(buf, "# TYPE {} {}", name, metric_type).unwrap();
+        for (labels, point) in samples {
+            buf.push_str(name.as_str());
+            let pairs = labels
+                .vec()
+                .iter()
+                .filter(|l| l.key() != "__name__")
+                .map(|l| format!("{}=\"{}\"", l.key(), escape_label_value(l.value())))
+                .collect::<Vec<String>>();
+            if !pairs.is_empty() {
+                write!(buf, "{{{}}}", pairs.join(",")).unwrap();
+            }
+            let timestamp = if openmetrics {
+                format_value(point.timestamp as f64 / 1000.0)
+            } else {
+                point.timestamp.to_string()
+            };
+            writeln!(buf, " {} {}", format_value(point.value), timestamp).unwrap();
+        }
+    }
+    if openmetrics {
+        buf.push_str("# EOF\n");
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::{Label, Labels};
+    use crate::common::time_point::TimePoint;
+    use crate::server::federate::encode;
+
+    fn samples() -> Vec<(Labels, TimePoint)> {
+        let series = |name: &str, job: &str| {
+            Labels::from_vec(vec![
+                Label::from_key_value("__name__", name),
+                Label::from_key_value("job", job),
+            ])
+        };
+        vec![
+            (series("up", "a"), TimePoint::new(1500, 1.0)),
+            (series("go_goroutines", "a\"b"), TimePoint::new(2000, 8.5)),
+            (series("up", "b"), TimePoint::new(1000, 0.0)),
+            (
+                Labels::from_vec(vec![Label::from_key_value("job", "a")]),
+                TimePoint::new(1000, 1.0),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_encode_text() {
+        assert_eq!(
+            encode(samples(), false),
+            "# TYPE go_goroutines untyped\n\
+             go_goroutines{job=\"a\\\"b\"} 8.5 2000\n\
+             # TYPE up untyped\n\
+             up{job=\"a\"} 1 1500\n\
+             up{job=\"b\"} 0 1000\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_openmetrics() {
+        assert_eq!(
+            encode(samples(), true),
+            "# TYPE go_goroutines unknown\n\
+             go_goroutines{job=\"a\\\"b\"} 8.5 2\n\
+             # TYPE up unknown\n\
+             up{job=\"a\"} 1 1.5\n\
+             up{job=\"b\"} 0 1\n\
+             # EOF\n"
+        );
+    }
+}
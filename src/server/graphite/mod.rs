@@ -0,0 +1,316 @@
+use crate::common::label::Labels;
+use crate::common::time_point::TimePoint;
+use crate::common::utils::get_current_timestamp;
+use crate::indexer::Indexer;
+use crate::storage::Storage;
+use crate::{MonolithDb, MonolithErr, Result, Timestamp};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+
+mod pickle;
+mod template;
+
+pub use template::GraphiteTemplates;
+
+/// Max number of time points buffered before writing into db
+const MAX_BATCH_SIZE: usize = 1000;
+
+/// Max size of one pickle message, same as carbon
+const MAX_PICKLE_SIZE: usize = 1 << 20;
+
+/// Protocol accepted by a `GraphiteListener`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GraphiteProtocol {
+    /// Lines of `path value timestamp`, usually on port 2003
+    Plaintext,
+    /// Length prefixed pickle of `[(path, (timestamp, value)), ...]`, usually on port 2004
+    Pickle,
+}
+
+///
+/// TCP listener that accepts metrics sent by carbon or collectd, each connection is served by its own
+/// thread and writes into `MonolithDb`.
+///
+/// Graphite timestamps are in seconds, negative timestamp means current time.
+pub struct GraphiteListener<S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    db: Arc<MonolithDb<S, I>>,
+    templates: Arc<GraphiteTemplates>,
+    protocol: GraphiteProtocol,
+}
+
+impl<S, I> GraphiteListener<S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    pub fn new(
+        db: Arc<MonolithDb<S, I>>,
+        templates: Arc<GraphiteTemplates>,
+        protocol: GraphiteProtocol,
+    ) -> Self {
+        GraphiteListener {
+            db,
+            templates,
+            protocol,
+        }
+    }
+
+    /// Bind `addr` and accept connections in a background thread
+    pub fn spawn(self, addr: &str) -> Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Graphite {:?} listener started at {}", self.protocol, addr);
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let handler = self.clone();
+                        thread::spawn(move || handler.handle_connection(stream));
+                    }
+                    Err(err) => error!("Cannot accept graphite connection, {}", err),
+                }
+            }
+        }))
+    }
+
+    fn handle_connection(&self, stream: TcpStream) {
+        let peer = stream
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        let res = match self.protocol {
+            GraphiteProtocol::Plaintext => self.read_plaintext(stream),
+            GraphiteProtocol::Pickle => self.read_pickle(stream),
+        };
+        if let Err(err) = res {
+            warn!("Close graphite connection from {}, {}", peer, err);
+        }
+    }
+
+    /// Read lines until EOF, points are written when no more data is buffered or batch is full.
+    fn read_plaintext<R: Read>(&self, reader: R) -> Result<()> {
+        let mut reader = BufReader::new(reader);
+        let mut batch: HashMap<Labels, Vec<TimePoint>> = HashMap::new();
+        let mut batch_size = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            match parse_line(
+                line.as_str(),
+                self.templates.as_ref(),
+                get_current_timestamp(),
+            ) {
+                Ok((labels, point)) => {
+                    batch.entry(labels).or_default().push(point);
+                    batch_size += 1;
+                }
+                Err(err) => warn!("Invalid graphite line {:?}, {}", line.trim(), err),
+            }
+            if reader.buffer().is_empty() || batch_size >= MAX_BATCH_SIZE {
+                self.flush(&mut batch);
+                batch_size = 0;
+            }
+        }
+        self.flush(&mut batch);
+        Ok(())
+    }
+
+    fn read_pickle<R: Read>(&self, mut reader: R) -> Result<()> {
+        loop {
+            let mut header = [0u8; 4];
+            match reader.read_exact(&mut header) {
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                res => res?,
+            }
+            let len = u32::from_be_bytes(header) as usize;
+            if len > MAX_PICKLE_SIZE {
+                return Err(MonolithErr::GraphiteErr(format!(
+                    "pickle message of {} bytes is too large",
+                    len
+                )));
+            }
+            let mut payload = vec![0u8; len];
+            reader.read_exact(payload.as_mut_slice())?;
+
+            let now = get_current_timestamp();
+            let mut batch: HashMap<Labels, Vec<TimePoint>> = HashMap::new();
+            for (path, timestamp, value) in pickle::decode_metrics(payload.as_slice())? {
+                match to_time_point(timestamp, value, now) {
+                    Ok(point) => batch
+                        .entry(self.templates.labels(path.as_str(), &[]))
+                        .or_default()
+                        .push(point),
+                    Err(err) => warn!("Invalid graphite metric {}, {}", path, err),
+                }
+            }
+            self.flush(&mut batch);
+        }
+    }
+
+    fn flush(&self, batch: &mut HashMap<Labels, Vec<TimePoint>>) {
+        for (labels, points) in batch.drain() {
+            if let Err(err) = self.db.write_time_points(labels, points) {
+                error!("Error when write graphite metrics into db, {}", err);
+            }
+        }
+    }
+}
+
+impl<S, I> Clone for GraphiteListener<S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    fn clone(&self) -> Self {
+        GraphiteListener {
+            db: Arc::clone(&self.db),
+            templates: Arc::clone(&self.templates),
+            protocol: self.protocol,
+        }
+    }
+}
+
+///
+/// Parse plaintext line of `path value timestamp`, path can carry graphite tags like
+/// `disk.used;host=a;mount=/`.
+fn parse_line(
+    line: &str,
+    templates: &GraphiteTemplates,
+    now: Timestamp,
+) -> std::result::Result<(Labels, TimePoint), String> {
+    let fields = line.split_whitespace().collect::<Vec<&str>>();
+    if fields.len() != 3 {
+        return Err("expect path, value and timestamp".to_string());
+    }
+    let value = fields[1]
+        .parse::<f64>()
+        .map_err(|_| format!("invalid value {}", fields[1]))?;
+    let timestamp = fields[2]
+        .parse::<f64>()
+        .map_err(|_| format!("invalid timestamp {}", fields[2]))?;
+
+    let mut nodes = fields[0].split(';');
+    let path = nodes.next().unwrap_or("");
+    if path.is_empty() {
+        return Err("empty path".to_string());
+    }
+    let mut tags = Vec::new();
+    for tag in nodes {
+        let mut kv = tag.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(key), Some(value)) if !key.is_empty() && !value.is_empty() => {
+                tags.push((key, value))
+            }
+            _ => return Err(format!("invalid tag {}", tag)),
+        }
+    }
+    let point = to_time_point(timestamp, value, now)?;
+    Ok((templates.labels(path, tags.as_slice()), point))
+}
+
+fn to_time_point(
+    timestamp: f64,
+    value: f64,
+    now: Timestamp,
+) -> std::result::Result<TimePoint, String> {
+    if !value.is_finite() {
+        return Err(format!("invalid value {}", value));
+    }
+    if !timestamp.is_finite() {
+        return Err(format!("invalid timestamp {}", timestamp));
+    }
+    let timestamp = if timestamp < 0.0 {
+        now
+    } else {
+        (timestamp * 1000.0) as Timestamp
+    };
+    Ok(TimePoint::new(timestamp, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::LabelMatcher;
+    use crate::common::utils::get_current_timestamp;
+    use crate::indexer::{SledIndexer, SledIndexerBuilder};
+    use crate::option::DbOpts;
+    use crate::server::graphite::{
+        parse_line, GraphiteListener, GraphiteProtocol, GraphiteTemplates,
+    };
+    use crate::storage::{SledStorage, SledStorageBuilder};
+    use crate::{MonolithDb, Result};
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_line() {
+        let templates = GraphiteTemplates::new(&["servers.* .host.measurement*"]).unwrap();
+        let (labels, tp) =
+            parse_line("servers.web01.cpu.idle 99.5 1500000000\n", &templates, 42).unwrap();
+        assert_eq!(labels.vec()[0].value(), "cpu_idle");
+        assert_eq!(labels.vec()[1].value(), "web01");
+        assert_eq!(tp.timestamp, 1500000000000);
+        assert_eq!(tp.value, 99.5);
+
+        let (labels, tp) = parse_line("disk.used;mount=/data 10 -1", &templates, 42).unwrap();
+        assert_eq!(labels.vec()[1].value(), "/data");
+        assert_eq!(tp.timestamp, 42);
+
+        for line in vec![
+            "disk.used 10",
+            "disk.used abc 1500000000",
+            "disk.used nan 1500000000",
+            "disk.used;mount 10 1500000000",
+        ] {
+            assert!(parse_line(line, &templates, 42).is_err(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_read_plaintext() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        let listener = GraphiteListener::new(
+            Arc::clone(&db),
+            Arc::new(GraphiteTemplates::new(&[".host.measurement"])?),
+            GraphiteProtocol::Plaintext,
+        );
+
+        let ts = get_current_timestamp() / 1000 + 1;
+        let input = format!(
+            "collectd.host1.load 1 {}\ninvalid line\ncollectd.host1.load 2 {}\ncollectd.host2.load 3 {}\n",
+            ts,
+            ts + 1,
+            ts
+        );
+        listener.read_plaintext(Cursor::new(input.into_bytes()))?;
+
+        let matchers = vec![LabelMatcher::from(&crate::label::Label::from_key_value(
+            "__name__", "load",
+        ))];
+        let res = db.query(matchers.as_slice(), ts * 1000, (ts + 1) * 1000, None)?;
+        assert_eq!(res.len(), 2);
+        let points = res
+            .iter()
+            .map(|(_, points)| points.len())
+            .collect::<Vec<usize>>();
+        assert_eq!(points.iter().sum::<usize>(), 3);
+        Ok(())
+    }
+}
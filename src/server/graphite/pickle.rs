@@ -0,0 +1,468 @@
+use crate::{MonolithErr, Result};
+use std::collections::HashMap;
+
+/// Value decoded from pickle, only types that appear in carbon pickle messages are supported.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PickleValue {
+    None,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<PickleValue>),
+    Tuple(Vec<PickleValue>),
+}
+
+impl PickleValue {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            PickleValue::Int(v) => Some(*v as f64),
+            PickleValue::Float(v) => Some(*v),
+            PickleValue::Str(v) => v.trim().parse::<f64>().ok(),
+            PickleValue::Bytes(v) => String::from_utf8_lossy(v).trim().parse::<f64>().ok(),
+            _ => None,
+        }
+    }
+
+    fn as_string(&self) -> Option<String> {
+        match self {
+            PickleValue::Str(v) => Some(v.clone()),
+            PickleValue::Bytes(v) => String::from_utf8(v.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    fn items(&self) -> Option<&Vec<PickleValue>> {
+        match self {
+            PickleValue::List(items) | PickleValue::Tuple(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Number of values inside, used to limit the cost of copying values from memo.
+    fn weight(&self) -> usize {
+        match self {
+            PickleValue::List(items) | PickleValue::Tuple(items) => {
+                1 + items.iter().map(PickleValue::weight).sum::<usize>()
+            }
+            _ => 1,
+        }
+    }
+}
+
+///
+/// Decode the payload sent by carbon, which is a pickled list of `(path, (timestamp, value))`.
+///
+/// Returns `(path, timestamp in seconds, value)`, malformed entries are skipped.
+pub fn decode_metrics(data: &[u8]) -> Result<Vec<(String, f64, f64)>> {
+    let value = Unpickler::new(data).load()?;
+    let items = value
+        .items()
+        .ok_or_else(|| invalid("expect a list of metrics".to_string()))?;
+    let mut res = Vec::new();
+    for item in items {
+        let metric = item
+            .items()
+            .filter(|m| m.len() == 2)
+            .and_then(|m| Some((m[0].as_string()?, m[1].items()?)))
+            .filter(|(_, point)| point.len() == 2)
+            .and_then(|(path, point)| Some((path, point[0].as_f64()?, point[1].as_f64()?)));
+        match metric {
+            Some(metric) => res.push(metric),
+            None => warn!("Skip malformed graphite pickle entry {:?}", item),
+        }
+    }
+    Ok(res)
+}
+
+fn invalid(reason: String) -> MonolithErr {
+    MonolithErr::GraphiteErr(format!("cannot decode pickle, {}", reason))
+}
+
+///
+/// Stack machine that evaluates pickle opcodes of protocol 0 to 5.
+///
+/// Values in memo are copied instead of shared, which is fine because carbon never mutates a list
+/// after getting it from memo.
+struct Unpickler<'a> {
+    data: &'a [u8],
+    pos: usize,
+    stack: Vec<PickleValue>,
+    marks: Vec<usize>,
+    memo: HashMap<usize, PickleValue>,
+    // values that can still be copied from memo, prevent small payload from allocating too much memory
+    budget: usize,
+}
+
+impl<'a> Unpickler<'a> {
+    fn new(data: &'a [u8]) -> Unpickler<'a> {
+        Unpickler {
+            data,
+            pos: 0,
+            stack: Vec::new(),
+            marks: Vec::new(),
+            memo: HashMap::new(),
+            budget: data.len(),
+        }
+    }
+
+    fn load(mut self) -> Result<PickleValue> {
+        loop {
+            let opcode = self.read_byte()?;
+            match opcode {
+                0x80 => {
+                    // PROTO
+                    self.read_byte()?;
+                }
+                0x95 => {
+                    // FRAME
+                    self.read(8)?;
+                }
+                b'.' => return self.pop(),
+                b'(' => self.marks.push(self.stack.len()),
+                b'N' => self.stack.push(PickleValue::None),
+                0x88 => self.stack.push(PickleValue::Bool(true)),
+                0x89 => self.stack.push(PickleValue::Bool(false)),
+                b'I' => {
+                    let value = match self.read_line()? {
+                        "00" => PickleValue::Bool(false),
+                        "01" => PickleValue::Bool(true),
+                        line => PickleValue::Int(parse_line(line)?),
+                    };
+                    self.stack.push(value)
+                }
+                b'L' => {
+                    let line = self.read_line()?;
+                    let value = parse_line(line.trim_end_matches('L'))?;
+                    self.stack.push(PickleValue::Int(value))
+                }
+                b'J' => {
+                    let value = i32::from_le_bytes(self.read_array()?);
+                    self.stack.push(PickleValue::Int(value as i64))
+                }
+                b'K' => {
+                    let value = self.read_byte()?;
+                    self.stack.push(PickleValue::Int(value as i64))
+                }
+                b'M' => {
+                    let value = u16::from_le_bytes(self.read_array()?);
+                    self.stack.push(PickleValue::Int(value as i64))
+                }
+                0x8a => {
+                    let len = self.read_byte()? as usize;
+                    let value = decode_long(self.read(len)?)?;
+                    self.stack.push(PickleValue::Int(value))
+                }
+                0x8b => {
+                    let len = self.read_len(4)?;
+                    let value = decode_long(self.read(len)?)?;
+                    self.stack.push(PickleValue::Int(value))
+                }
+                b'F' => {
+                    let value = parse_line(self.read_line()?)?;
+                    self.stack.push(PickleValue::Float(value))
+                }
+                b'G' => {
+                    let value = f64::from_be_bytes(self.read_array()?);
+                    self.stack.push(PickleValue::Float(value))
+                }
+                b'S' => {
+                    let value = unquote(self.read_line()?)?;
+                    self.stack.push(PickleValue::Str(value))
+                }
+                b'V' => {
+                    let value = self.read_line()?.to_string();
+                    self.stack.push(PickleValue::Str(value))
+                }
+                b'T' | b'U' | b'B' | b'C' | 0x8e => {
+                    let len = match opcode {
+                        b'U' | b'C' => self.read_byte()? as usize,
+                        0x8e => self.read_len(8)?,
+                        _ => self.read_len(4)?,
+                    };
+                    let value = self.read(len)?;
+                    self.stack.push(match opcode {
+                        b'B' | b'C' | 0x8e => PickleValue::Bytes(value.to_vec()),
+                        _ => PickleValue::Str(String::from_utf8_lossy(value).to_string()),
+                    })
+                }
+                b'X' | 0x8c | 0x8d => {
+                    let len = match opcode {
+                        0x8c => self.read_byte()? as usize,
+                        0x8d => self.read_len(8)?,
+                        _ => self.read_len(4)?,
+                    };
+                    let value = String::from_utf8(self.read(len)?.to_vec())
+                        .map_err(|_| invalid("invalid utf8 string".to_string()))?;
+                    self.stack.push(PickleValue::Str(value))
+                }
+                b']' => self.stack.push(PickleValue::List(Vec::new())),
+                b')' => self.stack.push(PickleValue::Tuple(Vec::new())),
+                b'l' => {
+                    let items = self.pop_mark()?;
+                    self.stack.push(PickleValue::List(items))
+                }
+                b't' => {
+                    let items = self.pop_mark()?;
+                    self.stack.push(PickleValue::Tuple(items))
+                }
+                0x85..=0x87 => {
+                    let len = (opcode - 0x84) as usize;
+                    if self.stack.len() < len {
+                        return Err(invalid("stack underflow".to_string()));
+                    }
+                    let items = self.stack.split_off(self.stack.len() - len);
+                    self.stack.push(PickleValue::Tuple(items))
+                }
+                b'a' => {
+                    let item = self.pop()?;
+                    self.top_list()?.push(item)
+                }
+                b'e' => {
+                    let mut items = self.pop_mark()?;
+                    self.top_list()?.append(&mut items)
+                }
+                b'p' => {
+                    let idx = parse_line(self.read_line()?)?;
+                    self.put(idx)?
+                }
+                b'q' => {
+                    let idx = self.read_byte()? as usize;
+                    self.put(idx)?
+                }
+                b'r' => {
+                    let idx = self.read_len(4)?;
+                    self.put(idx)?
+                }
+                0x94 => {
+                    let idx = self.memo.len();
+                    self.put(idx)?
+                }
+                b'g' => {
+                    let idx = parse_line(self.read_line()?)?;
+                    self.get(idx)?
+                }
+                b'h' => {
+                    let idx = self.read_byte()? as usize;
+                    self.get(idx)?
+                }
+                b'j' => {
+                    let idx = self.read_len(4)?;
+                    self.get(idx)?
+                }
+                b'0' => {
+                    self.pop()?;
+                }
+                b'1' => {
+                    self.pop_mark()?;
+                }
+                b'2' => {
+                    let top = self
+                        .stack
+                        .last()
+                        .ok_or_else(|| invalid("stack underflow".to_string()))?
+                        .clone();
+                    self.consume(top.weight())?;
+                    self.stack.push(top)
+                }
+                _ => return Err(invalid(format!("unsupported opcode 0x{:02x}", opcode))),
+            }
+        }
+    }
+
+    fn read(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.pos < len {
+            return Err(invalid("unexpected end of data".to_string()));
+        }
+        let res = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(res)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_array<T: Default + AsMut<[u8]>>(&mut self) -> Result<T> {
+        let mut res = T::default();
+        let len = res.as_mut().len();
+        res.as_mut().copy_from_slice(self.read(len)?);
+        Ok(res)
+    }
+
+    /// Read a little endian length with `size` bytes
+    fn read_len(&mut self, size: usize) -> Result<usize> {
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(self.read(size)?);
+        Ok(u64::from_le_bytes(buf) as usize)
+    }
+
+    fn read_line(&mut self) -> Result<&'a str> {
+        let len = self.data[self.pos..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .ok_or_else(|| invalid("unexpected end of data".to_string()))?;
+        let line = self.read(len + 1)?;
+        std::str::from_utf8(&line[..len]).map_err(|_| invalid("invalid utf8 string".to_string()))
+    }
+
+    fn pop(&mut self) -> Result<PickleValue> {
+        self.stack
+            .pop()
+            .ok_or_else(|| invalid("stack underflow".to_string()))
+    }
+
+    fn pop_mark(&mut self) -> Result<Vec<PickleValue>> {
+        let mark = self
+            .marks
+            .pop()
+            .ok_or_else(|| invalid("mark not found".to_string()))?;
+        if mark > self.stack.len() {
+            return Err(invalid("stack underflow".to_string()));
+        }
+        Ok(self.stack.split_off(mark))
+    }
+
+    fn top_list(&mut self) -> Result<&mut Vec<PickleValue>> {
+        match self.stack.last_mut() {
+            Some(PickleValue::List(items)) => Ok(items),
+            _ => Err(invalid("append to non-list value".to_string())),
+        }
+    }
+
+    fn put(&mut self, idx: usize) -> Result<()> {
+        let top = self
+            .stack
+            .last()
+            .ok_or_else(|| invalid("stack underflow".to_string()))?
+            .clone();
+        self.memo.insert(idx, top);
+        Ok(())
+    }
+
+    fn get(&mut self, idx: usize) -> Result<()> {
+        let value = self
+            .memo
+            .get(&idx)
+            .ok_or_else(|| invalid(format!("memo {} not found", idx)))?
+            .clone();
+        self.consume(value.weight())?;
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn consume(&mut self, weight: usize) -> Result<()> {
+        if weight > self.budget {
+            return Err(invalid("too many values copied from memo".to_string()));
+        }
+        self.budget -= weight;
+        Ok(())
+    }
+}
+
+fn parse_line<T: std::str::FromStr>(line: &str) -> Result<T> {
+    line.trim()
+        .parse::<T>()
+        .map_err(|_| invalid(format!("invalid number {}", line)))
+}
+
+/// Decode little endian two's complement integer
+fn decode_long(bytes: &[u8]) -> Result<i64> {
+    if bytes.len() > 8 {
+        return Err(invalid("integer overflow".to_string()));
+    }
+    if bytes.is_empty() {
+        return Ok(0);
+    }
+    let fill = if bytes[bytes.len() - 1] & 0x80 != 0 {
+        0xff
+    } else {
+        0x00
+    };
+    let mut buf = [fill; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    Ok(i64::from_le_bytes(buf))
+}
+
+/// Decode python string literal of protocol 0, like `'abc'`
+fn unquote(line: &str) -> Result<String> {
+    let quoted = line.len() >= 2
+        && (line.starts_with('\'') && line.ends_with('\'')
+            || line.starts_with('"') && line.ends_with('"'));
+    if !quoted {
+        return Err(invalid(format!("invalid string {}", line)));
+    }
+    let mut res = String::new();
+    let mut chars = line[1..line.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            res.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => res.push('\n'),
+            Some('t') => res.push('\t'),
+            Some('r') => res.push('\r'),
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                let byte = u8::from_str_radix(hex.as_str(), 16)
+                    .map_err(|_| invalid(format!("invalid string {}", line)))?;
+                res.push(byte as char)
+            }
+            Some(c) => res.push(c),
+            None => return Err(invalid(format!("invalid string {}", line))),
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::graphite::pickle::{decode_metrics, Unpickler};
+
+    fn expected() -> Vec<(String, f64, f64)> {
+        vec![
+            ("servers.host1.cpu".to_string(), 1500000000.0, 0.5),
+            ("servers.host1.mem".to_string(), 1500000000.5, 1024.0),
+            ("servers.host1.cpu".to_string(), 1500000010.0, -1.0),
+        ]
+    }
+
+    #[test]
+    fn test_decode_protocol_0() {
+        let data = b"(lp0\n(Vservers.host1.cpu\np1\n(I1500000000\nF0.5\ntp2\ntp3\na(Vservers.host1.mem\np4\n(F1500000000.5\nI1024\ntp5\ntp6\na(g1\n(I1500000010\nI-1\ntp7\ntp8\na.";
+        assert_eq!(decode_metrics(data).unwrap(), expected());
+    }
+
+    #[test]
+    fn test_decode_protocol_2() {
+        let data = b"\x80\x02]q\x00(X\x11\x00\x00\x00servers.host1.cpuq\x01J\x00/hYG?\xe0\x00\x00\x00\x00\x00\x00\x86q\x02\x86q\x03X\x11\x00\x00\x00servers.host1.memq\x04GA\xd6Z\x0b\xc0 \x00\x00M\x00\x04\x86q\x05\x86q\x06h\x01J\n/hYJ\xff\xff\xff\xff\x86q\x07\x86q\x08e.";
+        assert_eq!(decode_metrics(data).unwrap(), expected());
+    }
+
+    #[test]
+    fn test_decode_protocol_4() {
+        let data = b"\x80\x04\x95_\x00\x00\x00\x00\x00\x00\x00]\x94(\x8c\x11servers.host1.cpu\x94J\x00/hYG?\xe0\x00\x00\x00\x00\x00\x00\x86\x94\x86\x94\x8c\x11servers.host1.mem\x94GA\xd6Z\x0b\xc0 \x00\x00M\x00\x04\x86\x94\x86\x94h\x01J\n/hYJ\xff\xff\xff\xff\x86\x94\x86\x94e.";
+        assert_eq!(decode_metrics(data).unwrap(), expected());
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        // truncated
+        assert!(decode_metrics(b"\x80\x02]q\x00(X\x11\x00").is_err());
+        // not a list
+        assert!(decode_metrics(b"K\x01.").is_err());
+        // unsupported opcode
+        assert!(decode_metrics(b"c__builtin__\neval\n.").is_err());
+        // malformed entries are skipped
+        assert_eq!(decode_metrics(b"(lp0\nI1\naS'a'\na.").unwrap(), vec![]);
+        // exponential growth through memo
+        let mut data = b"]q\x00".to_vec();
+        for i in 0..40u8 {
+            data.extend_from_slice(&[b'(', b'h', i, b'h', i, b'l', b'q', i + 1]);
+        }
+        data.push(b'.');
+        assert!(Unpickler::new(data.as_slice()).load().is_err());
+    }
+}
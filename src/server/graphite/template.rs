@@ -0,0 +1,252 @@
+use crate::common::label::{Label, Labels};
+use crate::common::selector::{is_valid_label_name, sanitize_name, METRIC_NAME_LABEL};
+use crate::{MonolithErr, Result};
+use regex::Regex;
+use std::collections::BTreeMap;
+
+const MEASUREMENT: &str = "measurement";
+const FIELD: &str = "field";
+
+/// Meaning of one node in template
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Skip,
+    /// `measurement`, or `measurement*` which takes all remaining nodes
+    Measurement(bool),
+    /// `field`, or `field*` which takes all remaining nodes
+    Field(bool),
+    Tag(String),
+}
+
+///
+/// Rule that maps a dotted graphite path into metric name and labels, in the format of
+/// `[filter] template [tag=value,...]`, for example `servers.* .host.measurement.field* dc=east`.
+///
+/// 1. Filter is a dotted pattern where `*` matches any characters inside one node, the rule applies to
+///    paths starting with matched nodes. Rule without filter applies to all paths.
+/// 2. Each node of template describes the node at the same position of the path. `measurement` and
+///    `field` nodes are joined by `_` to build the metric name, empty node is skipped and any other node
+///    is used as label name.
+/// 3. Optional tags are added to all metrics matched by this rule.
+#[derive(Clone, Debug)]
+struct Template {
+    filter: Option<Regex>,
+    parts: Vec<Part>,
+    tags: Vec<(String, String)>,
+}
+
+impl Template {
+    fn parse(rule: &str) -> Result<Template> {
+        let tokens = rule.split_whitespace().collect::<Vec<&str>>();
+        let (filter, template, tags) = match tokens.as_slice() {
+            [template] => (None, *template, None),
+            [template, tags] if tags.contains('=') => (None, *template, Some(*tags)),
+            [filter, template] => (Some(*filter), *template, None),
+            [filter, template, tags] => (Some(*filter), *template, Some(*tags)),
+            _ => return Err(invalid(rule, "expect [filter] template [tags]")),
+        };
+
+        let filter = match filter {
+            Some(filter) => {
+                let pattern = filter
+                    .split('.')
+                    .map(|node| regex::escape(node).replace("\\*", "[^.]*"))
+                    .collect::<Vec<String>>()
+                    .join("\\.");
+                Some(
+                    Regex::new(format!("^{}(\\..*)?$", pattern).as_str())
+                        .map_err(|e| invalid(rule, e.to_string().as_str()))?,
+                )
+            }
+            None => None,
+        };
+
+        let nodes = template.split('.').collect::<Vec<&str>>();
+        let mut parts = Vec::new();
+        for (idx, node) in nodes.iter().enumerate() {
+            let greedy = node.ends_with('*');
+            if greedy && idx != nodes.len() - 1 {
+                return Err(invalid(rule, "wildcard is only allowed in the last node"));
+            }
+            let part = match node.trim_end_matches('*') {
+                "" if !greedy => Part::Skip,
+                MEASUREMENT => Part::Measurement(greedy),
+                FIELD => Part::Field(greedy),
+                tag if !greedy && is_valid_label_name(tag) && tag != METRIC_NAME_LABEL => {
+                    Part::Tag(tag.to_string())
+                }
+                _ => return Err(invalid(rule, format!("invalid node {}", node).as_str())),
+            };
+            parts.push(part);
+        }
+        if !parts.iter().any(|p| matches!(p, Part::Measurement(_))) {
+            return Err(invalid(rule, "measurement is required"));
+        }
+
+        let mut default_tags = Vec::new();
+        for tag in tags
+            .map(|t| t.split(',').collect())
+            .unwrap_or_else(Vec::new)
+        {
+            let mut kv = tag.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) if is_valid_label_name(key) && !value.is_empty() => {
+                    default_tags.push((key.to_string(), value.to_string()))
+                }
+                _ => return Err(invalid(rule, format!("invalid tag {}", tag).as_str())),
+            }
+        }
+
+        Ok(Template {
+            filter,
+            parts,
+            tags: default_tags,
+        })
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        self.filter.as_ref().map_or(true, |f| f.is_match(path))
+    }
+
+    /// Return metric name and tags, or `None` if the path is too short to build a name
+    fn apply(&self, path: &str) -> Option<(String, BTreeMap<String, String>)> {
+        let nodes = path.split('.').collect::<Vec<&str>>();
+        let mut measurement = Vec::new();
+        let mut field = Vec::new();
+        let mut extracted: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for (part, idx) in self.parts.iter().zip(0..nodes.len()) {
+            match part {
+                Part::Skip => {}
+                Part::Measurement(false) => measurement.push(nodes[idx]),
+                Part::Measurement(true) => measurement.extend_from_slice(&nodes[idx..]),
+                Part::Field(false) => field.push(nodes[idx]),
+                Part::Field(true) => field.extend_from_slice(&nodes[idx..]),
+                // same tag in multiple nodes are joined
+                Part::Tag(name) => extracted.entry(name).or_default().push(nodes[idx]),
+            }
+        }
+        let mut tags: BTreeMap<String, String> = self.tags.iter().cloned().collect();
+        for (name, values) in extracted {
+            tags.insert(name.to_string(), values.join("_"));
+        }
+        if measurement.is_empty() {
+            return None;
+        }
+        measurement.append(&mut field);
+        Some((measurement.join("_"), tags))
+    }
+}
+
+fn invalid(rule: &str, reason: &str) -> MonolithErr {
+    MonolithErr::GraphiteErr(format!("invalid template `{}`, {}", rule, reason))
+}
+
+///
+/// Ordered template rules, the first rule whose filter matches the path is used. Paths that no rule
+/// matches are converted into metric names by replacing `.` with `_`.
+#[derive(Clone, Debug, Default)]
+pub struct GraphiteTemplates {
+    templates: Vec<Template>,
+}
+
+impl GraphiteTemplates {
+    pub fn new(rules: &[&str]) -> Result<GraphiteTemplates> {
+        let mut templates = Vec::new();
+        for rule in rules {
+            templates.push(Template::parse(rule)?);
+        }
+        Ok(GraphiteTemplates { templates })
+    }
+
+    /// Build labels of `path`, `tags` from graphite tagged series overwrite tags from template.
+    pub fn labels(&self, path: &str, tags: &[(&str, &str)]) -> Labels {
+        let (name, mut res) = self
+            .templates
+            .iter()
+            .filter(|t| t.matches(path))
+            .find_map(|t| t.apply(path))
+            .unwrap_or_else(|| (path.replace('.', "_"), BTreeMap::new()));
+        for (key, value) in tags {
+            res.insert(sanitize_name(key, false), value.to_string());
+        }
+        res.insert(
+            METRIC_NAME_LABEL.to_string(),
+            sanitize_name(name.as_str(), true),
+        );
+        Labels::from_vec(
+            res.into_iter()
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(key, value)| Label::new(key, value))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::graphite::template::GraphiteTemplates;
+
+    fn labels(templates: &GraphiteTemplates, path: &str) -> Vec<String> {
+        templates
+            .labels(path, &[])
+            .vec()
+            .iter()
+            .map(|l| format!("{}={}", l.key(), l.value()))
+            .collect()
+    }
+
+    #[test]
+    fn test_templates() {
+        let templates = GraphiteTemplates::new(&[
+            "servers.* .host.measurement.field* dc=east",
+            "stats.*.counters measurement.region.measurement",
+            "nginx.* measurement.app env=prod",
+            ".measurement.host",
+        ])
+        .unwrap();
+        assert_eq!(
+            labels(&templates, "servers.web-01.cpu.load.shortterm"),
+            vec!["__name__=cpu_load_shortterm", "dc=east", "host=web-01"]
+        );
+        assert_eq!(
+            labels(&templates, "stats.eu.counters.requests"),
+            vec!["__name__=stats_counters", "region=eu"]
+        );
+        assert_eq!(
+            labels(&templates, "nginx.requests"),
+            vec!["__name__=nginx", "app=requests", "env=prod"]
+        );
+        assert_eq!(
+            labels(&templates, "collectd.memory.db-01"),
+            vec!["__name__=memory", "host=db-01"]
+        );
+        // path too short for the first matched template
+        assert_eq!(labels(&templates, "a"), vec!["__name__=a"]);
+    }
+
+    #[test]
+    fn test_default_template() {
+        let templates = GraphiteTemplates::new(&[]).unwrap();
+        assert_eq!(
+            labels(&templates, "servers.host-1.cpu"),
+            vec!["__name__=servers_host_1_cpu"]
+        );
+        let labels = templates.labels("disk.used", &[("mount", "/"), ("host", "a")]);
+        assert_eq!(labels.len(), 3);
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for rule in vec![
+            "",
+            "host.field",
+            "measurement*.host",
+            "measurement.ho-st",
+            "measurement.__name__",
+            "measurement dc",
+            "a b c d",
+        ] {
+            assert!(GraphiteTemplates::new(&[rule]).is_err(), "{}", rule);
+        }
+    }
+}
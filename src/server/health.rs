@@ -0,0 +1,23 @@
+use crate::server::http::Response;
+
+/// Path of liveness probe, which is always ok while the server is serving
+pub const HEALTHY_PATH: &str = "/-/healthy";
+/// Path of readiness probe, which is ok once existing chunks are read and before shutting down
+pub const READY_PATH: &str = "/-/ready";
+
+/// Probes carry no data, so they are served without authentication
+pub fn is_probe(path: &str) -> bool {
+    path == HEALTHY_PATH || path == READY_PATH
+}
+
+pub fn healthy() -> Response {
+    Response::from_string("Monolith is Healthy.\n")
+}
+
+pub fn ready(is_ready: bool) -> Response {
+    if is_ready {
+        Response::from_string("Monolith is Ready.\n")
+    } else {
+        Response::from_string("Service Unavailable\n").with_status_code(503)
+    }
+}
@@ -0,0 +1,346 @@
+use crate::limits::LimitReason;
+use crate::{MonolithErr, Result};
+use futures::channel::oneshot;
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderMap, CONTENT_LENGTH};
+use std::io;
+use std::io::Write;
+
+pub use hyper::Method;
+
+/// Max size of request body, which is also the max size of decompressed remote read or write request
+pub const MAX_REQUEST_BODY_SIZE: usize = 32 * 1024 * 1024;
+
+/// Seconds that clients should wait before retrying when server is overloaded
+const RETRY_AFTER_SECS: &str = "5";
+
+///
+/// Request whose body has been read, so that handlers don't wait on the connection.
+pub struct Request {
+    method: Method,
+    url: String,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: Method, url: &str, headers: HeaderMap, body: Vec<u8>) -> Request {
+        Request {
+            method,
+            url: url.to_string(),
+            headers,
+            body,
+        }
+    }
+
+    /// Read the body of `request`, fail with `RequestTooLargeErr` if it's larger than `limit`
+    pub async fn read(
+        request: hyper::Request<hyper::Body>,
+        limit: usize,
+    ) -> std::result::Result<Request, HttpError> {
+        let (parts, mut body) = request.into_parts();
+        let len = parts
+            .headers
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<usize>().ok());
+        if len.map_or(false, |len| len > limit) {
+            return Err(MonolithErr::RequestTooLargeErr(limit).into());
+        }
+        let mut data = Vec::with_capacity(len.unwrap_or(0));
+        while let Some(chunk) = body.data().await {
+            let chunk =
+                chunk.map_err(|e| HttpError::bad_request(format!("cannot read body, {}", e)))?;
+            if data.len() + chunk.len() > limit {
+                return Err(MonolithErr::RequestTooLargeErr(limit).into());
+            }
+            data.extend_from_slice(&chunk);
+        }
+        let url = parts
+            .uri
+            .path_and_query()
+            .map_or(parts.uri.path(), |p| p.as_str());
+        Ok(Request::new(parts.method, url, parts.headers, data))
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// Path and query string
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    pub fn path(&self) -> &str {
+        self.url.split('?').next().unwrap_or("")
+    }
+
+    /// Value of header `name`, case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    pub fn body(&self) -> &[u8] {
+        self.body.as_slice()
+    }
+}
+
+type BodyFn = Box<dyn FnOnce(&mut dyn Write) -> Result<()> + Send>;
+
+/// Body of `Response`
+pub enum Body {
+    Data(Vec<u8>),
+    /// Called with the body after the status and headers are sent, so that large responses are streamed
+    /// to client while they are produced.
+    Writer(BodyFn),
+}
+
+///
+/// Response of handlers, which is converted to the response of hyper by `respond`.
+pub struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Body,
+}
+
+impl Response {
+    pub fn from_data<D: Into<Vec<u8>>>(data: D) -> Response {
+        Response {
+            status: 200,
+            headers: Vec::new(),
+            body: Body::Data(data.into()),
+        }
+    }
+
+    pub fn from_string<T: Into<String>>(data: T) -> Response {
+        Response::from_data(data.into().into_bytes())
+    }
+
+    pub fn from_writer<F>(writer: F) -> Response
+    where
+        F: FnOnce(&mut dyn Write) -> Result<()> + Send + 'static,
+    {
+        Response {
+            status: 200,
+            headers: Vec::new(),
+            body: Body::Writer(Box::new(writer)),
+        }
+    }
+
+    pub fn empty(status: u16) -> Response {
+        Response::from_data(Vec::new()).with_status_code(status)
+    }
+
+    pub fn with_status_code(mut self, status: u16) -> Response {
+        self.status = status;
+        self
+    }
+
+    pub fn with_header(mut self, name: &'static str, value: &str) -> Response {
+        self.add_header(name, value);
+        self
+    }
+
+    pub fn add_header(&mut self, name: &'static str, value: &str) {
+        self.headers.push((name, value.to_string()));
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Values of header `name`, case insensitive
+    pub fn headers(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    /// Data of body, `None` if the body is streamed
+    pub fn data(&self) -> Option<&[u8]> {
+        match &self.body {
+            Body::Data(data) => Some(data.as_slice()),
+            Body::Writer(_) => None,
+        }
+    }
+
+    ///
+    /// Send the response through `sender`, streamed body is written on current thread afterwards and
+    /// blocks it until the client has received the body or gone.
+    pub fn respond(self, sender: oneshot::Sender<hyper::Response<hyper::Body>>) {
+        let (response, writer) = self.into_hyper();
+        if sender.send(response).is_err() {
+            // connection has been closed, nobody reads the body
+            return;
+        }
+        if let Some((writer, sender)) = writer {
+            let mut body = BodyWriter(sender);
+            if let Err(err) = writer(&mut body) {
+                error!("Error when streaming response, {}", err);
+                // make client see an incomplete body instead of a truncated one
+                body.0.abort();
+            }
+        }
+    }
+
+    /// Convert to the response of hyper, and the writer of body with its sender if the body is streamed.
+    fn into_hyper(
+        self,
+    ) -> (
+        hyper::Response<hyper::Body>,
+        Option<(BodyFn, hyper::body::Sender)>,
+    ) {
+        let (body, writer) = match self.body {
+            Body::Data(data) => (hyper::Body::from(data), None),
+            Body::Writer(writer) => {
+                let (sender, body) = hyper::Body::channel();
+                (body, Some((writer, sender)))
+            }
+        };
+        let mut response = hyper::Response::builder().status(self.status);
+        for (name, value) in self.headers.iter() {
+            response = response.header(*name, value.as_str());
+        }
+        let response = response.body(body).unwrap_or_else(|err| {
+            error!("Cannot build response, {}", err);
+            let mut response = hyper::Response::new(hyper::Body::empty());
+            *response.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        });
+        (response, writer)
+    }
+}
+
+/// Blocking writer of streamed body, each write is sent as one chunk
+struct BodyWriter(hyper::body::Sender);
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        futures::executor::block_on(self.0.send_data(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection has been closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+///
+/// Error responded to client with the error message as plain text body.
+///
+/// Prometheus decides whether to retry by status code, it retries on 5xx and drops the data on 4xx.
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(status: u16, message: String) -> HttpError {
+        HttpError { status, message }
+    }
+
+    pub fn bad_request(message: String) -> HttpError {
+        HttpError::new(400, message)
+    }
+
+    pub fn into_response(self) -> Response {
+        let mut response = Response::from_string(self.message)
+            .with_status_code(self.status)
+            .with_header("Content-Type", "text/plain");
+        if self.status == 429 || self.status == 503 {
+            response.add_header("Retry-After", RETRY_AFTER_SECS);
+        }
+        response
+    }
+}
+
+/// Error responded before the request reaches workers, it has no streamed body.
+impl From<HttpError> for hyper::Response<hyper::Body> {
+    fn from(err: HttpError) -> Self {
+        err.into_response().into_hyper().0
+    }
+}
+
+impl From<MonolithErr> for HttpError {
+    fn from(err: MonolithErr) -> Self {
+        HttpError::new(status_code(&err), err.to_string())
+    }
+}
+
+/// Status code of `err`, 4xx if caused by the request and 5xx if caused by server or backend.
+pub fn status_code(err: &MonolithErr) -> u16 {
+    match err {
+        MonolithErr::RequestTooLargeErr(_) => 413,
+        MonolithErr::LimitErr(LimitReason::RateLimited, _) => 429,
+        MonolithErr::ParseErr
+        | MonolithErr::InvalidMatcherErr(_)
+        | MonolithErr::InvalidSeriesErr(_)
+        | MonolithErr::InvalidTenantErr(_)
+        | MonolithErr::LimitErr(_, _)
+        | MonolithErr::QueryParseErr(_)
+        | MonolithErr::GraphiteErr(_)
+        | MonolithErr::OutOfRangeErr(_, _) => 400,
+        MonolithErr::ReadOnlyErr => 403,
+        MonolithErr::QueryExecErr(_) => 422,
+        MonolithErr::IoError(_) | MonolithErr::TiKvErr(_) | MonolithErr::ClosedErr => 503,
+        _ => 500,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::http::{status_code, HttpError, Response};
+    use crate::MonolithErr;
+    use futures::channel::oneshot;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(status_code(&MonolithErr::RequestTooLargeErr(1)), 413);
+        assert_eq!(status_code(&MonolithErr::ParseErr), 400);
+        assert_eq!(status_code(&MonolithErr::ReadOnlyErr), 403);
+        assert_eq!(status_code(&MonolithErr::IoError("".to_string())), 503);
+        assert_eq!(status_code(&MonolithErr::InternalErr("".to_string())), 500);
+    }
+
+    #[test]
+    fn test_into_response() {
+        let response = HttpError::new(503, "overloaded".to_string()).into_response();
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.headers("retry-after"), vec!["5"]);
+        assert_eq!(response.data(), Some(&b"overloaded"[..]));
+
+        let response = HttpError::bad_request("bad".to_string()).into_response();
+        assert_eq!(response.status(), 400);
+        assert!(response.headers("Retry-After").is_empty());
+    }
+
+    #[test]
+    fn test_respond() {
+        let (sender, receiver) = oneshot::channel();
+        let handle = std::thread::spawn(move || {
+            Response::from_writer(|writer| {
+                writer.write_all(b"hello, ")?;
+                writer.write_all(b"world")?;
+                Ok(())
+            })
+            .with_header("Content-Type", "text/plain")
+            .respond(sender)
+        });
+        // the body is streamed after the response is received
+        let response = block_on(receiver).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        let body = block_on(hyper::body::to_bytes(response.into_body())).unwrap();
+        assert_eq!(&body[..], b"hello, world");
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,366 @@
+use crate::common::label::{Label, Labels};
+use crate::common::selector::{sanitize_name, METRIC_NAME_LABEL};
+use crate::common::time_point::TimePoint;
+use crate::common::utils::get_current_timestamp;
+use crate::indexer::Indexer;
+use crate::server::api::Params;
+use crate::server::http::{status_code, Request, Response};
+use crate::storage::Storage;
+use crate::{MonolithDb, MonolithErr, Result, Timestamp};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Path that accepts InfluxDB line protocol, same as InfluxDB 2.x
+pub const INFLUX_WRITE_PATH: &str = "/api/v2/write";
+
+/// Field that will not be appended to metric name
+const DEFAULT_FIELD: &str = "value";
+
+/// Precision of timestamp in line protocol
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Precision {
+    Nanosecond,
+    Microsecond,
+    Millisecond,
+    Second,
+}
+
+impl Precision {
+    /// Parse precision from query parameter, default to nanosecond
+    pub fn from_param(param: Option<&str>) -> Result<Precision> {
+        match param {
+            None | Some("ns") => Ok(Precision::Nanosecond),
+            Some("us") => Ok(Precision::Microsecond),
+            Some("ms") => Ok(Precision::Millisecond),
+            Some("s") => Ok(Precision::Second),
+            Some(other) => Err(MonolithErr::InternalErr(format!(
+                "invalid precision {}",
+                other
+            ))),
+        }
+    }
+
+    fn to_millis(self, timestamp: u64) -> Timestamp {
+        match self {
+            Precision::Nanosecond => timestamp / 1_000_000,
+            Precision::Microsecond => timestamp / 1_000,
+            Precision::Millisecond => timestamp,
+            Precision::Second => timestamp * 1_000,
+        }
+    }
+}
+
+/// Error of one line, `line` starts from 1
+#[derive(Debug)]
+pub struct LineError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Serve write request of line protocol, valid lines are written even if some other lines are invalid.
+pub fn handle<S, I>(db: &MonolithDb<S, I>, request: &Request) -> Response
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let precision = match Params::from_request(request)
+        .and_then(|params| Precision::from_param(params.get("precision")))
+    {
+        Ok(precision) => precision,
+        Err(err) => return error_response(400, "invalid", err.to_string(), Vec::new()),
+    };
+    let body = match std::str::from_utf8(request.body()) {
+        Ok(body) => body,
+        Err(_) => {
+            let err = MonolithErr::ParseErr;
+            return error_response(status_code(&err), "invalid", err.to_string(), Vec::new());
+        }
+    };
+
+    let (points, errors) = parse_lines(body, precision, get_current_timestamp());
+    let mut series: HashMap<Labels, Vec<TimePoint>> = HashMap::new();
+    for (labels, point) in points {
+        series.entry(labels).or_default().push(point);
+    }
+    for (labels, points) in series {
+        if let Err(err) = db.write_time_points(labels, points) {
+            error!("Error when write line protocol into db, {}", err);
+            return error_response(
+                status_code(&err),
+                "internal error",
+                err.to_string(),
+                Vec::new(),
+            );
+        }
+    }
+
+    if errors.is_empty() {
+        Response::empty(204)
+    } else {
+        let message = format!(
+            "partial write, {} line(s) cannot be parsed, first error at line {}: {}",
+            errors.len(),
+            errors[0].line,
+            errors[0].message
+        );
+        let errors = errors
+            .into_iter()
+            .map(|e| json!({"line": e.line, "message": e.message}))
+            .collect();
+        error_response(400, "invalid", message, errors)
+    }
+}
+
+fn error_response(status: u16, code: &str, message: String, errors: Vec<Value>) -> Response {
+    let body = json!({"code": code, "message": message, "errors": errors});
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header("Content-Type", "application/json")
+}
+
+/// Parse all lines in `body`, time points without timestamp use `now`.
+pub fn parse_lines(
+    body: &str,
+    precision: Precision,
+    now: Timestamp,
+) -> (Vec<(Labels, TimePoint)>, Vec<LineError>) {
+    let mut points = Vec::new();
+    let mut errors = Vec::new();
+    for (idx, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_line(line, precision, now) {
+            Ok(mut res) => points.append(&mut res),
+            Err(message) => errors.push(LineError {
+                line: idx + 1,
+                message,
+            }),
+        }
+    }
+    (points, errors)
+}
+
+///
+/// Parse one line of `measurement[,tag=value...] field=value[,field=value...] [timestamp]`.
+///
+/// Each numeric field becomes one time series named `<measurement>_<field>`, or `<measurement>` if the
+/// field is `value`. Tags become labels. String fields are ignored since only float values can be stored.
+fn parse_line(
+    line: &str,
+    precision: Precision,
+    now: Timestamp,
+) -> std::result::Result<Vec<(Labels, TimePoint)>, String> {
+    let sections = split_unescaped(line, ' ', true)
+        .into_iter()
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>();
+    if sections.len() < 2 || sections.len() > 3 {
+        return Err("expect measurement, fields and optional timestamp".to_string());
+    }
+
+    let mut series_key = split_unescaped(sections[0], ',', false).into_iter();
+    let measurement = unescape(series_key.next().unwrap_or(""));
+    if measurement.is_empty() {
+        return Err("missing measurement".to_string());
+    }
+    let mut tags = Vec::new();
+    for tag in series_key {
+        let (key, value) = split_key_value(tag)?;
+        if key.is_empty() || value.is_empty() {
+            return Err(format!("invalid tag {}", tag));
+        }
+        tags.push(Label::from_key_value(
+            sanitize_name(key.as_str(), false).as_str(),
+            value.as_str(),
+        ));
+    }
+
+    let timestamp = match sections.get(2) {
+        Some(ts) => {
+            let ts = ts
+                .parse::<u64>()
+                .map_err(|_| format!("invalid timestamp {}", ts))?;
+            precision.to_millis(ts)
+        }
+        None => now,
+    };
+
+    let mut res = Vec::new();
+    for field in split_unescaped(sections[1], ',', true) {
+        let (key, value) = split_key_value(field)?;
+        if key.is_empty() {
+            return Err(format!("invalid field {}", field));
+        }
+        let value = match parse_field_value(value.as_str())? {
+            Some(value) => value,
+            None => continue,
+        };
+        let name = if key == DEFAULT_FIELD {
+            measurement.clone()
+        } else {
+            format!("{}_{}", measurement, key)
+        };
+        let mut labels = Labels::from_vec(tags.clone());
+        labels.add(Label::from_key_value(
+            METRIC_NAME_LABEL,
+            sanitize_name(name.as_str(), true).as_str(),
+        ));
+        labels.sort();
+        res.push((labels, TimePoint::new(timestamp, value)));
+    }
+    if res.is_empty() {
+        return Err("no numeric field".to_string());
+    }
+    Ok(res)
+}
+
+/// Return `None` for string value
+fn parse_field_value(value: &str) -> std::result::Result<Option<f64>, String> {
+    if value.starts_with('"') {
+        if value.len() < 2 || !value.ends_with('"') {
+            return Err(format!("unterminated string field {}", value));
+        }
+        return Ok(None);
+    }
+    let invalid = || format!("invalid field value {}", value);
+    let res = match value {
+        "t" | "T" | "true" | "True" | "TRUE" => 1.0,
+        "f" | "F" | "false" | "False" | "FALSE" => 0.0,
+        v if v.ends_with('i') => v[..v.len() - 1].parse::<i64>().map_err(|_| invalid())? as f64,
+        v if v.ends_with('u') => v[..v.len() - 1].parse::<u64>().map_err(|_| invalid())? as f64,
+        v => v.parse::<f64>().map_err(|_| invalid())?,
+    };
+    if !res.is_finite() {
+        return Err(invalid());
+    }
+    Ok(Some(res))
+}
+
+/// Split `s` at `sep` which is not escaped by `\`, and not inside double quotes if `quote_aware` is set.
+fn split_unescaped(s: &str, sep: char, quote_aware: bool) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut quoted = false;
+    for (idx, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if quote_aware && c == '"' {
+            quoted = !quoted;
+        } else if c == sep && !quoted {
+            res.push(&s[start..idx]);
+            start = idx + c.len_utf8();
+        }
+    }
+    res.push(&s[start..]);
+    res
+}
+
+fn split_key_value(s: &str) -> std::result::Result<(String, String), String> {
+    let parts = split_unescaped(s, '=', true);
+    if parts.len() < 2 {
+        return Err(format!("missing = in {}", s));
+    }
+    let key = unescape(parts[0]);
+    // value may contains = inside quotes
+    let value = &s[parts[0].len() + 1..];
+    if value.starts_with('"') {
+        Ok((key, value.to_string()))
+    } else {
+        Ok((key, unescape(value)))
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.peek() {
+                if *next == ',' || *next == '=' || *next == ' ' || *next == '\\' {
+                    res.push(*next);
+                    chars.next();
+                    continue;
+                }
+            }
+        }
+        res.push(c);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::Labels;
+    use crate::server::influx::{parse_lines, Precision};
+
+    fn label_value(labels: &Labels, name: &str) -> String {
+        labels
+            .vec()
+            .iter()
+            .find(|l| l.key() == name)
+            .map(|l| l.value().clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let body = "cpu,host=server\\ 1,region=us-west usage_idle=99.5,usage_user=0.5 1500000000000000000\n\
+                    \n\
+                    mem value=1024i,status=\"ok, fine\" 1500000000000000000\n\
+                    disk,path=/ free=true";
+        let (points, errors) = parse_lines(body, Precision::Nanosecond, 42);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(points.len(), 4);
+
+        let (labels, tp) = &points[0];
+        assert_eq!(label_value(labels, "__name__"), "cpu_usage_idle");
+        assert_eq!(label_value(labels, "host"), "server 1");
+        assert_eq!(label_value(labels, "region"), "us-west");
+        assert_eq!(tp.timestamp, 1500000000000);
+        assert_eq!(tp.value, 99.5);
+
+        let (labels, tp) = &points[2];
+        assert_eq!(label_value(labels, "__name__"), "mem");
+        assert_eq!(tp.value, 1024.0);
+
+        let (labels, tp) = &points[3];
+        assert_eq!(label_value(labels, "__name__"), "disk_free");
+        assert_eq!(tp.timestamp, 42);
+        assert_eq!(tp.value, 1.0);
+    }
+
+    #[test]
+    fn test_parse_invalid_lines() {
+        let body = "cpu\n\
+                    cpu value=abc\n\
+                    cpu value=1 abc\n\
+                    cpu status=\"ok\"\n\
+                    cpu value=1 1500000000";
+        let (points, errors) = parse_lines(body, Precision::Second, 0);
+        assert_eq!(points.len(), 1);
+        assert_eq!(points[0].1.timestamp, 1500000000000);
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<usize>>(),
+            vec![1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_precision() {
+        assert_eq!(
+            Precision::from_param(Some("us")).unwrap(),
+            Precision::Microsecond
+        );
+        assert_eq!(Precision::from_param(None).unwrap(), Precision::Nanosecond);
+        assert!(Precision::from_param(Some("m")).is_err());
+        assert_eq!(
+            Precision::Microsecond.to_millis(1500000000000000),
+            1500000000000
+        );
+    }
+}
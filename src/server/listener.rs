@@ -0,0 +1,177 @@
+use crate::option::ListenAddr;
+use crate::server::{tls, unix, Shutdown};
+use crate::{MonolithErr, Result};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Request, Response};
+use openssl::ssl::SslAcceptor;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::{mpsc, watch};
+
+/// How often the listener checks if it should shut down
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Wait before accepting again if accepting fails, like running out of file descriptors
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+///
+/// Listener of the http server, on TCP with or without TLS, or on unix socket.
+///
+/// Each connection is served in its own task, as HTTP/1.1 with keep-alive and pipelining, or as HTTP/2
+/// if the client starts with its preface or chooses it through ALPN.
+pub enum Listener {
+    Tcp(TcpListener),
+    Tls(TcpListener, Arc<SslAcceptor>),
+    Unix(UnixListener),
+}
+
+enum Stream {
+    Tcp(TcpStream, SocketAddr),
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Bind `addr`, TLS is used if certificate and key are given, but not on unix socket.
+    pub async fn bind(addr: &ListenAddr, tls_files: Option<(&Path, &Path)>) -> Result<Listener> {
+        match addr {
+            ListenAddr::Tcp(addr) => {
+                let listener = TcpListener::bind(addr.as_str()).await.map_err(|e| {
+                    MonolithErr::IoError(format!("Cannot listen on {}, {}", addr, e))
+                })?;
+                match tls_files {
+                    Some((cert, key)) => Ok(Listener::Tls(
+                        listener,
+                        Arc::new(tls::build_acceptor(cert, key)?),
+                    )),
+                    None => Ok(Listener::Tcp(listener)),
+                }
+            }
+            ListenAddr::Unix(path) => {
+                if tls_files.is_some() {
+                    warn!("TLS is ignored when listening on unix socket");
+                }
+                unix::bind(path.as_path()).map(Listener::Unix).map_err(|e| {
+                    MonolithErr::IoError(format!("Cannot listen on {}, {}", path.display(), e))
+                })
+            }
+        }
+    }
+
+    ///
+    /// Serve connections by `service` until `shutdown` is triggered, which is called with each request
+    /// and the address of client, `None` for unix socket clients.
+    ///
+    /// After shutdown, no more connections are accepted. Open connections finish their in-flight requests
+    /// and then are closed, this returns after all of them are closed.
+    pub async fn serve<F, R>(self, shutdown: Shutdown, service: F)
+    where
+        F: Fn(Request<Body>, Option<SocketAddr>) -> R + Clone + Send + 'static,
+        R: Future<Output = std::result::Result<Response<Body>, Infallible>> + Send + 'static,
+    {
+        // each connection holds a sender, `recv` returns `None` once all of them are closed
+        let (opened, mut closed) = mpsc::channel::<()>(1);
+        let (close, closing) = watch::channel(false);
+        let mut ticker = tokio::time::interval(SHUTDOWN_POLL_INTERVAL);
+        loop {
+            let accepted = tokio::select! {
+                _ = ticker.tick() => {
+                    if shutdown.is_triggered() {
+                        break;
+                    }
+                    continue;
+                }
+                accepted = self.accept() => accepted,
+            };
+            let stream = match accepted {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("Cannot accept connection, {}", err);
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+            let acceptor = match &self {
+                Listener::Tls(_, acceptor) => Some(Arc::clone(acceptor)),
+                _ => None,
+            };
+            let service = service.clone();
+            let closing = closing.clone();
+            let opened = opened.clone();
+            tokio::spawn(async move {
+                match (stream, acceptor) {
+                    (Stream::Tcp(stream, addr), Some(acceptor)) => {
+                        match tls::accept(acceptor.as_ref(), stream).await {
+                            Ok(stream) => {
+                                serve_connection(stream, Some(addr), service, closing).await
+                            }
+                            Err(err) => {
+                                debug!("Cannot accept tls connection from {}, {}", addr, err)
+                            }
+                        }
+                    }
+                    (Stream::Tcp(stream, addr), None) => {
+                        serve_connection(stream, Some(addr), service, closing).await
+                    }
+                    (Stream::Unix(stream), _) => {
+                        serve_connection(stream, None, service, closing).await
+                    }
+                }
+                drop(opened);
+            });
+        }
+        // stop accepting connections before waiting for the open ones
+        drop(self);
+        close.send(true).ok();
+        drop(opened);
+        closed.recv().await;
+    }
+
+    async fn accept(&self) -> std::io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) | Listener::Tls(listener, _) => {
+                let (stream, addr) = listener.accept().await?;
+                // responses are small and pipelined, don't wait to fill a packet
+                stream.set_nodelay(true)?;
+                Ok(Stream::Tcp(stream, addr))
+            }
+            Listener::Unix(listener) => Ok(Stream::Unix(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// Serve requests on `io` until the client closes it, or close it gracefully once `closing` is set
+async fn serve_connection<T, F, R>(
+    io: T,
+    client: Option<SocketAddr>,
+    service: F,
+    mut closing: watch::Receiver<bool>,
+) where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    F: Fn(Request<Body>, Option<SocketAddr>) -> R + Send + 'static,
+    R: Future<Output = std::result::Result<Response<Body>, Infallible>> + Send + 'static,
+{
+    let conn =
+        Http::new().serve_connection(io, service_fn(move |request| service(request, client)));
+    tokio::pin!(conn);
+    let mut closed = false;
+    loop {
+        tokio::select! {
+            res = conn.as_mut() => {
+                if let Err(err) = res {
+                    debug!("Error when serving connection, {}", err);
+                }
+                return;
+            }
+            _ = closing.changed(), if !closed => {
+                closed = true;
+                conn.as_mut().graceful_shutdown();
+            }
+        }
+    }
+}
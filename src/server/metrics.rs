@@ -0,0 +1,124 @@
+use crate::indexer::Indexer;
+use crate::metrics::{encode_global, TextEncoder};
+use crate::server::http::Response;
+use crate::storage::Storage;
+use crate::tenant::Tenants;
+use std::time::Duration;
+
+/// Path of self instrumentation in Prometheus text format
+pub const METRICS_PATH: &str = "/metrics";
+
+const TEXT_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+///
+/// Respond metrics of the server and all dbs.
+///
+/// Metrics of dbs are labeled by `tenant`, which is empty for the default db. Process wide metrics,
+/// like request latency, are not labeled by tenant.
+///
+/// `replication_lag` is given if the server is a read replica, which is `None` before the replica
+/// has caught up with its leader.
+pub fn handle<S, I>(tenants: &Tenants<S, I>, replication_lag: Option<Option<Duration>>) -> Response
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let mut encoder = TextEncoder::new();
+    encode_dbs(tenants, &mut encoder);
+    if let Some(lag) = replication_lag {
+        encoder.header(
+            "monolith_replication_lag_seconds",
+            "Seconds since the replica last caught up with its leader",
+            "gauge",
+        );
+        encoder.sample(
+            "monolith_replication_lag_seconds",
+            &[],
+            lag.map_or(std::f64::INFINITY, |lag| lag.as_secs_f64()),
+        );
+    }
+    encode_global(&mut encoder);
+    Response::from_string(encoder.finish()).with_header("Content-Type", TEXT_CONTENT_TYPE)
+}
+
+fn encode_dbs<S, I>(tenants: &Tenants<S, I>, encoder: &mut TextEncoder)
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let dbs = tenants
+        .all()
+        .into_iter()
+        .map(|(tenant, db)| (tenant.unwrap_or_default(), db))
+        .collect::<Vec<_>>();
+
+    encoder.header(
+        "monolith_ingested_samples_total",
+        "Number of samples written into chunks",
+        "counter",
+    );
+    for (tenant, db) in dbs.iter() {
+        encoder.sample(
+            "monolith_ingested_samples_total",
+            &[("tenant", tenant.as_str())],
+            db.ingested_samples() as f64,
+        );
+    }
+
+    encoder.header(
+        "monolith_discarded_samples_total",
+        "Number of samples rejected by limits",
+        "counter",
+    );
+    for (tenant, db) in dbs.iter() {
+        for (reason, samples) in db.rejected_samples() {
+            encoder.sample(
+                "monolith_discarded_samples_total",
+                &[("tenant", tenant.as_str()), ("reason", reason.as_str())],
+                samples as f64,
+            );
+        }
+    }
+
+    encoder.header(
+        "monolith_active_series",
+        "Number of series in the current chunk",
+        "gauge",
+    );
+    for (tenant, db) in dbs.iter() {
+        encoder.sample(
+            "monolith_active_series",
+            &[("tenant", tenant.as_str())],
+            db.active_series() as f64,
+        );
+    }
+
+    encoder.header("monolith_chunks", "Number of chunks", "gauge");
+    for (tenant, db) in dbs.iter() {
+        encoder.sample(
+            "monolith_chunks",
+            &[("tenant", tenant.as_str())],
+            db.chunk_count() as f64,
+        );
+    }
+
+    encoder.header(
+        "monolith_chunk_disk_bytes",
+        "Size of chunk dir on disk",
+        "gauge",
+    );
+    for (tenant, db) in dbs.iter() {
+        match db.chunk_disk_usage() {
+            Ok(usage) => {
+                for (chunk, bytes) in usage {
+                    encoder.sample(
+                        "monolith_chunk_disk_bytes",
+                        &[("tenant", tenant.as_str()), ("chunk", chunk.as_str())],
+                        bytes as f64,
+                    );
+                }
+            }
+            Err(err) => warn!("Cannot get disk usage of chunks, {}", err),
+        }
+    }
+}
@@ -0,0 +1,706 @@
+use crate::MonolithDb;
+
+use crate::common::hint::QueryHint;
+use crate::common::label::{LabelMatcher, Labels};
+use crate::common::selector::is_valid_label_name;
+use crate::common::time_series::{LabelPointPairs, TimeSeries};
+use crate::proto::{
+    QueryResult, ReadRequest, ReadRequest_ResponseType, ReadResponse, WriteRequest,
+};
+use crate::server::http::{HttpError, Method, Request, Response};
+use crate::storage::Storage;
+use crate::{MonolithErr, Result, Timestamp, FORWARD_DIR};
+use futures::channel::oneshot;
+use protobuf::{Message, RepeatedField};
+use std::collections::HashSet;
+use std::convert::Infallible;
+
+use crate::forward::{Forwarder, RemoteWriteConfig};
+use crate::indexer::Indexer;
+use crate::metrics::{observe_since, REQUEST_DURATION};
+use crate::option::{ListenAddr, ServerOpts};
+use crate::replica::Replicator;
+use crate::server::auth::Authenticator;
+use crate::server::graphite::{GraphiteListener, GraphiteProtocol, GraphiteTemplates};
+use crate::server::listener::Listener;
+use crate::server::pool::WorkerPool;
+use crate::tenant::Tenants;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod admin;
+mod api;
+mod auth;
+mod chunked;
+mod federate;
+mod graphite;
+mod health;
+mod http;
+mod influx;
+mod listener;
+mod metrics;
+mod opentsdb;
+mod pool;
+mod replication;
+mod shutdown;
+mod tls;
+mod unix;
+
+pub use replication::REPLICATION_PREFIX;
+pub use shutdown::Shutdown;
+
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+const REMOTE_READ_VERSION_HEADER: &str = "X-Prometheus-Remote-Read-Version";
+const REMOTE_WRITE_VERSION_HEADER: &str = "X-Prometheus-Remote-Write-Version";
+/// Header of tenant id, the same as Cortex and Loki
+pub const TENANT_HEADER: &str = "X-Scope-OrgID";
+/// Only version 0.1.x of remote read and write protocol is supported
+const SUPPORTED_REMOTE_VERSION: &str = "0.1.";
+
+/// Max number of rejected series listed in the response of a write request
+const MAX_REPORTED_REJECTED_SERIES: usize = 10;
+/// How often the server checks if requests of closed connections are done when shutting down
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Http Server that accept Prometheus requests
+///
+/// Besides remote read and write, the server accepts InfluxDB, OpenTSDB and Graphite writes, and serves
+/// the Prometheus HTTP API, federation, admin, health and replication endpoints, see `Handler::route`.
+///
+/// Note that the Prometheus remote storage requests using __unframed__ snappy encoding __proto__ object.
+///
+pub struct MonolithServer<'a, S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    tenants: Arc<Tenants<S, I>>,
+    port: i32,
+    listen_address: Option<&'a str>,
+    tls_cert_file: Option<PathBuf>,
+    tls_key_file: Option<PathBuf>,
+    basic_auth_file: Option<PathBuf>,
+    bearer_token_file: Option<PathBuf>,
+    read_path: &'a str,
+    write_path: &'a str,
+    worker_num: usize,
+    graphite_port: Option<i32>,
+    graphite_pickle_port: Option<i32>,
+    graphite_templates: Vec<&'a str>,
+    enable_admin_api: bool,
+    remote_write_config: Option<PathBuf>,
+    forwarder: Option<Arc<Forwarder>>,
+    replicate_from: Option<&'a str>,
+    replica_max_lag: Duration,
+    replicator: Option<Arc<Replicator>>,
+    shutdown: Shutdown,
+}
+
+impl<'a, S, I> MonolithServer<'a, S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    pub fn new(opts: ServerOpts<'a>, db: Arc<MonolithDb<S, I>>) -> Self {
+        MonolithServer {
+            tenants: Arc::new(Tenants::new(db)),
+            port: opts.port,
+            listen_address: opts.listen_address,
+            tls_cert_file: opts.tls_cert_file,
+            tls_key_file: opts.tls_key_file,
+            basic_auth_file: opts.basic_auth_file,
+            bearer_token_file: opts.bearer_token_file,
+            read_path: opts.read_path,
+            write_path: opts.write_path,
+            worker_num: opts.worker_num,
+            graphite_port: opts.graphite_port,
+            graphite_pickle_port: opts.graphite_pickle_port,
+            graphite_templates: opts.graphite_templates,
+            enable_admin_api: opts.enable_admin_api,
+            remote_write_config: opts.remote_write_config,
+            forwarder: None,
+            replicate_from: opts.replicate_from,
+            replica_max_lag: opts.replica_max_lag,
+            replicator: None,
+            shutdown: Shutdown::new(),
+        }
+    }
+
+    /// Stop serving when `shutdown` is triggered, the server never stops by default
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    ///
+    /// Listen on `127.0.0.1:<port>`, or on `listen_address` which can be any TCP address or a unix socket,
+    /// and serve until `Shutdown` is triggered.
+    ///
+    /// Https is served if certificate and key are configured. Connections are served asynchronously as
+    /// HTTP/1.1 with keep-alive and pipelining, or as HTTP/2, see `Listener`. Graphite listeners,
+    /// forwarder and replicator are started along with the server if they are configured.
+    ///
+    /// When shutting down, the server stops accepting requests, waits for the pending ones and closes all
+    /// dbs before returning.
+    pub fn serve(mut self) -> Result<()> {
+        let listen_addr = ListenAddr::from_opts(self.listen_address, self.port)?;
+        let auth = Authenticator::from_files(
+            self.basic_auth_file.as_deref(),
+            self.bearer_token_file.as_deref(),
+        )?;
+
+        let tls_files = match (&self.tls_cert_file, &self.tls_key_file) {
+            (Some(cert), Some(key)) => Some((cert.as_path(), key.as_path())),
+            _ => None,
+        };
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("monolith-http")
+            .build()?;
+        let listener = runtime.block_on(Listener::bind(&listen_addr, tls_files))?;
+        info!("Server listening on {}", listen_addr);
+
+        self.serve_graphite(listen_addr.host())?;
+        self.forwarder = self.start_forwarder()?;
+        self.replicator = self.start_replicator()?;
+
+        let handler = Arc::new(Handler {
+            tenants: Arc::clone(&self.tenants),
+            auth,
+            read_path: self.read_path.to_string(),
+            write_path: self.write_path.to_string(),
+            enable_admin_api: self.enable_admin_api,
+            forwarder: self.forwarder.clone(),
+            replica_max_lag: self.replica_max_lag,
+            replicator: self.replicator.clone(),
+            shutdown: self.shutdown.clone(),
+        });
+        let pool = Arc::new(WorkerPool::new(self.worker_num)?);
+        let service = {
+            let pool = Arc::clone(&pool);
+            move |request, client| {
+                Handler::serve(Arc::clone(&handler), Arc::clone(&pool), request, client)
+            }
+        };
+        runtime.block_on(listener.serve(self.shutdown.clone(), service));
+
+        // requests of connections closed by clients may be still running
+        info!(
+            "Shutting down, waiting for {} pending requests",
+            pool.pending()
+        );
+        while pool.pending() > 0 {
+            std::thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+        if let Some(forwarder) = &self.forwarder {
+            forwarder.close();
+        }
+        if let Some(replicator) = &self.replicator {
+            replicator.close();
+        }
+        self.tenants.close_all()?;
+        info!("Server stopped");
+        Ok(())
+    }
+
+    /// Start graphite listeners in background if their ports are configured, they always write to the
+    /// default db
+    fn serve_graphite(&self, host: &str) -> Result<()> {
+        let templates = Arc::new(GraphiteTemplates::new(self.graphite_templates.as_slice())?);
+        for &(port, protocol) in [
+            (self.graphite_port, GraphiteProtocol::Plaintext),
+            (self.graphite_pickle_port, GraphiteProtocol::Pickle),
+        ]
+        .iter()
+        {
+            if let Some(port) = port {
+                let db = Arc::clone(self.tenants.default_db());
+                GraphiteListener::new(db, Arc::clone(&templates), protocol)
+                    .spawn(format!("{}:{}", host, port).as_str())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start forwarding remote writes if endpoints are configured, queues are kept in base dir of default db
+    fn start_forwarder(&self) -> Result<Option<Arc<Forwarder>>> {
+        let path = match &self.remote_write_config {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let configs = RemoteWriteConfig::from_file(path.as_path())?;
+        let dir = self.tenants.default_db().base_dir().join(FORWARD_DIR);
+        Ok(Some(Arc::new(Forwarder::new(configs, dir.as_path())?)))
+    }
+
+    /// Start replicating the default db from the leader if `replicate_from` is set
+    fn start_replicator(&self) -> Result<Option<Arc<Replicator>>> {
+        match self.replicate_from {
+            Some(leader) => Ok(Some(Arc::new(Replicator::start(
+                leader,
+                Arc::clone(self.tenants.default_db()),
+            )?))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn query(db: &MonolithDb<S, I>, read_rq: ReadRequest) -> Result<ReadResponse> {
+        let mut results = Vec::new();
+        for q in read_rq.queries.iter() {
+            let mut matchers = Vec::new();
+            for m in q.matchers.iter() {
+                matchers.push(LabelMatcher::from_label_matcher(m)?);
+            }
+            let hint = q
+                .hints
+                .as_ref()
+                .and_then(|h| QueryHint::from_read_hints(h, q.start_timestamp_ms as Timestamp));
+            let timeseries = db
+                .query(
+                    matchers.as_slice(),
+                    q.start_timestamp_ms as Timestamp,
+                    q.end_timestamp_ms as Timestamp,
+                    hint.as_ref(),
+                )?
+                .iter()
+                .map(crate::proto::TimeSeries::from)
+                .collect::<Vec<crate::proto::TimeSeries>>();
+            results.push(QueryResult {
+                timeseries: RepeatedField::from(timeseries),
+                unknown_fields: Default::default(),
+                cached_size: Default::default(),
+            });
+        }
+        Ok(ReadResponse {
+            results: RepeatedField::from(results),
+            unknown_fields: Default::default(),
+            cached_size: Default::default(),
+        })
+    }
+
+    ///
+    /// Write all series of `write_rq`, return the points written of each series, and the rejected series
+    /// with the reason.
+    ///
+    /// A series is both written and rejected if only some of its points are accepted.
+    pub fn write(
+        db: &MonolithDb<S, I>,
+        write_rq: &WriteRequest,
+    ) -> (LabelPointPairs, Vec<(Labels, MonolithErr)>) {
+        let mut written = Vec::new();
+        let mut rejected = Vec::new();
+        for time_series in write_rq.timeseries.iter() {
+            let _ts: TimeSeries = TimeSeries::from(time_series);
+            let mut accepted = Vec::new();
+            let res = MonolithServer::<S, I>::validate_labels(_ts.meta_data()).and_then(|_| {
+                db.write_time_points_accepted(
+                    _ts.meta_data().clone(),
+                    _ts.time_points().clone(),
+                    &mut accepted,
+                )
+            });
+            if !accepted.is_empty() {
+                written.push((_ts.meta_data().clone(), accepted));
+            }
+            if let Err(err) = res {
+                rejected.push((_ts.meta_data().clone(), err));
+            }
+        }
+        (written, rejected)
+    }
+
+    /// Series must have at least one label, and label names must be valid and unique.
+    fn validate_labels(labels: &Labels) -> Result<()> {
+        if labels.len() == 0 {
+            return Err(MonolithErr::InvalidSeriesErr(
+                "series without labels".to_string(),
+            ));
+        }
+        let mut names = HashSet::new();
+        for label in labels.vec() {
+            if !is_valid_label_name(label.key()) {
+                return Err(MonolithErr::InvalidSeriesErr(format!(
+                    "invalid label name {}",
+                    label.key()
+                )));
+            }
+            if !names.insert(label.key()) {
+                return Err(MonolithErr::InvalidSeriesErr(format!(
+                    "duplicate label name {}",
+                    label.key()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+///
+/// State of the server shared by all requests, requests are read by connections and handled in the
+/// worker pool.
+struct Handler<S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    tenants: Arc<Tenants<S, I>>,
+    auth: Option<Authenticator>,
+    read_path: String,
+    write_path: String,
+    enable_admin_api: bool,
+    forwarder: Option<Arc<Forwarder>>,
+    replica_max_lag: Duration,
+    replicator: Option<Arc<Replicator>>,
+    shutdown: Shutdown,
+}
+
+impl<S, I> Handler<S, I>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    /// Read the request and handle it in `pool`, reject it without reading the body if too many
+    /// requests are pending.
+    async fn serve(
+        handler: Arc<Handler<S, I>>,
+        pool: Arc<WorkerPool>,
+        request: hyper::Request<hyper::Body>,
+        client: Option<SocketAddr>,
+    ) -> std::result::Result<hyper::Response<hyper::Body>, Infallible> {
+        let path = request.uri().path().to_string();
+        if pool.is_full() {
+            return Ok(handler.reject_overloaded(path.as_str(), client).into());
+        }
+        let request = match Request::read(request, http::MAX_REQUEST_BODY_SIZE).await {
+            Ok(request) => request,
+            Err(err) => {
+                warn!("Reject bad request to {}, {}", path, err.message);
+                return Ok(err.into());
+            }
+        };
+        let (sender, receiver) = oneshot::channel();
+        let worker = Arc::clone(&handler);
+        if !pool.try_spawn(move || worker.handle(request).respond(sender)) {
+            return Ok(handler.reject_overloaded(path.as_str(), client).into());
+        }
+        Ok(receiver.await.unwrap_or_else(|_| {
+            HttpError::new(500, format!("error when process request to {}", path)).into()
+        }))
+    }
+
+    /// Writes are rejected with 503 so that Prometheus will retry them later, other requests with 429.
+    fn reject_overloaded(&self, path: &str, client: Option<SocketAddr>) -> HttpError {
+        let status = if path == self.write_path { 503 } else { 429 };
+        match client {
+            Some(client) => warn!(
+                "Reject request to {} from {} because too many requests are pending",
+                path, client
+            ),
+            None => warn!(
+                "Reject request to {} because too many requests are pending",
+                path
+            ),
+        }
+        HttpError::new(status, "too many pending requests".to_string())
+    }
+
+    /// Replica is readable once it has caught up with the leader within `replica_max_lag`, the leader
+    /// is always readable
+    fn is_readable(&self) -> bool {
+        match &self.replicator {
+            Some(replicator) => replicator
+                .lag()
+                .map_or(false, |lag| lag <= self.replica_max_lag),
+            None => true,
+        }
+    }
+
+    /// Requests other than probes must carry valid basic auth or bearer token if authentication is
+    /// configured.
+    fn handle(&self, request: Request) -> Response {
+        let path = request.path();
+        match &self.auth {
+            Some(auth) if !health::is_probe(path) && !auth.authenticate(&request) => {
+                return auth.unauthorized();
+            }
+            _ => {}
+        }
+        let start = Instant::now();
+        let handler = self.handler_name(path);
+        let response = self.route(&request);
+        observe_since(&REQUEST_DURATION, &[handler], start);
+        response
+    }
+
+    /// Name of the handler that serves `path`, used to label request metrics
+    fn handler_name(&self, path: &str) -> &'static str {
+        if path == self.read_path {
+            "read"
+        } else if path == self.write_path {
+            "write"
+        } else if path.starts_with(admin::ADMIN_PREFIX) {
+            "admin"
+        } else if path.starts_with(api::API_V1_PREFIX) {
+            "api"
+        } else if path == influx::INFLUX_WRITE_PATH {
+            "influx_write"
+        } else if path == opentsdb::OPENTSDB_PUT_PATH {
+            "opentsdb_put"
+        } else if path == metrics::METRICS_PATH {
+            "metrics"
+        } else if path == federate::FEDERATE_PATH {
+            "federate"
+        } else if path.starts_with(replication::REPLICATION_PREFIX) {
+            "replication"
+        } else if health::is_probe(path) {
+            "health"
+        } else {
+            "other"
+        }
+    }
+
+    ///
+    /// Serve `request` by its path.
+    ///
+    /// Metrics and the probes are served for the whole server, and it's ready once existing chunks of
+    /// the default db are read and the replica has caught up. Other requests use the db of the tenant in
+    /// `X-Scope-OrgID` header, or the default db without the header. A replica responds reads with 503
+    /// until it has caught up with the leader.
+    fn route(&self, request: &Request) -> Response {
+        let path = request.path();
+        if path == metrics::METRICS_PATH {
+            let replication_lag = self.replicator.as_ref().map(|r| r.lag());
+            return metrics::handle(self.tenants.as_ref(), replication_lag);
+        }
+        if path == health::HEALTHY_PATH {
+            return health::healthy();
+        }
+        if path == health::READY_PATH {
+            let ready = self.tenants.default_db().is_ready()
+                && self.is_readable()
+                && !self.shutdown.is_triggered();
+            return health::ready(ready);
+        }
+        let db = match self.tenants.get(request.header(TENANT_HEADER)) {
+            Ok(db) => db,
+            Err(err) => {
+                warn!("Reject request to {}, {}", path, err);
+                return HttpError::from(err).into_response();
+            }
+        };
+        if path.starts_with(replication::REPLICATION_PREFIX) {
+            return match api::Params::from_request(request) {
+                Ok(params) => replication::handle(db.as_ref(), request.method(), path, &params),
+                Err(err) => api::ApiResponse::from(err).into_response(),
+            };
+        }
+        let is_read = path == self.read_path
+            || path == federate::FEDERATE_PATH
+            || (path.starts_with(api::API_V1_PREFIX) && !path.starts_with(admin::ADMIN_PREFIX));
+        if is_read && !self.is_readable() {
+            warn!("Reject request to {}, replica lags behind the leader", path);
+            return HttpError::new(503, "replica lags behind the leader".to_string())
+                .into_response();
+        }
+        if path.starts_with(admin::ADMIN_PREFIX) {
+            return match api::Params::from_request(request) {
+                Ok(params) => admin::handle(
+                    db.as_ref(),
+                    request.method(),
+                    path,
+                    &params,
+                    self.enable_admin_api,
+                ),
+                Err(err) => api::ApiResponse::from(err).into_response(),
+            };
+        }
+        if path.starts_with(api::API_V1_PREFIX) {
+            let response = match api::Params::from_request(request) {
+                Ok(params) => api::handle(db.as_ref(), path, &params),
+                Err(err) => api::ApiResponse::from(err),
+            };
+            return response.into_response();
+        }
+        if path == federate::FEDERATE_PATH {
+            return match api::Params::from_request(request) {
+                Ok(params) => federate::handle(db.as_ref(), &params, request.header("Accept")),
+                Err(err) => api::ApiResponse::from(err).into_response(),
+            };
+        }
+        if path == influx::INFLUX_WRITE_PATH {
+            return influx::handle(db.as_ref(), request);
+        }
+        if path == opentsdb::OPENTSDB_PUT_PATH {
+            return opentsdb::handle(db.as_ref(), request);
+        }
+
+        let response = if path == self.read_path {
+            Handler::handle_read(db, request)
+        } else if path == self.write_path {
+            self.handle_write(db.as_ref(), request)
+        } else {
+            Err(HttpError::new(404, format!("{} not found", path)))
+        };
+        response.unwrap_or_else(|err| {
+            if err.status >= 500 {
+                error!("Error when process request to {}, {}", path, err.message);
+            } else {
+                warn!("Reject bad request to {}, {}", path, err.message);
+            }
+            err.into_response()
+        })
+    }
+
+    /// Respond the snappy encoded `ReadResponse`, or a stream of `ChunkedReadResponse` frames which is
+    /// not snappy encoded if the client accepts `STREAMED_XOR_CHUNKS`.
+    fn handle_read(
+        db: Arc<MonolithDb<S, I>>,
+        request: &Request,
+    ) -> std::result::Result<Response, HttpError> {
+        let content = Handler::<S, I>::decode_request(request, REMOTE_READ_VERSION_HEADER)?;
+        let mut read_req = ReadRequest::new();
+        read_req
+            .merge_from_bytes(content.as_slice())
+            .map_err(|e| HttpError::bad_request(format!("cannot decode read request, {}", e)))?;
+        if read_req.queries.is_empty() {
+            return Err(HttpError::bad_request("empty read request".to_string()));
+        }
+
+        if Handler::<S, I>::negotiate_response_type(&read_req)
+            == ReadRequest_ResponseType::STREAMED_XOR_CHUNKS
+        {
+            return Ok(chunked::stream_read(db, read_req)
+                .with_header("Content-Type", chunked::STREAMED_CONTENT_TYPE));
+        }
+
+        let read_res = MonolithServer::query(db.as_ref(), read_req)?;
+        let content = read_res
+            .write_to_bytes()
+            .map_err(|e| HttpError::new(500, format!("cannot encode read response, {}", e)))?;
+        let content = snap::raw::Encoder::new()
+            .compress_vec(content.as_slice())
+            .map_err(|e| HttpError::new(500, format!("cannot compress read response, {}", e)))?;
+        Ok(Response::from_data(content)
+            .with_header("Content-Type", PROTOBUF_CONTENT_TYPE)
+            .with_header("Content-Encoding", "snappy"))
+    }
+
+    ///
+    /// Write all series of the request and forward the accepted samples if forwarding is configured.
+    ///
+    /// Malformed series are rejected with 4xx and errors of server with 5xx, so that Prometheus only
+    /// retries the requests that may succeed later.
+    fn handle_write(
+        &self,
+        db: &MonolithDb<S, I>,
+        request: &Request,
+    ) -> std::result::Result<Response, HttpError> {
+        let content = Handler::<S, I>::decode_request(request, REMOTE_WRITE_VERSION_HEADER)?;
+        let mut write_req = WriteRequest::new();
+        write_req
+            .merge_from_bytes(content.as_slice())
+            .map_err(|e| HttpError::bad_request(format!("cannot decode write request, {}", e)))?;
+
+        let total = write_req.timeseries.len();
+        let (written, rejected) = MonolithServer::write(db, &write_req);
+        // only the points accepted by the local db are forwarded
+        if let Some(forwarder) = &self.forwarder {
+            if !written.is_empty() {
+                forwarder.forward(request.header(TENANT_HEADER), written.as_slice());
+            }
+        }
+        if rejected.is_empty() {
+            return Ok(Response::empty(200));
+        }
+        // any error of server makes Prometheus retry the whole request
+        let status = rejected
+            .iter()
+            .map(|(_, err)| http::status_code(err))
+            .max()
+            .unwrap_or(500);
+        let mut message = format!("{} of {} series rejected", rejected.len(), total);
+        for (labels, err) in rejected.iter().take(MAX_REPORTED_REJECTED_SERIES) {
+            message.push_str(format!("\n{}: {}", labels, err).as_str());
+        }
+        if rejected.len() > MAX_REPORTED_REJECTED_SERIES {
+            message.push_str("\n...");
+        }
+        Err(HttpError::new(status, message))
+    }
+
+    ///
+    /// Validate headers of remote read or write request, and return the decompressed body.
+    ///
+    /// Headers are optional, but must have the values that Prometheus sends if present.
+    fn decode_request(
+        request: &Request,
+        version_header: &'static str,
+    ) -> std::result::Result<Vec<u8>, HttpError> {
+        if *request.method() != Method::POST {
+            return Err(HttpError::new(
+                405,
+                format!("method {} not allowed", request.method()),
+            ));
+        }
+        match request.header("Content-Encoding") {
+            Some(encoding) if !encoding.eq_ignore_ascii_case("snappy") => {
+                return Err(HttpError::new(
+                    415,
+                    format!("unsupported content encoding {}", encoding),
+                ))
+            }
+            _ => {}
+        }
+        match request.header("Content-Type") {
+            Some(content_type) if !content_type.starts_with(PROTOBUF_CONTENT_TYPE) => {
+                return Err(HttpError::new(
+                    415,
+                    format!("unsupported content type {}", content_type),
+                ))
+            }
+            _ => {}
+        }
+        match request.header(version_header) {
+            Some(version) if !version.starts_with(SUPPORTED_REMOTE_VERSION) => {
+                return Err(HttpError::bad_request(format!(
+                    "unsupported {} {}",
+                    version_header, version
+                )))
+            }
+            _ => {}
+        }
+
+        let content = request.body();
+        let len = snap::raw::decompress_len(content)
+            .map_err(|e| HttpError::bad_request(format!("cannot decompress body, {}", e)))?;
+        if len > http::MAX_REQUEST_BODY_SIZE {
+            return Err(MonolithErr::RequestTooLargeErr(http::MAX_REQUEST_BODY_SIZE).into());
+        }
+        snap::raw::Decoder::new()
+            .decompress_vec(content)
+            .map_err(|e| HttpError::bad_request(format!("cannot decompress body, {}", e)))
+    }
+
+    /// Pick the response type the client prefers most, fall back to `SAMPLES` if none is given. Both
+    /// `SAMPLES` and `STREAMED_XOR_CHUNKS` are supported, which are all types of the protocol.
+    fn negotiate_response_type(read_rq: &ReadRequest) -> ReadRequest_ResponseType {
+        read_rq
+            .accepted_response_types
+            .first()
+            .cloned()
+            .unwrap_or(ReadRequest_ResponseType::SAMPLES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Result;
+
+    #[test]
+    #[ignore]
+    fn test_serve() -> Result<()> {
+        unimplemented!()
+    }
+}
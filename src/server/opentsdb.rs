@@ -0,0 +1,232 @@
+use crate::common::label::{Label, Labels};
+use crate::common::selector::{sanitize_name, METRIC_NAME_LABEL};
+use crate::common::time_point::TimePoint;
+use crate::indexer::Indexer;
+use crate::server::api::Params;
+use crate::server::http::{status_code, Request, Response};
+use crate::storage::Storage;
+use crate::{MonolithDb, Timestamp};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// Path of OpenTSDB HTTP put API
+pub const OPENTSDB_PUT_PATH: &str = "/api/put";
+
+/// Timestamps larger than this are treated as milliseconds, same as OpenTSDB
+const MAX_SECOND_TIMESTAMP: u64 = 0xFFFF_FFFF;
+
+/// Error of one datapoint, `datapoint` is the original json object
+#[derive(Debug)]
+pub struct DatapointError {
+    pub datapoint: Value,
+    pub message: String,
+}
+
+///
+/// Serve OpenTSDB put request, the body is a single datapoint or an array of datapoints.
+///
+/// Like OpenTSDB, response is `204` if all datapoints are written and `400` if any fails. With
+/// `summary` param the numbers of failed and succeeded datapoints are returned, `details` also
+/// returns the failed datapoints with reasons.
+pub fn handle<S, I>(db: &MonolithDb<S, I>, request: &Request) -> Response
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let params = match Params::from_request(request) {
+        Ok(params) => params,
+        Err(err) => return error_response(status_code(&err), err.to_string()),
+    };
+    let details = params.get("details").is_some();
+    let summary = details || params.get("summary").is_some();
+
+    let datapoints = match serde_json::from_slice::<Value>(request.body()) {
+        Ok(Value::Array(datapoints)) => datapoints,
+        Ok(datapoint @ Value::Object(_)) => vec![datapoint],
+        Ok(_) => {
+            return error_response(
+                400,
+                "expect a datapoint or an array of datapoints".to_string(),
+            )
+        }
+        Err(err) => return error_response(400, format!("cannot parse json, {}", err)),
+    };
+
+    let total = datapoints.len();
+    let errors = write_datapoints(db, datapoints);
+    let failed = errors.len();
+
+    if !summary {
+        return if failed == 0 {
+            Response::empty(204)
+        } else {
+            error_response(
+                400,
+                format!(
+                    "{} of {} data point(s) had errors, first error: {}",
+                    failed, total, errors[0].message
+                ),
+            )
+        };
+    }
+    let mut res = json!({"failed": failed, "success": total - failed});
+    if details {
+        res["errors"] = Value::Array(
+            errors
+                .into_iter()
+                .map(|e| json!({"datapoint": e.datapoint, "error": e.message}))
+                .collect(),
+        );
+    }
+    let status = if failed == 0 { 200 } else { 400 };
+    json_response(status, res)
+}
+
+/// Write valid datapoints into db, return errors of the invalid or unwritable ones.
+fn write_datapoints<S, I>(db: &MonolithDb<S, I>, datapoints: Vec<Value>) -> Vec<DatapointError>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let mut errors = Vec::new();
+    let mut series: HashMap<Labels, Vec<(Value, TimePoint)>> = HashMap::new();
+    for datapoint in datapoints {
+        match parse_datapoint(&datapoint) {
+            Ok((labels, point)) => series.entry(labels).or_default().push((datapoint, point)),
+            Err(message) => errors.push(DatapointError { datapoint, message }),
+        }
+    }
+    for (labels, points) in series {
+        let time_points = points.iter().map(|(_, tp)| tp.clone()).collect();
+        if let Err(err) = db.write_time_points(labels, time_points) {
+            error!("Error when write OpenTSDB datapoints into db, {}", err);
+            for (datapoint, _) in points {
+                errors.push(DatapointError {
+                    datapoint,
+                    message: err.to_string(),
+                });
+            }
+        }
+    }
+    errors
+}
+
+/// Convert one datapoint into labels and time point, metric becomes `__name__` and tags become labels.
+pub fn parse_datapoint(datapoint: &Value) -> std::result::Result<(Labels, TimePoint), String> {
+    let metric = match datapoint.get("metric") {
+        Some(Value::String(metric)) if !metric.is_empty() => metric,
+        _ => return Err("metric is missing or not a string".to_string()),
+    };
+    let timestamp = match datapoint.get("timestamp").and_then(Value::as_u64) {
+        Some(0) | None => return Err("timestamp is missing or invalid".to_string()),
+        Some(ts) if ts > MAX_SECOND_TIMESTAMP => ts as Timestamp,
+        Some(ts) => ts * 1000,
+    };
+    let value = match datapoint.get("value") {
+        Some(Value::Number(value)) => value.as_f64(),
+        Some(Value::String(value)) => value.parse::<f64>().ok(),
+        _ => None,
+    };
+    let value = match value {
+        Some(value) if value.is_finite() => value,
+        _ => return Err("value is missing or not a number".to_string()),
+    };
+    let tags = match datapoint.get("tags") {
+        Some(Value::Object(tags)) if !tags.is_empty() => tags,
+        _ => return Err("at least one tag is required".to_string()),
+    };
+
+    let mut labels = parse_tags(tags)?;
+    labels.add(Label::from_key_value(
+        METRIC_NAME_LABEL,
+        sanitize_name(metric.as_str(), true).as_str(),
+    ));
+    labels.sort();
+    Ok((labels, TimePoint::new(timestamp, value)))
+}
+
+fn parse_tags(tags: &Map<String, Value>) -> std::result::Result<Labels, String> {
+    let mut labels = Labels::new();
+    for (key, value) in tags {
+        let value = match value {
+            Value::String(value) if !value.is_empty() => value.clone(),
+            Value::Number(value) => value.to_string(),
+            _ => return Err(format!("invalid value of tag {}", key)),
+        };
+        labels.add(Label::from_key_value(
+            sanitize_name(key.as_str(), false).as_str(),
+            value.as_str(),
+        ));
+    }
+    Ok(labels)
+}
+
+fn error_response(status: u16, message: String) -> Response {
+    json_response(
+        status,
+        json!({"error": {"code": status, "message": message}}),
+    )
+}
+
+fn json_response(status: u16, body: Value) -> Response {
+    Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header("Content-Type", "application/json")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::opentsdb::parse_datapoint;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_datapoint() {
+        let (labels, tp) = parse_datapoint(&json!({
+            "metric": "sys.cpu.nice",
+            "timestamp": 1346846400,
+            "value": 18,
+            "tags": {"host": "web01", "dc": "lga"}
+        }))
+        .unwrap();
+        let labels = labels
+            .vec()
+            .iter()
+            .map(|l| format!("{}={}", l.key(), l.value()))
+            .collect::<Vec<String>>();
+        assert_eq!(
+            labels,
+            vec!["__name__=sys_cpu_nice", "dc=lga", "host=web01"]
+        );
+        assert_eq!(tp.timestamp, 1346846400000);
+        assert_eq!(tp.value, 18.0);
+
+        let (_, tp) = parse_datapoint(&json!({
+            "metric": "sys.cpu.nice",
+            "timestamp": 1346846400123u64,
+            "value": "0.5",
+            "tags": {"host": "web01"}
+        }))
+        .unwrap();
+        assert_eq!(tp.timestamp, 1346846400123);
+        assert_eq!(tp.value, 0.5);
+    }
+
+    #[test]
+    fn test_parse_invalid_datapoint() {
+        let valid = json!({"metric": "m", "timestamp": 1, "value": 1, "tags": {"a": "b"}});
+        assert!(parse_datapoint(&valid).is_ok());
+        for (field, value) in vec![
+            ("metric", json!("")),
+            ("timestamp", json!(-1)),
+            ("timestamp", json!("1")),
+            ("value", json!("abc")),
+            ("value", json!(null)),
+            ("tags", json!({})),
+            ("tags", json!({"a": ""})),
+        ] {
+            let mut datapoint = valid.clone();
+            datapoint[field] = value;
+            assert!(parse_datapoint(&datapoint).is_err(), "{}", datapoint);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{MonolithErr, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Jobs beyond `size * MAX_PENDING_JOBS_PER_WORKER` are rejected
+const MAX_PENDING_JOBS_PER_WORKER: usize = 16;
+
+///
+/// Bounded pool of threads that run the blocking work of requests, like reading and writing db.
+///
+/// Connections are served asynchronously and never wait for db, while the db is accessed by at most
+/// `size` threads at the same time. Jobs wait in queue if all threads are busy, and new jobs are
+/// rejected once too many are pending, so that the server pushes back instead of queueing requests
+/// until clients time out.
+pub struct WorkerPool {
+    pool: rayon::ThreadPool,
+    pending: Arc<AtomicUsize>,
+    max_pending: usize,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> Result<WorkerPool> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(size)
+            .thread_name(|idx| format!("monolith-worker-{}", idx))
+            // the response of a panicked job is dropped, and its client gets 500
+            .panic_handler(|_| error!("Worker panicked when handling request"))
+            .build()
+            .map_err(|e| MonolithErr::InternalErr(format!("Cannot start workers, {}", e)))?;
+        Ok(WorkerPool {
+            pool,
+            pending: Arc::new(AtomicUsize::new(0)),
+            max_pending: size * MAX_PENDING_JOBS_PER_WORKER,
+        })
+    }
+
+    /// Run `job` in the pool, return false without running it if too many jobs are pending.
+    pub fn try_spawn<F>(&self, job: F) -> bool
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let reserved = self
+            .pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                if pending < self.max_pending {
+                    Some(pending + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok();
+        if !reserved {
+            return false;
+        }
+        let pending = PendingGuard(Arc::clone(&self.pending));
+        self.pool.spawn(move || {
+            let _pending = pending;
+            job();
+        });
+        true
+    }
+
+    /// Whether new jobs would be rejected
+    pub fn is_full(&self) -> bool {
+        self.pending() >= self.max_pending
+    }
+
+    /// Num of jobs that are running or waiting
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
+}
+
+/// Decrease the number of pending jobs when the job is done, even if it panics.
+struct PendingGuard(Arc<AtomicUsize>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::pool::{WorkerPool, MAX_PENDING_JOBS_PER_WORKER};
+    use crate::Result;
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Barrier};
+
+    #[test]
+    fn test_try_spawn() -> Result<()> {
+        let pool = WorkerPool::new(1)?;
+        // block the only worker until all jobs are spawned
+        let barrier = Arc::new(Barrier::new(2));
+        let (sender, receiver) = channel();
+        for idx in 0..MAX_PENDING_JOBS_PER_WORKER {
+            let barrier = Arc::clone(&barrier);
+            let sender = sender.clone();
+            assert!(pool.try_spawn(move || {
+                if idx == 0 {
+                    barrier.wait();
+                }
+                sender.send(idx).unwrap();
+            }));
+        }
+        assert!(pool.is_full());
+        assert!(!pool.try_spawn(|| {}));
+
+        barrier.wait();
+        drop(sender);
+        assert_eq!(receiver.iter().count(), MAX_PENDING_JOBS_PER_WORKER);
+        // a panicked job is not pending anymore either
+        while pool.pending() > 0 {
+            std::thread::yield_now();
+        }
+        assert!(pool.try_spawn(|| panic!("job panics")));
+        while pool.pending() > 0 {
+            std::thread::yield_now();
+        }
+        assert!(pool.try_spawn(|| {}));
+        Ok(())
+    }
+}
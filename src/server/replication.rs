@@ -0,0 +1,179 @@
+use crate::indexer::Indexer;
+use crate::proto::WriteRequest;
+use crate::server::api::{ApiResponse, Params};
+use crate::server::http::{Method, Response};
+use crate::storage::Storage;
+use crate::wal::{checkpoint_path, list_segments, segment_path};
+use crate::{MonolithDb, MonolithErr, Result, Timestamp};
+use protobuf::{Message, RepeatedField};
+use serde_json::json;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Prefix of the endpoints that read replicas pull from
+pub const REPLICATION_PREFIX: &str = "/replication/";
+
+/// Max bytes of a segment responded at once, replicas ask for the rest from the next offset
+const MAX_SEGMENT_READ_SIZE: u64 = 4 * 1024 * 1024;
+/// Max series of a chunk responded at once, replicas ask for the rest from the next offset
+const MAX_CHUNK_PAGE_SERIES: usize = 1000;
+
+const OCTET_STREAM_CONTENT_TYPE: &str = "application/octet-stream";
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+///
+/// Serve requests under `REPLICATION_PREFIX`, which are only accepted with GET.
+///
+/// `chunks` lists the range of the current chunk and the finalized chunks, and `chunk?start=&offset=`
+/// responds a page of series of a finalized chunk from `offset` as a snappy compressed remote write
+/// request, which has no series after the last page. `wal` lists the segments of write ahead log and
+/// whether it's enabled, `wal/segment?seq=&offset=` responds the bytes of a segment from `offset`, and
+/// `wal/checkpoint?seq=` responds the checkpoint of a segment. Missing chunks and files are responded
+/// with 404, and the segments and checkpoints with 503 if the log is disabled.
+pub fn handle<S, I>(db: &MonolithDb<S, I>, method: &Method, path: &str, params: &Params) -> Response
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    if *method != Method::GET {
+        return ApiResponse::error(405, "bad_data", format!("method {} not allowed", method))
+            .into_response();
+    }
+    let res = match path.trim_start_matches(REPLICATION_PREFIX) {
+        "chunks" => Ok(chunks(db)),
+        "chunk" => chunk(db, params),
+        "wal" => wal(db),
+        "wal/segment" => segment(db, params),
+        "wal/checkpoint" => parse_seq(params).and_then(|seq| {
+            let path = checkpoint_path(wal_dir(db)?.as_path(), seq);
+            read_file(path, 0, std::u64::MAX)
+        }),
+        _ => Err(MonolithErr::NotFoundErr),
+    };
+    res.unwrap_or_else(|err| match err {
+        MonolithErr::NotFoundErr => {
+            ApiResponse::error(404, "not_found", format!("{} not found", path)).into_response()
+        }
+        err => {
+            error!("Error when process request to {}, {}", path, err);
+            ApiResponse::from(err).into_response()
+        }
+    })
+}
+
+fn chunks<S, I>(db: &MonolithDb<S, I>) -> Response
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let (start, end) = db.current_chunk_range();
+    let finalized = db
+        .finalized_chunks()
+        .into_iter()
+        .map(|(start, end)| json!({"start": start, "end": end}))
+        .collect::<Vec<_>>();
+    ApiResponse::success(json!({
+        "current": {"start": start, "end": end},
+        "finalized": finalized,
+    }))
+    .into_response()
+}
+
+fn chunk<S, I>(db: &MonolithDb<S, I>, params: &Params) -> Result<Response>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let start = parse_param(params, "start")? as Timestamp;
+    let offset = match params.get("offset") {
+        Some(_) => parse_param(params, "offset")? as usize,
+        None => 0,
+    };
+    let timeseries = db
+        .export_chunk(start, offset, MAX_CHUNK_PAGE_SERIES)?
+        .iter()
+        .map(crate::proto::TimeSeries::from)
+        .collect::<Vec<_>>();
+    let mut request = WriteRequest::new();
+    request.set_timeseries(RepeatedField::from_vec(timeseries));
+    let content = request
+        .write_to_bytes()
+        .map_err(|e| MonolithErr::InternalErr(e.to_string()))?;
+    let body = snap::raw::Encoder::new()
+        .compress_vec(content.as_slice())
+        .map_err(|e| MonolithErr::InternalErr(e.to_string()))?;
+    Ok(Response::from_data(body)
+        .with_header("Content-Type", PROTOBUF_CONTENT_TYPE)
+        .with_header("Content-Encoding", "snappy"))
+}
+
+fn wal<S, I>(db: &MonolithDb<S, I>) -> Result<Response>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let segments = match db.wal_dir() {
+        Some(dir) => list_segments(dir.as_path())?,
+        None => Vec::new(),
+    };
+    Ok(ApiResponse::success(json!({
+        "enabled": db.wal_dir().is_some(),
+        "segments": segments,
+    }))
+    .into_response())
+}
+
+fn segment<S, I>(db: &MonolithDb<S, I>, params: &Params) -> Result<Response>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    let seq = parse_seq(params)?;
+    let offset = match params.get("offset") {
+        Some(_) => parse_param(params, "offset")?,
+        None => 0,
+    };
+    read_file(
+        segment_path(wal_dir(db)?.as_path(), seq),
+        offset,
+        MAX_SEGMENT_READ_SIZE,
+    )
+}
+
+fn wal_dir<S, I>(db: &MonolithDb<S, I>) -> Result<PathBuf>
+where
+    S: Sync + Storage + Send + 'static,
+    I: Sync + Indexer + Send + 'static,
+{
+    db.wal_dir()
+        .ok_or_else(|| MonolithErr::IoError("write ahead log disabled".to_string()))
+}
+
+fn parse_seq(params: &Params) -> Result<u64> {
+    parse_param(params, "seq")
+}
+
+fn parse_param(params: &Params, name: &str) -> Result<u64> {
+    let value = params
+        .get(name)
+        .ok_or_else(|| MonolithErr::InvalidMatcherErr(format!("no {} parameter provided", name)))?;
+    value
+        .parse::<u64>()
+        .map_err(|_| MonolithErr::InvalidMatcherErr(format!("invalid {} {}", name, value)))
+}
+
+/// Read at most `limit` bytes of file from `offset`, which is empty if the file is shorter
+fn read_file(path: PathBuf, offset: u64, limit: u64) -> Result<Response> {
+    let mut file = match File::open(path.as_path()) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Err(MonolithErr::NotFoundErr),
+        Err(err) => return Err(err.into()),
+    };
+    let mut data = Vec::new();
+    if offset < file.metadata()?.len() {
+        file.seek(SeekFrom::Start(offset))?;
+        file.take(limit).read_to_end(&mut data)?;
+    }
+    Ok(Response::from_data(data).with_header("Content-Type", OCTET_STREAM_CONTENT_TYPE))
+}
@@ -0,0 +1,70 @@
+use crate::{MonolithErr, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Set by the handler of SIGTERM and SIGINT
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    // a second signal means the user doesn't want to wait for the graceful shutdown
+    if SIGNALED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(1) };
+    }
+}
+
+///
+/// Tell the server to stop accepting requests.
+///
+/// Clones share the same state, so one clone can be kept to trigger the shutdown of a server
+/// running in another thread.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    triggered: Arc<AtomicBool>,
+    signals: bool,
+}
+
+impl Shutdown {
+    /// Shutdown that is only triggered by `trigger`
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// Shutdown that is also triggered by SIGTERM or SIGINT of the process
+    pub fn on_signals() -> Result<Shutdown> {
+        for signal in [libc::SIGTERM, libc::SIGINT].iter() {
+            let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            if unsafe { libc::signal(*signal, handler) } == libc::SIG_ERR {
+                return Err(MonolithErr::IoError(format!(
+                    "Cannot register handler of signal {}",
+                    signal
+                )));
+            }
+        }
+        Ok(Shutdown {
+            triggered: Arc::new(AtomicBool::new(false)),
+            signals: true,
+        })
+    }
+
+    pub fn trigger(&self) {
+        self.triggered.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.triggered.load(Ordering::SeqCst) || (self.signals && SIGNALED.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::Shutdown;
+
+    #[test]
+    fn test_trigger() {
+        let shutdown = Shutdown::new();
+        let cloned = shutdown.clone();
+        assert!(!cloned.is_triggered());
+        shutdown.trigger();
+        assert!(cloned.is_triggered());
+    }
+}
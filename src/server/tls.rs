@@ -0,0 +1,49 @@
+use crate::{MonolithErr, Result};
+use openssl::ssl::{select_next_proto, AlpnError, Ssl, SslAcceptor, SslFiletype, SslMethod};
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_openssl::SslStream;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Protocols offered by ALPN in wire format, HTTP/2 is preferred
+const ALPN_PROTOCOLS: &[u8] = b"\x02h2\x08http/1.1";
+
+/// Load certificate chain and private key, clients that support HTTP/2 will use it through ALPN.
+pub fn build_acceptor(cert_file: &Path, key_file: &Path) -> Result<SslAcceptor> {
+    let build = || {
+        let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+        builder.set_certificate_chain_file(cert_file)?;
+        builder.set_private_key_file(key_file, SslFiletype::PEM)?;
+        builder.check_private_key()?;
+        builder.set_alpn_select_callback(|_, client| {
+            select_next_proto(ALPN_PROTOCOLS, client).ok_or(AlpnError::NOACK)
+        });
+        Ok(builder.build())
+    };
+    build().map_err(|e: openssl::error::ErrorStack| {
+        MonolithErr::IoError(format!(
+            "Cannot load certificate {} and key {}, {}",
+            cert_file.display(),
+            key_file.display(),
+            e
+        ))
+    })
+}
+
+/// TLS handshake of a client, which fails if it doesn't finish in `HANDSHAKE_TIMEOUT`
+pub async fn accept(acceptor: &SslAcceptor, stream: TcpStream) -> io::Result<SslStream<TcpStream>> {
+    let to_io_err = |e: openssl::error::ErrorStack| io::Error::new(io::ErrorKind::Other, e);
+    let ssl = Ssl::new(acceptor.context()).map_err(to_io_err)?;
+    let mut stream = SslStream::new(ssl, stream).map_err(to_io_err)?;
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, Pin::new(&mut stream).accept()).await {
+        Ok(Ok(())) => Ok(stream),
+        Ok(Err(err)) => Err(io::Error::new(io::ErrorKind::Other, err.to_string())),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "tls handshake timed out",
+        )),
+    }
+}
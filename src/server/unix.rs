@@ -0,0 +1,15 @@
+use crate::Result;
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use tokio::net::UnixListener;
+
+/// Listen on unix socket `path`, stale socket file left by previous process is removed before binding.
+pub fn bind(path: &Path) -> Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(UnixListener::bind(path)?)
+}
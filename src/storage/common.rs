@@ -18,6 +18,21 @@ pub trait Storage: Sized + HasTypeName {
         value: Value,
     ) -> Result<()>;
 
+    /// Write time points into series at once, the value of an existing timestamp is overwritten
+    ///
+    /// Used to flush the in-memory current chunk, storage that rewrites the whole series on each write
+    /// should merge the points in one write.
+    fn write_time_series(
+        &self,
+        time_series_id: TimeSeriesId,
+        points: Vec<TimePoint>,
+    ) -> Result<()> {
+        for tp in points {
+            self.write_time_point(time_series_id, tp.timestamp, tp.value)?;
+        }
+        Ok(())
+    }
+
     /// Read time series from storage
     ///
     /// If no such time series found, then a NotFoundErr will be return
@@ -28,6 +43,35 @@ pub trait Storage: Sized + HasTypeName {
         end_time: Timestamp,
     ) -> Result<Vec<TimePoint>>;
 
+    /// Delete time points of series within [`start_time`, `end_time`]
+    ///
+    /// The series is removed from storage if no time point is left. Deleting a series that is not
+    /// found is not an error.
+    fn delete_time_points(
+        &self,
+        time_series_id: TimeSeriesId,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<()>;
+
+    /// Flush buffered writes to disk, storage that writes through does nothing
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Re-encode the series in compact format once its chunk is closed, reads decode it transparently.
+    ///
+    /// Storage that doesn't compress does nothing.
+    fn compact_time_series(&self, _time_series_id: TimeSeriesId) -> Result<()> {
+        Ok(())
+    }
+
+    /// Remove all data of the storage when its chunk expires, storage in chunk dir does nothing
+    /// because the dir is removed
+    fn destroy(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// Select time points that within [`start_time`, `end_time`]
     fn trim_time_series(
         series: Vec<TimePoint>,
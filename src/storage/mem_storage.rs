@@ -0,0 +1,317 @@
+use crate::common::time_point::TimePoint;
+use crate::common::time_series::TimeSeriesId;
+use crate::compaction::{CompactType, Compactor, Decompactor};
+use crate::storage::Storage;
+use crate::MonolithErr::{NotFoundErr, OutOfRangeErr};
+use crate::{HasTypeName, Result, Timestamp, Value};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Num of samples appended to a block before a new one is started
+const BLOCK_SIZE: usize = 120;
+
+///
+/// In-memory storage of the current chunk, which is flushed into the storage of db when the chunk is
+/// swapped out.
+///
+/// Each series is a list of Gorilla compacted blocks sorted by timestamp, in-order points are appended
+/// to the last block, and a new block is started once it holds `BLOCK_SIZE` points. Out-of-order points,
+/// overwrites and deletes decompact the block they fall in and compact it again.
+/// Nothing is persisted, samples are recovered from write ahead log after a crash, or from the chunk in
+/// backend they are written through into if write ahead log is disabled.
+#[derive(Default)]
+pub struct MemStorage {
+    series: RwLock<HashMap<TimeSeriesId, Vec<Block>>>,
+}
+
+/// Compacted time points with unique timestamps within [`start`, `end`]
+struct Block {
+    compactor: Compactor,
+    start: Timestamp,
+    end: Timestamp,
+    len: usize,
+}
+
+impl Block {
+    fn new(points: &[TimePoint]) -> Block {
+        let mut compactor = Compactor::new(CompactType::Gorilla);
+        for tp in points {
+            compactor.compact(tp);
+        }
+        Block {
+            compactor,
+            start: points.first().map_or(0, |tp| tp.timestamp),
+            end: points.last().map_or(0, |tp| tp.timestamp),
+            len: points.len(),
+        }
+    }
+
+    fn append(&mut self, tp: &TimePoint) {
+        if self.len == 0 {
+            self.start = tp.timestamp;
+        }
+        self.compactor.compact(tp);
+        self.end = tp.timestamp;
+        self.len += 1;
+    }
+
+    fn points(&self) -> Result<Vec<TimePoint>> {
+        Decompactor::from(CompactType::Gorilla, self.compactor.get_bytes_vec())?.decompact()
+    }
+}
+
+impl MemStorage {
+    pub fn new() -> MemStorage {
+        MemStorage::default()
+    }
+
+    /// Insert a time point into sorted `blocks`, the value of an existing timestamp is overwritten
+    fn insert_time_point(blocks: &mut Vec<Block>, tp: TimePoint) -> Result<()> {
+        // the block that `tp` falls in, or the one before the gap it falls in
+        let idx = match blocks.iter().rposition(|b| b.start <= tp.timestamp) {
+            Some(idx) => idx,
+            None if blocks.is_empty() => {
+                blocks.push(Block::new(&[tp]));
+                return Ok(());
+            }
+            None => 0,
+        };
+        let is_last = idx + 1 == blocks.len();
+        let block = &mut blocks[idx];
+        if tp.timestamp > block.end {
+            if is_last && block.len >= BLOCK_SIZE {
+                blocks.push(Block::new(&[tp]));
+                return Ok(());
+            }
+            if is_last {
+                block.append(&tp);
+                return Ok(());
+            }
+        }
+        let mut points = block.points()?;
+        match points.binary_search_by_key(&tp.timestamp, |p| p.timestamp) {
+            Ok(idx) => points[idx] = tp,
+            Err(idx) => points.insert(idx, tp),
+        }
+        *block = Block::new(&points);
+        Ok(())
+    }
+}
+
+impl HasTypeName for MemStorage {
+    fn get_type_name() -> &'static str {
+        "MemStorage"
+    }
+}
+
+impl Storage for MemStorage {
+    fn write_time_point(
+        &self,
+        time_series_id: TimeSeriesId,
+        timestamp: Timestamp,
+        value: Value,
+    ) -> Result<()> {
+        let mut series = self.series.write().unwrap();
+        MemStorage::insert_time_point(
+            series.entry(time_series_id).or_default(),
+            TimePoint::new(timestamp, value),
+        )
+    }
+
+    fn write_time_series(
+        &self,
+        time_series_id: TimeSeriesId,
+        points: Vec<TimePoint>,
+    ) -> Result<()> {
+        let mut series = self.series.write().unwrap();
+        let blocks = series.entry(time_series_id).or_default();
+        for tp in points {
+            MemStorage::insert_time_point(blocks, tp)?;
+        }
+        Ok(())
+    }
+
+    fn read_time_series(
+        &self,
+        time_series_id: TimeSeriesId,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<Vec<TimePoint>> {
+        let series = self.series.read().unwrap();
+        let blocks = match series.get(&time_series_id) {
+            Some(blocks) if !blocks.is_empty() => blocks,
+            _ => return Err(NotFoundErr),
+        };
+        let (first, last) = (blocks[0].start, blocks[blocks.len() - 1].end);
+        if first > end_time || last < start_time {
+            return Err(OutOfRangeErr(first, last));
+        }
+        let mut res = vec![];
+        for block in blocks
+            .iter()
+            .filter(|b| b.start <= end_time && b.end >= start_time)
+        {
+            res.extend(
+                block
+                    .points()?
+                    .into_iter()
+                    .filter(|tp| tp.timestamp >= start_time && tp.timestamp <= end_time),
+            );
+        }
+        Ok(res)
+    }
+
+    fn delete_time_points(
+        &self,
+        time_series_id: TimeSeriesId,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<()> {
+        let mut series = self.series.write().unwrap();
+        let blocks = match series.get_mut(&time_series_id) {
+            Some(blocks) => blocks,
+            None => return Ok(()),
+        };
+        // blocks are replaced only after all of them are decompacted
+        let mut filtered = vec![];
+        for (idx, block) in blocks.iter().enumerate() {
+            if block.start > end_time || block.end < start_time {
+                continue;
+            }
+            let points = block
+                .points()?
+                .into_iter()
+                .filter(|tp| tp.timestamp < start_time || tp.timestamp > end_time)
+                .collect::<Vec<_>>();
+            filtered.push((idx, points));
+        }
+        for (idx, points) in filtered.into_iter().rev() {
+            if points.is_empty() {
+                blocks.remove(idx);
+            } else {
+                blocks[idx] = Block::new(&points);
+            }
+        }
+        if blocks.is_empty() {
+            series.remove(&time_series_id);
+        }
+        Ok(())
+    }
+
+    /// Drop all samples, the chunk is not readable after it
+    fn destroy(&self) -> Result<()> {
+        self.series.write().unwrap().clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::time_point::TimePoint;
+    use crate::storage::mem_storage::BLOCK_SIZE;
+    use crate::storage::{MemStorage, Storage};
+    use crate::{MonolithErr, Result};
+
+    #[test]
+    fn test_write_time_point() -> Result<()> {
+        let storage = MemStorage::new();
+        for (ts, v) in vec![(10, 1.0), (20, 2.0), (15, 1.5), (20, 3.0), (5, 0.5)] {
+            storage.write_time_point(1, ts, v)?;
+        }
+        let expected = vec![(5, 0.5), (10, 1.0), (15, 1.5), (20, 3.0)]
+            .into_iter()
+            .map(|(ts, v)| TimePoint::new(ts, v))
+            .collect::<Vec<_>>();
+        assert_eq!(storage.read_time_series(1, 0, 100)?, expected);
+        assert_eq!(
+            storage.read_time_series(1, 10, 15)?,
+            expected[1..3].to_vec()
+        );
+        assert_eq!(storage.read_time_series(1, 20, 20)?[0].value, 3.0);
+        match storage.read_time_series(1, 30, 40) {
+            Err(MonolithErr::OutOfRangeErr(5, 20)) => {}
+            _ => panic!("expect out of range"),
+        }
+        match storage.read_time_series(2, 0, 100) {
+            Err(MonolithErr::NotFoundErr) => {}
+            _ => panic!("expect series not found"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_time_series() -> Result<()> {
+        let storage = MemStorage::new();
+        storage.write_time_point(1, 20, 2.0)?;
+        storage.write_time_series(
+            1,
+            vec![
+                TimePoint::new(10, 1.0),
+                TimePoint::new(20, 4.0),
+                TimePoint::new(30, 3.0),
+            ],
+        )?;
+        assert_eq!(
+            storage.read_time_series(1, 0, 100)?,
+            vec![
+                TimePoint::new(10, 1.0),
+                TimePoint::new(20, 4.0),
+                TimePoint::new(30, 3.0)
+            ]
+        );
+        assert_eq!(storage.read_time_series(1, 20, 20)?[0].value, 4.0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_time_points() -> Result<()> {
+        let storage = MemStorage::new();
+        for ts in 1..=5 {
+            storage.write_time_point(1, ts, ts as f64)?;
+        }
+        storage.delete_time_points(1, 2, 4)?;
+        assert_eq!(
+            storage.read_time_series(1, 0, 10)?,
+            vec![TimePoint::new(1, 1.0), TimePoint::new(5, 5.0)]
+        );
+        storage.delete_time_points(1, 0, 10)?;
+        assert!(storage.read_time_series(1, 0, 10).is_err());
+        // deleting a missing series is not an error
+        storage.delete_time_points(2, 0, 10)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_blocks() -> Result<()> {
+        let storage = MemStorage::new();
+        let points = (0..(BLOCK_SIZE as u64 * 3))
+            .map(|ts| TimePoint::new(ts * 10, ts as f64 * 0.5))
+            .collect::<Vec<_>>();
+        for tp in points.iter() {
+            storage.write_time_point(1, tp.timestamp, tp.value)?;
+        }
+        assert_eq!(storage.series.read().unwrap()[&1].len(), 3);
+        assert_eq!(storage.read_time_series(1, 0, u64::max_value())?, points);
+
+        // out-of-order points and overwrites go to the block they fall in
+        storage.write_time_point(1, 5, 100.0)?;
+        storage.write_time_point(1, 10 * BLOCK_SIZE as u64, 200.0)?;
+        let mut expected = points.clone();
+        expected.insert(1, TimePoint::new(5, 100.0));
+        expected[BLOCK_SIZE + 1] = TimePoint::new(10 * BLOCK_SIZE as u64, 200.0);
+        assert_eq!(storage.read_time_series(1, 0, u64::max_value())?, expected);
+        assert_eq!(storage.series.read().unwrap()[&1].len(), 3);
+
+        // deleting a whole block drops it
+        let (start, end) = (10 * BLOCK_SIZE as u64, 20 * BLOCK_SIZE as u64 - 10);
+        storage.delete_time_points(1, start, end)?;
+        expected.retain(|tp| tp.timestamp < start || tp.timestamp > end);
+        assert_eq!(storage.series.read().unwrap()[&1].len(), 2);
+        assert_eq!(storage.read_time_series(1, 0, u64::max_value())?, expected);
+        assert_eq!(
+            storage.read_time_series(1, start - 10, end + 10)?,
+            expected[BLOCK_SIZE..BLOCK_SIZE + 2].to_vec()
+        );
+        Ok(())
+    }
+}
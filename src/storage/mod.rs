@@ -1,7 +1,9 @@
 mod common;
+mod mem_storage;
 mod sled_storage;
 mod tikv_storage;
 
 pub use common::*;
+pub use mem_storage::MemStorage;
 pub use sled_storage::{SledStorage, SledStorageBuilder};
 pub use tikv_storage::{TiKvStorage, TiKvStorageBuilder};
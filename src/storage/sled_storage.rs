@@ -1,5 +1,6 @@
 use crate::common::time_point::TimePoint;
 
+use crate::compaction::{CompactType, Compactor, Decompactor};
 use crate::storage::{Decoder, Encoder, Storage};
 use crate::MonolithErr::{NotFoundErr, OutOfRangeErr};
 use crate::{Builder, HasTypeName, MonolithErr, Result, Timestamp, Value};
@@ -7,10 +8,13 @@ use sled::{Db, Tree};
 
 use crate::chunk::ChunkOpts;
 use crate::option::DbOpts;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::path::{Path, PathBuf};
 
 const TIME_SERIES_PREFIX: &str = "TS";
+/// Prefix of series compacted by `compact_time_series`, whose raw series is removed then
+const COMPACTED_SERIES_PREFIX: &str = "CS";
 const TIME_POINT_PREFIX: &str = "TP";
 
 ///
@@ -37,27 +41,34 @@ impl SledStorage {
     }
 
     fn get_series_by_id(&self, time_series_id: u64) -> Result<Option<Vec<TimePoint>>> {
+        match self.get_series(time_series_id)? {
+            Some((series, _)) => Ok(Some(series)),
+            None => Err(NotFoundErr),
+        }
+    }
+
+    /// Raw series is read first, it's only left with the compacted one if compaction is interrupted.
+    ///
+    /// Return the series and whether it's only kept in compacted format, callers writing the series
+    /// back in that format hold the write lock of chunk, so that it's not compacted in the meantime.
+    fn get_series(&self, time_series_id: u64) -> Result<Option<(Vec<TimePoint>, bool)>> {
         let tree: &Tree = &self.storage;
         let key_name = SledStorage::parse_key_name::<u64>(TIME_SERIES_PREFIX, time_series_id);
+        if let Some(val) = tree.get(key_name)? {
+            return Ok(Some((KvStorageProcessor::decode_time_series(&val)?, false)));
+        }
+        let key_name = SledStorage::parse_key_name::<u64>(COMPACTED_SERIES_PREFIX, time_series_id);
         match tree.get(key_name)? {
-            None => Err(NotFoundErr),
-            Some(val) => {
-                let val_vec = AsRef::<[u8]>::as_ref(&val).to_vec();
-                let mut res: Vec<TimePoint> = Vec::new();
-                for timepoint_bytes in
-                    val_vec.chunks(std::mem::size_of::<Timestamp>() + std::mem::size_of::<Value>())
-                {
-                    res.push(KvStorageProcessor::decode_time_point(timepoint_bytes)?);
-                }
-
-                return Ok(Some(res));
-            }
+            Some(val) => Ok(Some((
+                KvStorageProcessor::decompact_time_series(val.to_vec())?,
+                true,
+            ))),
+            None => Ok(None),
         }
     }
 }
 
 impl Storage for SledStorage {
-    //todo: check if timestamp is earlier than last timestamp in series. If so, how to deal with?
     fn write_time_point(
         &self,
         time_series_id: u64,
@@ -66,13 +77,51 @@ impl Storage for SledStorage {
     ) -> Result<()> {
         let tree: &Tree = &self.storage;
         let key_name = SledStorage::parse_key_name::<u64>(TIME_SERIES_PREFIX, time_series_id);
-        let mut value = KvStorageProcessor::encode_time_point(timestamp, value)?;
         if let Some(current_val) = tree.get(key_name.clone())? {
-            let mut current_val_bytes = current_val.to_vec();
-            current_val_bytes.append(&mut value);
-            tree.set(key_name.clone(), current_val_bytes);
+            let mut series = current_val.to_vec();
+            KvStorageProcessor::insert_time_point(&mut series, timestamp, value)?;
+            tree.set(key_name, series)?;
+            return Ok(());
+        }
+        let compacted_key =
+            SledStorage::parse_key_name::<u64>(COMPACTED_SERIES_PREFIX, time_series_id);
+        if let Some(compacted) = tree.get(compacted_key.clone())? {
+            // late point of a closed chunk, compact the series again with it
+            let series = KvStorageProcessor::merge_time_series(
+                KvStorageProcessor::decompact_time_series(compacted.to_vec())?,
+                vec![TimePoint::new(timestamp, value)],
+            );
+            tree.set(
+                compacted_key,
+                KvStorageProcessor::compact_time_series(series),
+            )?;
+            return Ok(());
+        }
+        tree.set(
+            key_name,
+            KvStorageProcessor::encode_time_point(timestamp, value)?,
+        )?;
+        Ok(())
+    }
+
+    fn write_time_series(&self, time_series_id: u64, points: Vec<TimePoint>) -> Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        let (existing, compacted) = self.get_series(time_series_id)?.unwrap_or_default();
+        let series = KvStorageProcessor::merge_time_series(existing, points);
+        let tree: &Tree = &self.storage;
+        // keep the series in its format
+        if compacted {
+            let key_name =
+                SledStorage::parse_key_name::<u64>(COMPACTED_SERIES_PREFIX, time_series_id);
+            tree.set(key_name, KvStorageProcessor::compact_time_series(series))?;
         } else {
-            tree.set(key_name.clone(), value)?;
+            let key_name = SledStorage::parse_key_name::<u64>(TIME_SERIES_PREFIX, time_series_id);
+            tree.set(
+                key_name,
+                KvStorageProcessor::encode_time_series(series.as_slice())?,
+            )?;
         }
         Ok(())
     }
@@ -94,6 +143,60 @@ impl Storage for SledStorage {
         }
         SledStorage::trim_time_series(series, start_time, end_time)
     }
+
+    fn delete_time_points(
+        &self,
+        time_series_id: u64,
+        start_time: u64,
+        end_time: u64,
+    ) -> Result<()> {
+        let (series, compacted) = match self.get_series(time_series_id)? {
+            Some(series) => series,
+            None => return Ok(()),
+        };
+        let tree: &Tree = &self.storage;
+        let kept = series
+            .into_iter()
+            .filter(|tp| tp.timestamp < start_time || tp.timestamp > end_time)
+            .collect::<Vec<TimePoint>>();
+        // keep the series in its format
+        let (key_name, value) = if compacted {
+            (
+                SledStorage::parse_key_name::<u64>(COMPACTED_SERIES_PREFIX, time_series_id),
+                KvStorageProcessor::compact_time_series(kept.clone()),
+            )
+        } else {
+            (
+                SledStorage::parse_key_name::<u64>(TIME_SERIES_PREFIX, time_series_id),
+                KvStorageProcessor::encode_time_series(kept.as_slice())?,
+            )
+        };
+        if kept.is_empty() {
+            tree.del(key_name)?;
+        } else {
+            tree.set(key_name, value)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.storage.flush()?;
+        Ok(())
+    }
+
+    fn compact_time_series(&self, time_series_id: u64) -> Result<()> {
+        let tree: &Tree = &self.storage;
+        let raw_key = SledStorage::parse_key_name::<u64>(TIME_SERIES_PREFIX, time_series_id);
+        if let Some(val) = tree.get(raw_key.clone())? {
+            let series = KvStorageProcessor::decode_time_series(&val)?;
+            let key_name =
+                SledStorage::parse_key_name::<u64>(COMPACTED_SERIES_PREFIX, time_series_id);
+            // the raw series is removed only after the compacted one is written
+            tree.set(key_name, KvStorageProcessor::compact_time_series(series))?;
+            tree.del(raw_key)?;
+        }
+        Ok(())
+    }
 }
 
 impl HasTypeName for SledStorage {
@@ -105,6 +208,90 @@ impl HasTypeName for SledStorage {
 //TODO: create a independent package for processor, create a KvProcessor for all key-value database
 pub(crate) struct KvStorageProcessor {}
 
+impl KvStorageProcessor {
+    ///
+    /// Insert a time point into encoded `series`, keeping it sorted by timestamp.
+    ///
+    /// In-order points are appended, late ones are inserted at their position, and the value of an
+    /// existing timestamp is overwritten, so that retried writes are idempotent.
+    pub fn insert_time_point(
+        series: &mut Vec<u8>,
+        timestamp: Timestamp,
+        value: Value,
+    ) -> Result<()> {
+        let size = std::mem::size_of::<Timestamp>() + std::mem::size_of::<Value>();
+        let encoded = KvStorageProcessor::encode_time_point(timestamp, value)?;
+        let len = series.len() / size;
+        let timestamp_at = |idx: usize| -> Result<Timestamp> {
+            Ok(
+                KvStorageProcessor::decode_time_point(&series[idx * size..(idx + 1) * size])?
+                    .timestamp,
+            )
+        };
+        if len == 0 || timestamp_at(len - 1)? < timestamp {
+            series.extend_from_slice(encoded.as_slice());
+            return Ok(());
+        }
+        // find the first point not earlier than timestamp
+        let (mut left, mut right) = (0, len);
+        while left < right {
+            let mid = (left + right) / 2;
+            if timestamp_at(mid)? < timestamp {
+                left = mid + 1;
+            } else {
+                right = mid;
+            }
+        }
+        let offset = left * size;
+        if left < len && timestamp_at(left)? == timestamp {
+            series.splice(offset..offset + size, encoded);
+        } else {
+            series.splice(offset..offset, encoded);
+        }
+        Ok(())
+    }
+
+    /// Merge `points` into sorted `series`, the value of an existing timestamp is overwritten by `points`
+    pub fn merge_time_series(series: Vec<TimePoint>, points: Vec<TimePoint>) -> Vec<TimePoint> {
+        let mut merged = BTreeMap::new();
+        for tp in series.into_iter().chain(points) {
+            merged.insert(tp.timestamp, tp.value);
+        }
+        merged
+            .into_iter()
+            .map(|(timestamp, value)| TimePoint::new(timestamp, value))
+            .collect()
+    }
+
+    /// Decode series of 16 bytes time points
+    pub fn decode_time_series(raw: &[u8]) -> Result<Vec<TimePoint>> {
+        raw.chunks(std::mem::size_of::<Timestamp>() + std::mem::size_of::<Value>())
+            .map(KvStorageProcessor::decode_time_point)
+            .collect()
+    }
+
+    pub fn encode_time_series(series: &[TimePoint]) -> Result<Vec<u8>> {
+        let mut res = Vec::new();
+        for tp in series {
+            res.append(&mut KvStorageProcessor::encode_time_point(
+                tp.timestamp,
+                tp.value,
+            )?);
+        }
+        Ok(res)
+    }
+
+    /// Compact series with Gorilla encoding, tagged with `CompactType` in the last byte
+    pub fn compact_time_series(series: Vec<TimePoint>) -> Vec<u8> {
+        Compactor::new(CompactType::Gorilla).compact_vec(series)
+    }
+
+    /// Decode series compacted by `compact_time_series` according to its `CompactType` tag
+    pub fn decompact_time_series(data: Vec<u8>) -> Result<Vec<TimePoint>> {
+        Decompactor::from(CompactType::Gorilla, data)?.decompact()
+    }
+}
+
 impl Encoder for KvStorageProcessor {
     fn encode_time_point(timestamp: Timestamp, value: Value) -> Result<Vec<u8>> {
         let timestamp_bytes = timestamp.to_be_bytes();
@@ -149,9 +336,9 @@ impl Builder<SledStorage> for SledStorageBuilder {
 
     fn read_from_chunk(&self, dir: &Path, _: Option<&ChunkOpts>) -> Result<Option<SledStorage>> {
         // Sled will create an empty db if there is nothing in dir.
-        let config = sled::ConfigBuilder::default().path(dir).read_only(true);
+        // Not read only, tombstoned data in closed chunks needs to be purged.
         Ok(Some(SledStorage {
-            storage: sled::Db::start(config.build())?,
+            storage: sled::Db::start_default(dir)?,
         }))
     }
 
@@ -162,6 +349,10 @@ impl Builder<SledStorage> for SledStorageBuilder {
     fn read_config(&self, _dir: &Path) -> Result<()> {
         Ok(())
     }
+
+    fn tenant_builder(&self, _tenant: &str) -> Box<dyn Builder<SledStorage> + Sync + Send> {
+        Box::new(SledStorageBuilder::new())
+    }
 }
 
 impl SledStorageBuilder {
@@ -4,7 +4,7 @@ use crate::common::option::DbOpts;
 use crate::common::time_point::TimePoint;
 use crate::common::time_series::TimeSeriesId;
 use crate::storage::sled_storage::KvStorageProcessor;
-use crate::storage::{Decoder, Encoder, Storage};
+use crate::storage::{Encoder, Storage};
 use crate::{Builder, HasTypeName, MonolithErr, Result, Timestamp, Value};
 use std::path::Path;
 use std::path::PathBuf;
@@ -17,7 +17,39 @@ pub struct TiKvStorage {
     storage_identifier: Vec<u8>,
 }
 
-impl TiKvStorage {}
+/// Marks the key of series compacted by `compact_time_series`, between storage identifier and series id
+const COMPACTED_SERIES_MARK: u8 = b'C';
+
+impl TiKvStorage {
+    fn series_key(&self, time_series_id: TimeSeriesId) -> Vec<u8> {
+        let mut key = self.storage_identifier.clone();
+        key.extend_from_slice(&time_series_id.to_be_bytes()[..]);
+        key
+    }
+
+    fn compacted_series_key(&self, time_series_id: TimeSeriesId) -> Vec<u8> {
+        let mut key = self.storage_identifier.clone();
+        key.push(COMPACTED_SERIES_MARK);
+        key.extend_from_slice(&time_series_id.to_be_bytes()[..]);
+        key
+    }
+
+    /// Raw series is read first, it's only left with the compacted one if compaction is interrupted.
+    ///
+    /// Return the series and whether it's only kept in compacted format.
+    fn get_series(&self, time_series_id: TimeSeriesId) -> Result<Option<(Vec<TimePoint>, bool)>> {
+        if let Some(val) = self.client.get(self.series_key(time_series_id))? {
+            return Ok(Some((KvStorageProcessor::decode_time_series(&val)?, false)));
+        }
+        match self.client.get(self.compacted_series_key(time_series_id))? {
+            Some(val) => Ok(Some((
+                KvStorageProcessor::decompact_time_series(val)?,
+                true,
+            ))),
+            None => Ok(None),
+        }
+    }
+}
 
 impl Storage for TiKvStorage {
     fn write_time_point(
@@ -26,28 +58,51 @@ impl Storage for TiKvStorage {
         timestamp: Timestamp,
         value: Value,
     ) -> Result<()> {
-        let timepoint_size = std::mem::size_of::<Timestamp>() + std::mem::size_of::<Value>();
-        let mut current_value = KvStorageProcessor::encode_time_point(timestamp, value)?;
+        let key = self.series_key(time_series_id);
+        if let Some(mut series) = self.client.get(key.clone())? {
+            KvStorageProcessor::insert_time_point(&mut series, timestamp, value)?;
+            return self.client.set(key, series);
+        }
+        let compacted_key = self.compacted_series_key(time_series_id);
+        if let Some(compacted) = self.client.get(compacted_key.clone())? {
+            // late point of a closed chunk, compact the series again with it
+            let series = KvStorageProcessor::merge_time_series(
+                KvStorageProcessor::decompact_time_series(compacted)?,
+                vec![TimePoint::new(timestamp, value)],
+            );
+            return self.client.set(
+                compacted_key,
+                KvStorageProcessor::compact_time_series(series),
+            );
+        }
+        self.client.set(
+            key,
+            KvStorageProcessor::encode_time_point(timestamp, value)?,
+        )
+    }
 
-        let mut key = self.storage_identifier.clone();
-        let mut time_series_id_bytes: Vec<u8> = Vec::from(&time_series_id.to_be_bytes()[..]);
-        key.append(&mut time_series_id_bytes);
-        if let Some(mut val) = self.client.get(key.clone())? {
-            //if has previous timestamp
-            let last_time_point =
-                KvStorageProcessor::decode_time_point(&val[(val.len() - timepoint_size)..])?;
-            if last_time_point.timestamp >= timestamp {
-                return Err(MonolithErr::InternalErr(
-                    "Try to store a earlier time stamp".into(),
-                ));
-            } else {
-                val.append(&mut current_value);
-                self.client.set(key, val)?;
-            }
+    fn write_time_series(
+        &self,
+        time_series_id: TimeSeriesId,
+        points: Vec<TimePoint>,
+    ) -> Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        let (existing, compacted) = self.get_series(time_series_id)?.unwrap_or_default();
+        let series = KvStorageProcessor::merge_time_series(existing, points);
+        // keep the series in its format
+        if compacted {
+            self.client.set(
+                self.compacted_series_key(time_series_id),
+                KvStorageProcessor::compact_time_series(series),
+            )
         } else {
-            self.client.set(key, current_value)?;
+            self.client.set(
+                self.series_key(time_series_id),
+                KvStorageProcessor::encode_time_series(series.as_slice())?,
+            )
         }
-        Ok(())
     }
 
     fn read_time_series(
@@ -56,17 +111,7 @@ impl Storage for TiKvStorage {
         start_time: Timestamp,
         end_time: Timestamp,
     ) -> Result<Vec<TimePoint>> {
-        let timepoint_size = std::mem::size_of::<Timestamp>() + std::mem::size_of::<Value>();
-        let mut key = self.storage_identifier.clone();
-        let mut time_series_id_bytes: Vec<u8> = Vec::from(&time_series_id.to_be_bytes()[..]);
-        key.append(&mut time_series_id_bytes);
-
-        if let Some(val) = self.client.get(key.clone())? {
-            let mut series: Vec<TimePoint> = Vec::new();
-            for timepoint_bytes in val.chunks(timepoint_size) {
-                series.push(KvStorageProcessor::decode_time_point(timepoint_bytes)?);
-            }
-
+        if let Some((series, _)) = self.get_series(time_series_id)? {
             if series.first().unwrap().timestamp > end_time
                 || series.last().unwrap().timestamp < start_time
             {
@@ -81,6 +126,59 @@ impl Storage for TiKvStorage {
             Err(MonolithErr::NotFoundErr)
         }
     }
+
+    fn delete_time_points(
+        &self,
+        time_series_id: TimeSeriesId,
+        start_time: Timestamp,
+        end_time: Timestamp,
+    ) -> Result<()> {
+        if let Some((series, compacted)) = self.get_series(time_series_id)? {
+            let len = series.len();
+            let kept = series
+                .into_iter()
+                .filter(|tp| tp.timestamp < start_time || tp.timestamp > end_time)
+                .collect::<Vec<TimePoint>>();
+            // keep the series in its format
+            let (key, value) = if compacted {
+                (
+                    self.compacted_series_key(time_series_id),
+                    KvStorageProcessor::compact_time_series(kept.clone()),
+                )
+            } else {
+                (
+                    self.series_key(time_series_id),
+                    KvStorageProcessor::encode_time_series(kept.as_slice())?,
+                )
+            };
+            if kept.is_empty() {
+                self.client.delete(key)?;
+            } else if kept.len() != len {
+                self.client.set(key, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn compact_time_series(&self, time_series_id: TimeSeriesId) -> Result<()> {
+        let key = self.series_key(time_series_id);
+        if let Some(val) = self.client.get(key.clone())? {
+            let series = KvStorageProcessor::decode_time_series(&val)?;
+            // the raw series is removed only after the compacted one is written
+            self.client.set(
+                self.compacted_series_key(time_series_id),
+                KvStorageProcessor::compact_time_series(series),
+            )?;
+            self.client.delete(key)?;
+        }
+        Ok(())
+    }
+
+    /// Delete all series and the mapping from chunk to components
+    fn destroy(&self) -> Result<()> {
+        self.client.delete_prefix(self.storage_identifier.clone())?;
+        self.client.delete(self.chunk_identifier.clone())
+    }
 }
 
 impl HasTypeName for TiKvStorage {
@@ -149,12 +247,19 @@ impl Builder<TiKvStorage> for TiKvStorageBuilder {
     fn read_config(&self, _dir: &Path) -> Result<()> {
         Ok(())
     }
+
+    fn tenant_builder(&self, tenant: &str) -> Box<dyn Builder<TiKvStorage> + Sync + Send> {
+        Box::new(TiKvStorageBuilder {
+            backend_builder: self.backend_builder.for_tenant(tenant),
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::backend::tikv::TiKvRawBackend;
     use crate::common::test_utils::DummyTiKvBackend;
+    use crate::common::time_point::TimePoint;
     use crate::storage::sled_storage::KvStorageProcessor;
     use crate::storage::tikv_storage::TiKvStorage;
     use crate::storage::{Encoder, Storage};
@@ -190,9 +295,15 @@ mod tests {
         first_entry.append(&mut second_entry);
         assert_eq!(val.unwrap(), first_entry);
 
-        // insert invalid time point, who has smaller timestamp the the previous one.
-        let res = storage.write_time_point(1u64, 120u64, 120.0);
-        assert!(res.is_err());
+        // insert late time point, who has smaller timestamp the the previous one.
+        storage.write_time_point(1u64, 120u64, 120.0)?;
+        // overwrite the value of an existing timestamp
+        storage.write_time_point(1u64, 123u64, 12.3)?;
+        let mut expected = Vec::new();
+        for (ts, v) in vec![(120u64, 120.0), (123, 12.3), (124, 66.6)] {
+            expected.append(&mut KvStorageProcessor::encode_time_point(ts, v)?);
+        }
+        assert_eq!(dummy_backend.get(key)?.unwrap(), expected);
 
         Ok(())
     }
@@ -227,4 +338,130 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_delete_time_points() -> Result<()> {
+        let dummy_backend = DummyTiKvBackend::new();
+        let storage = TiKvStorage {
+            client: Box::new(dummy_backend.clone()),
+            chunk_identifier: "whatever".to_string().into_bytes(),
+            storage_identifier: "storage".to_string().into_bytes(),
+        };
+        for ts in vec![120u64, 123, 156, 190] {
+            storage.write_time_point(1, ts, ts as f64)?;
+        }
+
+        storage.delete_time_points(1, 121, 160)?;
+        let res = storage
+            .read_time_series(1, 0, 200)?
+            .iter()
+            .map(|tp| tp.timestamp)
+            .collect::<Vec<u64>>();
+        assert_eq!(res, vec![120, 190]);
+
+        // series is removed when nothing left
+        storage.delete_time_points(1, 0, 200)?;
+        assert!(storage.read_time_series(1, 0, 200).is_err());
+        assert!(dummy_backend.scan_prefix(b"storage".to_vec())?.is_empty());
+        // deleting a missing series is fine
+        storage.delete_time_points(2, 0, 200)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_time_series() -> Result<()> {
+        let dummy_backend = DummyTiKvBackend::new();
+        let storage = TiKvStorage {
+            client: Box::new(dummy_backend.clone()),
+            chunk_identifier: "whatever".to_string().into_bytes(),
+            storage_identifier: "storage".to_string().into_bytes(),
+        };
+        for ts in vec![120u64, 123, 156, 190] {
+            storage.write_time_point(1, ts, ts as f64)?;
+        }
+        storage.compact_time_series(1)?;
+        storage.compact_time_series(1)?;
+        assert_eq!(dummy_backend.get(storage.series_key(1))?, None);
+        assert!(dummy_backend
+            .get(storage.compacted_series_key(1))?
+            .is_some());
+        let read = |start, end| -> Result<Vec<(u64, f64)>> {
+            Ok(storage
+                .read_time_series(1, start, end)?
+                .iter()
+                .map(|tp| (tp.timestamp, tp.value))
+                .collect())
+        };
+        assert_eq!(read(121, 160)?, vec![(123, 123.0), (156, 156.0)]);
+
+        // late samples and deletion keep the series compacted
+        storage.write_time_point(1, 100, 1.0)?;
+        storage.delete_time_points(1, 150, 200)?;
+        assert_eq!(dummy_backend.get(storage.series_key(1))?, None);
+        assert_eq!(read(0, 200)?, vec![(100, 1.0), (120, 120.0), (123, 123.0)]);
+
+        storage.delete_time_points(1, 0, 200)?;
+        assert!(dummy_backend.scan_prefix(b"storage".to_vec())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_time_series() -> Result<()> {
+        let dummy_backend = DummyTiKvBackend::new();
+        let storage = TiKvStorage {
+            client: Box::new(dummy_backend.clone()),
+            chunk_identifier: "whatever".to_string().into_bytes(),
+            storage_identifier: "storage".to_string().into_bytes(),
+        };
+        let points = |samples: Vec<(u64, f64)>| {
+            samples
+                .into_iter()
+                .map(|(ts, v)| TimePoint::new(ts, v))
+                .collect::<Vec<TimePoint>>()
+        };
+        storage.write_time_point(1, 123, 1.0)?;
+        storage.write_time_series(1, points(vec![(120, 2.0), (123, 3.0), (124, 4.0)]))?;
+        let mut expected = Vec::new();
+        for (ts, v) in vec![(120u64, 2.0), (123, 3.0), (124, 4.0)] {
+            expected.append(&mut KvStorageProcessor::encode_time_point(ts, v)?);
+        }
+        assert_eq!(dummy_backend.get(storage.series_key(1))?.unwrap(), expected);
+
+        // compacted series is kept compacted
+        storage.compact_time_series(1)?;
+        storage.write_time_series(1, points(vec![(100, 1.0)]))?;
+        assert_eq!(dummy_backend.get(storage.series_key(1))?, None);
+        let read = storage
+            .read_time_series(1, 0, 200)?
+            .iter()
+            .map(|tp| (tp.timestamp, tp.value))
+            .collect::<Vec<_>>();
+        assert_eq!(read, vec![(100, 1.0), (120, 2.0), (123, 3.0), (124, 4.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_destroy() -> Result<()> {
+        let dummy_backend = DummyTiKvBackend::new();
+        let chunk_identifier = b"chunk".to_vec();
+        let storage = TiKvStorage {
+            storage_identifier: dummy_backend.init_component(chunk_identifier.clone(), false)?,
+            client: Box::new(dummy_backend.clone()),
+            chunk_identifier: chunk_identifier.clone(),
+        };
+        // series of another chunk
+        dummy_backend.set(b"other".to_vec(), b"value".to_vec())?;
+        storage.write_time_point(1, 120, 1.0)?;
+        storage.write_time_point(2, 120, 2.0)?;
+
+        storage.destroy()?;
+        assert!(storage.read_time_series(1, 0, 200).is_err());
+        assert!(storage.read_time_series(2, 0, 200).is_err());
+        assert_eq!(dummy_backend.get(chunk_identifier)?, None);
+        assert_eq!(
+            dummy_backend.get(b"other".to_vec())?,
+            Some(b"value".to_vec())
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,285 @@
+use crate::indexer::Indexer;
+use crate::storage::Storage;
+use crate::{MonolithDb, MonolithErr, Result};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Max length of tenant id
+const MAX_TENANT_ID_LEN: usize = 150;
+
+/// Db of a tenant, empty until the first request of the tenant opens it
+type TenantSlot<S, I> = Arc<Mutex<Option<Arc<MonolithDb<S, I>>>>>;
+
+///
+/// Dbs of all tenants, created lazily when the tenant is first used.
+///
+/// Data without tenant goes to the default db, and each tenant has its own db built from the default
+/// one, see `MonolithDb::open_tenant`. Tenants never share chunks, storage or indexer.
+///
+/// A db is opened while holding the slot of its tenant only, so that requests of other tenants are
+/// not blocked by loading it. At most `max_tenants` of the default db are opened.
+pub struct Tenants<S, I>
+where
+    S: Storage + Send + Sync + 'static,
+    I: Indexer + Send + Sync + 'static,
+{
+    default_db: Arc<MonolithDb<S, I>>,
+    dbs: RwLock<HashMap<String, TenantSlot<S, I>>>,
+    max_tenants: Option<usize>,
+}
+
+impl<S, I> Tenants<S, I>
+where
+    S: Storage + Send + Sync + 'static,
+    I: Indexer + Send + Sync + 'static,
+{
+    pub fn new(default_db: Arc<MonolithDb<S, I>>) -> Tenants<S, I> {
+        Tenants {
+            max_tenants: default_db.max_tenants(),
+            default_db,
+            dbs: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn default_db(&self) -> &Arc<MonolithDb<S, I>> {
+        &self.default_db
+    }
+
+    ///
+    /// The default db followed by dbs of all tenants opened so far, in the order of tenant id.
+    ///
+    /// Tenants whose db is being opened are skipped instead of waiting for them.
+    pub fn all(&self) -> Vec<(Option<String>, Arc<MonolithDb<S, I>>)> {
+        let mut res = vec![(None, Arc::clone(&self.default_db))];
+        let mut slots = self
+            .dbs
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(tenant, slot)| (tenant.clone(), Arc::clone(slot)))
+            .collect::<Vec<_>>();
+        slots.sort_by(|a, b| a.0.cmp(&b.0));
+        for (tenant, slot) in slots {
+            if let Ok(db) = slot.try_lock() {
+                if let Some(db) = db.as_ref() {
+                    res.push((Some(tenant), Arc::clone(db)));
+                }
+            }
+        }
+        res
+    }
+
+    /// Close all dbs, return the first error after trying to close every one of them
+    pub fn close_all(&self) -> Result<()> {
+        let mut res = Ok(());
+        for (tenant, db) in self.all() {
+            if let Err(err) = db.close() {
+                error!("Cannot close db of tenant {:?}, {}", tenant, err);
+                if res.is_ok() {
+                    res = Err(err);
+                }
+            }
+        }
+        res
+    }
+
+    /// Get db of `tenant`, or the default db if `tenant` is `None`
+    pub fn get(&self, tenant: Option<&str>) -> Result<Arc<MonolithDb<S, I>>> {
+        let tenant = match tenant {
+            Some(tenant) => tenant,
+            None => return Ok(Arc::clone(&self.default_db)),
+        };
+        loop {
+            let slot = self.dbs.read().unwrap().get(tenant).map(Arc::clone);
+            let slot = match slot {
+                Some(slot) => slot,
+                None => self.create_slot(tenant)?,
+            };
+            let mut db = slot.lock().unwrap();
+            if let Some(db) = db.as_ref() {
+                return Ok(Arc::clone(db));
+            }
+            // removed by a request that failed to open the db while waiting for the slot
+            if !self.is_registered(tenant, &slot) {
+                continue;
+            }
+            match self.default_db.open_tenant(tenant) {
+                Ok(opened) => {
+                    *db = Some(Arc::clone(&opened));
+                    return Ok(opened);
+                }
+                Err(err) => {
+                    // so that a tenant failed to open doesn't count towards `max_tenants`
+                    self.dbs.write().unwrap().remove(tenant);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Whether `slot` is still the slot of `tenant`
+    fn is_registered(&self, tenant: &str, slot: &TenantSlot<S, I>) -> bool {
+        self.dbs
+            .read()
+            .unwrap()
+            .get(tenant)
+            .map_or(false, |s| Arc::ptr_eq(s, slot))
+    }
+
+    /// Add an empty slot of `tenant` unless there are already `max_tenants` of them
+    fn create_slot(&self, tenant: &str) -> Result<TenantSlot<S, I>> {
+        validate_tenant_id(tenant)?;
+        let mut dbs = self.dbs.write().unwrap();
+        // may be created by others while waiting for the lock
+        if let Some(slot) = dbs.get(tenant) {
+            return Ok(Arc::clone(slot));
+        }
+        if let Some(max) = self.max_tenants {
+            if dbs.len() >= max {
+                return Err(MonolithErr::InvalidTenantErr(format!(
+                    "cannot create tenant {}, the limit of {} tenants is reached",
+                    tenant, max
+                )));
+            }
+        }
+        let slot = Arc::new(Mutex::new(None));
+        dbs.insert(tenant.to_string(), Arc::clone(&slot));
+        Ok(slot)
+    }
+}
+
+/// Tenant id is used as dir name and key prefix, so only `[a-zA-Z0-9_.-]` is allowed.
+pub fn validate_tenant_id(tenant: &str) -> Result<()> {
+    if tenant.is_empty() || tenant.len() > MAX_TENANT_ID_LEN {
+        return Err(MonolithErr::InvalidTenantErr(format!(
+            "tenant id must have 1 to {} characters",
+            MAX_TENANT_ID_LEN
+        )));
+    }
+    if tenant == "." || tenant == ".." {
+        return Err(MonolithErr::InvalidTenantErr(format!(
+            "tenant id cannot be {}",
+            tenant
+        )));
+    }
+    if let Some(c) = tenant
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '.' || *c == '-'))
+    {
+        return Err(MonolithErr::InvalidTenantErr(format!(
+            "invalid character {:?} in tenant id {}",
+            c, tenant
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::{Label, Labels};
+    use crate::common::time_point::TimePoint;
+    use crate::indexer::{SledIndexer, SledIndexerBuilder};
+    use crate::option::DbOpts;
+    use crate::storage::{SledStorage, SledStorageBuilder};
+    use crate::tenant::{validate_tenant_id, Tenants};
+    use crate::utils::get_current_timestamp;
+    use crate::{MonolithDb, Result};
+    use std::fs;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_validate_tenant_id() {
+        assert!(validate_tenant_id("team-a_1.prod").is_ok());
+        assert!(validate_tenant_id("").is_err());
+        assert!(validate_tenant_id("..").is_err());
+        assert!(validate_tenant_id("a/b").is_err());
+        assert!(validate_tenant_id(&"a".repeat(151)).is_err());
+    }
+
+    #[test]
+    fn test_tenant_isolation() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        let tenants = Tenants::new(db);
+
+        let labels = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+        let now = get_current_timestamp() + 1000;
+        let a = tenants.get(Some("a"))?;
+        a.write_time_points(labels.clone(), vec![TimePoint::new(now, 1.0)])?;
+        assert!(Arc::ptr_eq(&a, &tenants.get(Some("a"))?));
+        assert!(dir.path().join("tenants").join("a").is_dir());
+
+        assert_eq!(a.label_values("__name__", 0, now + 1)?, vec!["up"]);
+        let b = tenants.get(Some("b"))?;
+        assert!(b.label_values("__name__", 0, now + 1)?.is_empty());
+        assert!(tenants
+            .get(None)?
+            .label_values("__name__", 0, now + 1)?
+            .is_empty());
+        assert!(tenants.get(Some("../a")).is_err());
+
+        let ids = tenants
+            .all()
+            .into_iter()
+            .map(|(tenant, _)| tenant)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![None, Some("a".to_string()), Some("b".to_string())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_tenants() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        opts.max_tenants = Some(1);
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        let tenants = Tenants::new(db);
+        let a = tenants.get(Some("a"))?;
+        assert!(tenants.get(Some("b")).is_err());
+        assert!(!dir.path().join("tenants").join("b").exists());
+        // opened tenants and the default one are still served
+        assert!(Arc::ptr_eq(&a, &tenants.get(Some("a"))?));
+        tenants.get(None)?;
+        assert_eq!(tenants.all().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_tenant_not_counted() -> Result<()> {
+        let dir = TempDir::new()?;
+        let mut opts = DbOpts::default();
+        opts.base_dir = dir.path().to_path_buf();
+        opts.max_tenants = Some(1);
+        let db = MonolithDb::<SledStorage, SledIndexer>::new(
+            opts,
+            Box::new(SledStorageBuilder::new()),
+            Box::new(SledIndexerBuilder::new()),
+        )?;
+        let tenants = Tenants::new(db);
+        // the dir of tenant cannot be created
+        fs::create_dir_all(dir.path().join("tenants"))?;
+        fs::write(dir.path().join("tenants").join("a"), "")?;
+        assert!(tenants.get(Some("a")).is_err());
+        assert_eq!(tenants.all().len(), 1);
+
+        let b = tenants.get(Some("b"))?;
+        assert!(Arc::ptr_eq(&b, &tenants.get(Some("b"))?));
+        assert_eq!(tenants.all().len(), 2);
+        Ok(())
+    }
+}
@@ -1,10 +1,21 @@
+use crate::{MonolithErr, Result};
 use crc::Hasher32;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
+mod reader;
+mod record;
 mod segment;
 mod writer;
 
+pub use reader::{SegmentDecoder, WalReader};
+pub use record::Record;
+pub use writer::WalWriter;
+
+pub(crate) use record::check_series_size;
+pub(crate) use segment::{checkpoint_path, list_segments, segment_path};
+
 /// Note that we only use the first three byte in this magic number
 /// User can still use the remaining 5 bytes to add more metadata
 /// Like WAL for different component
@@ -62,7 +73,19 @@ impl Entry {
     }
 
     pub fn len(&self) -> usize {
-        8 + 1 + self.content.len() + 4 // seq_id + entry_type + content + crc32
+        8 + 1 + 2 + self.content.len() + 4 // seq_id + entry_type + len + content + crc32
+    }
+
+    pub fn seq_id(&self) -> u64 {
+        self.seq_id
+    }
+
+    pub fn entry_type(&self) -> u8 {
+        self.entry_type
+    }
+
+    pub fn content(&self) -> &[u8] {
+        self.content.as_slice()
     }
 
     pub fn get_bytes(&self) -> Vec<u8> {
@@ -86,8 +109,11 @@ pub struct WalConfig {
     pub filepath: PathBuf,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum FlushPolicy {
-    TimeBased(std::time::Duration),
+    // flush when the oldest cached entry is older than the duration. `WalWriter` also flushes
+    // every duration in background, so cached entries are flushed even if nothing is written.
+    TimeBased(Duration),
     // flush based on the num of entries.
     NumBased(usize),
     // flush based on the num of bytes. Note that there is no guarantee that we will flush at exact
@@ -97,15 +123,40 @@ pub enum FlushPolicy {
     Immediate,
 }
 
+/// Parse `immediate`, `entries:<num>`, `bytes:<num>` or `interval:<milliseconds>`
+impl FromStr for FlushPolicy {
+    type Err = MonolithErr;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, value) = match s.split_once(':') {
+            Some((kind, value)) => (kind, Some(value)),
+            None => (s, None),
+        };
+        match (kind, value) {
+            ("immediate", None) => Ok(FlushPolicy::Immediate),
+            ("entries", Some(num)) => Ok(FlushPolicy::NumBased(num.parse()?)),
+            ("bytes", Some(num)) => Ok(FlushPolicy::SizeBased(num.parse()?)),
+            ("interval", Some(millis)) => Ok(FlushPolicy::TimeBased(Duration::from_millis(
+                millis.parse()?,
+            ))),
+            _ => {
+                error!("Invalid flush policy of write ahead log {}", s);
+                Err(MonolithErr::OptionErr)
+            }
+        }
+    }
+}
+
 /// FlushCache tells segment how to cache bytes
 pub enum FlushCache {
     TimeBased {
-        handler: std::thread::JoinHandle<()>,
-        cache: Arc<Mutex<Vec<Entry>>>,
+        interval: Duration,
+        /// When the first entry of cache is written
+        since: Instant,
+        cache: Vec<Entry>,
     },
     NumBased {
         limit: usize,
-        idx: usize,
         cache: Vec<Entry>,
     },
     SizeBased {
@@ -118,6 +169,10 @@ pub enum FlushCache {
 
 pub enum EntryType {
     Default = 0, //
+    /// A series written into the segment for the first time, see `Record::Series`
+    Series = 1,
+    /// Samples of a series, see `Record::Samples`
+    Samples = 2,
 }
 
 #[derive(Debug, Fail)]
@@ -127,12 +182,39 @@ pub enum WalErr {
 
     #[fail(display = "{}", _0)]
     FileIoErr(std::io::Error),
+
+    /// Content of segment is broken, not just torn at the end by a crash
+    #[fail(display = "Corrupted segment {}, {}", _0, _1)]
+    CorruptedErr(String, String),
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::wal::Entry;
+    use crate::wal::{Entry, FlushPolicy};
     use crc::Hasher32;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_parse_flush_policy() {
+        assert_eq!(
+            "immediate".parse::<FlushPolicy>().ok(),
+            Some(FlushPolicy::Immediate)
+        );
+        assert_eq!(
+            "entries:100".parse::<FlushPolicy>().ok(),
+            Some(FlushPolicy::NumBased(100))
+        );
+        assert_eq!(
+            "bytes:4096".parse::<FlushPolicy>().ok(),
+            Some(FlushPolicy::SizeBased(4096))
+        );
+        assert_eq!(
+            "interval:100".parse::<FlushPolicy>().ok(),
+            Some(FlushPolicy::TimeBased(Duration::from_millis(100)))
+        );
+        assert!("entries".parse::<FlushPolicy>().is_err());
+        assert!("bytes:many".parse::<FlushPolicy>().is_err());
+    }
 
     #[test]
     pub fn test_entry_get_bytes() {
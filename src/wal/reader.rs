@@ -0,0 +1,343 @@
+use crate::common::label::Labels;
+use crate::common::time_point::TimePoint;
+use crate::wal::segment::{checkpoint_path, list_segments, segment_path};
+use crate::wal::{Entry, Record, WalErr, WAL_MAGIC_NUMBER};
+use crate::Result;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Size of seq_id, type and len of an entry
+const ENTRY_HEADER_SIZE: usize = 8 + 1 + 2;
+const ENTRY_CRC_SIZE: usize = 4;
+const MAGIC_NUMBER_SIZE: usize = 8;
+const SEGMENT_CRC_SIZE: usize = 8;
+
+///
+/// Reads the segments of a write ahead log in order.
+///
+/// Segments that are not closed, like the one being written when the process crashed, may end with
+/// a torn record, which is dropped. Any other broken entry, or a CRC64 mismatch of a closed segment
+/// is returned as `CorruptedErr`.
+pub struct WalReader {
+    dir: PathBuf,
+    segments: Vec<u64>,
+}
+
+///
+/// Decodes the entries of a segment from its bytes in pieces, like a segment still being written.
+///
+/// Bytes after the last complete entry are kept until the rest of the entry is pushed. Entries are
+/// checked by their CRC32, while the CRC64 of a closed segment is skipped.
+pub struct SegmentDecoder {
+    name: String,
+    buf: Vec<u8>,
+    started: bool,
+    next_seq: u64,
+    closed: bool,
+}
+
+/// Result of decoding the bytes at the start of a slice as an entry
+enum Decoded {
+    Entry(Entry, usize),
+    /// Slice ends before the entry
+    Incomplete,
+    /// Entry of the size is broken
+    Invalid(usize),
+}
+
+impl WalReader {
+    /// Only the segments existing in `dir` at this time are read
+    pub fn open(dir: &Path) -> Result<WalReader> {
+        fs::create_dir_all(dir)?;
+        Ok(WalReader {
+            dir: dir.to_path_buf(),
+            segments: list_segments(dir)?,
+        })
+    }
+
+    /// Sequence numbers of the segments to read, in ascending order
+    pub fn segments(&self) -> &[u64] {
+        self.segments.as_slice()
+    }
+
+    /// Call `f` with the labels and samples of each samples entry in written order, return the
+    /// number of samples
+    pub fn replay<F>(&self, mut f: F) -> Result<usize>
+    where
+        F: FnMut(&Labels, Vec<TimePoint>),
+    {
+        let mut samples = 0;
+        for seq in self.segments.iter() {
+            let path = segment_path(&self.dir, *seq);
+            // series refs are only valid in their segment, besides the series in its checkpoint
+            let mut series = HashMap::<u64, Labels>::new();
+            let checkpoint = checkpoint_path(&self.dir, *seq);
+            if checkpoint.exists() {
+                for entry in read_segment(&checkpoint)? {
+                    if let Record::Series(series_ref, labels) = Record::from_entry(&entry)? {
+                        series.insert(series_ref, labels);
+                    }
+                }
+            }
+            for entry in read_segment(&path)? {
+                match Record::from_entry(&entry)? {
+                    Record::Series(series_ref, labels) => {
+                        series.insert(series_ref, labels);
+                    }
+                    Record::Samples(series_ref, points) => {
+                        let labels = series.get(&series_ref).ok_or_else(|| {
+                            WalErr::CorruptedErr(
+                                path.display().to_string(),
+                                format!("unknown series {}", series_ref),
+                            )
+                        })?;
+                        samples += points.len();
+                        f(labels, points);
+                    }
+                }
+            }
+        }
+        Ok(samples)
+    }
+}
+
+impl SegmentDecoder {
+    /// `name` of the segment is only used in errors
+    pub fn new(name: &str) -> SegmentDecoder {
+        SegmentDecoder {
+            name: name.to_string(),
+            buf: Vec::new(),
+            started: false,
+            next_seq: 0,
+            closed: false,
+        }
+    }
+
+    /// Decode the entries completed by `data`, which follows the bytes pushed before
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<Entry>> {
+        let name = self.name.clone();
+        let corrupted = |reason: String| WalErr::CorruptedErr(name.clone(), reason);
+        self.buf.extend_from_slice(data);
+        let mut offset = 0;
+        if !self.started {
+            if self.buf.len() < MAGIC_NUMBER_SIZE {
+                return Ok(Vec::new());
+            }
+            if self.buf[..3] != WAL_MAGIC_NUMBER.to_be_bytes()[..3] {
+                return Err(corrupted("invalid magic number".to_string()).into());
+            }
+            self.started = true;
+            offset = MAGIC_NUMBER_SIZE;
+        }
+        let mut entries = Vec::new();
+        while offset < self.buf.len() && !self.closed {
+            let rest = &self.buf[offset..];
+            match decode_entry(rest, self.next_seq) {
+                Decoded::Entry(entry, size) => {
+                    entries.push(entry);
+                    offset += size;
+                    self.next_seq += 1;
+                }
+                Decoded::Incomplete => {
+                    // an entry always starts with the next seq_id, otherwise it's the CRC64
+                    if rest.len() >= SEGMENT_CRC_SIZE
+                        && rest[..SEGMENT_CRC_SIZE] != self.next_seq.to_be_bytes()[..]
+                    {
+                        if rest.len() > SEGMENT_CRC_SIZE {
+                            return Err(corrupted(format!("broken entry {}", self.next_seq)).into());
+                        }
+                        self.closed = true;
+                        offset += SEGMENT_CRC_SIZE;
+                    }
+                    break;
+                }
+                Decoded::Invalid(_) => {
+                    return Err(corrupted(format!("broken entry {}", self.next_seq)).into())
+                }
+            }
+        }
+        self.buf.drain(..offset);
+        Ok(entries)
+    }
+
+    /// Whether the CRC64 of closed segment is read, nothing is written after it
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+}
+
+/// Read the entries of segment, checking the CRC32 of each entry and the CRC64 of the segment if it's
+/// closed
+pub(crate) fn read_segment(path: &Path) -> Result<Vec<Entry>> {
+    let corrupted = |reason: String| WalErr::CorruptedErr(path.display().to_string(), reason);
+    let data = fs::read(path)?;
+    if data.len() < MAGIC_NUMBER_SIZE {
+        warn!("Drop torn magic number of {}", path.display());
+        return Ok(Vec::new());
+    }
+    // only the first three bytes are the magic number
+    if data[..3] != WAL_MAGIC_NUMBER.to_be_bytes()[..3] {
+        return Err(corrupted("invalid magic number".to_string()).into());
+    }
+    let mut entries = Vec::new();
+    let mut offset = MAGIC_NUMBER_SIZE;
+    while offset < data.len() {
+        let rest = &data[offset..];
+        let next_seq = entries.len() as u64;
+        let size = match decode_entry(rest, next_seq) {
+            Decoded::Entry(entry, size) => {
+                entries.push(entry);
+                offset += size;
+                continue;
+            }
+            Decoded::Incomplete => None,
+            Decoded::Invalid(size) => Some(size),
+        };
+        // a torn entry of 8 bytes only has the seq_id, otherwise it's the CRC64 of closed segment
+        if rest.len() == SEGMENT_CRC_SIZE && rest != &next_seq.to_be_bytes()[..] {
+            let crc = u64::from_be_bytes(rest.try_into().unwrap());
+            if crc != crc::crc64::checksum_ecma(&data[MAGIC_NUMBER_SIZE..offset]) {
+                return Err(corrupted("checksum mismatch".to_string()).into());
+            }
+            return Ok(entries);
+        }
+        match size {
+            // the last entry is torn if it is cut, or broken without anything after it
+            None => warn!("Drop torn entry at offset {} of {}", offset, path.display()),
+            Some(size) if size == rest.len() => {
+                warn!("Drop broken entry at the end of {}", path.display())
+            }
+            Some(_) => return Err(corrupted(format!("broken entry at offset {}", offset)).into()),
+        }
+        break;
+    }
+    Ok(entries)
+}
+
+fn decode_entry(data: &[u8], seq_id: u64) -> Decoded {
+    if data.len() < ENTRY_HEADER_SIZE {
+        return Decoded::Incomplete;
+    }
+    let len = u16::from_be_bytes(data[9..ENTRY_HEADER_SIZE].try_into().unwrap()) as usize;
+    let size = ENTRY_HEADER_SIZE + len + ENTRY_CRC_SIZE;
+    if data.len() < size {
+        return Decoded::Incomplete;
+    }
+    let content = &data[ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + len];
+    let crc = u32::from_be_bytes(data[size - ENTRY_CRC_SIZE..size].try_into().unwrap());
+    if u64::from_be_bytes(data[..8].try_into().unwrap()) != seq_id
+        || crc != crc::crc32::checksum_ieee(content)
+    {
+        return Decoded::Invalid(size);
+    }
+    let mut entry = Entry {
+        seq_id,
+        entry_type: data[8],
+        ..Entry::default()
+    };
+    entry.push(content.to_vec());
+    Decoded::Entry(entry, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::time_point::TimePoint;
+    use crate::wal::reader::{read_segment, SegmentDecoder};
+    use crate::wal::segment::{segment_path, Segment};
+    use crate::wal::{FlushPolicy, Record};
+    use crate::{MonolithErr, Result};
+    use std::fs::{self, File};
+    use tempfile::TempDir;
+
+    /// Write a segment of `num` samples entries, return the size of segment before closed
+    fn write_segment(dir: &TempDir, num: u64, close: bool) -> Result<u64> {
+        let path = segment_path(dir.path(), 0);
+        let mut segment = Segment::new(FlushPolicy::Immediate, File::create(&path)?)?;
+        for i in 0..num {
+            let record = Record::Samples(1, vec![TimePoint::new(i, i as f64)]);
+            segment.write_entry(record.to_entry(i)?)?;
+        }
+        let size = segment.size();
+        if close {
+            segment.close()?;
+        }
+        Ok(size)
+    }
+
+    #[test]
+    fn test_read_closed_segment() -> Result<()> {
+        let dir = TempDir::new()?;
+        write_segment(&dir, 3, true)?;
+        let path = segment_path(dir.path(), 0);
+        assert_eq!(read_segment(&path)?.len(), 3);
+
+        // flip a bit of the CRC64
+        let mut data = fs::read(&path)?;
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&path, data)?;
+        match read_segment(&path) {
+            Err(MonolithErr::WalErr(_)) => {}
+            _ => panic!("expect corrupted segment"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_torn_segment() -> Result<()> {
+        let dir = TempDir::new()?;
+        let size = write_segment(&dir, 3, false)?;
+        let path = segment_path(dir.path(), 0);
+        let data = fs::read(&path)?;
+        assert_eq!(data.len() as u64, size);
+
+        // every cut of the last entry is dropped
+        let entry_size = (data.len() - 8) / 3;
+        for cut in 1..entry_size {
+            fs::write(&path, &data[..data.len() - cut])?;
+            assert_eq!(read_segment(&path)?.len(), 2);
+        }
+
+        // broken entry before the last one is not torn
+        let mut broken = data.clone();
+        broken[8 + entry_size - 1] ^= 1;
+        fs::write(&path, broken)?;
+        assert!(read_segment(&path).is_err());
+
+        // broken last entry is dropped
+        let mut broken = data.clone();
+        let last = broken.len() - 1;
+        broken[last] ^= 1;
+        fs::write(&path, broken)?;
+        assert_eq!(read_segment(&path)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_segment_in_pieces() -> Result<()> {
+        let dir = TempDir::new()?;
+        write_segment(&dir, 3, true)?;
+        let data = fs::read(segment_path(dir.path(), 0))?;
+        for piece in [1, 7, 16, data.len()].iter() {
+            let mut decoder = SegmentDecoder::new("0");
+            let mut entries = Vec::new();
+            for bytes in data.chunks(*piece) {
+                assert!(!decoder.is_closed());
+                entries.extend(decoder.push(bytes)?);
+            }
+            assert!(decoder.is_closed());
+            assert_eq!(
+                entries.iter().map(|e| e.seq_id()).collect::<Vec<u64>>(),
+                vec![0, 1, 2]
+            );
+        }
+
+        // broken entry is never completed by more bytes
+        let mut broken = data.clone();
+        broken[8 + 12] ^= 1;
+        assert!(SegmentDecoder::new("0").push(&broken).is_err());
+        Ok(())
+    }
+}
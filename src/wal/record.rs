@@ -0,0 +1,187 @@
+use crate::common::label::{Label, Labels};
+use crate::common::time_point::TimePoint;
+use crate::wal::{Entry, EntryType, WalErr};
+use crate::{MonolithErr, Result};
+use std::convert::TryInto;
+
+/// Content of an entry is at most `u16::MAX` bytes
+const MAX_CONTENT_SIZE: usize = u16::MAX as usize;
+/// Size of a sample in content, timestamp(u64) and value(f64)
+const SAMPLE_SIZE: usize = 16;
+/// Samples of a series are split into entries of at most this many samples
+pub(crate) const MAX_SAMPLES_PER_ENTRY: usize = (MAX_CONTENT_SIZE - 8) / SAMPLE_SIZE;
+
+///
+/// Record in write ahead log, which is the decoded content of an entry.
+///
+/// Series are referred by a number that is unique in its segment, so the series entry is written
+/// before any samples of the series in each segment:
+///
+/// ```text
+/// Series:  | series_ref(u64) | label_num(u16) | key_len(u16) | key | value_len(u16) | value | ... |
+/// Samples: | series_ref(u64) | timestamp(u64) | value(f64) | timestamp(u64) | value(f64) | ... |
+/// ```
+#[derive(Clone)]
+pub enum Record {
+    Series(u64, Labels),
+    Samples(u64, Vec<TimePoint>),
+}
+
+impl Record {
+    pub fn to_entry(&self, seq_id: u64) -> Result<Entry> {
+        let (entry_type, content) = match self {
+            Record::Series(series_ref, labels) => {
+                let mut content = Vec::from(&series_ref.to_be_bytes()[..]);
+                content.extend_from_slice(&(labels.len() as u16).to_be_bytes()[..]);
+                for label in labels.vec() {
+                    for s in [label.key(), label.value()].iter() {
+                        content.extend_from_slice(&(s.len() as u16).to_be_bytes()[..]);
+                        content.extend_from_slice(s.as_bytes());
+                    }
+                }
+                (EntryType::Series, content)
+            }
+            Record::Samples(series_ref, points) => {
+                let mut content = Vec::with_capacity(8 + points.len() * SAMPLE_SIZE);
+                content.extend_from_slice(&series_ref.to_be_bytes()[..]);
+                for point in points {
+                    content.extend_from_slice(&point.timestamp.to_be_bytes()[..]);
+                    content.extend_from_slice(&point.value.to_be_bytes()[..]);
+                }
+                (EntryType::Samples, content)
+            }
+        };
+        if content.len() > MAX_CONTENT_SIZE {
+            return Err(WalErr::InternalError(format!(
+                "record of {} bytes is too large for an entry",
+                content.len()
+            ))
+            .into());
+        }
+        let mut entry = Entry::new(seq_id, entry_type);
+        entry.push(content);
+        Ok(entry)
+    }
+
+    pub fn from_entry(entry: &Entry) -> Result<Record> {
+        let mut content = entry.content();
+        let invalid = || WalErr::InternalError(format!("invalid entry {}", entry.seq_id()));
+        let series_ref = take(&mut content, 8).ok_or_else(invalid)?;
+        let series_ref = u64::from_be_bytes(series_ref.try_into().unwrap());
+        match entry.entry_type() {
+            t if t == EntryType::Series as u8 => {
+                let num = take(&mut content, 2).ok_or_else(invalid)?;
+                let num = u16::from_be_bytes(num.try_into().unwrap());
+                let mut labels = Vec::with_capacity(num as usize);
+                for _ in 0..num {
+                    let key = take_string(&mut content).ok_or_else(invalid)?;
+                    let value = take_string(&mut content).ok_or_else(invalid)?;
+                    labels.push(Label::new(key, value));
+                }
+                Ok(Record::Series(series_ref, Labels::from_vec(labels)))
+            }
+            t if t == EntryType::Samples as u8 => {
+                if content.len() % SAMPLE_SIZE != 0 {
+                    return Err(invalid().into());
+                }
+                let points = content
+                    .chunks(SAMPLE_SIZE)
+                    .map(|sample| {
+                        TimePoint::new(
+                            u64::from_be_bytes(sample[..8].try_into().unwrap()),
+                            f64::from_be_bytes(sample[8..].try_into().unwrap()),
+                        )
+                    })
+                    .collect();
+                Ok(Record::Samples(series_ref, points))
+            }
+            _ => Err(invalid().into()),
+        }
+    }
+}
+
+/// Series is rejected with `InvalidSeriesErr` if its labels are too large for an entry, so that it's
+/// never logged.
+pub(crate) fn check_series_size(labels: &Labels) -> Result<()> {
+    let size = labels
+        .vec()
+        .iter()
+        .map(|label| 4 + label.key().len() + label.value().len())
+        .sum::<usize>();
+    // series_ref and label_num
+    if 8 + 2 + size > MAX_CONTENT_SIZE {
+        return Err(MonolithErr::InvalidSeriesErr(format!(
+            "labels of {} bytes are too large, at most {} bytes are accepted",
+            size,
+            MAX_CONTENT_SIZE - 8 - 2
+        )));
+    }
+    Ok(())
+}
+
+/// Take the first `len` bytes of `content`
+fn take<'a>(content: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if content.len() < len {
+        return None;
+    }
+    let (head, rest) = content.split_at(len);
+    *content = rest;
+    Some(head)
+}
+
+fn take_string(content: &mut &[u8]) -> Option<String> {
+    let len = u16::from_be_bytes(take(content, 2)?.try_into().unwrap()) as usize;
+    String::from_utf8(take(content, len)?.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::label::{Label, Labels};
+    use crate::common::time_point::TimePoint;
+    use crate::wal::record::check_series_size;
+    use crate::wal::Record;
+    use crate::{MonolithErr, Result};
+
+    #[test]
+    fn test_record_entry() -> Result<()> {
+        let labels = Labels::from_vec(vec![
+            Label::from_key_value("__name__", "up"),
+            Label::from_key_value("job", "node"),
+        ]);
+        let entry = Record::Series(3, labels.clone()).to_entry(1)?;
+        match Record::from_entry(&entry)? {
+            Record::Series(series_ref, res) => {
+                assert_eq!(series_ref, 3);
+                assert!(res == labels);
+            }
+            _ => panic!("expect series"),
+        }
+
+        let points = vec![TimePoint::new(1000, 1.5), TimePoint::new(2000, -0.5)];
+        let entry = Record::Samples(3, points.clone()).to_entry(2)?;
+        match Record::from_entry(&entry)? {
+            Record::Samples(series_ref, res) => {
+                assert_eq!(series_ref, 3);
+                assert_eq!(res, points);
+                assert_eq!(res[1].value, -0.5);
+            }
+            _ => panic!("expect samples"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_series_size() -> Result<()> {
+        let labels = Labels::from_vec(vec![Label::from_key_value("job", "node")]);
+        check_series_size(&labels)?;
+        Record::Series(1, labels).to_entry(1)?;
+
+        // value longer than u16::MAX is rejected before it's encoded
+        let labels = Labels::from_vec(vec![Label::from_key_value("job", &"a".repeat(70000))]);
+        match check_series_size(&labels) {
+            Err(MonolithErr::InvalidSeriesErr(_)) => {}
+            res => panic!("too large labels should be rejected, got {:?}", res),
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,31 @@
 use crate::wal::{Entry, FlushCache, FlushPolicy, WalErr, WAL_MAGIC_NUMBER};
 use crate::Result;
-use std::fs::File;
+use std::fs::{self, File};
 use std::hash::Hasher;
-use std::io::Write;
-use std::sync::atomic::AtomicU64;
-use std::sync::{Arc, Mutex};
-
-type FileMutex = Arc<Mutex<File>>;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 const AVG_NUM_BYTES_IN_ENTRY: usize = 8;
+const SEGMENT_SUFFIX: &str = ".wal";
+const CHECKPOINT_SUFFIX: &str = ".checkpoint";
+
+/// Writer of a segment, which can sync the data written to disk
+pub trait SyncData: Write {
+    fn sync_data(&self) -> std::io::Result<()>;
+}
+
+impl SyncData for File {
+    fn sync_data(&self) -> std::io::Result<()> {
+        File::sync_data(self)
+    }
+}
+
+impl<W: SyncData> SyncData for BufWriter<W> {
+    fn sync_data(&self) -> std::io::Result<()> {
+        self.get_ref().sync_data()
+    }
+}
 
 /// Segment is one write ahead file
 ///
@@ -17,114 +34,140 @@ pub struct Segment<W: Write> {
     writer: W,
     cache: FlushCache,
     crc: crc::crc64::Digest,
+    /// Bytes written into writer, including the magic number
+    size: u64,
+    /// Whether bytes are written into writer since the last sync
+    unsynced: bool,
+    closed: bool,
 }
 
-impl<W: Write> Write for Segment<W> {
+impl<W: SyncData> Write for Segment<W> {
     fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
         self.crc.write(bytes); // update crc
-        self.writer.write(bytes)
+        self.writer.write_all(bytes)?;
+        self.size += bytes.len() as u64;
+        self.unsynced = true;
+        Ok(bytes.len())
     }
 
+    /// Write cached entries into writer, and sync them to disk, so that flushed entries are not lost
+    /// even if the machine crashes.
     fn flush(&mut self) -> std::io::Result<()> {
         let remaining = match &mut self.cache {
             &mut FlushCache::None => vec![],
-            &mut FlushCache::NumBased {
-                limit: ref size,
-                ref mut idx,
-                ref mut cache,
-            } => {
-                *idx = 0;
-                let _cache = cache.clone();
-                *cache = vec![Entry::default(); *size];
-                _cache
-            }
-            &mut FlushCache::TimeBased { .. } => {
-                // fixme and above
-                vec![]
-            }
+            &mut FlushCache::NumBased { ref mut cache, .. } => std::mem::take(cache),
+            &mut FlushCache::TimeBased { ref mut cache, .. } => std::mem::take(cache),
             &mut FlushCache::SizeBased {
-                ref limit,
                 ref mut cache,
                 ref mut size,
+                ..
             } => {
                 *size = 0;
-                let _cache = cache.clone();
-                *cache = Vec::with_capacity(limit * AVG_NUM_BYTES_IN_ENTRY);
-                _cache
+                std::mem::take(cache)
             }
         };
 
-        if remaining.len() > 0 {
-            for entry in remaining {
-                self.write(entry.content.as_slice())?;
-            }
+        for entry in remaining {
+            self.write_all(entry.get_bytes().as_slice())?;
         }
 
-        self.writer.flush()
+        self.writer.flush()?;
+        if self.unsynced {
+            self.writer.sync_data()?;
+            self.unsynced = false;
+        }
+        Ok(())
     }
 }
 
 impl<W> Segment<W>
-    where
-        W: Write,
+where
+    W: SyncData,
 {
     pub fn new(flush_policy: FlushPolicy, mut writer: W) -> Result<Segment<W>> {
         // write magic number. Note that we don't include this when compute CRC
-        writer.write(&WAL_MAGIC_NUMBER.to_be_bytes()[..]);
-        writer.flush();
+        writer.write_all(&WAL_MAGIC_NUMBER.to_be_bytes()[..])?;
+        writer.flush()?;
 
         Ok(Segment {
             writer,
             cache: match flush_policy {
                 FlushPolicy::Immediate => FlushCache::None,
-                FlushPolicy::TimeBased(dur) => {
-                    let cache = Arc::new(Mutex::new(vec![]));
-                    FlushCache::TimeBased {
-                        handler: std::thread::spawn(|| {}), //todo: figure out how to do time based flush
-                        cache,
-                    }
-                }
+                FlushPolicy::TimeBased(interval) => FlushCache::TimeBased {
+                    interval,
+                    since: Instant::now(),
+                    cache: vec![],
+                },
                 FlushPolicy::NumBased(limit) => FlushCache::NumBased {
                     limit,
-                    idx: 0,
                     cache: Vec::with_capacity(limit),
                 },
                 FlushPolicy::SizeBased(limit) => FlushCache::SizeBased {
                     limit,
                     size: 0,
-                    cache: Vec::with_capacity(limit * AVG_NUM_BYTES_IN_ENTRY),
+                    cache: Vec::with_capacity(limit / AVG_NUM_BYTES_IN_ENTRY),
                 },
             },
             crc: crc::crc64::Digest::new(crc::crc64::ECMA),
+            size: WAL_MAGIC_NUMBER.to_be_bytes().len() as u64,
+            unsynced: false,
+            closed: false,
         })
     }
 
-    /// flush all data and close the file
+    /// Bytes written into the underlying writer, cached entries are not included
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// flush all data and append the CRC64 of entries, nothing can be written after closed
     pub fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.flush()?;
         let _crc = self.crc.finish();
-        self.write(&_crc.to_be_bytes())?; // append crc result
+        self.writer.write_all(&_crc.to_be_bytes())?; // append crc result
+        self.writer.flush()?;
+        self.size += 8;
+        self.closed = true;
         Ok(())
     }
 
     pub fn write_entry(&mut self, entry: Entry) -> Result<()> {
+        if self.closed {
+            return Err(WalErr::InternalError("segment is closed".to_string()).into());
+        }
         match &mut self.cache {
             &mut FlushCache::None => {
-                self.write(entry.get_bytes().as_slice())?;
-                self.flush();
+                self.write_all(entry.get_bytes().as_slice())?;
+                self.flush()?;
             }
             &mut FlushCache::NumBased {
-                limit: ref size,
-                ref mut idx,
+                ref limit,
                 ref mut cache,
             } => {
-                cache[*idx] = entry;
-                *idx += 1;
-                if idx == size {
-                    self.flush();
+                cache.push(entry);
+                if cache.len() >= *limit {
+                    self.flush()?;
                 }
             }
-            &mut FlushCache::TimeBased { ref mut cache, .. } => {
-                cache.lock().unwrap().push(entry);
+            &mut FlushCache::TimeBased {
+                ref interval,
+                ref mut since,
+                ref mut cache,
+            } => {
+                if cache.is_empty() {
+                    *since = Instant::now();
+                }
+                cache.push(entry);
+                if since.elapsed() >= *interval {
+                    self.flush()?;
+                }
             }
             &mut FlushCache::SizeBased {
                 ref mut cache,
@@ -134,7 +177,7 @@ impl<W> Segment<W>
                 let entry_len = entry.len();
                 cache.push(entry);
                 if *size + entry_len >= *limit {
-                    self.flush();
+                    self.flush()?;
                 } else {
                     *size += entry_len;
                 }
@@ -144,16 +187,91 @@ impl<W> Segment<W>
     }
 }
 
+pub(crate) fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}{}", seq, SEGMENT_SUFFIX))
+}
+
+/// Checkpoint of a segment has the series that can be referred in the segment without series entries
+pub(crate) fn checkpoint_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}{}", seq, CHECKPOINT_SUFFIX))
+}
+
+/// Sequence numbers of segments in dir, in ascending order
+pub(crate) fn list_segments(dir: &Path) -> Result<Vec<u64>> {
+    list_files(dir, SEGMENT_SUFFIX)
+}
+
+/// Sequence numbers of checkpoints in dir, in ascending order
+pub(crate) fn list_checkpoints(dir: &Path) -> Result<Vec<u64>> {
+    list_files(dir, CHECKPOINT_SUFFIX)
+}
+
+fn list_files(dir: &Path, suffix: &str) -> Result<Vec<u64>> {
+    let mut res = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if let Some(seq) = name.strip_suffix(suffix) {
+            if let Ok(seq) = seq.parse::<u64>() {
+                res.push(seq);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::wal::segment::Segment;
+    use crate::wal::segment::{Segment, SyncData};
     use crate::wal::{Entry, EntryType, FlushPolicy, WAL_MAGIC_NUMBER};
     use crate::Result;
     use futures::io::SeekFrom;
+    use std::cell::Cell;
     use std::fs::{File, OpenOptions};
-    use std::io::{Read, Seek};
+    use std::io::{Read, Seek, Write};
     use tempfile::TempPath;
 
+    /// Writer that counts the syncs
+    #[derive(Default)]
+    struct SyncCounter {
+        buf: Vec<u8>,
+        syncs: Cell<usize>,
+    }
+
+    impl Write for SyncCounter {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.buf.write(bytes)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SyncData for SyncCounter {
+        fn sync_data(&self) -> std::io::Result<()> {
+            self.syncs.set(self.syncs.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_sync_on_flush() -> Result<()> {
+        let mut segment = Segment::new(FlushPolicy::NumBased(2), SyncCounter::default())?;
+        segment.write_entry(Entry::new(1u64, EntryType::Default))?;
+        assert_eq!(segment.get_ref().syncs.get(), 0);
+        // entries are synced once the flush policy fires
+        segment.write_entry(Entry::new(2u64, EntryType::Default))?;
+        assert_eq!(segment.get_ref().syncs.get(), 1);
+        // nothing to sync
+        segment.flush()?;
+        assert_eq!(segment.get_ref().syncs.get(), 1);
+        segment.write_entry(Entry::new(3u64, EntryType::Default))?;
+        segment.close()?;
+        assert_eq!(segment.get_ref().syncs.get(), 2);
+        Ok(())
+    }
+
     #[test]
     pub fn test_write_entry() -> Result<()> {
         let temp_path = tempfile::tempdir()?;
@@ -1,23 +1,347 @@
-use std::sync::Mutex;
-use std::fs::File;
-use std::io::Write;
-use std::sync::atomic::AtomicU64;
-use crate::wal::segment::Segment;
-
-pub struct WalWriter<W: Write> {
-    next_seq: AtomicU64, // generate the file name to maintain file order.
-    active_seg: Segment<W>, // active segment
-    segments: Vec<Segment<W>>, // cannot write again
-    crc: crc::crc64::Digest
+use crate::common::label::Labels;
+use crate::common::time_point::TimePoint;
+use crate::wal::record::MAX_SAMPLES_PER_ENTRY;
+use crate::wal::segment::{
+    checkpoint_path, list_checkpoints, list_segments, segment_path, Segment,
+};
+use crate::wal::{FlushPolicy, Record};
+use crate::Result;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A new segment is started once the active one is larger than this
+const MAX_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+/// Entries of a checkpoint are written into file in batches of this size
+const CHECKPOINT_BATCH_SIZE: usize = 1024;
+
+///
+/// Appends samples to the segments of a write ahead log, it's safe to share between threads.
+///
+/// Series entries are flushed as soon as they are written, samples entries are cached according to
+/// the `FlushPolicy`, and flushed in background with `TimeBased` policy. Entries are synced to disk
+/// when they are flushed.
+///
+/// When a new segment is started, series written into the previous one are kept in the checkpoint
+/// of the new segment, so that old segments can be removed by `truncate` without losing series.
+pub struct WalWriter {
+    state: Arc<Mutex<WriterState>>,
+    flush_stop: Mutex<Option<Sender<()>>>,
+    flush_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+struct WriterState {
+    dir: PathBuf,
+    flush_policy: FlushPolicy,
+    seq: u64,
+    active_seg: Segment<File>,
+    next_entry: u64,
+    next_ref: u64,
+    /// Refs of series that can be referred in the active segment
+    series: HashMap<Labels, u64>,
+    /// Refs of series written into the active segment
+    written: HashSet<u64>,
+}
+
+impl WalWriter {
+    /// Start a new segment after the existing ones in `dir`, which are never written again
+    pub fn open(dir: &Path, flush_policy: FlushPolicy) -> Result<WalWriter> {
+        fs::create_dir_all(dir)?;
+        let seq = list_segments(dir)?.last().map(|seq| seq + 1).unwrap_or(0);
+        let state = WriterState {
+            dir: dir.to_path_buf(),
+            active_seg: WriterState::create_segment(dir, seq, flush_policy.clone())?,
+            flush_policy: flush_policy.clone(),
+            seq,
+            next_entry: 0,
+            next_ref: 1,
+            series: HashMap::new(),
+            written: HashSet::new(),
+        };
+        let writer = WalWriter {
+            state: Arc::new(Mutex::new(state)),
+            flush_stop: Mutex::new(None),
+            flush_thread: Mutex::new(None),
+        };
+        if let FlushPolicy::TimeBased(interval) = flush_policy {
+            writer.start_flusher(interval);
+        }
+        Ok(writer)
+    }
+
+    /// Flush cached entries every `interval` until the writer is closed or dropped
+    fn start_flusher(&self, interval: Duration) {
+        let (stop_tx, stop_rx) = channel::<()>();
+        let state = Arc::downgrade(&self.state);
+        let handle = thread::spawn(move || loop {
+            if let Err(RecvTimeoutError::Disconnected) | Ok(()) = stop_rx.recv_timeout(interval) {
+                return;
+            }
+            let state = match state.upgrade() {
+                Some(state) => state,
+                None => return,
+            };
+            let mut state = state.lock().unwrap();
+            if let Err(err) = state.active_seg.flush() {
+                error!("Cannot flush write ahead log, {}", err);
+            }
+        });
+        *self.flush_stop.lock().unwrap() = Some(stop_tx);
+        *self.flush_thread.lock().unwrap() = Some(handle);
+    }
+
+    /// Sequence number of the segment being written
+    pub fn active_segment(&self) -> u64 {
+        self.state.lock().unwrap().seq
+    }
+
+    /// Append `points` of the series `labels`
+    pub fn write(&self, labels: &Labels, points: &[TimePoint]) -> Result<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        let mut state = self.state.lock().unwrap();
+        if state.active_seg.size() >= MAX_SEGMENT_SIZE {
+            state.rotate()?;
+        }
+        state.write(labels, points)
+    }
+
+    /// Write cached entries into the segment file
+    pub fn flush(&self) -> Result<()> {
+        self.state.lock().unwrap().active_seg.flush()?;
+        Ok(())
+    }
+
+    /// Close the active segment and start the next one, return the sequence number of the new one
+    pub fn rotate(&self) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        state.rotate()?;
+        Ok(state.seq)
+    }
+
+    /// Remove segments and checkpoints before segment `seq`, the active segment is never removed
+    pub fn truncate(&self, seq: u64) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let seq = std::cmp::min(seq, state.seq);
+        for segment in list_segments(&state.dir)? {
+            if segment < seq {
+                fs::remove_file(segment_path(&state.dir, segment))?;
+            }
+        }
+        for checkpoint in list_checkpoints(&state.dir)? {
+            if checkpoint < seq {
+                fs::remove_file(checkpoint_path(&state.dir, checkpoint))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop flushing in background, flush and close the active segment, then sync it to disk
+    pub fn close(&self) -> Result<()> {
+        // dropping the sender wakes up the flusher
+        self.flush_stop.lock().unwrap().take();
+        if let Some(handle) = self.flush_thread.lock().unwrap().take() {
+            if handle.join().is_err() {
+                error!("Flusher of write ahead log panicked");
+            }
+        }
+        self.state.lock().unwrap().close()
+    }
+}
+
+impl WriterState {
+    fn create_segment(dir: &Path, seq: u64, flush_policy: FlushPolicy) -> Result<Segment<File>> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(segment_path(dir, seq))?;
+        Segment::new(flush_policy, file)
+    }
+
+    fn write(&mut self, labels: &Labels, points: &[TimePoint]) -> Result<()> {
+        let series_ref = match self.series.get(labels) {
+            Some(series_ref) => *series_ref,
+            None => {
+                // series must be on disk before its samples, otherwise its samples cannot be replayed
+                let series_ref = self.next_ref;
+                self.write_record(Record::Series(series_ref, labels.clone()))?;
+                self.active_seg.flush()?;
+                self.series.insert(labels.clone(), series_ref);
+                self.next_ref += 1;
+                series_ref
+            }
+        };
+        self.written.insert(series_ref);
+        for points in points.chunks(MAX_SAMPLES_PER_ENTRY) {
+            self.write_record(Record::Samples(series_ref, points.to_vec()))?;
+        }
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: Record) -> Result<()> {
+        self.active_seg
+            .write_entry(record.to_entry(self.next_entry)?)?;
+        self.next_entry += 1;
+        Ok(())
+    }
+
+    /// Close the active segment and start the next one. Series written into the closed segment are
+    /// kept in the checkpoint of the new one, other series are written again if they come back.
+    fn rotate(&mut self) -> Result<()> {
+        self.close()?;
+        let seq = self.seq + 1;
+        let written = std::mem::take(&mut self.written);
+        let series = self
+            .series
+            .drain()
+            .filter(|(_, series_ref)| written.contains(series_ref))
+            .collect::<HashMap<Labels, u64>>();
+        write_checkpoint(self.dir.as_path(), seq, &series)?;
+        self.active_seg = Self::create_segment(self.dir.as_path(), seq, self.flush_policy.clone())?;
+        self.seq = seq;
+        self.next_entry = 0;
+        self.series = series;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.active_seg.close()?;
+        self.active_seg.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+/// Checkpoint is a closed segment of series entries. It's written into a temp file and then renamed,
+/// so that an existing checkpoint is always complete.
+fn write_checkpoint(dir: &Path, seq: u64, series: &HashMap<Labels, u64>) -> Result<()> {
+    let path = checkpoint_path(dir, seq);
+    let tmp = path.with_extension("tmp");
+    let file = BufWriter::new(File::create(&tmp)?);
+    let mut checkpoint = Segment::new(FlushPolicy::NumBased(CHECKPOINT_BATCH_SIZE), file)?;
+    for (seq_id, (labels, series_ref)) in series.iter().enumerate() {
+        let record = Record::Series(*series_ref, labels.clone());
+        checkpoint.write_entry(record.to_entry(seq_id as u64)?)?;
+    }
+    checkpoint.close()?;
+    checkpoint.get_ref().get_ref().sync_all()?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
 }
 
-impl<W> Write for WalWriter<W>
-    where W: Write{
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        unimplemented!()
+#[cfg(test)]
+mod tests {
+    use crate::common::label::{Label, Labels};
+    use crate::common::time_point::TimePoint;
+    use crate::wal::{FlushPolicy, WalReader, WalWriter};
+    use crate::Result;
+    use std::fs;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn series(job: &str) -> Labels {
+        Labels::from_vec(vec![
+            Label::from_key_value("__name__", "up"),
+            Label::from_key_value("job", job),
+        ])
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        unimplemented!()
+    fn replay(dir: &TempDir) -> Result<Vec<(String, u64, f64)>> {
+        let mut res = Vec::new();
+        WalReader::open(dir.path())?.replay(|labels, points| {
+            for tp in points {
+                res.push((labels.to_string(), tp.timestamp, tp.value));
+            }
+        })?;
+        Ok(res)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_write_replay() -> Result<()> {
+        let dir = TempDir::new()?;
+        {
+            let writer = WalWriter::open(dir.path(), FlushPolicy::NumBased(10))?;
+            writer.write(&series("a"), &[TimePoint::new(1000, 1.0)])?;
+            writer.write(&series("b"), &[TimePoint::new(1000, 2.0)])?;
+            writer.write(
+                &series("a"),
+                &[TimePoint::new(2000, 3.0), TimePoint::new(3000, 4.0)],
+            )?;
+            writer.close()?;
+        }
+        // crashed without closing, samples cached after the last new series are lost
+        {
+            let writer = WalWriter::open(dir.path(), FlushPolicy::NumBased(10))?;
+            assert_eq!(writer.active_segment(), 1);
+            writer.write(&series("c"), &[TimePoint::new(4000, 5.0)])?;
+            writer.write(&series("d"), &[TimePoint::new(4000, 6.0)])?;
+            writer.write(&series("c"), &[TimePoint::new(5000, 7.0)])?;
+        }
+        let a = series("a").to_string();
+        let b = series("b").to_string();
+        let c = series("c").to_string();
+        assert_eq!(
+            replay(&dir)?,
+            vec![
+                (a.clone(), 1000, 1.0),
+                (b, 1000, 2.0),
+                (a.clone(), 2000, 3.0),
+                (a, 3000, 4.0),
+                (c, 4000, 5.0),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_truncate() -> Result<()> {
+        let dir = TempDir::new()?;
+        let writer = WalWriter::open(dir.path(), FlushPolicy::Immediate)?;
+        writer.write(&series("a"), &[TimePoint::new(1000, 1.0)])?;
+        writer.write(&series("b"), &[TimePoint::new(1000, 2.0)])?;
+        assert_eq!(writer.rotate()?, 1);
+        // series "a" is only declared by the checkpoint of segment 1
+        writer.write(&series("a"), &[TimePoint::new(2000, 3.0)])?;
+        assert_eq!(writer.rotate()?, 2);
+        writer.write(&series("a"), &[TimePoint::new(3000, 4.0)])?;
+        writer.write(&series("b"), &[TimePoint::new(3000, 5.0)])?;
+        writer.truncate(2)?;
+        writer.close()?;
+
+        let mut files = fs::read_dir(dir.path())?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+            .collect::<Result<Vec<String>>>()?;
+        files.sort();
+        assert_eq!(
+            files,
+            vec![format!("{:020}.checkpoint", 2), format!("{:020}.wal", 2)]
+        );
+        assert_eq!(
+            replay(&dir)?,
+            vec![
+                (series("a").to_string(), 3000, 4.0),
+                (series("b").to_string(), 3000, 5.0),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_based_flush() -> Result<()> {
+        let dir = TempDir::new()?;
+        let policy = FlushPolicy::TimeBased(Duration::from_millis(10));
+        let writer = WalWriter::open(dir.path(), policy)?;
+        writer.write(&series("a"), &[TimePoint::new(1000, 1.0)])?;
+        writer.write(&series("a"), &[TimePoint::new(2000, 2.0)])?;
+        // cached samples are flushed in background without closing the writer
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(replay(&dir)?.len(), 2);
+        writer.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,825 @@
+use monolith::indexer::{SledIndexer, SledIndexerBuilder};
+use monolith::label::{Label, Labels};
+use monolith::option::{DbOpts, ListenAddr, ServerOpts};
+use monolith::replica::Replicator;
+use monolith::server::{MonolithServer, Shutdown};
+use monolith::storage::{SledStorage, SledStorageBuilder};
+use monolith::time_point::TimePoint;
+use monolith::utils::get_current_timestamp;
+use monolith::wal::FlushPolicy;
+use monolith::{MonolithDb, MonolithErr, Result};
+use openssl::asn1::Asn1Time;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use openssl::x509::{X509NameBuilder, X509};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn free_port() -> Result<u16> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+fn start_server(dir: &TempDir) -> Result<u16> {
+    let port = free_port()?;
+    let mut server_opts = ServerOpts::default();
+    server_opts.port = port as i32;
+    start_server_with_opts(dir, server_opts)?;
+    Ok(port)
+}
+
+fn start_server_with_opts(dir: &TempDir, server_opts: ServerOpts<'static>) -> Result<()> {
+    start_server_with_db_opts(dir, DbOpts::default(), server_opts)
+}
+
+fn start_server_with_db_opts(
+    dir: &TempDir,
+    mut opts: DbOpts,
+    server_opts: ServerOpts<'static>,
+) -> Result<()> {
+    let addr = server_opts.listen_addr()?;
+    opts.base_dir = dir.path().to_path_buf();
+    let db = MonolithDb::<SledStorage, SledIndexer>::new(
+        opts,
+        Box::new(SledStorageBuilder::new()),
+        Box::new(SledIndexerBuilder::new()),
+    )?;
+    thread::spawn(move || MonolithServer::new(server_opts, db).serve());
+    for _ in 0..50 {
+        let connected = match &addr {
+            ListenAddr::Tcp(addr) => TcpStream::connect(addr.as_str()).is_ok(),
+            ListenAddr::Unix(path) => UnixStream::connect(path).is_ok(),
+        };
+        if connected {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// Send a request and return status code and body
+fn send(
+    port: u16,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> (u16, String) {
+    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    send_on(stream, method, path, headers, body)
+}
+
+fn send_on<T: Read + Write>(
+    mut stream: T,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> (u16, String) {
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        path,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(format!("{}: {}\r\n", name, value).as_str());
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).unwrap();
+    stream.write_all(body).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let status = response[9..12].parse::<u16>().unwrap();
+    let body = response
+        .splitn(2, "\r\n\r\n")
+        .nth(1)
+        .unwrap_or("")
+        .to_string();
+    (status, body)
+}
+
+fn encode_varint(mut value: u64, buf: &mut Vec<u8>) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_bytes(field: u64, value: &[u8], buf: &mut Vec<u8>) {
+    encode_varint(field << 3 | 2, buf);
+    encode_varint(value.len() as u64, buf);
+    buf.extend_from_slice(value);
+}
+
+/// Encode a snappy compressed `WriteRequest` with one sample per series
+fn write_request(series: &[(Vec<(&str, &str)>, i64, f64)]) -> Vec<u8> {
+    let mut req = Vec::new();
+    for (labels, timestamp, value) in series {
+        let mut ts = Vec::new();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            encode_bytes(1, name.as_bytes(), &mut label);
+            encode_bytes(2, value.as_bytes(), &mut label);
+            encode_bytes(1, label.as_slice(), &mut ts);
+        }
+        let mut sample = vec![0x09];
+        sample.extend_from_slice(&value.to_le_bytes());
+        sample.push(0x10);
+        encode_varint(*timestamp as u64, &mut sample);
+        encode_bytes(2, sample.as_slice(), &mut ts);
+        encode_bytes(1, ts.as_slice(), &mut req);
+    }
+    snap::raw::Encoder::new()
+        .compress_vec(req.as_slice())
+        .unwrap()
+}
+
+const HEADERS: [(&str, &str); 3] = [
+    ("Content-Encoding", "snappy"),
+    ("Content-Type", "application/x-protobuf"),
+    ("X-Prometheus-Remote-Write-Version", "0.1.0"),
+];
+
+#[test]
+fn test_remote_write_status() -> Result<()> {
+    let dir = TempDir::new()?;
+    let port = start_server(&dir)?;
+    let now = get_current_timestamp() as i64 + 1000;
+    let valid = write_request(&[(vec![("__name__", "up"), ("job", "a")], now, 1.0)]);
+
+    let (status, _) = send(port, "POST", "/write", &HEADERS, valid.as_slice());
+    assert_eq!(status, 200);
+
+    let (status, _) = send(port, "GET", "/write", &[], &[]);
+    assert_eq!(status, 405);
+
+    let (status, _) = send(port, "POST", "/unknown", &HEADERS, valid.as_slice());
+    assert_eq!(status, 404);
+
+    let (status, body) = send(
+        port,
+        "POST",
+        "/write",
+        &[("Content-Encoding", "gzip")],
+        valid.as_slice(),
+    );
+    assert_eq!(status, 415);
+    assert!(body.contains("gzip"));
+
+    let (status, _) = send(
+        port,
+        "POST",
+        "/write",
+        &[("X-Prometheus-Remote-Write-Version", "2.0.0")],
+        valid.as_slice(),
+    );
+    assert_eq!(status, 400);
+
+    let (status, body) = send(port, "POST", "/write", &HEADERS, b"not snappy");
+    assert_eq!(status, 400);
+    assert!(body.contains("decompress"));
+
+    let garbage = snap::raw::Encoder::new()
+        .compress_vec(b"\xff\xff\xff")
+        .unwrap();
+    let (status, body) = send(port, "POST", "/write", &HEADERS, garbage.as_slice());
+    assert_eq!(status, 400);
+    assert!(body.contains("decode"));
+
+    let partial = write_request(&[
+        (vec![("__name__", "up"), ("job", "b")], now, 1.0),
+        (vec![("__name__", "up"), ("job-name", "c")], now, 1.0),
+    ]);
+    let (status, body) = send(port, "POST", "/write", &HEADERS, partial.as_slice());
+    assert_eq!(status, 400);
+    assert!(body.starts_with("1 of 2 series rejected"), "{}", body);
+    assert!(body.contains("job-name"), "{}", body);
+
+    let (status, body) = send(port, "GET", "/api/v1/series?match[]=up", &[], &[]);
+    assert_eq!(status, 200);
+    assert!(body.contains("\"b\""), "{}", body);
+    Ok(())
+}
+
+#[test]
+fn test_keep_alive_and_pipelining() -> Result<()> {
+    let dir = TempDir::new()?;
+    let port = start_server(&dir)?;
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    // both requests are sent before reading any response
+    stream.write_all(
+        b"GET /-/healthy HTTP/1.1\r\nHost: localhost\r\n\r\n\
+          GET /api/v1/labels HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let healthy = response.find("Monolith is Healthy").unwrap();
+    let labels = response.find("\"status\":\"success\"").unwrap();
+    assert!(healthy < labels, "{}", response);
+    assert_eq!(response.matches("HTTP/1.1 200").count(), 2, "{}", response);
+    Ok(())
+}
+
+#[test]
+fn test_auth() -> Result<()> {
+    let dir = TempDir::new()?;
+    let basic_auth_file = dir.path().join("users");
+    fs::write(&basic_auth_file, "prometheus:secret\n")?;
+    let bearer_token_file = dir.path().join("tokens");
+    fs::write(&bearer_token_file, "token\n")?;
+
+    let port = free_port()?;
+    let mut opts = ServerOpts::default();
+    opts.port = port as i32;
+    opts.basic_auth_file = Some(basic_auth_file);
+    opts.bearer_token_file = Some(bearer_token_file);
+    start_server_with_opts(&dir, opts)?;
+
+    let path = "/api/v1/labels";
+    let (status, _) = send(port, "GET", path, &[], &[]);
+    assert_eq!(status, 401);
+    let (status, _) = send(port, "GET", path, &[("Authorization", "Bearer wrong")], &[]);
+    assert_eq!(status, 401);
+    let (status, _) = send(port, "GET", path, &[("Authorization", "Bearer token")], &[]);
+    assert_eq!(status, 200);
+    // base64 of prometheus:secret
+    let basic = "Basic cHJvbWV0aGV1czpzZWNyZXQ=";
+    let (status, _) = send(port, "GET", path, &[("Authorization", basic)], &[]);
+    assert_eq!(status, 200);
+    Ok(())
+}
+
+#[test]
+fn test_unix_socket() -> Result<()> {
+    let dir = TempDir::new()?;
+    let socket = dir.path().join("monolith.sock");
+    let address = format!("unix:{}", socket.display());
+    let mut opts = ServerOpts::default();
+    opts.listen_address = Some(Box::leak(address.into_boxed_str()));
+    start_server_with_opts(&dir, opts)?;
+
+    let (status, body) = send_on(
+        UnixStream::connect(&socket)?,
+        "GET",
+        "/api/v1/labels",
+        &[],
+        &[],
+    );
+    assert_eq!(status, 200);
+    assert!(body.contains("success"), "{}", body);
+    Ok(())
+}
+
+/// Write a self signed certificate and its private key into `dir`
+fn self_signed_cert(dir: &TempDir) -> (PathBuf, PathBuf) {
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+    let mut cert = X509::builder().unwrap();
+    cert.set_version(2).unwrap();
+    cert.set_subject_name(&name).unwrap();
+    cert.set_issuer_name(&name).unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+    let cert_file = dir.path().join("tls.crt");
+    let key_file = dir.path().join("tls.key");
+    fs::write(&cert_file, cert.build().to_pem().unwrap()).unwrap();
+    fs::write(&key_file, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    (cert_file, key_file)
+}
+
+#[test]
+fn test_tls() -> Result<()> {
+    let dir = TempDir::new()?;
+    let (cert_file, key_file) = self_signed_cert(&dir);
+    let port = free_port()?;
+    let mut opts = ServerOpts::default();
+    opts.port = port as i32;
+    opts.tls_cert_file = Some(cert_file);
+    opts.tls_key_file = Some(key_file);
+    start_server_with_opts(&dir, opts)?;
+
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_verify(SslVerifyMode::NONE);
+    let connector = connector.build();
+    let stream = TcpStream::connect(("127.0.0.1", port))?;
+    let stream = connector.connect("localhost", stream).unwrap();
+    let (status, body) = send_on(stream, "GET", "/api/v1/labels", &[], &[]);
+    assert_eq!(status, 200);
+    assert!(body.contains("success"), "{}", body);
+
+    // keep-alive and pipelined requests work through tls as well
+    let stream = TcpStream::connect(("127.0.0.1", port))?;
+    let mut stream = connector.connect("localhost", stream).unwrap();
+    stream.write_all(
+        b"GET /-/healthy HTTP/1.1\r\nHost: localhost\r\n\r\n\
+          GET /api/v1/labels HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    assert_eq!(response.matches("HTTP/1.1 200").count(), 2, "{}", response);
+
+    // plain http is not served on tls port
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    stream.write_all(b"GET /api/v1/labels HTTP/1.1\r\n\r\n")?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).ok();
+    assert!(!response.starts_with(b"HTTP/1.1 200"));
+    Ok(())
+}
+
+/// Read an HTTP/2 frame, return its type, flags, stream id and payload
+fn read_h2_frame<T: Read>(stream: &mut T) -> (u8, u8, u32, Vec<u8>) {
+    let mut header = [0u8; 9];
+    stream.read_exact(&mut header).unwrap();
+    let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).unwrap();
+    (header[3], header[4], stream_id, payload)
+}
+
+/// Get `/-/healthy` with HTTP/2, return whether the status is 200 and the body
+fn h2_get_healthy<T: Read + Write>(mut stream: T) -> (bool, String) {
+    stream
+        .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+        .unwrap();
+    // empty SETTINGS
+    stream.write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0]).unwrap();
+    // :method GET, :scheme http, then :path and :authority as literals without huffman coding
+    let mut block = vec![0x82, 0x86, 0x04, 10];
+    block.extend_from_slice(b"/-/healthy");
+    block.extend_from_slice(&[0x01, 9]);
+    block.extend_from_slice(b"localhost");
+    // HEADERS with END_STREAM and END_HEADERS on stream 1
+    stream
+        .write_all(&[0, 0, block.len() as u8, 1, 0x5, 0, 0, 0, 1])
+        .unwrap();
+    stream.write_all(block.as_slice()).unwrap();
+
+    let mut ok = false;
+    let mut body = Vec::new();
+    loop {
+        let (kind, flags, stream_id, payload) = read_h2_frame(&mut stream);
+        match kind {
+            // indexed :status 200 of the static table
+            1 if stream_id == 1 => ok = payload.first() == Some(&0x88),
+            0 if stream_id == 1 => body.extend_from_slice(payload.as_slice()),
+            // SETTINGS that is not an ACK
+            4 if flags & 0x1 == 0 => stream.write_all(&[0, 0, 0, 4, 1, 0, 0, 0, 0]).unwrap(),
+            _ => {}
+        }
+        if stream_id == 1 && flags & 0x1 != 0 {
+            break;
+        }
+    }
+    (ok, String::from_utf8(body).unwrap())
+}
+
+#[test]
+fn test_http2() -> Result<()> {
+    let dir = TempDir::new()?;
+    let port = start_server(&dir)?;
+    // with prior knowledge on plain tcp
+    let stream = TcpStream::connect(("127.0.0.1", port))?;
+    let (ok, body) = h2_get_healthy(stream);
+    assert!(ok);
+    assert!(body.contains("Monolith is Healthy"), "{}", body);
+
+    let dir = TempDir::new()?;
+    let (cert_file, key_file) = self_signed_cert(&dir);
+    let port = free_port()?;
+    let mut opts = ServerOpts::default();
+    opts.port = port as i32;
+    opts.tls_cert_file = Some(cert_file);
+    opts.tls_key_file = Some(key_file);
+    start_server_with_opts(&dir, opts)?;
+
+    // negotiated through ALPN on tls
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_verify(SslVerifyMode::NONE);
+    connector.set_alpn_protos(b"\x02h2\x08http/1.1").unwrap();
+    let connector = connector.build();
+    let stream = TcpStream::connect(("127.0.0.1", port))?;
+    let stream = connector.connect("localhost", stream).unwrap();
+    assert_eq!(stream.ssl().selected_alpn_protocol(), Some(&b"h2"[..]));
+    let (ok, body) = h2_get_healthy(stream);
+    assert!(ok);
+    assert!(body.contains("Monolith is Healthy"), "{}", body);
+    Ok(())
+}
+
+#[test]
+fn test_tenant_isolation() -> Result<()> {
+    let dir = TempDir::new()?;
+    let port = start_server(&dir)?;
+    let now = get_current_timestamp() as i64 + 1000;
+    let req = write_request(&[(vec![("__name__", "up"), ("job", "a")], now, 1.0)]);
+    let mut headers = HEADERS.to_vec();
+    headers.push(("X-Scope-OrgID", "team-a"));
+    let (status, _) = send(port, "POST", "/write", &headers, req.as_slice());
+    assert_eq!(status, 200);
+
+    let path = "/api/v1/label/job/values";
+    let (status, body) = send(port, "GET", path, &[("X-Scope-OrgID", "team-a")], &[]);
+    assert_eq!(status, 200);
+    assert!(body.contains("\"a\""), "{}", body);
+    let (_, body) = send(port, "GET", path, &[("X-Scope-OrgID", "team-b")], &[]);
+    assert!(!body.contains("\"a\""), "{}", body);
+    let (_, body) = send(port, "GET", path, &[], &[]);
+    assert!(!body.contains("\"a\""), "{}", body);
+
+    let (status, _) = send(port, "GET", path, &[("X-Scope-OrgID", "../team-a")], &[]);
+    assert_eq!(status, 400);
+    Ok(())
+}
+
+#[test]
+fn test_forward_remote_write() -> Result<()> {
+    let central_dir = TempDir::new()?;
+    let central = start_server(&central_dir)?;
+
+    let dir = TempDir::new()?;
+    let config = dir.path().join("remote_write.yml");
+    fs::write(
+        &config,
+        format!(
+            "- url: http://127.0.0.1:{}/write\n  \
+               shards: 2\n  \
+               write_relabel_configs:\n    \
+                 - source_labels: [job]\n      \
+                   regex: secret\n      \
+                   action: drop\n",
+            central
+        ),
+    )?;
+    let port = free_port()?;
+    let mut opts = ServerOpts::default();
+    opts.port = port as i32;
+    opts.remote_write_config = Some(config);
+    start_server_with_opts(&dir, opts)?;
+
+    let now = get_current_timestamp() as i64 + 1000;
+    let req = write_request(&[
+        (vec![("__name__", "up"), ("job", "a")], now, 1.0),
+        (vec![("__name__", "up"), ("job", "b")], now, 1.0),
+        (vec![("__name__", "up"), ("job", "secret")], now, 1.0),
+    ]);
+    let mut headers = HEADERS.to_vec();
+    headers.push(("X-Scope-OrgID", "team-a"));
+    let (status, _) = send(port, "POST", "/write", &headers, req.as_slice());
+    assert_eq!(status, 200);
+
+    let path = "/api/v1/label/job/values";
+    let mut body = String::new();
+    for _ in 0..50 {
+        body = send(central, "GET", path, &[("X-Scope-OrgID", "team-a")], &[]).1;
+        if body.contains("\"a\"") && body.contains("\"b\"") {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(body.contains("\"a\"") && body.contains("\"b\""), "{}", body);
+    assert!(!body.contains("secret"), "{}", body);
+    Ok(())
+}
+
+#[test]
+fn test_federate() -> Result<()> {
+    let dir = TempDir::new()?;
+    let port = start_server(&dir)?;
+    let now = get_current_timestamp() as i64 + 1000;
+    let req = write_request(&[
+        (vec![("__name__", "up"), ("job", "a")], now, 1.0),
+        (vec![("__name__", "up"), ("job", "a")], now + 10, 0.0),
+        (vec![("__name__", "up"), ("job", "b")], now, 1.0),
+        (vec![("__name__", "down"), ("job", "a")], now, 1.0),
+    ]);
+    let (status, _) = send(port, "POST", "/write", &HEADERS, req.as_slice());
+    assert_eq!(status, 200);
+
+    let (status, body) = send(port, "GET", "/federate?match[]=up", &[], &[]);
+    assert_eq!(status, 200);
+    assert_eq!(
+        body,
+        format!(
+            "# TYPE up untyped\nup{{job=\"a\"}} 0 {}\nup{{job=\"b\"}} 1 {}\n",
+            now + 10,
+            now
+        )
+    );
+
+    let accept = [("Accept", "application/openmetrics-text; version=1.0.0")];
+    let (status, body) = send(port, "GET", "/federate?match[]=down", &accept, &[]);
+    assert_eq!(status, 200);
+    assert!(body.starts_with("# TYPE down unknown\n"), "{}", body);
+    assert!(body.ends_with("# EOF\n"), "{}", body);
+
+    let (status, _) = send(port, "GET", "/federate", &[], &[]);
+    assert_eq!(status, 400);
+    Ok(())
+}
+
+#[test]
+fn test_limits() -> Result<()> {
+    let dir = TempDir::new()?;
+    let port = free_port()?;
+    let mut opts = DbOpts::default();
+    opts.limits.max_series_per_metric = Some(1);
+    opts.limits.max_label_value_length = Some(8);
+    opts.limits.ingestion_rate = Some(1.0);
+    opts.limits.ingestion_burst = Some(2);
+    let mut server_opts = ServerOpts::default();
+    server_opts.port = port as i32;
+    start_server_with_db_opts(&dir, opts, server_opts)?;
+
+    let now = get_current_timestamp() as i64 + 1000;
+    let req = write_request(&[
+        (vec![("__name__", "up"), ("job", "a")], now, 1.0),
+        (vec![("__name__", "up"), ("job", "b")], now, 1.0),
+        (
+            vec![("__name__", "down"), ("job", "too long value")],
+            now,
+            1.0,
+        ),
+    ]);
+    let (status, body) = send(port, "POST", "/write", &HEADERS, req.as_slice());
+    assert_eq!(status, 400);
+    assert!(body.starts_with("2 of 3 series rejected"), "{}", body);
+    assert!(
+        body.contains("series limit of 1 exceeded for metric up"),
+        "{}",
+        body
+    );
+    assert!(body.contains("job"), "{}", body);
+
+    // both tokens are taken by the valid series
+    let req = write_request(&[(vec![("__name__", "up"), ("job", "a")], now + 1, 1.0)]);
+    let (status, body) = send(port, "POST", "/write", &HEADERS, req.as_slice());
+    assert_eq!(status, 429);
+    assert!(body.contains("rate limit"), "{}", body);
+    Ok(())
+}
+
+#[test]
+fn test_admin_api() -> Result<()> {
+    let dir = TempDir::new()?;
+    let port = free_port()?;
+    let mut server_opts = ServerOpts::default();
+    server_opts.port = port as i32;
+    server_opts.enable_admin_api = true;
+    start_server_with_opts(&dir, server_opts)?;
+
+    let now = get_current_timestamp() as i64 + 1000;
+    let req = write_request(&[
+        (vec![("__name__", "up"), ("job", "a")], now, 1.0),
+        (vec![("__name__", "up"), ("job", "b")], now, 1.0),
+    ]);
+    let (status, _) = send(port, "POST", "/write", &HEADERS, req.as_slice());
+    assert_eq!(status, 200);
+
+    let form = [("Content-Type", "application/x-www-form-urlencoded")];
+    let delete_path = "/api/v1/admin/tsdb/delete_series";
+    let (status, _) = send(port, "GET", delete_path, &[], &[]);
+    assert_eq!(status, 405);
+    let (status, _) = send(port, "POST", delete_path, &[], &[]);
+    assert_eq!(status, 400);
+    let (status, body) = send(port, "POST", delete_path, &form, b"match[]=up{job=\"a\"}");
+    assert_eq!(status, 204, "{}", body);
+
+    let (_, body) = send(port, "POST", "/api/v1/series", &form, b"match[]=up");
+    assert!(!body.contains("\"a\""), "{}", body);
+    assert!(body.contains("\"b\""), "{}", body);
+    let query = format!("query=up&time={}", now as f64 / 1000.0);
+    let (_, body) = send(port, "POST", "/api/v1/query", &form, query.as_bytes());
+    assert!(!body.contains("\"a\""), "{}", body);
+    // label values are removed after tombstones are cleaned
+    let (_, body) = send(port, "GET", "/api/v1/label/job/values", &[], &[]);
+    assert!(body.contains("\"a\""), "{}", body);
+    let (status, _) = send(port, "PUT", "/api/v1/admin/tsdb/clean_tombstones", &[], &[]);
+    assert_eq!(status, 204);
+    let (_, body) = send(port, "GET", "/api/v1/label/job/values", &[], &[]);
+    assert!(!body.contains("\"a\""), "{}", body);
+    assert!(body.contains("\"b\""), "{}", body);
+    Ok(())
+}
+
+#[test]
+fn test_admin_api_disabled() -> Result<()> {
+    let dir = TempDir::new()?;
+    let port = start_server(&dir)?;
+    let path = "/api/v1/admin/tsdb/clean_tombstones";
+    let (status, body) = send(port, "POST", path, &[], &[]);
+    assert_eq!(status, 503);
+    assert!(body.contains("admin APIs disabled"), "{}", body);
+    Ok(())
+}
+
+#[test]
+fn test_metrics() -> Result<()> {
+    let dir = TempDir::new()?;
+    let port = start_server(&dir)?;
+    let now = get_current_timestamp() as i64 + 1000;
+    let req = write_request(&[
+        (vec![("__name__", "up"), ("job", "a")], now, 1.0),
+        (vec![("__name__", "up"), ("job", "b")], now, 1.0),
+    ]);
+    let mut headers = HEADERS.to_vec();
+    headers.push(("X-Scope-OrgID", "team-a"));
+    let (status, _) = send(port, "POST", "/write", &headers, req.as_slice());
+    assert_eq!(status, 200);
+
+    let (status, body) = send(port, "GET", "/metrics", &[], &[]);
+    assert_eq!(status, 200);
+    for line in &[
+        "monolith_ingested_samples_total{tenant=\"team-a\"} 2",
+        "monolith_ingested_samples_total{tenant=\"\"} 0",
+        "monolith_active_series{tenant=\"team-a\"} 2",
+        "monolith_chunks{tenant=\"team-a\"} 1",
+        "monolith_discarded_samples_total{tenant=\"team-a\",reason=\"rate_limited\"} 0",
+        "monolith_request_duration_seconds_count{handler=\"write\"}",
+        "# TYPE monolith_indexer_lookup_duration_seconds histogram",
+        "monolith_indexer_lookup_duration_seconds_count{backend=\"SledIndexer\"}",
+        "# TYPE monolith_tikv_errors_total counter",
+    ] {
+        assert!(body.contains(line), "{} not found in {}", line, body);
+    }
+    assert!(
+        body.contains("monolith_chunk_disk_bytes{tenant=\"team-a\",chunk="),
+        "{}",
+        body
+    );
+    Ok(())
+}
+
+#[test]
+fn test_health_and_shutdown() -> Result<()> {
+    let dir = TempDir::new()?;
+    let token_file = dir.path().join("tokens");
+    fs::write(&token_file, "token\n")?;
+    let mut opts = DbOpts::default();
+    opts.base_dir = dir.path().to_path_buf();
+    let db = MonolithDb::<SledStorage, SledIndexer>::new(
+        opts,
+        Box::new(SledStorageBuilder::new()),
+        Box::new(SledIndexerBuilder::new()),
+    )?;
+    let port = free_port()?;
+    let mut server_opts = ServerOpts::default();
+    server_opts.port = port as i32;
+    server_opts.bearer_token_file = Some(token_file);
+    let shutdown = Shutdown::new();
+    let server = MonolithServer::new(server_opts, db.clone()).with_shutdown(shutdown.clone());
+    let handle = thread::spawn(move || server.serve());
+    db.wait_ready()?;
+    for _ in 0..50 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // probes don't need authentication
+    let (status, body) = send(port, "GET", "/-/healthy", &[], &[]);
+    assert_eq!(status, 200, "{}", body);
+    let (status, body) = send(port, "GET", "/-/ready", &[], &[]);
+    assert_eq!(status, 200, "{}", body);
+    let (status, _) = send(port, "GET", "/api/v1/labels", &[], &[]);
+    assert_eq!(status, 401);
+
+    shutdown.trigger();
+    handle.join().unwrap()?;
+    assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    let labels = Labels::from_vec(vec![Label::from_key_value("__name__", "up")]);
+    let point = TimePoint::new(get_current_timestamp() + 1000, 1.0);
+    match db.write_time_point(labels, point) {
+        Err(MonolithErr::ClosedErr) => {}
+        res => panic!("write after shutdown should fail, got {:?}", res),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_read_replica() -> Result<()> {
+    let leader_dir = TempDir::new()?;
+    let leader_port = free_port()?;
+    let mut opts = DbOpts::default();
+    opts.wal_flush_policy = Some(FlushPolicy::Immediate);
+    let mut server_opts = ServerOpts::default();
+    server_opts.port = leader_port as i32;
+    start_server_with_db_opts(&leader_dir, opts, server_opts)?;
+    let now = get_current_timestamp() as i64 + 1000;
+    let req = write_request(&[(vec![("__name__", "up"), ("job", "a")], now, 1.0)]);
+    let (status, _) = send(leader_port, "POST", "/write", &HEADERS, req.as_slice());
+    assert_eq!(status, 200);
+
+    let replica_dir = TempDir::new()?;
+    let replica_port = free_port()?;
+    let mut opts = DbOpts::default();
+    opts.replica = true;
+    let mut server_opts = ServerOpts::default();
+    server_opts.port = replica_port as i32;
+    let leader = format!("http://127.0.0.1:{}", leader_port);
+    server_opts.replicate_from = Some(Box::leak(leader.into_boxed_str()));
+    start_server_with_db_opts(&replica_dir, opts, server_opts)?;
+
+    let mut replicated = false;
+    for _ in 0..50 {
+        let (status, body) = send(replica_port, "GET", "/api/v1/series?match[]=up", &[], &[]);
+        if status == 200 && body.contains("\"a\"") {
+            replicated = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(replicated, "series are not replicated");
+
+    // samples written after the replica caught up are replicated too
+    let req = write_request(&[(vec![("__name__", "up"), ("job", "b")], now, 1.0)]);
+    let (status, _) = send(leader_port, "POST", "/write", &HEADERS, req.as_slice());
+    assert_eq!(status, 200);
+    let mut replicated = false;
+    for _ in 0..50 {
+        let (_, body) = send(replica_port, "GET", "/api/v1/series?match[]=up", &[], &[]);
+        if body.contains("\"b\"") {
+            replicated = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(replicated, "series are not replicated");
+
+    let (status, _) = send(replica_port, "POST", "/write", &HEADERS, req.as_slice());
+    assert_eq!(status, 403);
+    let (status, body) = send(replica_port, "GET", "/metrics", &[], &[]);
+    assert_eq!(status, 200);
+    assert!(
+        body.contains("monolith_replication_lag_seconds "),
+        "{}",
+        body
+    );
+    let (status, _) = send(replica_port, "GET", "/-/ready", &[], &[]);
+    assert_eq!(status, 200);
+
+    let (status, body) = send(leader_port, "GET", "/replication/wal", &[], &[]);
+    assert_eq!(status, 200);
+    assert!(body.contains("\"enabled\":true"), "{}", body);
+    assert!(body.contains("\"segments\":[0]"), "{}", body);
+    let (status, _) = send(
+        leader_port,
+        "GET",
+        "/replication/wal/segment?seq=9",
+        &[],
+        &[],
+    );
+    assert_eq!(status, 404);
+    Ok(())
+}
+
+#[test]
+fn test_replicate_without_wal() -> Result<()> {
+    let leader_dir = TempDir::new()?;
+    let leader_port = free_port()?;
+    let mut opts = DbOpts::default();
+    opts.wal_flush_policy = None;
+    let mut server_opts = ServerOpts::default();
+    server_opts.port = leader_port as i32;
+    start_server_with_db_opts(&leader_dir, opts, server_opts)?;
+    let (status, body) = send(leader_port, "GET", "/replication/wal", &[], &[]);
+    assert_eq!(status, 200);
+    assert!(body.contains("\"enabled\":false"), "{}", body);
+
+    let replica_dir = TempDir::new()?;
+    let mut opts = DbOpts::default();
+    opts.base_dir = replica_dir.path().to_path_buf();
+    opts.replica = true;
+    let replica = MonolithDb::<SledStorage, SledIndexer>::new(
+        opts,
+        Box::new(SledStorageBuilder::new()),
+        Box::new(SledIndexerBuilder::new()),
+    )?;
+    let leader = format!("http://127.0.0.1:{}", leader_port);
+    match Replicator::start(leader.as_str(), replica) {
+        Err(MonolithErr::OptionErr) => {}
+        Err(err) => panic!("unexpected error {}", err),
+        Ok(_) => panic!("leader without write ahead log should be refused"),
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use monolith::indexer::{SledIndexer, SledIndexerBuilder};
+use monolith::label::{Label, Labels};
+use monolith::option::DbOpts;
+use monolith::promql::{Engine, Value};
+use monolith::storage::{SledStorage, SledStorageBuilder};
+use monolith::time_point::TimePoint;
+use monolith::utils::get_current_timestamp;
+use monolith::{MonolithDb, Result};
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn labels(pairs: Vec<(&str, &str)>) -> Labels {
+    Labels::from_vec(
+        pairs
+            .into_iter()
+            .map(|(k, v)| Label::from_key_value(k, v))
+            .collect(),
+    )
+}
+
+#[test]
+fn test_query() -> Result<()> {
+    let dir = TempDir::new()?;
+    let mut opts = DbOpts::default();
+    opts.base_dir = dir.path().to_path_buf();
+    opts.chunk_size = Duration::from_secs(3600);
+    let db = MonolithDb::<SledStorage, SledIndexer>::new(
+        opts,
+        Box::new(SledStorageBuilder::new()),
+        Box::new(SledIndexerBuilder::new()),
+    )?;
+
+    // counters that increase 1 per second for job a, 2 per second for job b, sampled every 10s
+    let start = get_current_timestamp() + 1000;
+    for (job, speed) in vec![("a", 1.0), ("b", 2.0)] {
+        let points = (0..10)
+            .map(|i| TimePoint::new(start + i * 10000, i as f64 * 10.0 * speed))
+            .collect();
+        db.write_time_points(
+            labels(vec![("__name__", "requests_total"), ("job", job)]),
+            points,
+        )?;
+    }
+    let end = start + 90000;
+
+    let engine = Engine::new(db.as_ref());
+    match engine.instant_query("sum(rate(requests_total[1m]))", end)? {
+        Value::Vector(samples) => {
+            assert_eq!(samples.len(), 1);
+            assert!((samples[0].value - 3.0).abs() < 1e-9);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match engine.instant_query(r#"requests_total{job="b"} / on(job) requests_total"#, end)? {
+        Value::Vector(samples) => {
+            assert_eq!(samples.len(), 1);
+            assert_eq!(samples[0].value, 1.0);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match engine.instant_query("1 + 2 * 3", end)? {
+        Value::Scalar(v) => assert_eq!(v, 7.0),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let series = engine.range_query("max_over_time(requests_total[20s])", start, end, 30000)?;
+    assert_eq!(series.len(), 2);
+    let values = series[1]
+        .points
+        .iter()
+        .map(|tp| tp.value)
+        .collect::<Vec<f64>>();
+    assert_eq!(values, vec![0.0, 60.0, 120.0, 180.0]);
+    assert!(engine
+        .range_query("requests_total[1m]", start, end, 30000)
+        .is_err());
+    Ok(())
+}
@@ -1,5 +1,6 @@
 use monolith::storage::{SledStorage, Storage};
 use monolith::test_utils::Ingester;
+use monolith::time_point::TimePoint;
 use monolith::{Result, Timestamp, Value};
 use tempfile::TempDir;
 
@@ -47,3 +48,67 @@ fn test_get_time_series() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_write_out_of_order() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let storage = SledStorage::new(temp_dir.path())?;
+    for (timestamp, value) in vec![(200, 2.0), (100, 1.0), (300, 3.0), (150, 1.5), (200, 2.5)] {
+        storage.write_time_point(1, timestamp, value)?;
+    }
+
+    let res = storage.read_time_series(1, 0, 1000)?;
+    assert_eq!(
+        res,
+        vec![
+            TimePoint::new(100, 1.0),
+            TimePoint::new(150, 1.5),
+            TimePoint::new(200, 2.5),
+            TimePoint::new(300, 3.0),
+        ]
+    );
+    // value of the same timestamp is overwritten
+    assert_eq!(res[2].value, 2.5);
+    Ok(())
+}
+
+#[test]
+fn test_compact_time_series() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let storage = SledStorage::new(temp_dir.path())?;
+    let db = storage.clone().get_storage();
+    let expect_series = (0..100)
+        .map(|i| TimePoint::new(1000 + i * 15000, (i % 10) as f64))
+        .collect::<Vec<TimePoint>>();
+    for tp in expect_series.iter() {
+        storage.write_time_point(1, tp.timestamp, tp.value)?;
+    }
+    let raw_size = db.get("TS1")?.unwrap().len();
+
+    storage.compact_time_series(1)?;
+    // compacting twice is fine
+    storage.compact_time_series(1)?;
+    assert!(db.get("TS1")?.is_none());
+    let compacted_size = db.get("CS1")?.unwrap().len();
+    assert!(compacted_size < raw_size);
+
+    let res = storage.read_time_series(1, 0, u64::MAX)?;
+    assert_eq!(res.len(), expect_series.len());
+    for (res, expect) in res.iter().zip(expect_series.iter()) {
+        assert_eq!(res.timestamp, expect.timestamp);
+        assert_eq!(res.value, expect.value);
+    }
+
+    // late samples and deletion keep the series compacted
+    let last = expect_series.last().unwrap().timestamp;
+    storage.write_time_point(1, last + 1, 1.0)?;
+    storage.delete_time_points(1, 0, expect_series[0].timestamp)?;
+    assert!(db.get("TS1")?.is_none());
+    let res = storage.read_time_series(1, 0, u64::MAX)?;
+    assert_eq!(res.len(), expect_series.len());
+    assert_eq!(res.last().unwrap().timestamp, last + 1);
+
+    // missing series is skipped
+    storage.compact_time_series(2)?;
+    Ok(())
+}